@@ -0,0 +1,176 @@
+use std::{fs::File, io, num::NonZeroUsize, ops::Range};
+
+use crate::{page_size, round_up_to_page, AddressSpace, Protection};
+
+/// A region of address space reserved at a fixed base address, into which
+/// mappings are placed at stable offsets
+///
+/// Data structures holding raw pointers can only be saved to a file and
+/// mapped back in if everything they point to is at the same address in the
+/// next run. Reserving a range at a configured base early in `main`, before
+/// the heap and thread stacks fragment the address space, and mapping the
+/// snapshot and its companion regions at fixed offsets inside it makes every
+/// address identical run to run.
+///
+/// The reservation is inaccessible until mappings are placed in it. Each
+/// mapping is placed at the cursor, which starts at the base and moves past
+/// each mapping in turn, so the same sequence of calls always produces the
+/// same addresses; [`seek`](Self::seek) moves it to an explicit offset.
+/// Placed mappings live as long as the plan and are returned as raw pointers;
+/// placing one over another fails, as in [`AddressSpace`].
+pub struct AddressPlan {
+    space: AddressSpace,
+    cursor: usize,
+}
+
+impl AddressPlan {
+    /// Reserve `len` bytes, rounded up to whole pages, at exactly `base`
+    ///
+    /// Fails with [`AddrInUse`](io::ErrorKind::AddrInUse) rather than
+    /// replacing anything if part of the range is already mapped.
+    pub fn reserve(base: usize, len: NonZeroUsize) -> io::Result<Self> {
+        Ok(Self {
+            space: AddressSpace::reserve_at(base, len)?,
+            cursor: 0,
+        })
+    }
+
+    /// Start of the reservation
+    pub fn base(&self) -> *mut u8 {
+        self.space.base()
+    }
+
+    /// Size of the reservation, in bytes
+    pub fn reserved_size(&self) -> usize {
+        self.space.reserved_size()
+    }
+
+    /// Offset from the base at which the next mapping will be placed
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Place the next mapping `offset` bytes from the base, which must be a
+    /// multiple of the page size
+    pub fn seek(&mut self, offset: usize) -> io::Result<()> {
+        if !offset.is_multiple_of(page_size()) || offset > self.reserved_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is not a page within the reservation",
+            ));
+        }
+
+        self.cursor = offset;
+
+        Ok(())
+    }
+
+    /// Map `len` bytes of zeroed, private, readable and writable memory at
+    /// the cursor
+    pub fn map_anon(&mut self, len: NonZeroUsize) -> io::Result<*mut u8> {
+        let range = self.next_range(len)?;
+        let ptr = self
+            .space
+            .place_anon(range.clone(), Protection::READ | Protection::WRITE)?;
+
+        self.cursor = range.end;
+
+        Ok(ptr)
+    }
+
+    /// Map `len` bytes of `file` starting at `offset`, which must be a
+    /// multiple of the page size, at the cursor
+    ///
+    /// Writable mappings are shared, so writes reach the file.
+    pub fn map_file(
+        &mut self,
+        file: &File,
+        offset: u64,
+        len: NonZeroUsize,
+        writable: bool,
+    ) -> io::Result<*mut u8> {
+        let prot = if writable {
+            Protection::READ | Protection::WRITE
+        } else {
+            Protection::READ
+        };
+
+        let range = self.next_range(len)?;
+        let ptr = self.space.place_file(file, offset, range.clone(), prot)?;
+
+        self.cursor = range.end;
+
+        Ok(ptr)
+    }
+
+    /// The pages a mapping of `len` bytes at the cursor would cover
+    fn next_range(&self, len: NonZeroUsize) -> io::Result<Range<usize>> {
+        let len = round_up_to_page(len.get());
+
+        if len > self.reserved_size() - self.cursor {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mapping does not fit in the reservation",
+            ));
+        }
+
+        Ok(self.cursor..self.cursor + len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{page_size, test_util::temp_file, AddressPlan, AddressSpace};
+
+    #[test]
+    fn places_at_stable_addresses() {
+        let page = page_size();
+        let len = NonZeroUsize::new(16 * page).unwrap();
+
+        let file = temp_file("address-plan");
+
+        file.write_all_at(b"snapshot", 0).unwrap();
+
+        // hold a free range until the plan takes it over, so nothing else in
+        // the process is handed the base in the meantime
+        let space = AddressSpace::reserve(len).unwrap();
+        let base = space.base() as usize;
+
+        assert_eq!(
+            AddressPlan::reserve(base, len).err().unwrap().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        drop(space);
+
+        for _ in 0..2 {
+            let mut plan = AddressPlan::reserve(base, len).unwrap();
+
+            assert_eq!(
+                AddressPlan::reserve(base, len).err().unwrap().kind(),
+                io::ErrorKind::AddrInUse
+            );
+
+            let heap = plan.map_anon(NonZeroUsize::new(10).unwrap()).unwrap();
+            let snapshot = plan
+                .map_file(&file, 0, NonZeroUsize::new(page).unwrap(), false)
+                .unwrap();
+
+            assert_eq!(heap as usize, base);
+            assert_eq!(snapshot as usize, base + page);
+            assert_eq!(plan.cursor(), 2 * page);
+            assert_eq!(unsafe { *snapshot.add(4) }, b's');
+
+            unsafe { *heap = 1 };
+
+            plan.seek(15 * page).unwrap();
+
+            assert!(plan.map_anon(NonZeroUsize::new(2 * page).unwrap()).is_err());
+            assert!(plan.seek(17 * page).is_err());
+        }
+
+        assert!(AddressPlan::reserve(base + 1, len).is_err());
+    }
+}
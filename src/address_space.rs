@@ -0,0 +1,303 @@
+use std::{
+    collections::BTreeMap, fs::File, io, num::NonZeroUsize, ops::Range, os::unix::io::AsRawFd, ptr,
+};
+
+use crate::{
+    flag::{Flag, UniqueFlag},
+    mmap_raw, munmap_raw, page_size, round_up_to_page, Protection,
+};
+
+/// A reserved, initially inaccessible range of addresses, over parts of which
+/// file and anonymous mappings are placed with `MAP_FIXED`
+///
+/// Nothing else in the process can be mapped inside the reservation, so
+/// mappings can be placed at chosen offsets from one base address, such as
+/// the memory of a guest or the regions of a sandboxed plugin, without racing
+/// other threads for the addresses. The space tracks what has been placed, so
+/// placing over an existing mapping is refused rather than silently replacing
+/// it, and [`unplace`](Self::unplace) returns a range to the reservation.
+///
+/// Ranges are offsets from the base and must start and end on page
+/// boundaries. Mappings are returned as raw pointers and are unmapped with the
+/// space.
+pub struct AddressSpace {
+    base: *mut u8,
+    len: usize,
+    /// Start and end of each placed mapping
+    placed: BTreeMap<usize, usize>,
+}
+
+unsafe impl Send for AddressSpace {}
+unsafe impl Sync for AddressSpace {}
+
+impl AddressSpace {
+    /// Reserve `size` bytes, rounded up to whole pages, wherever the kernel
+    /// chooses
+    pub fn reserve(size: NonZeroUsize) -> io::Result<Self> {
+        let len = round_up_to_page(size.get());
+
+        Ok(Self {
+            base: unsafe { reserve_raw(ptr::null_mut(), len, Flag(0))? },
+            len,
+            placed: BTreeMap::new(),
+        })
+    }
+
+    /// Reserve `size` bytes, rounded up to whole pages, at exactly `base`,
+    /// failing with [`AddrInUse`](io::ErrorKind::AddrInUse) if part of the
+    /// range is already mapped
+    pub(crate) fn reserve_at(base: usize, size: NonZeroUsize) -> io::Result<Self> {
+        if !base.is_multiple_of(page_size()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "base address is not a multiple of the page size",
+            ));
+        }
+
+        let len = round_up_to_page(size.get());
+        let in_use = || io::Error::new(io::ErrorKind::AddrInUse, "address range is already in use");
+
+        let ptr = unsafe { reserve_raw(base as *mut u8, len, Flag::MAP_FIXED_NOREPLACE) }.map_err(
+            |e| match e.raw_os_error() {
+                Some(libc::EEXIST) => in_use(),
+                _ => e,
+            },
+        )?;
+
+        // kernels before 4.17 treat the address as a hint
+        if ptr as usize != base {
+            unsafe { munmap_raw(ptr, len) };
+            return Err(in_use());
+        }
+
+        Ok(Self {
+            base: ptr,
+            len,
+            placed: BTreeMap::new(),
+        })
+    }
+
+    /// Start of the reservation
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    /// Size of the reservation, in bytes
+    pub fn reserved_size(&self) -> usize {
+        self.len
+    }
+
+    /// The ranges which currently have mappings placed over them, in order
+    pub fn placements(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.placed.iter().map(|(&start, &end)| start..end)
+    }
+
+    /// Place zeroed, private memory over `range`
+    pub fn place_anon(&mut self, range: Range<usize>, prot: Protection) -> io::Result<*mut u8> {
+        self.place(
+            range,
+            prot,
+            UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    }
+
+    /// Place a shared mapping of `file` starting at `offset`, which must be a
+    /// multiple of the page size, over `range`
+    pub fn place_file(
+        &mut self,
+        file: &File,
+        offset: u64,
+        range: Range<usize>,
+        prot: Protection,
+    ) -> io::Result<*mut u8> {
+        let offset = i64::try_from(offset)
+            .ok()
+            .filter(|offset| offset % page_size() as i64 == 0)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "offset is not a multiple of the page size",
+                )
+            })?;
+
+        self.place(
+            range,
+            prot,
+            UniqueFlag::MAP_SHARED,
+            file.as_raw_fd(),
+            offset,
+        )
+    }
+
+    fn place(
+        &mut self,
+        range: Range<usize>,
+        prot: Protection,
+        flags: UniqueFlag,
+        fd: i32,
+        offset: i64,
+    ) -> io::Result<*mut u8> {
+        self.check(&range)?;
+
+        let overlaps = self
+            .placed
+            .range(..range.end)
+            .next_back()
+            .is_some_and(|(_, &end)| end > range.start);
+
+        if overlaps {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "range overlaps a placed mapping",
+            ));
+        }
+
+        let ptr = unsafe {
+            mmap_raw(
+                self.base.add(range.start),
+                range.len(),
+                prot,
+                (flags | Flag::MAP_FIXED).0,
+                fd,
+                offset,
+            )?
+        };
+
+        self.placed.insert(range.start, range.end);
+
+        Ok(ptr)
+    }
+
+    /// Unmap whatever is placed in `range`, returning it to the inaccessible
+    /// reservation
+    ///
+    /// The range may cover parts of several placed mappings, or none.
+    pub fn unplace(&mut self, range: Range<usize>) -> io::Result<()> {
+        self.check(&range)?;
+
+        unsafe { reserve_raw(self.base.add(range.start), range.len(), Flag::MAP_FIXED)? };
+
+        let overlapping: Vec<_> = self
+            .placed
+            .range(..range.end)
+            .rev()
+            .take_while(|(_, &end)| end > range.start)
+            .map(|(&start, &end)| (start, end))
+            .collect();
+
+        for (start, end) in overlapping {
+            self.placed.remove(&start);
+
+            if start < range.start {
+                self.placed.insert(start, range.start);
+            }
+
+            if end > range.end {
+                self.placed.insert(range.end, end);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, range: &Range<usize>) -> io::Result<()> {
+        if range.start >= range.end
+            || range.end > self.len
+            || !range.start.is_multiple_of(page_size())
+            || !range.end.is_multiple_of(page_size())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range is not a non-empty run of pages within the reservation",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.base, self.len);
+        }
+    }
+}
+
+/// Map `len` inaccessible bytes at `addr` which reserve the addresses without
+/// committing memory
+unsafe fn reserve_raw(addr: *mut u8, len: usize, flags: Flag) -> io::Result<*mut u8> {
+    mmap_raw(
+        addr,
+        len,
+        Protection::NONE,
+        (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_NORESERVE | flags).0,
+        -1,
+        0,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{page_size, test_util::temp_file, AddressSpace, Protection};
+
+    #[test]
+    fn tracks_placements() {
+        let page = page_size();
+
+        let file = temp_file("address-space");
+
+        file.set_len(2 * page as u64).unwrap();
+        file.write_all_at(b"rom", page as u64).unwrap();
+
+        let mut space = AddressSpace::reserve(NonZeroUsize::new(8 * page).unwrap()).unwrap();
+
+        let ram = space
+            .place_anon(0..4 * page, Protection::READ | Protection::WRITE)
+            .unwrap();
+        let rom = space
+            .place_file(&file, page as u64, 6 * page..7 * page, Protection::READ)
+            .unwrap();
+
+        assert_eq!(ram, space.base());
+        assert_eq!(rom as usize, space.base() as usize + 6 * page);
+        assert_eq!(unsafe { *rom.add(2) }, b'm');
+
+        unsafe { *ram.add(3 * page) = 1 };
+
+        assert_eq!(
+            space
+                .place_anon(3 * page..5 * page, Protection::READ)
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::AddrInUse
+        );
+        assert!(space
+            .place_anon(7 * page..9 * page, Protection::READ)
+            .is_err());
+        assert!(space.place_anon(0..1, Protection::READ).is_err());
+
+        space.unplace(page..2 * page).unwrap();
+
+        assert_eq!(
+            space.placements().collect::<Vec<_>>(),
+            [0..page, 2 * page..4 * page, 6 * page..7 * page]
+        );
+
+        // the rest of the placement is untouched
+        assert_eq!(unsafe { *ram.add(3 * page) }, 1);
+
+        space.unplace(0..8 * page).unwrap();
+
+        assert_eq!(space.placements().count(), 0);
+
+        let ram = space.place_anon(page..4 * page, Protection::READ).unwrap();
+
+        assert_eq!(unsafe { *ram.add(2 * page) }, 0);
+    }
+}
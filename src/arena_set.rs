@@ -0,0 +1,196 @@
+use std::{alloc::Layout, io, marker::PhantomData, num::NonZeroUsize};
+
+use crate::{madvise_raw, round_up_to_page, AddressSpace, Protection};
+
+/// A fixed number of equally sized bump arenas, carved back to back out of
+/// one [`AddressSpace`] reservation
+///
+/// Giving each worker thread its own arena avoids contention on a shared
+/// allocator, while keeping them in one contiguous range costs a single
+/// reservation and a single mapping rather than one per arena, and keeps the
+/// workers' memory together. Borrow the arenas with [`arenas`](Self::arenas) and move one into
+/// each worker.
+pub struct ArenaSet {
+    space: AddressSpace,
+    /// Distance between the starts of consecutive arenas
+    stride: usize,
+    arena_size: usize,
+    /// Bytes handed out by each arena
+    used: Vec<usize>,
+}
+
+impl ArenaSet {
+    /// Reserve `count` arenas of `arena_size` bytes each, one per worker
+    /// thread
+    ///
+    /// Arenas start on page boundaries, so each one's share of the
+    /// reservation is rounded up to whole pages.
+    pub fn per_thread(count: NonZeroUsize, arena_size: NonZeroUsize) -> io::Result<Self> {
+        let overflow = || io::Error::new(io::ErrorKind::InvalidInput, "arenas overflow");
+
+        let stride = round_up_to_page(arena_size.get());
+        let total = stride.checked_mul(count.get()).ok_or_else(overflow)?;
+
+        let mut space = AddressSpace::reserve(NonZeroUsize::new(total).unwrap())?;
+
+        space.place_anon(0..total, Protection::READ | Protection::WRITE)?;
+
+        Ok(Self {
+            space,
+            stride,
+            arena_size: arena_size.get(),
+            used: vec![0; count.get()],
+        })
+    }
+
+    /// Number of arenas
+    pub fn len(&self) -> usize {
+        self.used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used.is_empty()
+    }
+
+    /// Capacity of each arena, in bytes
+    pub fn arena_size(&self) -> usize {
+        self.arena_size
+    }
+
+    /// Borrow every arena, in address order, to hand out to the workers
+    ///
+    /// Allocations outlive the borrow: borrowing the arenas again continues
+    /// where they left off.
+    pub fn arenas(&mut self) -> Vec<Arena<'_>> {
+        let base = self.space.base();
+
+        self.used
+            .iter_mut()
+            .enumerate()
+            .map(|(idx, used)| Arena {
+                ptr: unsafe { base.add(idx * self.stride) },
+                len: self.arena_size,
+                used,
+                _set: PhantomData,
+            })
+            .collect()
+    }
+}
+
+/// One arena of an [`ArenaSet`], which hands out memory by bumping an offset
+/// until it is [`reset`](Self::reset)
+pub struct Arena<'a> {
+    ptr: *mut u8,
+    len: usize,
+    used: &'a mut usize,
+    _set: PhantomData<&'a mut ArenaSet>,
+}
+
+unsafe impl Send for Arena<'_> {}
+
+impl Arena<'_> {
+    /// Start of the arena's memory
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Capacity of the arena, in bytes
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes handed out since the arena was last reset, including padding
+    pub fn used(&self) -> usize {
+        *self.used
+    }
+
+    /// Allocate zeroed memory for `layout`, or `None` if the arena is full
+    ///
+    /// The memory stays valid until the arena is reset or the set is
+    /// dropped.
+    pub fn alloc(&mut self, layout: Layout) -> Option<*mut u8> {
+        let start = (self.ptr as usize + *self.used).checked_next_multiple_of(layout.align())?
+            - self.ptr as usize;
+        let end = start.checked_add(layout.size())?;
+
+        if end > self.len {
+            return None;
+        }
+
+        *self.used = end;
+
+        Some(unsafe { self.ptr.add(start) })
+    }
+
+    /// Free every allocation at once, returning the pages they used to the
+    /// kernel so they read as zero again
+    pub fn reset(&mut self) -> io::Result<()> {
+        let used = round_up_to_page(*self.used);
+
+        if used != 0 {
+            unsafe { madvise_raw(self.ptr, used, libc::MADV_DONTNEED)? };
+        }
+
+        *self.used = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{alloc::Layout, num::NonZeroUsize};
+
+    use crate::{page_size, ArenaSet};
+
+    #[test]
+    fn one_arena_per_thread() {
+        let size = 3 * page_size() - 100;
+        let mut set = ArenaSet::per_thread(
+            NonZeroUsize::new(4).unwrap(),
+            NonZeroUsize::new(size).unwrap(),
+        )
+        .unwrap();
+
+        let mut arenas = set.arenas();
+        let stride = arenas[1].as_ptr() as usize - arenas[0].as_ptr() as usize;
+
+        assert_eq!(stride, 3 * page_size());
+        assert_eq!(
+            arenas[3].as_ptr() as usize - arenas[0].as_ptr() as usize,
+            3 * stride
+        );
+
+        std::thread::scope(|scope| {
+            for (idx, arena) in arenas.iter_mut().enumerate() {
+                scope.spawn(move || {
+                    let layout = Layout::new::<u64>();
+
+                    while let Some(ptr) = arena.alloc(layout) {
+                        assert_eq!(unsafe { *(ptr as *mut u64) }, 0);
+                        unsafe { *(ptr as *mut u64) = idx as u64 };
+                    }
+
+                    assert_eq!(arena.used(), size / 8 * 8);
+                });
+            }
+        });
+
+        drop(arenas);
+
+        let mut arenas = set.arenas();
+
+        assert!(arenas[2].alloc(Layout::new::<u64>()).is_none());
+        assert_eq!(unsafe { *(arenas[2].as_ptr() as *const u64) }, 2);
+
+        arenas[2].reset().unwrap();
+
+        let ptr = arenas[2]
+            .alloc(Layout::from_size_align(1, 64).unwrap())
+            .unwrap();
+
+        assert_eq!(ptr, arenas[2].as_ptr());
+        assert_eq!(unsafe { *ptr }, 0);
+        assert_eq!(unsafe { *(arenas[1].as_ptr() as *const u64) }, 1);
+    }
+}
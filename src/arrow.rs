@@ -0,0 +1,326 @@
+use std::{fs::File, io, num::NonZeroUsize};
+
+use crate::{Mmap, MmapMut};
+
+/// Magic string at both ends of an Arrow IPC file
+const MAGIC: &[u8] = b"ARROW1";
+
+/// The leading magic string, padded to 8 bytes
+const HEADER_LEN: usize = 8;
+
+/// Footer length and the trailing magic string
+const TRAILER_LEN: usize = 4 + MAGIC.len();
+
+/// Size of a `Block` struct in the footer
+const BLOCK_LEN: usize = 24;
+
+/// Alignment Arrow buffers are expected to have in memory
+const ALIGN: usize = 64;
+
+/// Fields of the `Footer` table
+const FOOTER_DICTIONARIES: u16 = 2;
+const FOOTER_RECORD_BATCHES: u16 = 3;
+
+/// Location of a message in the file, from the footer
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    offset: usize,
+    metadata_len: usize,
+    body_len: usize,
+}
+
+/// A message of an [`ArrowFile`]: its flatbuffer metadata, including the
+/// length prefix, and its body holding the buffers
+#[derive(Debug, Clone, Copy)]
+pub struct ArrowMessage<'a> {
+    pub metadata: &'a [u8],
+    /// Starts at an address aligned to 64 bytes
+    pub body: &'a [u8],
+}
+
+/// A mapped Arrow IPC file, giving access to the bytes of its record batches
+/// and dictionaries without copying them
+///
+/// [`open`](Self::open) checks the magic strings and reads the block list in
+/// the footer. Arrow expects the buffers in a message body to be aligned to
+/// 64 bytes; bodies which the file places at an offset that isn't are copied
+/// into anonymous memory once when opening, while the rest are used straight
+/// from the mapping.
+///
+/// Decoding the schema and message flatbuffers is left to an Arrow
+/// implementation; [`footer`](Self::footer) returns the footer holding the
+/// schema.
+pub struct ArrowFile {
+    map: Mmap<'static>,
+    footer: (usize, usize),
+    dictionaries: Vec<Block>,
+    record_batches: Vec<Block>,
+    /// Copies of bodies which are misaligned in the file, by body offset
+    realigned: Vec<(usize, MmapMut<'static>)>,
+}
+
+impl ArrowFile {
+    /// Map `file` and read its footer
+    pub fn open(file: &File) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let map = Mmap::new_file(file)?;
+
+        if map.len < HEADER_LEN + TRAILER_LEN || !map.starts_with(MAGIC) || !map.ends_with(MAGIC) {
+            return Err(invalid("file is not in the Arrow IPC file format"));
+        }
+
+        let footer_len = u32::from_le_bytes(map[map.len - TRAILER_LEN..][..4].try_into().unwrap());
+        let footer_start = (map.len - TRAILER_LEN)
+            .checked_sub(footer_len as usize)
+            .filter(|&start| start >= HEADER_LEN)
+            .ok_or_else(|| invalid("Arrow footer is larger than the file"))?;
+
+        let footer = &map[footer_start..map.len - TRAILER_LEN];
+
+        let root = Table::root(footer).ok_or_else(|| invalid("Arrow footer is corrupt"))?;
+
+        let blocks = |field| {
+            let blocks = root
+                .blocks(field)
+                .ok_or_else(|| invalid("Arrow footer is corrupt"))?;
+
+            if blocks
+                .iter()
+                .any(|block| block.end().is_none_or(|end| end > footer_start))
+            {
+                return Err(invalid("Arrow block lies outside the file"));
+            }
+
+            Ok(blocks)
+        };
+
+        let dictionaries = blocks(FOOTER_DICTIONARIES)?;
+        let record_batches = blocks(FOOTER_RECORD_BATCHES)?;
+
+        let mut realigned = Vec::new();
+
+        for block in dictionaries.iter().chain(&record_batches) {
+            let body = block.offset + block.metadata_len;
+
+            if body % ALIGN == 0 || block.body_len == 0 {
+                continue;
+            }
+
+            let mut copy = MmapMut::new_anon(NonZeroUsize::new(block.body_len).unwrap())?;
+
+            copy[..block.body_len].copy_from_slice(&map[body..][..block.body_len]);
+            realigned.push((body, copy));
+        }
+
+        Ok(Self {
+            footer: (footer_start, footer_len as usize),
+            map,
+            dictionaries,
+            record_batches,
+            realigned,
+        })
+    }
+
+    /// The footer flatbuffer, holding the schema
+    pub fn footer(&self) -> &[u8] {
+        &self.map[self.footer.0..][..self.footer.1]
+    }
+
+    pub fn record_batch_count(&self) -> usize {
+        self.record_batches.len()
+    }
+
+    /// Record batch `idx`, or `None` if it is out of bounds
+    pub fn record_batch(&self, idx: usize) -> Option<ArrowMessage<'_>> {
+        Some(self.message(*self.record_batches.get(idx)?))
+    }
+
+    /// Every record batch, in file order
+    pub fn record_batches(&self) -> impl ExactSizeIterator<Item = ArrowMessage<'_>> {
+        self.record_batches.iter().map(|&block| self.message(block))
+    }
+
+    pub fn dictionary_count(&self) -> usize {
+        self.dictionaries.len()
+    }
+
+    /// Dictionary batch `idx`, or `None` if it is out of bounds
+    pub fn dictionary(&self, idx: usize) -> Option<ArrowMessage<'_>> {
+        Some(self.message(*self.dictionaries.get(idx)?))
+    }
+
+    /// Number of message bodies which had to be copied to align them
+    pub fn realigned_count(&self) -> usize {
+        self.realigned.len()
+    }
+
+    fn message(&self, block: Block) -> ArrowMessage<'_> {
+        let body = block.offset + block.metadata_len;
+
+        let body = match self.realigned.iter().find(|(offset, _)| *offset == body) {
+            Some((_, copy)) => &copy[..block.body_len],
+            None if block.body_len == 0 => &[],
+            None => &self.map[body..][..block.body_len],
+        };
+
+        ArrowMessage {
+            metadata: &self.map[block.offset..][..block.metadata_len],
+            body,
+        }
+    }
+}
+
+impl Block {
+    fn end(&self) -> Option<usize> {
+        self.offset
+            .checked_add(self.metadata_len)?
+            .checked_add(self.body_len)
+    }
+}
+
+/// Just enough of a flatbuffer reader to find the footer's block vectors,
+/// checking every offset against the buffer
+struct Table<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Table<'a> {
+    fn root(buf: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            buf,
+            pos: read_u32(buf, 0)? as usize,
+        })
+    }
+
+    /// Position of field `id`, or `None` if it is absent
+    fn field(&self, id: u16) -> Option<Option<usize>> {
+        let soffset = read_u32(self.buf, self.pos)? as i32;
+        let vtable = (self.pos as i64 - soffset as i64).try_into().ok()?;
+
+        let vtable_len = read_u16(self.buf, vtable)?;
+        let entry = 4 + 2 * id;
+
+        if entry + 2 > vtable_len {
+            return Some(None);
+        }
+
+        match read_u16(self.buf, vtable + entry as usize)? {
+            0 => Some(None),
+            offset => Some(Some(self.pos + offset as usize)),
+        }
+    }
+
+    /// The `Block` structs in vector field `id`, which is empty if absent
+    fn blocks(&self, id: u16) -> Option<Vec<Block>> {
+        let Some(field) = self.field(id)? else {
+            return Some(Vec::new());
+        };
+
+        let vector = field.checked_add(read_u32(self.buf, field)? as usize)?;
+        let count = read_u32(self.buf, vector)? as usize;
+
+        let elems = self
+            .buf
+            .get(vector + 4..vector + 4 + count.checked_mul(BLOCK_LEN)?)?;
+
+        elems
+            .chunks(BLOCK_LEN)
+            .map(|block| {
+                let field = |at: usize| u64::from_le_bytes(block[at..at + 8].try_into().unwrap());
+
+                Some(Block {
+                    offset: field(0).try_into().ok()?,
+                    metadata_len: (field(8) as u32).try_into().ok()?,
+                    body_len: field(16).try_into().ok()?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        buf.get(pos..pos.checked_add(2)?)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        buf.get(pos..pos.checked_add(4)?)?.try_into().unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, os::unix::fs::FileExt};
+
+    use crate::{test_util::temp_file, ArrowFile};
+
+    fn block(offset: u64, metadata_len: u32, body_len: u64) -> Vec<u8> {
+        let mut block = offset.to_le_bytes().to_vec();
+
+        block.extend_from_slice(&metadata_len.to_le_bytes());
+        block.extend_from_slice(&[0; 4]);
+        block.extend_from_slice(&body_len.to_le_bytes());
+
+        block
+    }
+
+    #[test]
+    fn reads_record_batches() {
+        let mut contents = b"ARROW1\0\0".to_vec();
+
+        // a batch whose body is misaligned at offset 24
+        contents.extend_from_slice(&[0xff; 4]);
+        contents.extend_from_slice(&8_u32.to_le_bytes());
+        contents.extend_from_slice(&[1; 8]);
+        contents.extend_from_slice(&[b'a'; 64]);
+        // and one whose body is aligned at offset 192
+        contents.resize(128, 0);
+        contents.extend_from_slice(&[2; 64]);
+        contents.extend_from_slice(&[b'b'; 64]);
+
+        // the footer table, with only the record batches field set
+        let mut footer = 16_u32.to_le_bytes().to_vec();
+
+        for field in [12_u16, 8, 0, 0, 0, 4] {
+            footer.extend_from_slice(&field.to_le_bytes());
+        }
+
+        footer.extend_from_slice(&12_i32.to_le_bytes());
+        footer.extend_from_slice(&4_u32.to_le_bytes());
+        footer.extend_from_slice(&2_u32.to_le_bytes());
+        footer.extend(block(8, 16, 64));
+        footer.extend(block(128, 64, 64));
+
+        contents.extend_from_slice(&footer);
+        contents.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+        contents.extend_from_slice(b"ARROW1");
+
+        let mut file = temp_file("arrow");
+
+        file.write_all(&contents).unwrap();
+
+        let arrow = ArrowFile::open(&file).unwrap();
+
+        assert_eq!(arrow.record_batch_count(), 2);
+        assert_eq!(arrow.dictionary_count(), 0);
+        assert_eq!(arrow.realigned_count(), 1);
+        assert_eq!(arrow.footer(), footer);
+
+        for (batch, byte) in arrow.record_batches().zip([b'a', b'b']) {
+            assert_eq!(batch.body, [byte; 64]);
+            assert_eq!(batch.body.as_ptr() as usize % 64, 0);
+        }
+
+        assert_eq!(arrow.record_batch(1).unwrap().metadata, [2; 64]);
+        assert!(arrow.record_batch(2).is_none());
+
+        // a block running into the footer
+        file.write_all_at(&[0xff], 256 + 28).unwrap();
+
+        assert!(ArrowFile::open(&file).is_err());
+    }
+}
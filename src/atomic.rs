@@ -0,0 +1,107 @@
+use std::{
+    io,
+    ops::RangeBounds,
+    slice,
+    sync::atomic::{
+        AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16, AtomicU32, AtomicU64,
+        AtomicU8, AtomicUsize,
+    },
+};
+
+use crate::{check_range, MmapMut};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Atomic integer types which may be overlaid onto mapped memory
+///
+/// This trait is sealed and implemented for the atomic types in
+/// [`std::sync::atomic`] which have the same in-memory representation as their
+/// underlying integer. `AtomicBool` is left out, as any byte other than zero
+/// or one would be an invalid `bool`.
+pub trait Atomic: private::Sealed + Sync {}
+
+macro_rules! atomic_impl {
+    ($($ty:ty),*) => {
+        $(
+            impl private::Sealed for $ty {}
+            impl Atomic for $ty {}
+        )*
+    };
+}
+
+atomic_impl!(
+    AtomicU8,
+    AtomicU16,
+    AtomicU32,
+    AtomicU64,
+    AtomicUsize,
+    AtomicI8,
+    AtomicI16,
+    AtomicI32,
+    AtomicI64,
+    AtomicIsize
+);
+
+impl<'a> MmapMut<'a> {
+    /// View the given byte range of the mapping as a slice of atomics
+    ///
+    /// Other processes sharing the mapping may modify this memory at any time,
+    /// so updates should go through atomics rather than `&mut [u8]`, which
+    /// would assert exclusive access.
+    ///
+    /// The range must be aligned for `A` and its length a multiple of the size
+    /// of `A`. The atomics borrow the mapping mutably, so no slices of it
+    /// obtained through `Deref` can be alive while they are written to.
+    pub fn as_atomics<A: Atomic>(&mut self, range: impl RangeBounds<usize>) -> io::Result<&[A]> {
+        let range = check_range(self.len, range)?;
+
+        let size = std::mem::size_of::<A>();
+
+        if !range.len().is_multiple_of(size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range length is not a multiple of the atomic size",
+            ));
+        }
+
+        let count = range.len() / size;
+
+        let ptr = self.typed_ptr::<A>(range.start, count)?;
+
+        Ok(unsafe { slice::from_raw_parts(ptr, count) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    };
+
+    use crate::MmapMut;
+
+    #[test]
+    fn atomics_see_writes() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let counters = map.as_atomics::<AtomicU64>(8..72).unwrap();
+
+        assert_eq!(counters.len(), 8);
+
+        counters[1].fetch_add(5, Ordering::SeqCst);
+
+        assert_eq!(&map[16..24], &5_u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn atomics_check_alignment_and_length() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        assert!(map.as_atomics::<AtomicU32>(2..10).is_err());
+        assert!(map.as_atomics::<AtomicU32>(0..6).is_err());
+        assert!(map.as_atomics::<AtomicU32>(4092..4100).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    io,
+    num::NonZeroUsize,
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::MmapMut;
+
+const WORD_BITS: usize = 64;
+
+/// A fixed-size set of bits stored in a mapping, one bit per index
+///
+/// Bits are packed into little-endian `u64` words starting at the beginning
+/// of the mapping, so a set built over a file-backed mapping is persisted by
+/// [`flush`](Self::flush) and can be reopened later, and one built over an
+/// anonymous mapping can grow far beyond what fits on the heap, with the
+/// kernel paging it as needed.
+///
+/// Besides plain access through `&mut self`, bits can be updated through
+/// `&self` with atomic read-modify-write operations on their word, for
+/// filters and visited-sets shared between threads.
+///
+/// [`rank`](Self::rank) and [`select`](Self::select) scan the words up to the
+/// requested position, counting bits with a popcount per word.
+pub struct MmapBitSet<'a> {
+    map: MmapMut<'a>,
+    words: usize,
+}
+
+// shared access only goes through atomics
+unsafe impl Send for MmapBitSet<'_> {}
+unsafe impl Sync for MmapBitSet<'_> {}
+
+impl<'a> MmapBitSet<'a> {
+    /// Use the whole of `map` as a bitset, keeping any bits already in it
+    pub fn new(map: MmapMut<'a>) -> io::Result<Self> {
+        let words = map.len / (WORD_BITS / 8);
+
+        // checks the alignment, which any real mapping satisfies
+        map.typed_ptr::<AtomicU64>(0, words)?;
+
+        Ok(Self { map, words })
+    }
+
+    /// A bitset of at least `bits` bits in anonymous memory, all clear
+    pub fn new_anon(bits: NonZeroUsize) -> io::Result<Self> {
+        let len = bits.get().div_ceil(WORD_BITS) * (WORD_BITS / 8);
+
+        Self::new(MmapMut::new_anon(NonZeroUsize::new(len).unwrap())?)
+    }
+
+    /// Number of bits in the set
+    pub fn len(&self) -> usize {
+        self.words * WORD_BITS
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words == 0
+    }
+
+    /// The bits as atomic words, bit `idx` being bit `idx % 64` of word
+    /// `idx / 64`
+    pub fn words(&self) -> &[AtomicU64] {
+        unsafe { slice::from_raw_parts(self.map.ptr as *const AtomicU64, self.words) }
+    }
+
+    fn words_mut(&mut self) -> &mut [u64] {
+        unsafe { slice::from_raw_parts_mut(self.map.ptr as *mut u64, self.words) }
+    }
+
+    /// Whether bit `idx` is set, or `false` if it is out of bounds
+    pub fn get(&self, idx: usize) -> bool {
+        self.words()
+            .get(idx / WORD_BITS)
+            .is_some_and(|word| word.load(Ordering::Relaxed) & bit(idx) != 0)
+    }
+
+    /// Set bit `idx` to `value`
+    ///
+    /// Panics if `idx` is out of bounds.
+    pub fn set(&mut self, idx: usize, value: bool) {
+        let word = &mut self.words_mut()[idx / WORD_BITS];
+
+        if value {
+            *word |= bit(idx);
+        } else {
+            *word &= !bit(idx);
+        }
+    }
+
+    /// Atomically set bit `idx`, returning whether it was already set
+    ///
+    /// Panics if `idx` is out of bounds.
+    pub fn test_and_set(&self, idx: usize) -> bool {
+        self.words()[idx / WORD_BITS].fetch_or(bit(idx), Ordering::AcqRel) & bit(idx) != 0
+    }
+
+    /// Atomically clear bit `idx`, returning whether it was set
+    ///
+    /// Panics if `idx` is out of bounds.
+    pub fn test_and_clear(&self, idx: usize) -> bool {
+        self.words()[idx / WORD_BITS].fetch_and(!bit(idx), Ordering::AcqRel) & bit(idx) != 0
+    }
+
+    /// Clear every bit
+    pub fn clear(&mut self) {
+        self.words_mut().fill(0);
+    }
+
+    /// Number of set bits
+    pub fn count_ones(&self) -> usize {
+        self.rank(self.len())
+    }
+
+    /// Number of set bits below `idx`
+    ///
+    /// Panics if `idx` is greater than [`len`](Self::len).
+    pub fn rank(&self, idx: usize) -> usize {
+        assert!(idx <= self.len(), "rank index out of bounds");
+
+        let words = self.words();
+        let full = words[..idx / WORD_BITS]
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed).count_ones() as usize)
+            .sum::<usize>();
+
+        match idx % WORD_BITS {
+            0 => full,
+            rem => {
+                let word = words[idx / WORD_BITS].load(Ordering::Relaxed);
+
+                full + (word & (bit(rem) - 1)).count_ones() as usize
+            }
+        }
+    }
+
+    /// Index of the set bit with `n` set bits below it, or `None` if fewer
+    /// than `n + 1` bits are set
+    pub fn select(&self, mut n: usize) -> Option<usize> {
+        for (idx, word) in self.words().iter().enumerate() {
+            let mut word = word.load(Ordering::Relaxed);
+            let ones = word.count_ones() as usize;
+
+            if n >= ones {
+                n -= ones;
+                continue;
+            }
+
+            // drop the lowest `n` set bits
+            for _ in 0..n {
+                word &= word - 1;
+            }
+
+            return Some(idx * WORD_BITS + word.trailing_zeros() as usize);
+        }
+
+        None
+    }
+
+    /// Indices of the set bits, in ascending order
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words().iter().enumerate().flat_map(|(idx, word)| {
+            let mut word = word.load(Ordering::Relaxed);
+
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+
+                Some(idx * WORD_BITS + bit)
+            })
+        })
+    }
+
+    /// Write the bits back to the backing file, waiting for the writes to
+    /// complete
+    ///
+    /// Does nothing for anonymous mappings.
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+
+    /// The underlying mapping
+    pub fn into_inner(self) -> MmapMut<'a> {
+        self.map
+    }
+}
+
+fn bit(idx: usize) -> u64 {
+    1 << (idx % WORD_BITS)
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{test_util::temp_file, MmapBitSet, MmapMut};
+
+    #[test]
+    fn rank_and_select() {
+        let mut set = MmapBitSet::new_anon(NonZeroUsize::new(1000).unwrap()).unwrap();
+
+        assert_eq!(set.len(), 1024);
+
+        for idx in [3, 64, 65, 700, 1023] {
+            set.set(idx, true);
+        }
+
+        assert!(set.get(64));
+        assert!(!set.get(66));
+        assert!(!set.get(5000));
+
+        assert_eq!(set.count_ones(), 5);
+        assert_eq!(set.rank(65), 2);
+        assert_eq!(set.rank(66), 3);
+        assert_eq!(set.rank(1024), 5);
+
+        assert_eq!(set.select(0), Some(3));
+        assert_eq!(set.select(2), Some(65));
+        assert_eq!(set.select(4), Some(1023));
+        assert_eq!(set.select(5), None);
+
+        assert_eq!(set.iter_ones().collect::<Vec<_>>(), [3, 64, 65, 700, 1023]);
+
+        assert!(!set.test_and_set(10));
+        assert!(set.test_and_set(10));
+        assert!(set.test_and_clear(10));
+        assert!(!set.get(10));
+
+        set.set(64, false);
+        assert_eq!(set.rank(1024), 4);
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn persists_to_file() {
+        let file = temp_file("bitset");
+
+        file.set_len(4096).unwrap();
+
+        let set = MmapBitSet::new(MmapMut::new_file(&file).unwrap()).unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for idx in (0..32_768).step_by(3) {
+                        set.test_and_set(idx);
+                    }
+                });
+            }
+        });
+
+        set.flush().unwrap();
+        drop(set);
+
+        let set = MmapBitSet::new(MmapMut::new_file(&file).unwrap()).unwrap();
+
+        assert_eq!(set.count_ones(), 32_768_usize.div_ceil(3));
+        assert!(set.get(12) && set.get(3) && !set.get(4));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{ptr, sync::OnceLock};
+
+use crate::{
+    flag::{Flag, UniqueFlag},
+    libc_compat,
+    options::huge_page_size,
+    page_size, soft_dirty,
+};
+
+/// Optional kernel features detected on the running system
+///
+/// Obtained from [`capabilities`]. APIs which depend on one of these return
+/// [`Unsupported`](std::io::ErrorKind::Unsupported) when it is missing, and
+/// callers can check up front to choose a fallback instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `MAP_FIXED_NOREPLACE` is honoured rather than treated as a hint
+    ///
+    /// (since Linux 4.17)
+    pub map_fixed_noreplace: bool,
+    /// `madvise(MADV_COLLAPSE)` is recognized
+    ///
+    /// (since Linux 6.1)
+    pub madv_collapse: bool,
+    /// `memfd_secret(2)` exists and is enabled
+    ///
+    /// (since Linux 5.14, and requires booting with `secretmem.enable=1` before
+    /// Linux 6.5)
+    pub memfd_secret: bool,
+    /// `mseal(2)` exists
+    ///
+    /// (since Linux 6.10)
+    pub mseal: bool,
+    /// Pages written since `/proc/self/clear_refs` was last reset are flagged
+    /// soft-dirty in `/proc/self/pagemap`
+    ///
+    /// (requires a kernel built with `CONFIG_MEM_SOFT_DIRTY`, which not every
+    /// architecture supports)
+    pub soft_dirty: bool,
+}
+
+/// Probe the running kernel for optional features, using throwaway mappings
+/// and system calls which have no lasting effect
+///
+/// The probes run once; later calls return the cached result.
+pub fn capabilities() -> &'static Capabilities {
+    static CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+
+    CAPABILITIES.get_or_init(|| Capabilities {
+        map_fixed_noreplace: probe_map_fixed_noreplace(),
+        madv_collapse: probe_madv_collapse(),
+        memfd_secret: probe_memfd_secret(),
+        mseal: probe_mseal(),
+        soft_dirty: probe_soft_dirty(),
+    })
+}
+
+fn last_errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/// Kernels before 4.17 ignore unknown flags, so treat `MAP_FIXED_NOREPLACE` as
+/// a plain hint and place the mapping elsewhere instead of failing with
+/// `EEXIST`
+fn probe_map_fixed_noreplace() -> bool {
+    let len = page_size();
+
+    unsafe {
+        let existing = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_NONE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS).0,
+            -1,
+            0,
+        );
+
+        if existing == libc::MAP_FAILED {
+            return false;
+        }
+
+        let probe = libc::mmap(
+            existing,
+            len,
+            libc::PROT_NONE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_FIXED_NOREPLACE).0,
+            -1,
+            0,
+        );
+
+        let supported = probe == libc::MAP_FAILED && last_errno() == libc::EEXIST;
+
+        if probe != libc::MAP_FAILED && probe != existing {
+            libc::munmap(probe, len);
+        }
+
+        libc::munmap(existing, len);
+
+        supported
+    }
+}
+
+/// Unknown advice fails with `EINVAL`, but so does `MADV_COLLAPSE` on a range
+/// holding no whole huge page. The probe therefore collapses a populated,
+/// huge-page-aligned range, which either succeeds or fails with something
+/// other than `EINVAL`.
+fn probe_madv_collapse() -> bool {
+    let huge_page = huge_page_size();
+    let len = 2 * huge_page;
+
+    unsafe {
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS).0,
+            -1,
+            0,
+        );
+
+        if ptr == libc::MAP_FAILED {
+            return false;
+        }
+
+        let aligned = (ptr as usize).next_multiple_of(huge_page) as *mut u8;
+
+        aligned.write_volatile(1);
+
+        let supported = libc::madvise(aligned.cast(), huge_page, libc_compat::MADV_COLLAPSE) == 0
+            || last_errno() != libc::EINVAL;
+
+        libc::munmap(ptr, len);
+
+        supported
+    }
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+fn probe_memfd_secret() -> bool {
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_secret, libc::O_CLOEXEC) };
+
+    if fd < 0 {
+        return false;
+    }
+
+    unsafe { libc::close(fd as i32) };
+
+    true
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+)))]
+fn probe_memfd_secret() -> bool {
+    false
+}
+
+/// Sealing an empty range is a no-op which succeeds whenever the system call
+/// exists
+fn probe_mseal() -> bool {
+    unsafe {
+        libc::syscall(
+            libc_compat::SYS_MSEAL,
+            ptr::null_mut::<libc::c_void>(),
+            0,
+            0,
+        ) == 0
+    }
+}
+
+/// A freshly written page is always soft-dirty, so the flag is only missing
+/// when the kernel does not track it
+fn probe_soft_dirty() -> bool {
+    let len = page_size();
+
+    unsafe {
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS).0,
+            -1,
+            0,
+        );
+
+        if ptr == libc::MAP_FAILED {
+            return false;
+        }
+
+        ptr.cast::<u8>().write_volatile(1);
+
+        let supported = soft_dirty::pagemap(ptr.cast(), 1)
+            .is_ok_and(|entries| entries[0] & soft_dirty::PM_SOFT_DIRTY != 0);
+
+        libc::munmap(ptr, len);
+
+        supported
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::capabilities;
+
+    #[test]
+    fn probes_are_cached() {
+        let first = capabilities();
+
+        assert!(std::ptr::eq(first, capabilities()));
+
+        // every kernel this crate supports in practice is newer than 4.17
+        assert!(first.map_fixed_noreplace);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{io, marker::PhantomData, ops::RangeBounds, ptr};
+
+use crate::{check_range, Atomic, MmapMut, Pod};
+
+/// A view of a mapping which other processes may modify concurrently
+///
+/// Dereferencing a shared mapping as `&[u8]` promises the compiler that the
+/// bytes do not change while the slice is alive, which another process
+/// writing to them breaks. This view never hands out references to the plain
+/// bytes. Instead every access is a volatile read or write of the current
+/// contents, or goes through an atomic.
+///
+/// # Memory model
+///
+/// - Volatile accesses are never merged, elided or reordered with each other
+///   by the compiler, but give no ordering guarantees with respect to other
+///   threads or processes, and a value written concurrently may be read torn.
+/// - To hand data to another process, write it with [`write`](Self::write) or
+///   [`copy_from`](Self::copy_from), then store to an [`atomic`](Self::atomic)
+///   with [`Release`](std::sync::atomic::Ordering::Release) ordering. A
+///   reader which loads that store with
+///   [`Acquire`](std::sync::atomic::Ordering::Acquire) ordering then sees the
+///   complete data. This is the same guarantee as between threads, as
+///   processes sharing memory are just threads sharing part of an address
+///   space as far as the hardware is concerned.
+/// - Without such synchronization a reader must treat whatever it reads as
+///   untrusted input which may change between two reads.
+/// - Within this process, volatile accesses from two threads to the same
+///   bytes, at least one of them a write, are a data race like any other. The
+///   view borrows the mapping mutably and is not `Sync`, so only one thread
+///   at a time accesses the bytes through it, and nothing else in the
+///   process can reference them meanwhile.
+pub struct MmapCell<'m> {
+    pub(crate) ptr: *mut u8,
+    pub(crate) len: usize,
+    _map: PhantomData<&'m mut [u8]>,
+}
+
+unsafe impl<'m> Send for MmapCell<'m> {}
+
+impl<'a> MmapMut<'a> {
+    /// View the mapping through an [`MmapCell`], for memory which other
+    /// processes write to concurrently
+    ///
+    /// The view borrows the mapping mutably, so no slices of it obtained
+    /// through `Deref` can be alive while the view writes to the bytes.
+    pub fn as_cell(&mut self) -> MmapCell<'_> {
+        MmapCell {
+            ptr: self.ptr,
+            len: self.len,
+            _map: PhantomData,
+        }
+    }
+}
+
+impl<'m> MmapCell<'m> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Volatile read of the value at `offset`, which must be aligned for `T`
+    pub fn read<T: Pod>(&self, offset: usize) -> io::Result<T> {
+        Ok(unsafe { ptr::read_volatile(self.typed_ptr::<T>(offset, 1)?) })
+    }
+
+    /// Volatile write of `value` at `offset`, which must be aligned for `T`
+    pub fn write<T: Pod>(&self, offset: usize, value: T) -> io::Result<()> {
+        unsafe { ptr::write_volatile(self.typed_ptr::<T>(offset, 1)?, value) };
+
+        Ok(())
+    }
+
+    /// Copy the bytes starting at `offset` into `dst` with volatile reads
+    pub fn copy_to(&self, offset: usize, dst: &mut [u8]) -> io::Result<()> {
+        let range = check_range(self.len, offset..offset.saturating_add(dst.len()))?;
+
+        for (i, byte) in range.zip(dst) {
+            *byte = unsafe { ptr::read_volatile(self.ptr.add(i)) };
+        }
+
+        Ok(())
+    }
+
+    /// Copy `src` into the bytes starting at `offset` with volatile writes
+    pub fn copy_from(&self, offset: usize, src: &[u8]) -> io::Result<()> {
+        let range = check_range(self.len, offset..offset.saturating_add(src.len()))?;
+
+        for (i, &byte) in range.zip(src) {
+            unsafe { ptr::write_volatile(self.ptr.add(i), byte) };
+        }
+
+        Ok(())
+    }
+
+    /// Read the bytes of `range` into a new vector
+    pub fn to_vec(&self, range: impl RangeBounds<usize>) -> io::Result<Vec<u8>> {
+        let range = check_range(self.len, range)?;
+        let mut buf = vec![0; range.len()];
+
+        self.copy_to(range.start, &mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// The atomic at `offset`, which must be aligned for `A`, for
+    /// synchronizing with other processes
+    pub fn atomic<A: Atomic>(&self, offset: usize) -> io::Result<&A> {
+        Ok(unsafe { &*self.typed_ptr::<A>(offset, 1)? })
+    }
+
+    /// Pointer to `count` values of `T` at `offset`, which must be aligned for
+    /// `T`
+    pub(crate) fn typed_ptr<T>(&self, offset: usize, count: usize) -> io::Result<*mut T> {
+        let size = std::mem::size_of::<T>().saturating_mul(count);
+        let range = check_range(self.len, offset..offset.saturating_add(size))?;
+
+        let ptr = unsafe { self.ptr.add(range.start) };
+
+        if !(ptr as usize).is_multiple_of(std::mem::align_of::<T>()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is not suitably aligned",
+            ));
+        }
+
+        Ok(ptr.cast())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use crate::MmapMut;
+
+    #[test]
+    fn publish_through_cell() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        let cell = map.as_cell();
+
+        cell.copy_from(8, b"message").unwrap();
+        cell.write::<u32>(16, 7).unwrap();
+        cell.atomic::<AtomicU32>(0)
+            .unwrap()
+            .store(1, Ordering::Release);
+
+        assert_eq!(
+            cell.atomic::<AtomicU32>(0).unwrap().load(Ordering::Acquire),
+            1
+        );
+        assert_eq!(cell.to_vec(8..15).unwrap(), b"message");
+        assert_eq!(cell.read::<u32>(16).unwrap(), 7);
+
+        assert!(cell.read::<u32>(2).is_err());
+        assert!(cell.read::<u64>(4092).is_err());
+        assert!(cell.copy_from(4090, b"too long").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    fs::File,
+    io,
+    ops::{Deref, RangeBounds},
+    os::unix::fs::FileExt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    check_range, mprotect_raw, page_size, register_guard, require_real_mapping, GuardAction,
+    GuardFault, GuardRegistration, Mmap, Protection,
+};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"MMAPCRC\0");
+
+/// Size of the sidecar header preceding the checksums
+const HEADER_LEN: u64 = 16;
+
+/// Bytes covered by each checksum, independent of the system page size so
+/// sidecars can move between machines
+const BLOCK_LEN: usize = 4096;
+
+/// Lookup table for the reflected CRC-32 (IEEE) polynomial
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+};
+
+/// The CRC-32 of `data`, as used by zlib and Ethernet
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32_extend(0, data)
+}
+
+/// The CRC-32 of the data whose CRC-32 is `crc` followed by `data`
+pub(crate) fn crc32_extend(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+struct ChecksumState {
+    ptr: *const u8,
+    len: usize,
+    page_size: usize,
+    checksums: Box<[u32]>,
+    verified: Box<[AtomicBool]>,
+    /// Set by the fault handler when a page fails verification
+    corrupt: AtomicBool,
+}
+
+impl ChecksumState {
+    /// Verify every block overlapping `start..end`, skipping ones already
+    /// verified. Async-signal-safe, as it runs in the fault handler.
+    fn verify(&self, start: usize, end: usize) -> Result<(), usize> {
+        let end = end.min(self.len);
+
+        for block in start / BLOCK_LEN..end.div_ceil(BLOCK_LEN) {
+            if self.verified[block].load(Ordering::Acquire) {
+                continue;
+            }
+
+            let offset = block * BLOCK_LEN;
+            let data = unsafe {
+                std::slice::from_raw_parts(self.ptr.add(offset), BLOCK_LEN.min(self.len - offset))
+            };
+
+            if crc32(data) != self.checksums[block] {
+                return Err(offset);
+            }
+
+            self.verified[block].store(true, Ordering::Release);
+        }
+
+        Ok(())
+    }
+}
+
+/// A read-only file mapping whose contents are checked against a CRC-32 per
+/// 4KiB block, stored in a separate sidecar file
+///
+/// Storage engines can use this to detect silent corruption instead of
+/// consuming garbage. Blocks are checked by [`verify`](Self::verify), or,
+/// when opened with `verify_on_access`, automatically: every page starts out
+/// inaccessible, and the first access to it verifies it before letting the
+/// access through. A page which fails verification on access is never made
+/// accessible, so the access crashes the process with `SIGSEGV` rather than
+/// returning corrupt data; [`corruption_detected`](Self::corruption_detected)
+/// reports this to a handler which catches the signal.
+///
+/// Create the sidecar with [`write_sidecar`](Self::write_sidecar).
+pub struct ChecksummedMmap {
+    map: Mmap<'static>,
+    // declared before `state` so the handler is unregistered before the
+    // state it points to is freed
+    registration: Option<GuardRegistration>,
+    state: Box<ChecksumState>,
+}
+
+impl ChecksummedMmap {
+    /// Compute the checksums of `data` and write them to `sidecar`, replacing
+    /// its contents
+    pub fn write_sidecar(data: &[u8], sidecar: &File) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(HEADER_LEN as usize + data.len() / BLOCK_LEN * 4 + 4);
+
+        buf.extend_from_slice(&MAGIC.to_le_bytes());
+        buf.extend_from_slice(&(BLOCK_LEN as u32).to_le_bytes());
+        buf.extend_from_slice(&(data.len().div_ceil(BLOCK_LEN) as u32).to_le_bytes());
+
+        for block in data.chunks(BLOCK_LEN) {
+            buf.extend_from_slice(&crc32(block).to_le_bytes());
+        }
+
+        sidecar.set_len(0)?;
+        sidecar.write_all_at(&buf, 0)?;
+        sidecar.sync_data()
+    }
+
+    /// Map `file` read-only, with the checksums in `sidecar`
+    pub fn open(file: &File, sidecar: &File, verify_on_access: bool) -> io::Result<Self> {
+        let map = Mmap::new_file(file)?;
+
+        let mut header = [0; HEADER_LEN as usize];
+        sidecar.read_exact_at(&mut header, 0)?;
+
+        let block_len = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let count = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+
+        if u64::from_le_bytes(header[..8].try_into().unwrap()) != MAGIC
+            || block_len as usize != BLOCK_LEN
+            || count != map.len.div_ceil(BLOCK_LEN)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sidecar does not match the file",
+            ));
+        }
+
+        let mut raw = vec![0; count * 4];
+        sidecar.read_exact_at(&mut raw, HEADER_LEN)?;
+
+        let state = Box::new(ChecksumState {
+            ptr: map.ptr,
+            len: map.len,
+            page_size: page_size(),
+            checksums: raw
+                .chunks(4)
+                .map(|crc| u32::from_le_bytes(crc.try_into().unwrap()))
+                .collect(),
+            verified: (0..count).map(|_| AtomicBool::new(false)).collect(),
+            corrupt: AtomicBool::new(false),
+        });
+
+        let registration = if verify_on_access {
+            require_real_mapping()?;
+
+            let registration = register_guard(
+                map.ptr as *mut u8,
+                map.len,
+                verify_page,
+                &*state as *const ChecksumState as usize,
+            )?;
+
+            unsafe { mprotect_raw(map.ptr as *mut u8, map.len, Protection::NONE)? };
+
+            Some(registration)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            map,
+            registration,
+            state,
+        })
+    }
+
+    /// Check every block overlapping `range` which has not been verified yet,
+    /// failing with [`InvalidData`](io::ErrorKind::InvalidData) at the first
+    /// corrupt one
+    pub fn verify(&self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let range = check_range(self.map.len, range)?;
+
+        if self.registration.is_some() {
+            let page = self.state.page_size;
+            let mut start = range.start - range.start % page;
+
+            while start < range.end {
+                let end = (start + page).min(self.map.len);
+
+                unsafe { open_page(&self.state, start, end - start) }.map_err(corrupt_block)?;
+
+                start = end;
+            }
+
+            return Ok(());
+        }
+
+        self.state
+            .verify(range.start, range.end)
+            .map_err(corrupt_block)
+    }
+
+    /// Whether an access has hit a page which failed verification
+    pub fn corruption_detected(&self) -> bool {
+        self.state.corrupt.load(Ordering::Acquire)
+    }
+}
+
+fn corrupt_block(offset: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("checksum mismatch in block at offset {offset:#x}"),
+    )
+}
+
+fn verify_page(fault: &GuardFault) -> GuardAction {
+    let state = unsafe { &*(fault.data as *const ChecksumState) };
+
+    let offset = fault.addr as usize - fault.region_start as usize;
+    let start = offset - offset % state.page_size;
+    let len = state.page_size.min(state.len - start);
+
+    match unsafe { open_page(state, start, len) } {
+        Ok(()) => GuardAction::Retry,
+        Err(..) => {
+            state.corrupt.store(true, Ordering::Release);
+            GuardAction::Abort
+        }
+    }
+}
+
+/// Make the page at `start` readable if its blocks pass verification,
+/// leaving it inaccessible otherwise
+///
+/// Checking the page needs it to be readable, so another thread can read it
+/// in the short window before a corrupt page is protected again.
+unsafe fn open_page(state: &ChecksumState, start: usize, len: usize) -> Result<(), usize> {
+    let page = state.ptr.add(start) as *mut u8;
+
+    if mprotect_raw(page, len, Protection::READ).is_err() {
+        return Err(start);
+    }
+
+    state.verify(start, start + len).inspect_err(|_| {
+        let _ = mprotect_raw(page, len, Protection::NONE);
+    })
+}
+
+impl Deref for ChecksummedMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl Drop for ChecksummedMmap {
+    fn drop(&mut self) {
+        if self.registration.is_some() {
+            unsafe {
+                let _ = mprotect_raw(self.map.ptr as *mut u8, self.map.len, Protection::READ);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::FileExt;
+
+    use crate::{
+        checksummed::crc32,
+        test_util::{open_file, temp_path},
+        ChecksummedMmap,
+    };
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn detects_corruption() {
+        let data_path = temp_path("checksummed");
+        let sidecar_path = temp_path("checksummed-crc");
+
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+
+        let file = open_file(&data_path);
+        let sidecar = open_file(&sidecar_path);
+
+        file.write_all_at(&data, 0).unwrap();
+        ChecksummedMmap::write_sidecar(&data, &sidecar).unwrap();
+
+        let map = ChecksummedMmap::open(&file, &sidecar, true).unwrap();
+
+        // verified by the fault on first access
+        assert_eq!(map[5_000], data[5_000]);
+        assert!(!map.corruption_detected());
+
+        map.verify(..).unwrap();
+        drop(map);
+
+        file.write_all_at(&[0xff], 9_000).unwrap();
+
+        let map = ChecksummedMmap::open(&file, &sidecar, false).unwrap();
+
+        map.verify(..8_192).unwrap();
+        assert!(map.verify(8_192..).is_err());
+
+        let map = ChecksummedMmap::open(&file, &sidecar, true).unwrap();
+
+        assert!(map.verify(8_000..).is_err());
+        assert_eq!(map[100], data[100]);
+
+        std::fs::remove_file(&data_path).unwrap();
+        std::fs::remove_file(&sidecar_path).unwrap();
+    }
+}
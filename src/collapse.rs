@@ -0,0 +1,132 @@
+use std::{io, ops::RangeBounds};
+
+use crate::{
+    capabilities, libc_compat, madvise_raw, maps, page_span, require_real_mapping, Mmap, MmapMut,
+};
+
+/// Synchronously collapse the pages in `[ptr, ptr + len)` into transparent huge
+/// pages
+fn collapse(ptr: *mut u8, len: usize) -> io::Result<()> {
+    if !capabilities().madv_collapse {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "MADV_COLLAPSE is not supported by this kernel (requires Linux 6.1)",
+        ));
+    }
+
+    match unsafe { madvise_raw(ptr, len, libc_compat::MADV_COLLAPSE) } {
+        Ok(()) => Ok(()),
+        Err(e) => Err(match e.raw_os_error() {
+            Some(libc::EINVAL) => io::Error::new(
+                io::ErrorKind::Unsupported,
+                "MADV_COLLAPSE is not supported for this mapping",
+            ),
+            Some(libc::EAGAIN) => io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "huge page collapse failed due to a transient condition; retry later",
+            ),
+            Some(libc::ENOMEM) => io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "no huge pages could be allocated for the collapse",
+            ),
+            _ => e,
+        }),
+    }
+}
+
+macro_rules! collapse_impl {
+    ($name:ident) => {
+        impl<'a> $name<'a> {
+            /// Ask the kernel to synchronously collapse the pages backing
+            /// `range` into transparent huge pages with `MADV_COLLAPSE`
+            ///
+            /// Only the huge-page-aligned parts of the range can be collapsed,
+            /// so for the best coverage the mapping should itself be aligned to
+            /// the huge page size. Unlike `MADV_HUGEPAGE`, this does not rely
+            /// on khugepaged eventually scanning the region, and reports
+            /// failure.
+            ///
+            /// (since Linux 6.1)
+            pub fn collapse_to_huge_pages(&self, range: impl RangeBounds<usize>) -> io::Result<()> {
+                let (ptr, len) = page_span(self.ptr, self.len, range)?;
+
+                collapse(ptr, len)
+            }
+
+            /// Bytes of the mapping currently backed by transparent huge
+            /// pages, as reported by `AnonHugePages` in `/proc/self/smaps`
+            ///
+            /// The count covers whole kernel mappings, so may include memory
+            /// of neighbouring mappings the kernel merged with this one.
+            pub fn anon_huge_pages(&self) -> io::Result<usize> {
+                require_real_mapping()?;
+
+                let start = self.ptr as usize;
+
+                maps::anon_huge_pages_self(start, start + self.len)
+            }
+        }
+    };
+}
+
+collapse_impl!(Mmap);
+collapse_impl!(MmapMut);
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io, num::NonZeroUsize};
+
+    use crate::MmapOptions;
+
+    /// Whether transparent huge pages are enabled and the kernel is new enough
+    /// to have `MADV_COLLAPSE`, in which case collapsing must work
+    fn collapse_expected() -> bool {
+        let enabled = fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")
+            .is_ok_and(|enabled| !enabled.contains("[never]"));
+
+        let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+        let mut version = release
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|part| part.parse::<u32>().unwrap_or(0));
+
+        enabled && (version.next(), version.next()) >= (Some(6), Some(1))
+    }
+
+    #[test]
+    fn collapse_reports_result() {
+        let len = 4 << 20;
+        // shared anonymous memory is only collapsed when shmem huge pages are
+        // enabled, which is rarely the case
+        let mut map = MmapOptions::new(NonZeroUsize::new(len).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        map.fill(1);
+
+        if collapse_expected() {
+            // a collapse can fail transiently, for example while pages are
+            // being migrated
+            let res = (0..10)
+                .map(|_| map.collapse_to_huge_pages(..))
+                .find(|res| !matches!(res, Err(e) if e.kind() == io::ErrorKind::WouldBlock))
+                .unwrap();
+
+            res.unwrap();
+        } else {
+            // without huge pages any failure must be one of the documented ones
+            match map.collapse_to_huge_pages(..) {
+                Ok(()) => {}
+                Err(e) => assert!(matches!(
+                    e.kind(),
+                    io::ErrorKind::Unsupported
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::OutOfMemory
+                )),
+            }
+        }
+
+        assert!(map.collapse_to_huge_pages(..len + 1).is_err());
+        assert!(map.iter().all(|&b| b == 1));
+    }
+}
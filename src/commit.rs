@@ -0,0 +1,68 @@
+use std::{io, ops::RangeBounds};
+
+use crate::{madvise_raw, mprotect_raw, page_aligned_span, MmapMut, Protection};
+
+impl<'a> MmapMut<'a> {
+    /// Release the memory backing `range` and make it inaccessible, without
+    /// changing the address or length of the mapping
+    ///
+    /// This is the equivalent of `VirtualFree(MEM_DECOMMIT)`: the range is
+    /// made `PROT_NONE` and its pages are discarded with `MADV_DONTNEED`. For
+    /// private mappings this returns the memory to the kernel; for shared
+    /// mappings the pages are only dropped from this process and the backing
+    /// shared memory is kept.
+    ///
+    /// The range must be page aligned.
+    ///
+    /// # Safety
+    ///
+    /// The range must not be accessed until it is [recommitted](Self::recommit),
+    /// which includes dereferencing the mapping as a slice spanning it.
+    pub unsafe fn decommit(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let (ptr, len) = page_aligned_span(self.ptr, self.len, range)?;
+
+        mprotect_raw(ptr, len, Protection::NONE)?;
+        madvise_raw(ptr, len, libc::MADV_DONTNEED)
+    }
+
+    /// Make a range released with [`decommit`](Self::decommit) accessible
+    /// again
+    ///
+    /// For private mappings the range reads as zeros afterwards.
+    pub fn recommit(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let (ptr, len) = page_aligned_span(self.ptr, self.len, range)?;
+
+        unsafe { mprotect_raw(ptr, len, Protection::READ | Protection::WRITE) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, MmapOptions};
+
+    #[test]
+    fn decommit_then_recommit() {
+        let page = page_size();
+
+        let mut map = MmapOptions::new(NonZeroUsize::new(4 * page).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        map.fill(1);
+
+        unsafe {
+            map.decommit(page..page * 3).unwrap();
+            assert!(map.decommit(1..page).is_err());
+        }
+
+        assert_eq!(map[page - 1], 1);
+        assert_eq!(map[page * 3], 1);
+
+        map.recommit(page..page * 3).unwrap();
+
+        assert!(map[page..page * 3].iter().all(|&b| b == 0));
+    }
+}
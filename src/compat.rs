@@ -0,0 +1,487 @@
+//! Drop-in replacements for the types of the `memmap2` crate
+//!
+//! [`Mmap`], [`MmapMut`] and [`MmapOptions`] have the same method names,
+//! signatures and semantics as their `memmap2` counterparts, so most projects
+//! can migrate by replacing `use memmap2::...` with `use mmap::compat::...`.
+//! Unlike [`crate::Mmap`], these types carry no lifetime parameter.
+
+use std::{
+    fmt,
+    fs::File,
+    io,
+    ops::{Deref, DerefMut},
+    os::unix::io::{AsRawFd, RawFd},
+    ptr, slice,
+};
+
+use crate::{
+    flag::{Flag, UniqueFlag},
+    mmap_raw, mprotect_raw, msync_raw, munmap_raw, page_size, Protection,
+};
+
+/// Anything a file descriptor to map can be taken from, as in `memmap2`
+pub trait MmapAsRawDesc {
+    fn as_raw_desc(&self) -> RawFd;
+}
+
+impl MmapAsRawDesc for RawFd {
+    fn as_raw_desc(&self) -> RawFd {
+        *self
+    }
+}
+
+impl<T: AsRawFd> MmapAsRawDesc for &T {
+    fn as_raw_desc(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+}
+
+/// Builder for mappings, with the same defaults as `memmap2`: the whole file
+/// from offset zero
+#[derive(Clone, Debug, Default)]
+pub struct MmapOptions {
+    offset: u64,
+    len: Option<usize>,
+    populate: bool,
+    stack: bool,
+}
+
+impl MmapOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offset into the file at which the mapping starts, which need not be
+    /// page aligned
+    pub fn offset(&mut self, offset: u64) -> &mut Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Length of the mapping, by default the rest of the file after the offset
+    pub fn len(&mut self, len: usize) -> &mut Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Prefault page tables for the mapping
+    pub fn populate(&mut self) -> &mut Self {
+        self.populate = true;
+        self
+    }
+
+    /// Mark anonymous mappings as suitable for a process or thread stack
+    pub fn stack(&mut self) -> &mut Self {
+        self.stack = true;
+        self
+    }
+
+    /// Map the file read-only
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the mapping exists,
+    /// by this or any other process.
+    pub unsafe fn map<T: MmapAsRawDesc>(&self, file: T) -> io::Result<Mmap> {
+        self.map_fd(file.as_raw_desc(), Protection::READ, UniqueFlag::MAP_SHARED)
+            .map(|inner| Mmap { inner })
+    }
+
+    /// Map the file read-only with execute permission
+    ///
+    /// # Safety
+    ///
+    /// As for [`map`](Self::map).
+    pub unsafe fn map_exec<T: MmapAsRawDesc>(&self, file: T) -> io::Result<Mmap> {
+        self.map_fd(
+            file.as_raw_desc(),
+            Protection::READ | Protection::EXEC,
+            UniqueFlag::MAP_SHARED,
+        )
+        .map(|inner| Mmap { inner })
+    }
+
+    /// Map the file writable, with writes carried through to it
+    ///
+    /// # Safety
+    ///
+    /// As for [`map`](Self::map).
+    pub unsafe fn map_mut<T: MmapAsRawDesc>(&self, file: T) -> io::Result<MmapMut> {
+        self.map_fd(
+            file.as_raw_desc(),
+            Protection::READ | Protection::WRITE,
+            UniqueFlag::MAP_SHARED,
+        )
+        .map(|inner| MmapMut { inner })
+    }
+
+    /// Map the file copy-on-write, so writes are private to the mapping
+    ///
+    /// # Safety
+    ///
+    /// As for [`map`](Self::map).
+    pub unsafe fn map_copy<T: MmapAsRawDesc>(&self, file: T) -> io::Result<MmapMut> {
+        self.map_fd(
+            file.as_raw_desc(),
+            Protection::READ | Protection::WRITE,
+            UniqueFlag::MAP_PRIVATE,
+        )
+        .map(|inner| MmapMut { inner })
+    }
+
+    /// Map the file copy-on-write but read-only, so later changes to the file
+    /// may or may not be visible
+    ///
+    /// # Safety
+    ///
+    /// As for [`map`](Self::map).
+    pub unsafe fn map_copy_read_only<T: MmapAsRawDesc>(&self, file: T) -> io::Result<Mmap> {
+        self.map_fd(
+            file.as_raw_desc(),
+            Protection::READ,
+            UniqueFlag::MAP_PRIVATE,
+        )
+        .map(|inner| Mmap { inner })
+    }
+
+    /// Create a zeroed anonymous mapping of the configured length, which is
+    /// shared with child processes
+    pub fn map_anon(&self) -> io::Result<MmapMut> {
+        let len = self.len.unwrap_or(0);
+
+        let mut flags = UniqueFlag::MAP_SHARED | Flag::MAP_ANONYMOUS;
+
+        if self.populate {
+            flags = flags | Flag::MAP_POPULATE;
+        }
+
+        if self.stack {
+            flags = flags | Flag::MAP_STACK;
+        }
+
+        MmapInner::new(len, Protection::READ | Protection::WRITE, flags.0, -1, 0)
+            .map(|inner| MmapMut { inner })
+    }
+
+    fn map_fd(&self, fd: RawFd, prot: Protection, sharing: UniqueFlag) -> io::Result<MmapInner> {
+        let len = match self.len {
+            Some(len) => len,
+            None => {
+                let file_len = unsafe { borrow_file(fd) }.metadata()?.len();
+
+                let len = file_len.checked_sub(self.offset).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "memory map offset is larger than length",
+                    )
+                })?;
+
+                usize::try_from(len).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "memory map length overflows usize",
+                    )
+                })?
+            }
+        };
+
+        let flags = if self.populate {
+            sharing | Flag::MAP_POPULATE
+        } else {
+            sharing | Flag(0)
+        };
+
+        MmapInner::new(len, prot, flags.0, fd, self.offset)
+    }
+}
+
+/// Borrow `fd` as a [`File`] without taking ownership of it
+unsafe fn borrow_file(fd: RawFd) -> std::mem::ManuallyDrop<File> {
+    use std::os::unix::io::FromRawFd;
+
+    std::mem::ManuallyDrop::new(File::from_raw_fd(fd))
+}
+
+/// A mapping whose start may lie part way into its first page, as the file
+/// offset need not be page aligned
+struct MmapInner {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for MmapInner {}
+unsafe impl Sync for MmapInner {}
+
+impl MmapInner {
+    fn new(len: usize, prot: Protection, flags: i32, fd: RawFd, offset: u64) -> io::Result<Self> {
+        let alignment = (offset % page_size() as u64) as usize;
+        let aligned_offset = offset - alignment as u64;
+
+        let aligned_offset = i64::try_from(aligned_offset).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "memory map offset is too large",
+            )
+        })?;
+
+        // as in memmap2, empty mappings still map a byte so the pointer is
+        // valid and unique
+        let map_len = (alignment + len).max(1);
+
+        let ptr = unsafe { mmap_raw(ptr::null_mut(), map_len, prot, flags, fd, aligned_offset)? };
+
+        Ok(Self {
+            ptr: unsafe { ptr.add(alignment) },
+            len,
+        })
+    }
+
+    /// Start of the page containing the first byte, and the length of the
+    /// whole mapping from there
+    fn page_span(&self) -> (*mut u8, usize) {
+        let alignment = self.ptr as usize % page_size();
+
+        (
+            unsafe { self.ptr.sub(alignment) },
+            (alignment + self.len).max(1),
+        )
+    }
+
+    fn flush(&self, offset: usize, len: usize, flags: i32) -> io::Result<()> {
+        if offset.checked_add(len).is_none_or(|end| end > self.len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range out of bounds of mapping",
+            ));
+        }
+
+        unsafe { msync_raw(self.ptr.add(offset), len, flags) }
+    }
+
+    fn mprotect(&self, prot: Protection) -> io::Result<()> {
+        let (ptr, len) = self.page_span();
+
+        unsafe { mprotect_raw(ptr, len, prot) }
+    }
+}
+
+impl Drop for MmapInner {
+    fn drop(&mut self) {
+        let (ptr, len) = self.page_span();
+
+        unsafe {
+            munmap_raw(ptr, len);
+        }
+    }
+}
+
+/// A read-only memory mapped buffer, like `memmap2::Mmap`
+pub struct Mmap {
+    inner: MmapInner,
+}
+
+impl Mmap {
+    /// Map the whole of `file` read-only, like
+    /// `MmapOptions::new().map(file)`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmapOptions::map`].
+    pub unsafe fn map<T: MmapAsRawDesc>(file: T) -> io::Result<Self> {
+        MmapOptions::new().map(file)
+    }
+
+    /// Make the mapping writable, which for file mappings means writes are
+    /// carried through to the file
+    pub fn make_mut(self) -> io::Result<MmapMut> {
+        self.inner.mprotect(Protection::READ | Protection::WRITE)?;
+
+        Ok(MmapMut { inner: self.inner })
+    }
+
+    /// Make the mapping executable
+    pub fn make_exec(self) -> io::Result<Mmap> {
+        self.inner.mprotect(Protection::READ | Protection::EXEC)?;
+
+        Ok(self)
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.inner.ptr, self.inner.len) }
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mmap")
+            .field("ptr", &self.inner.ptr)
+            .field("len", &self.inner.len)
+            .finish()
+    }
+}
+
+/// A writable memory mapped buffer, like `memmap2::MmapMut`
+pub struct MmapMut {
+    inner: MmapInner,
+}
+
+impl MmapMut {
+    /// Map the whole of `file` writable, like
+    /// `MmapOptions::new().map_mut(file)`
+    ///
+    /// # Safety
+    ///
+    /// See [`MmapOptions::map`].
+    pub unsafe fn map_mut<T: MmapAsRawDesc>(file: T) -> io::Result<Self> {
+        MmapOptions::new().map_mut(file)
+    }
+
+    /// Create a zeroed anonymous mapping of `len` bytes
+    pub fn map_anon(len: usize) -> io::Result<Self> {
+        MmapOptions::new().len(len).map_anon()
+    }
+
+    /// Write modified pages back to the file, waiting for the writes to
+    /// complete
+    pub fn flush(&self) -> io::Result<()> {
+        self.inner.flush(0, self.inner.len, libc::MS_SYNC)
+    }
+
+    /// Start writing modified pages back to the file without waiting
+    pub fn flush_async(&self) -> io::Result<()> {
+        self.inner.flush(0, self.inner.len, libc::MS_ASYNC)
+    }
+
+    /// [`flush`](Self::flush) only the `len` bytes at `offset`
+    pub fn flush_range(&self, offset: usize, len: usize) -> io::Result<()> {
+        self.inner.flush(offset, len, libc::MS_SYNC)
+    }
+
+    /// [`flush_async`](Self::flush_async) only the `len` bytes at `offset`
+    pub fn flush_async_range(&self, offset: usize, len: usize) -> io::Result<()> {
+        self.inner.flush(offset, len, libc::MS_ASYNC)
+    }
+
+    /// Make the mapping read-only, leaving its contents and address unchanged
+    pub fn make_read_only(self) -> io::Result<Mmap> {
+        self.inner.mprotect(Protection::READ)?;
+
+        Ok(Mmap { inner: self.inner })
+    }
+
+    /// Make the mapping read-only and executable
+    pub fn make_exec(self) -> io::Result<Mmap> {
+        self.inner.mprotect(Protection::READ | Protection::EXEC)?;
+
+        Ok(Mmap { inner: self.inner })
+    }
+}
+
+impl Deref for MmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.inner.ptr, self.inner.len) }
+    }
+}
+
+impl DerefMut for MmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.inner.ptr, self.inner.len) }
+    }
+}
+
+impl AsRef<[u8]> for MmapMut {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for MmapMut {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl fmt::Debug for MmapMut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapMut")
+            .field("ptr", &self.inner.ptr)
+            .field("len", &self.inner.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, os::unix::fs::FileExt};
+
+    use crate::{
+        compat::{Mmap, MmapMut, MmapOptions},
+        page_size,
+        test_util::temp_file,
+    };
+
+    #[test]
+    fn memmap2_workflow() {
+        let mut file = temp_file("compat");
+
+        file.set_len(page_size() as u64 * 2).unwrap();
+
+        let mut map = unsafe { MmapMut::map_mut(&file).unwrap() };
+
+        assert_eq!(map.len(), page_size() * 2);
+
+        map[..5].copy_from_slice(b"hello");
+        map[page_size() + 3..page_size() + 8].copy_from_slice(b"world");
+        map.flush().unwrap();
+        map.flush_async_range(1, 2).unwrap();
+        assert!(map.flush_range(1, page_size() * 2).is_err());
+
+        let mut contents = [0; 5];
+        file.read_exact(&mut contents).unwrap();
+
+        assert_eq!(&contents, b"hello");
+
+        let map = map.make_read_only().unwrap();
+
+        assert_eq!(&map[..5], b"hello");
+
+        // offsets need not be page aligned
+        let window = unsafe {
+            MmapOptions::new()
+                .offset(page_size() as u64 + 3)
+                .len(5)
+                .map(&file)
+                .unwrap()
+        };
+
+        assert_eq!(&*window, b"world");
+
+        let tail = unsafe { MmapOptions::new().offset(page_size() as u64 + 3).map(&file) };
+
+        assert_eq!(tail.unwrap().len(), page_size() - 3);
+
+        let mut copy = unsafe { MmapOptions::new().map_copy(&file).unwrap() };
+        copy[0] = b'j';
+
+        assert_eq!(&map[..5], b"hello");
+        file.write_at(b"y", 0).unwrap();
+        assert_eq!(unsafe { Mmap::map(&file) }.unwrap()[0], b'y');
+
+        let anon = MmapMut::map_anon(100).unwrap();
+
+        assert!(anon.iter().all(|&b| b == 0));
+        assert_eq!(MmapMut::map_anon(0).unwrap().len(), 0);
+    }
+}
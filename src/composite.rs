@@ -0,0 +1,254 @@
+use std::{fs::File, io, ops::Deref, os::unix::io::AsRawFd, ptr, slice};
+
+use crate::{
+    file_len_from,
+    flag::{Flag, UniqueFlag},
+    mmap_raw, munmap_raw, page_size, round_up_to_page, Protection,
+};
+
+/// Several files, or several extents of one file, mapped back to back into
+/// one contiguous, read-only range of addresses
+///
+/// A single inaccessible region is reserved up front and each part is mapped
+/// over its share of it with `MAP_FIXED`, so a logically concatenated dataset,
+/// such as the segments of a log or the chunks of a column scattered through a
+/// file, can be read as one slice without copying.
+///
+/// Mappings are made of whole pages, so every part except the last must have
+/// a length which is a multiple of the page size for the view to be
+/// contiguous.
+pub struct CompositeMmap {
+    ptr: *mut u8,
+    len: usize,
+    reserved: usize,
+    starts: Vec<usize>,
+}
+
+impl CompositeMmap {
+    pub fn new(files: &[File]) -> io::Result<Self> {
+        let lens = files
+            .iter()
+            .map(|file| file_len_from(file, 0))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        if let Some(idx) = lens[..lens.len().saturating_sub(1)]
+            .iter()
+            .position(|len| !len.is_multiple_of(page_size()))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "file {} is not a multiple of the page size, so cannot be followed by another",
+                    idx
+                ),
+            ));
+        }
+
+        let len = lens
+            .iter()
+            .try_fold(0_usize, |total, &len| total.checked_add(len))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "files overflow"))?;
+        let reserved = reserve(len)?;
+
+        let mut composite = Self {
+            ptr: reserved.0,
+            len,
+            reserved: reserved.1,
+            starts: Vec::with_capacity(files.len()),
+        };
+
+        let mut start = 0;
+
+        for (file, &file_len) in files.iter().zip(&lens) {
+            composite.starts.push(start);
+
+            if file_len != 0 {
+                composite.map_at(start, file, 0, file_len)?;
+            }
+
+            start += file_len;
+        }
+
+        Ok(composite)
+    }
+
+    /// Map the `(file_offset, len)` extents of `file` back to back, in order
+    ///
+    /// Every offset must be a multiple of the page size, as must every length
+    /// except the last, and every extent must lie within the file, as
+    /// accessing pages past its end would fault.
+    pub fn from_extents(file: &File, extents: &[(u64, usize)]) -> io::Result<Self> {
+        let page = page_size();
+        let file_len = file.metadata()?.len();
+
+        for (idx, &(offset, len)) in extents.iter().enumerate() {
+            let last = idx + 1 == extents.len();
+
+            if !offset.is_multiple_of(page as u64) || (!last && !len.is_multiple_of(page)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("extent {} is not page aligned", idx),
+                ));
+            }
+
+            if offset
+                .checked_add(len as u64)
+                .is_none_or(|end| end > file_len)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("extent {} extends past the end of the file", idx),
+                ));
+            }
+        }
+
+        let len = extents
+            .iter()
+            .try_fold(0_usize, |total, &(_, len)| total.checked_add(len))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "extents overflow"))?;
+
+        let reserved = reserve(len)?;
+
+        let mut composite = Self {
+            ptr: reserved.0,
+            len,
+            reserved: reserved.1,
+            starts: Vec::with_capacity(extents.len()),
+        };
+
+        let mut start = 0;
+
+        for &(offset, extent_len) in extents {
+            composite.starts.push(start);
+
+            if extent_len != 0 {
+                composite.map_at(start, file, offset, extent_len)?;
+            }
+
+            start += extent_len;
+        }
+
+        Ok(composite)
+    }
+
+    /// Map `len` bytes of `file` at `offset` over the reservation, `start`
+    /// bytes in
+    fn map_at(&mut self, start: usize, file: &File, offset: u64, len: usize) -> io::Result<()> {
+        let offset = i64::try_from(offset)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "offset is too large"))?;
+
+        unsafe {
+            mmap_raw(
+                self.ptr.add(start),
+                len,
+                Protection::READ,
+                (UniqueFlag::MAP_SHARED | Flag::MAP_FIXED).0,
+                file.as_raw_fd(),
+                offset,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Offset into the view at which each file or extent starts
+    pub fn starts(&self) -> &[usize] {
+        &self.starts
+    }
+}
+
+/// Reserve an inaccessible region of at least `len` bytes, returning its start
+/// and actual length
+fn reserve(len: usize) -> io::Result<(*mut u8, usize)> {
+    let reserved = round_up_to_page(len).max(page_size());
+
+    let ptr = unsafe {
+        mmap_raw(
+            ptr::null_mut(),
+            reserved,
+            Protection::NONE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_NORESERVE).0,
+            -1,
+            0,
+        )?
+    };
+
+    Ok((ptr, reserved))
+}
+
+impl Deref for CompositeMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for CompositeMmap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.reserved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, os::unix::fs::FileExt};
+
+    use crate::{page_size, test_util::temp_file, CompositeMmap};
+
+    #[test]
+    fn concatenates_files() {
+        let files = (0..3)
+            .map(|i| {
+                let mut file = temp_file(&format!("composite-{i}"));
+
+                let len = if i == 2 { 10 } else { page_size() };
+                file.write_all(&vec![i as u8 + 1; len]).unwrap();
+
+                file
+            })
+            .collect::<Vec<_>>();
+
+        let map = CompositeMmap::new(&files).unwrap();
+
+        assert_eq!(map.len(), page_size() * 2 + 10);
+        assert_eq!(map.starts(), &[0, page_size(), page_size() * 2]);
+        assert_eq!(map[page_size() - 1], 1);
+        assert_eq!(map[page_size()], 2);
+        assert_eq!(map[page_size() * 2 + 9], 3);
+
+        let mut files = files;
+        files.swap(1, 2);
+
+        assert!(CompositeMmap::new(&files).is_err());
+    }
+
+    #[test]
+    fn gathers_extents() {
+        let file = temp_file("extents");
+
+        let page = page_size();
+
+        for i in 0..4 {
+            file.write_all_at(&vec![i as u8; page], (page * i) as u64)
+                .unwrap();
+        }
+
+        let map = CompositeMmap::from_extents(&file, &[(page as u64 * 3, page), (page as u64, 5)])
+            .unwrap();
+
+        assert_eq!(map.len(), page + 5);
+        assert!(map[..page].iter().all(|&b| b == 3));
+        assert_eq!(&map[page..], &[1; 5]);
+
+        assert!(CompositeMmap::from_extents(&file, &[(0, 5), (page as u64, 5)]).is_err());
+        assert!(CompositeMmap::from_extents(&file, &[(1, page)]).is_err());
+        assert!(CompositeMmap::from_extents(&file, &[(page as u64 * 3, page + 1)]).is_err());
+        assert!(CompositeMmap::from_extents(&file, &[(page as u64 * 4, 1)]).is_err());
+        assert!(
+            CompositeMmap::from_extents(&file, &[(u64::MAX - (page as u64 - 1), page)]).is_err()
+        );
+    }
+}
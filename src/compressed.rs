@@ -0,0 +1,263 @@
+use std::{fs::File, io, num::NonZeroUsize, os::unix::fs::FileExt};
+
+use crate::MmapMut;
+
+/// Magic number of the skippable frame holding a seekable zstd seek table
+const SEEK_TABLE_MAGIC: u32 = 0x184d_2a5e;
+
+/// Magic number ending the seek table footer
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+
+/// Number of frames, descriptor byte and magic number
+const FOOTER_LEN: u64 = 9;
+
+/// Decompresses whole frames of a [`CompressedMmap`]
+///
+/// The crate does not ship a zstd decoder; implement this over the zstd
+/// bindings of your choice.
+pub trait FrameDecoder {
+    /// Decompress the frame `src` into `dst`, which is exactly its
+    /// decompressed size according to the seek table
+    fn decompress(&self, src: &[u8], dst: &mut [u8]) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FrameEntry {
+    /// Offsets of the frame in the compressed file and the decompressed data
+    compressed_offset: u64,
+    compressed_len: u32,
+    offset: u64,
+    len: u32,
+}
+
+/// Random access to the decompressed contents of a seekable zstd file
+///
+/// The file is a sequence of independently compressed frames followed by a
+/// seek table recording each frame's compressed and decompressed size, as
+/// written by `zstd --seekable` or the `zstd_seekable` API. Reads decompress
+/// only the frames they touch, into anonymous memory, and the most recently
+/// used `cache_frames` frames are kept so nearby reads do not decompress
+/// them again.
+///
+/// Frame checksums in the seek table are not verified.
+pub struct CompressedMmap<D: FrameDecoder> {
+    file: File,
+    decoder: D,
+    frames: Vec<FrameEntry>,
+    len: u64,
+    /// Decompressed frames, most recently used last
+    cache: Vec<(usize, MmapMut<'static>)>,
+    cache_frames: usize,
+}
+
+impl<D: FrameDecoder> CompressedMmap<D> {
+    /// Read the seek table at the end of `file`
+    pub fn open(file: File, decoder: D, cache_frames: NonZeroUsize) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let file_len = file.metadata()?.len();
+
+        if file_len < FOOTER_LEN + 8 {
+            return Err(invalid("file is too short to be seekable"));
+        }
+
+        let mut footer = [0; FOOTER_LEN as usize];
+        file.read_exact_at(&mut footer, file_len - FOOTER_LEN)?;
+
+        let count = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+        let descriptor = footer[4];
+        let magic = u32::from_le_bytes(footer[5..].try_into().unwrap());
+
+        if magic != SEEKABLE_MAGIC {
+            return Err(invalid("file has no seek table"));
+        }
+
+        let entry_len = if descriptor & 0x80 != 0 { 12 } else { 8 };
+        let table_len = count * entry_len;
+
+        let table_start = (file_len - FOOTER_LEN)
+            .checked_sub(table_len + 8)
+            .ok_or_else(|| invalid("seek table is larger than the file"))?;
+
+        let mut table = vec![0; (table_len + 8) as usize];
+        file.read_exact_at(&mut table, table_start)?;
+
+        let field = |at: usize| u32::from_le_bytes(table[at..at + 4].try_into().unwrap());
+
+        if field(0) != SEEK_TABLE_MAGIC || field(4) as u64 != table_len + FOOTER_LEN {
+            return Err(invalid("seek table header is corrupt"));
+        }
+
+        let mut frames = Vec::with_capacity(count as usize);
+        let mut compressed_offset = 0;
+        let mut offset = 0;
+
+        for idx in 0..count as usize {
+            let at = 8 + idx * entry_len as usize;
+            let entry = FrameEntry {
+                compressed_offset,
+                compressed_len: field(at),
+                offset,
+                len: field(at + 4),
+            };
+
+            compressed_offset += entry.compressed_len as u64;
+            offset += entry.len as u64;
+
+            frames.push(entry);
+        }
+
+        if compressed_offset > table_start {
+            return Err(invalid("frames overlap the seek table"));
+        }
+
+        Ok(Self {
+            file,
+            decoder,
+            frames,
+            len: offset,
+            cache: Vec::new(),
+            cache_frames: cache_frames.get(),
+        })
+    }
+
+    /// Length of the decompressed contents
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Read decompressed bytes starting at `offset` into `buf`, returning how
+    /// many were read, which is less than `buf.len()` only at the end
+    pub fn read_at(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let mut read = 0;
+
+        while read < buf.len() {
+            let pos = offset + read as u64;
+
+            if pos >= self.len {
+                break;
+            }
+
+            // the last frame starting at or before `pos`, skipping empty ones
+            let idx = self.frames.partition_point(|frame| frame.offset <= pos) - 1;
+            let start = (pos - self.frames[idx].offset) as usize;
+            let frame = self.frame(idx)?;
+
+            let n = (frame.len() - start).min(buf.len() - read);
+
+            buf[read..read + n].copy_from_slice(&frame[start..start + n]);
+            read += n;
+        }
+
+        Ok(read)
+    }
+
+    /// The decompressed contents of frame `idx`, from the cache if possible
+    fn frame(&mut self, idx: usize) -> io::Result<&[u8]> {
+        if let Some(pos) = self.cache.iter().position(|&(cached, _)| cached == idx) {
+            let entry = self.cache.remove(pos);
+            self.cache.push(entry);
+        } else {
+            let entry = self.frames[idx];
+
+            let mut src = vec![0; entry.compressed_len as usize];
+            self.file.read_exact_at(&mut src, entry.compressed_offset)?;
+
+            let mut map = match self.cache.len() >= self.cache_frames {
+                // reuse the least recently used frame's memory if it fits
+                true if self.cache[0].1.len >= entry.len as usize => self.cache.remove(0).1,
+                full => {
+                    if full {
+                        self.cache.remove(0);
+                    }
+
+                    MmapMut::new_anon(NonZeroUsize::new(entry.len as usize).unwrap())?
+                }
+            };
+
+            self.decoder
+                .decompress(&src, &mut map[..entry.len as usize])?;
+
+            self.cache.push((idx, map));
+        }
+
+        let len = self.frames[idx].len as usize;
+
+        Ok(&self.cache.last().unwrap().1[..len])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, io::Write, num::NonZeroUsize};
+
+    use crate::{test_util::temp_file, CompressedMmap, FrameDecoder};
+
+    /// Frames made of a repeat count and a byte
+    struct Rle;
+
+    impl FrameDecoder for Rle {
+        fn decompress(&self, src: &[u8], dst: &mut [u8]) -> io::Result<()> {
+            let mut out = 0;
+
+            for pair in src.chunks(2) {
+                dst[out..out + pair[0] as usize].fill(pair[1]);
+                out += pair[0] as usize;
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reads_across_frames() {
+        let frames: [&[u8]; 3] = [&[200, b'a', 100, b'b'], &[250, b'c'], &[10, b'd']];
+
+        let mut contents = Vec::new();
+        let mut table = Vec::new();
+
+        for frame in frames {
+            let len: u32 = frame.chunks(2).map(|pair| pair[0] as u32).sum();
+
+            contents.extend_from_slice(frame);
+            table.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            table.extend_from_slice(&len.to_le_bytes());
+        }
+
+        contents.extend_from_slice(&0x184d_2a5e_u32.to_le_bytes());
+        contents.extend_from_slice(&(table.len() as u32 + 9).to_le_bytes());
+        contents.extend_from_slice(&table);
+        contents.extend_from_slice(&3_u32.to_le_bytes());
+        contents.push(0);
+        contents.extend_from_slice(&0x8f92_eab1_u32.to_le_bytes());
+
+        let mut file = temp_file("compressed");
+
+        file.write_all(&contents).unwrap();
+
+        let mut map = CompressedMmap::open(file, Rle, NonZeroUsize::new(1).unwrap()).unwrap();
+
+        assert_eq!(map.len(), 560);
+        assert_eq!(map.frame_count(), 3);
+
+        let mut buf = [0; 8];
+
+        assert_eq!(map.read_at(&mut buf, 296).unwrap(), 8);
+        assert_eq!(&buf, b"bbbbcccc");
+
+        assert_eq!(map.read_at(&mut buf, 548).unwrap(), 8);
+        assert_eq!(&buf, b"ccdddddd");
+
+        assert_eq!(map.read_at(&mut buf, 556).unwrap(), 4);
+        assert_eq!(map.read_at(&mut buf, 0).unwrap(), 8);
+        assert_eq!(&buf, b"aaaaaaaa");
+    }
+}
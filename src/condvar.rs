@@ -0,0 +1,145 @@
+use std::{
+    io,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    futex::{futex_wait, futex_wake, futex_wake_all},
+    MmapCell, MmapMut, Pod, RobustShmMutexGuard,
+};
+
+/// A condition variable placed inside a shared mapping, used together with a
+/// [`RobustShmMutex`](crate::RobustShmMutex)
+///
+/// Built on a futex sequence counter: waiters sleep until a notification
+/// changes it. As with [`std::sync::Condvar`], wake-ups may be spurious, so
+/// waiters must recheck their condition in a loop, and the condition must
+/// only be changed while holding the mutex.
+///
+/// Re-locking the mutex after waiting may find that its owner died, so every
+/// wait takes the same `recover` callback as
+/// [`RobustShmMutex::lock`](crate::RobustShmMutex::lock).
+#[repr(C)]
+pub struct ShmCondvar {
+    seq: AtomicU32,
+}
+
+impl ShmCondvar {
+    /// Initialize a condition variable at `offset` in the mapping and return a
+    /// reference to it
+    pub fn init_in<'m>(map: &'m mut MmapMut, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        unsafe {
+            ptr.write(Self {
+                seq: AtomicU32::new(0),
+            });
+
+            Ok(&*ptr)
+        }
+    }
+
+    /// Attach to a condition variable previously initialized at `offset` in
+    /// a [view](MmapMut::as_cell) of the mapping, possibly by another process
+    pub fn attach<'m>(map: &'m MmapCell, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Unlock the mutex held by `guard`, sleep until notified, and lock it
+    /// again
+    pub fn wait<'a, T: Pod>(
+        &self,
+        guard: RobustShmMutexGuard<'a, T>,
+        recover: impl FnOnce(&mut T) -> bool,
+    ) -> io::Result<RobustShmMutexGuard<'a, T>> {
+        self.wait_inner(guard, None, recover)
+            .map(|(guard, _)| guard)
+    }
+
+    /// Like [`wait`](Self::wait), but give up after `timeout`, also returning
+    /// whether the wait timed out
+    pub fn wait_timeout<'a, T: Pod>(
+        &self,
+        guard: RobustShmMutexGuard<'a, T>,
+        timeout: Duration,
+        recover: impl FnOnce(&mut T) -> bool,
+    ) -> io::Result<(RobustShmMutexGuard<'a, T>, bool)> {
+        self.wait_inner(guard, Some(timeout), recover)
+    }
+
+    fn wait_inner<'a, T: Pod>(
+        &self,
+        guard: RobustShmMutexGuard<'a, T>,
+        timeout: Option<Duration>,
+        recover: impl FnOnce(&mut T) -> bool,
+    ) -> io::Result<(RobustShmMutexGuard<'a, T>, bool)> {
+        // read while still holding the mutex, so a notification sent after
+        // unlocking changes the value and the futex wait returns at once
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+
+        drop(guard);
+
+        let woken = futex_wait(&self.seq, seq, timeout);
+        let guard = mutex.lock(recover)?;
+
+        Ok((guard, !woken?))
+    }
+
+    /// Wake one waiter
+    pub fn notify_one(&self) -> io::Result<()> {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, 1).map(drop)
+    }
+
+    /// Wake every waiter
+    pub fn notify_all(&self) -> io::Result<()> {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake_all(&self.seq).map(drop)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, thread, time::Duration};
+
+    use crate::{MmapMut, RobustShmMutex, ShmCondvar};
+
+    #[test]
+    fn producer_consumer() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        RobustShmMutex::init_in(&mut map, 0, 0_u64).unwrap();
+        ShmCondvar::init_in(&mut map, 512).unwrap();
+
+        let cell = map.as_cell();
+        let mutex = RobustShmMutex::<u64>::attach(&cell, 0).unwrap();
+        let condvar = ShmCondvar::attach(&cell, 512).unwrap();
+
+        let guard = mutex.lock(|_| true).unwrap();
+        let (guard, timed_out) = condvar
+            .wait_timeout(guard, Duration::from_millis(1), |_| true)
+            .unwrap();
+
+        assert!(timed_out);
+        drop(guard);
+
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let mut guard = mutex.lock(|_| true).unwrap();
+
+                    while *guard == 0 {
+                        guard = condvar.wait(guard, |_| true).unwrap();
+                    }
+                });
+            }
+
+            *mutex.lock(|_| true).unwrap() = 1;
+            condvar.notify_all().unwrap();
+        });
+    }
+}
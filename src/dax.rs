@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    ops::{Deref, DerefMut, RangeBounds},
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr, slice,
+};
+
+use crate::{
+    check_range, file_len_from,
+    flag::{Flag, UniqueFlag},
+    mmap_raw, munmap_raw, pmem, Protection,
+};
+
+/// A shared, writable mapping of a file on a DAX-capable filesystem, created
+/// with `MAP_SYNC`
+///
+/// `MAP_SYNC` guarantees the file's metadata is durable whenever a page is
+/// writable, so data written through the mapping is persisted by flushing the
+/// CPU caches with [`persist`](Self::persist) rather than calling `msync(2)`.
+pub struct DaxMmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl DaxMmap {
+    /// Map the whole of `file`, which must be opened for reading and writing
+    ///
+    /// Fails with [`Unsupported`](io::ErrorKind::Unsupported) if the file is
+    /// not on a filesystem mounted with `-o dax`.
+    pub fn new(file: &File) -> io::Result<Self> {
+        let len = file_len_from(file, 0)?;
+
+        Self::map(file, 0, len)
+    }
+
+    /// Map `len` bytes starting at `offset` of a device-DAX character device
+    /// such as `/dev/dax0.0`
+    ///
+    /// Device files report a size of zero, so the device's size and required
+    /// mapping alignment are read from `/sys/bus/dax/devices/<name>/`. Both
+    /// `offset` and `len` must be multiples of that alignment, which is
+    /// commonly 2MiB rather than the page size.
+    pub fn open_device(path: impl AsRef<Path>, offset: u64, len: usize) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid device path"))?;
+
+        let sysfs = Path::new("/sys/bus/dax/devices").join(name);
+
+        let align = read_sysfs_u64(&sysfs.join("align"))?;
+        let size = read_sysfs_u64(&sysfs.join("size"))?;
+
+        check_device_region(offset, len, align, size)?;
+
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Self::map(&file, offset, len)
+    }
+
+    fn map(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty region",
+            ));
+        }
+
+        let offset = i64::try_from(offset)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "offset is too large"))?;
+
+        let ptr = unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                len,
+                Protection::READ | Protection::WRITE,
+                (UniqueFlag::MAP_SHARED_VALIDATE | Flag::MAP_SYNC).0,
+                file.as_raw_fd(),
+                offset,
+            )
+        }
+        .map_err(|err| match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP) => io::Error::new(
+                io::ErrorKind::Unsupported,
+                "file does not support MAP_SYNC; is it on a DAX filesystem?",
+            ),
+            _ => err,
+        })?;
+
+        Ok(Self { ptr, len })
+    }
+
+    /// Flush the CPU cache lines covering `range` and wait for them to reach
+    /// persistent memory
+    pub fn persist(&self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let range = check_range(self.len, range)?;
+
+        pmem::persist(&self[range]);
+
+        Ok(())
+    }
+
+    /// Copy `src` into `range` of the mapping and persist it with
+    /// [`pmem::copy_persist`]
+    pub fn pmem_copy(&mut self, range: impl RangeBounds<usize>, src: &[u8]) -> io::Result<()> {
+        let range = check_range(self.len, range)?;
+
+        if range.len() != src.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "source length does not match destination range",
+            ));
+        }
+
+        pmem::copy_persist(&mut self[range], src);
+
+        Ok(())
+    }
+}
+
+/// Validate a region of a device-DAX device of `size` bytes whose mappings
+/// must be aligned to `align`
+fn check_device_region(offset: u64, len: usize, align: u64, size: u64) -> io::Result<()> {
+    if align == 0 || !offset.is_multiple_of(align) || !(len as u64).is_multiple_of(align) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("device-DAX regions must be aligned to {} bytes", align),
+        ));
+    }
+
+    if !matches!(offset.checked_add(len as u64), Some(end) if end <= size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "region out of bounds of device",
+        ));
+    }
+
+    Ok(())
+}
+
+fn read_sysfs_u64(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl Deref for DaxMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for DaxMmap {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for DaxMmap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::check_device_region;
+    use crate::test_util::temp_file;
+    use crate::DaxMmap;
+
+    #[test]
+    fn validates_device_region() {
+        const MB2: u64 = 2 << 20;
+
+        assert!(check_device_region(0, MB2 as usize, MB2, 4 * MB2).is_ok());
+        assert!(check_device_region(MB2, 3 * MB2 as usize, MB2, 4 * MB2).is_ok());
+        assert!(check_device_region(4096, MB2 as usize, MB2, 4 * MB2).is_err());
+        assert!(check_device_region(0, 4096, MB2, 4 * MB2).is_err());
+        assert!(check_device_region(2 * MB2, 3 * MB2 as usize, MB2, 4 * MB2).is_err());
+        assert!(check_device_region(0, 4096, 0, 4 * MB2).is_err());
+    }
+
+    #[test]
+    fn rejects_non_dax_file() {
+        let file = temp_file("dax");
+        file.set_len(4096).unwrap();
+
+        match DaxMmap::new(&file) {
+            Ok(mut map) => {
+                map[0] = 1;
+                map.persist(..1).unwrap();
+                map.pmem_copy(8..12, b"dax!").unwrap();
+
+                assert_eq!(&map[8..12], b"dax!");
+            }
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::Unsupported),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+};
+
+use crate::{page_size, Mmap};
+
+/// Page-level redundancy between two mappings, from [`dedupe_report`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupeReport {
+    /// Pages in the first mapping, counting a partial last page
+    pub pages_a: usize,
+    pub pages_b: usize,
+    /// Pages of the second mapping with an identical page somewhere in the
+    /// first
+    pub shared_pages: usize,
+    /// Pages of either mapping which are entirely zero
+    pub zero_pages: usize,
+    /// Distinct page contents across both mappings
+    pub distinct_pages: usize,
+}
+
+impl DedupeReport {
+    /// Pages which would be freed if every identical page across both
+    /// mappings were stored once
+    pub fn redundant_pages(&self) -> usize {
+        self.pages_a + self.pages_b - self.distinct_pages
+    }
+}
+
+/// Compare the pages of `a` and `b` by content, to quantify how much memory
+/// deduplicating them would save
+///
+/// Every page is hashed and candidates are confirmed byte for byte, so the
+/// counts are exact. This gives the same answer KSM would converge on with
+/// both mappings marked `MADV_MERGEABLE`, without waiting for its scanner or
+/// depending on it being enabled, and without the counters of other processes'
+/// merged pages mixed in. Reading the mappings faults in any pages which are
+/// not resident.
+pub fn dedupe_report(a: &Mmap, b: &Mmap) -> DedupeReport {
+    let page = page_size();
+    let hasher = RandomState::new();
+
+    let mut report = DedupeReport::default();
+
+    // distinct contents with each hash, and whether they occur in `a`,
+    // all of which is seen before any of `b`
+    let mut seen: HashMap<u64, Vec<(&[u8], bool)>> = HashMap::new();
+
+    for (pages, in_b) in [(a.chunks(page), false), (b.chunks(page), true)] {
+        for chunk in pages {
+            if in_b {
+                report.pages_b += 1;
+            } else {
+                report.pages_a += 1;
+            }
+
+            if chunk.iter().all(|&b| b == 0) {
+                report.zero_pages += 1;
+            }
+
+            let copies = seen.entry(hasher.hash_one(chunk)).or_default();
+
+            match copies.iter().find(|(copy, _)| *copy == chunk) {
+                Some(&(_, in_a)) => report.shared_pages += usize::from(in_b && in_a),
+                None => {
+                    copies.push((chunk, !in_b));
+                    report.distinct_pages += 1;
+                }
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{dedupe_report, page_size, test_util::temp_file, Mmap, MmapOptions};
+
+    fn map_pages(name: &str, fills: &[u8], len: usize) -> Mmap<'static> {
+        let page = page_size();
+
+        let file = temp_file(&format!("dedupe-{name}"));
+
+        file.set_len(len as u64).unwrap();
+
+        for (idx, &fill) in fills.iter().enumerate() {
+            file.write_all_at(&vec![fill; page], (idx * page) as u64)
+                .unwrap();
+        }
+
+        MmapOptions::new(NonZeroUsize::new(len).unwrap())
+            .map_file(&file)
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_duplicate_pages() {
+        let page = page_size();
+
+        let a = map_pages("a", &[1, 2, 1, 0], 4 * page);
+        // the last page is partial, so differs from a's zero page
+        let b = map_pages("b", &[2, 2, 3], 3 * page + 10);
+
+        let report = dedupe_report(&a, &b);
+
+        assert_eq!(report.pages_a, 4);
+        assert_eq!(report.pages_b, 4);
+        assert_eq!(report.shared_pages, 2);
+        assert_eq!(report.zero_pages, 2);
+        // 1, 2, zero, 3 and the partial zero page
+        assert_eq!(report.distinct_pages, 5);
+        assert_eq!(report.redundant_pages(), 3);
+    }
+}
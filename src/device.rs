@@ -0,0 +1,156 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+    ptr,
+};
+
+use crate::{flag::UniqueFlag, mmap_raw, munmap_raw, page_size, Pod, Protection};
+
+/// A shared, read-write mapping of a region of a device file such as
+/// `/dev/mem` or `/dev/uioN`
+///
+/// Device files generally report a size of zero, so unlike
+/// [`MmapMut::new_file`](crate::MmapMut::new_file) the offset and length of the
+/// region must be given explicitly.
+///
+/// Memory-mapped registers must not be accessed through ordinary loads and
+/// stores, which the compiler is free to merge, reorder or elide, so this type
+/// intentionally does not dereference to a slice.
+pub struct DeviceMmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl DeviceMmap {
+    /// Open the device at `path` with `O_SYNC` and map `len` bytes starting at
+    /// `offset`, which must be a multiple of the page size
+    pub fn open(path: impl AsRef<Path>, offset: u64, len: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_SYNC)
+            .open(path)?;
+
+        Self::from_file(&file, offset, len)
+    }
+
+    /// Map `len` bytes of an already opened device file starting at `offset`,
+    /// which must be a multiple of the page size
+    pub fn from_file(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty region",
+            ));
+        }
+
+        if !offset.is_multiple_of(page_size() as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is not a multiple of the page size",
+            ));
+        }
+
+        let offset = i64::try_from(offset)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "offset is too large"))?;
+
+        let ptr = unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                len,
+                Protection::READ | Protection::WRITE,
+                UniqueFlag::MAP_SHARED.0,
+                file.as_raw_fd(),
+                offset,
+            )?
+        };
+
+        Ok(Self { ptr, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    fn register_ptr<T: Pod>(&self, offset: usize) -> io::Result<*mut T> {
+        if !matches!(offset.checked_add(std::mem::size_of::<T>()), Some(end) if end <= self.len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "register out of bounds of mapping",
+            ));
+        }
+
+        let ptr = unsafe { self.ptr.add(offset) };
+
+        if !(ptr as usize).is_multiple_of(std::mem::align_of::<T>()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "register offset is not suitably aligned",
+            ));
+        }
+
+        Ok(ptr.cast())
+    }
+
+    /// Perform a single volatile read of a `T` at `offset` bytes into the
+    /// mapping
+    pub fn read_volatile_at<T: Pod>(&self, offset: usize) -> io::Result<T> {
+        let ptr = self.register_ptr::<T>(offset)?;
+
+        Ok(unsafe { ptr::read_volatile(ptr) })
+    }
+
+    /// Perform a single volatile write of `val` at `offset` bytes into the
+    /// mapping
+    pub fn write_volatile_at<T: Pod>(&self, offset: usize, val: T) -> io::Result<()> {
+        let ptr = self.register_ptr::<T>(offset)?;
+
+        unsafe { ptr::write_volatile(ptr, val) };
+
+        Ok(())
+    }
+}
+
+impl Drop for DeviceMmap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use crate::DeviceMmap;
+
+    #[test]
+    fn volatile_roundtrip() {
+        let map = DeviceMmap::open("/dev/zero", 0, 4096).unwrap();
+
+        assert_eq!(map.read_volatile_at::<u32>(16).unwrap(), 0);
+
+        map.write_volatile_at(16, 0xdead_beef_u32).unwrap();
+
+        assert_eq!(map.read_volatile_at::<u32>(16).unwrap(), 0xdead_beef);
+        assert!(map.read_volatile_at::<u32>(18).is_err());
+        assert!(map.write_volatile_at(4094, 0_u32).is_err());
+
+        let err = DeviceMmap::open("/dev/zero", u64::MAX - 4095, 4096)
+            .err()
+            .unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{io, ops::Range};
+
+use crate::{mincore_raw, page_size, Mmap};
+
+/// Byte offsets of a run of whole pages, except that the last page of a
+/// mapping may be partial
+pub type PageRange = Range<usize>;
+
+/// Compare two mappings of equal length page by page, returning the runs of
+/// pages whose contents differ, in order and with adjacent pages merged
+///
+/// Every page is read, faulting in any which are not resident.
+pub fn diff_pages(a: &Mmap, b: &Mmap) -> io::Result<Vec<PageRange>> {
+    diff(a, b, false)
+}
+
+/// Like [`diff_pages`], but treating pages which neither side has resident
+/// as unchanged without reading them, so comparing mostly cold mappings does
+/// not fault them in
+///
+/// Only use this where pages which are not resident are known to be equal.
+/// Untouched anonymous memory reads as zero on both sides, but anonymous
+/// pages which were swapped out, and file pages which were evicted or
+/// written through `write(2)` rather than a mapping, are not resident either
+/// and their differences are missed.
+pub fn diff_resident_pages(a: &Mmap, b: &Mmap) -> io::Result<Vec<PageRange>> {
+    diff(a, b, true)
+}
+
+fn diff(a: &Mmap, b: &Mmap, resident_only: bool) -> io::Result<Vec<PageRange>> {
+    if a.len != b.len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mappings differ in length",
+        ));
+    }
+
+    let page = page_size();
+
+    let (resident_a, resident_b) = if resident_only {
+        (residency(a.ptr, a.len)?, residency(b.ptr, b.len)?)
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    let mut changed: Vec<PageRange> = Vec::new();
+
+    for (idx, (page_a, page_b)) in a.chunks(page).zip(b.chunks(page)).enumerate() {
+        let resident = resident_a.get(idx).is_none_or(|&r| r & 1 != 0)
+            || resident_b.get(idx).is_none_or(|&r| r & 1 != 0);
+
+        if !resident || pages_equal(page_a, page_b) {
+            continue;
+        }
+
+        let start = idx * page;
+        let end = start + page_a.len();
+
+        match changed.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => changed.push(start..end),
+        }
+    }
+
+    Ok(changed)
+}
+
+/// The `mincore(2)` vector of `[ptr, ptr + len)`
+fn residency(ptr: *const u8, len: usize) -> io::Result<Vec<u8>> {
+    let mut vec = vec![0_u8; len.div_ceil(page_size())];
+
+    unsafe { mincore_raw(ptr, len, &mut vec)? };
+
+    Ok(vec)
+}
+
+/// Compare 64 bytes at a time, folding each block's words together with xor
+/// and or, which the compiler turns into wide vector compares, and stopping
+/// at the first block which differs
+fn pages_equal(a: &[u8], b: &[u8]) -> bool {
+    const BLOCK: usize = 64;
+
+    let (blocks_a, blocks_b) = (a.chunks_exact(BLOCK), b.chunks_exact(BLOCK));
+
+    blocks_a.remainder() == blocks_b.remainder()
+        && blocks_a.zip(blocks_b).all(|(a, b)| {
+            let diff = a
+                .chunks_exact(8)
+                .zip(b.chunks_exact(8))
+                .fold(0, |acc, (a, b)| {
+                    acc | (u64::from_ne_bytes(a.try_into().unwrap())
+                        ^ u64::from_ne_bytes(b.try_into().unwrap()))
+                });
+
+            diff == 0
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{
+        diff_pages, diff_resident_pages, page_size, test_util::temp_file, Mmap, MmapOptions,
+    };
+
+    fn map_file(name: &str, len: usize, writes: &[(usize, &[u8])]) -> Mmap<'static> {
+        let file = temp_file(&format!("diff-{name}"));
+
+        file.set_len(len as u64).unwrap();
+
+        for &(offset, bytes) in writes {
+            file.write_all_at(bytes, offset as u64).unwrap();
+        }
+
+        MmapOptions::new(NonZeroUsize::new(len).unwrap())
+            .map_file(&file)
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_changed_pages() {
+        let page = page_size();
+        let len = 8 * page + 100;
+
+        let a = map_file("a", len, &[(0, &[1; 64]), (3 * page, b"same")]);
+        let b = map_file(
+            "b",
+            len,
+            &[
+                (0, &[1; 64]),
+                (page + 70, b"x"),
+                (3 * page, b"same"),
+                (2 * page + 10, b"y"),
+                (6 * page, b"z"),
+                (8 * page + 99, b"!"),
+            ],
+        );
+
+        assert_eq!(
+            diff_pages(&a, &b).unwrap(),
+            [page..3 * page, 6 * page..7 * page, 8 * page..len]
+        );
+        assert!(diff_pages(&a, &a).unwrap().is_empty());
+
+        let short = map_file("short", len - 1, &[]);
+
+        assert!(diff_pages(&a, &short).is_err());
+        assert!(diff_resident_pages(&a, &short).is_err());
+
+        // the pages written through the file are resident in the page cache
+        assert_eq!(
+            diff_resident_pages(&a, &b).unwrap(),
+            diff_pages(&a, &b).unwrap()
+        );
+
+        // untouched anonymous memory is skipped rather than faulted in
+        let anon = MmapOptions::new(NonZeroUsize::new(len).unwrap())
+            .map_anon()
+            .unwrap();
+
+        assert_eq!(diff_resident_pages(&anon, &anon).unwrap(), []);
+        assert_eq!(diff_pages(&anon, &anon).unwrap(), []);
+    }
+}
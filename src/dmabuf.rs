@@ -0,0 +1,207 @@
+use std::{
+    fs::File,
+    io,
+    ops::{Deref, DerefMut},
+    os::unix::io::AsRawFd,
+    ptr, slice,
+};
+
+use crate::{flag::UniqueFlag, mmap_raw, munmap_raw, Protection};
+
+/// `_IOW('b', 0, struct dma_buf_sync)`
+const DMA_BUF_IOCTL_SYNC: libc::Ioctl = 0x4008_6200;
+
+const DMA_BUF_SYNC_READ: u64 = 1;
+const DMA_BUF_SYNC_WRITE: u64 = 2;
+const DMA_BUF_SYNC_RW: u64 = DMA_BUF_SYNC_READ | DMA_BUF_SYNC_WRITE;
+const DMA_BUF_SYNC_START: u64 = 0;
+const DMA_BUF_SYNC_END: u64 = 4;
+
+#[repr(C)]
+struct DmaBufSync {
+    flags: u64,
+}
+
+/// A CPU mapping of a dma-buf file descriptor, as exported by GPU, V4L2 or DMA
+/// heap drivers
+///
+/// Device-shared buffers may live in memory which is not coherent with the CPU
+/// caches, so the contents are only accessible through guards which bracket
+/// the access with `DMA_BUF_IOCTL_SYNC` start and end calls.
+pub struct DmaBufMmap {
+    file: File,
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl DmaBufMmap {
+    /// Map the entirety of the dma-buf, taking ownership of its file descriptor
+    pub fn new(file: File) -> io::Result<Self> {
+        let fd = file.as_raw_fd();
+
+        // dma-bufs report their size through `lseek` rather than `fstat`
+        let len = unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
+
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty dma-buf",
+            ));
+        }
+
+        let len = len as usize;
+
+        let ptr = unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                len,
+                Protection::READ | Protection::WRITE,
+                UniqueFlag::MAP_SHARED.0,
+                fd,
+                0,
+            )?
+        };
+
+        Ok(Self { file, ptr, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    fn sync(&self, flags: u64) -> io::Result<()> {
+        let sync = DmaBufSync { flags };
+
+        loop {
+            let res = unsafe { libc::ioctl(self.file.as_raw_fd(), DMA_BUF_IOCTL_SYNC, &sync) };
+
+            if res == 0 {
+                return Ok(());
+            }
+
+            let err = io::Error::last_os_error();
+
+            if !matches!(err.raw_os_error(), Some(libc::EINTR | libc::EAGAIN)) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Begin a CPU read of the buffer, which ends when the guard is dropped
+    pub fn begin_read(&self) -> io::Result<DmaBufReadGuard<'_>> {
+        self.sync(DMA_BUF_SYNC_START | DMA_BUF_SYNC_READ)?;
+
+        Ok(DmaBufReadGuard { map: self })
+    }
+
+    /// Begin a CPU read and write of the buffer, which ends when the guard is
+    /// dropped
+    pub fn begin_write(&mut self) -> io::Result<DmaBufWriteGuard<'_>> {
+        self.sync(DMA_BUF_SYNC_START | DMA_BUF_SYNC_RW)?;
+
+        Ok(DmaBufWriteGuard { map: self })
+    }
+}
+
+impl Drop for DmaBufMmap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+/// Read access to a [`DmaBufMmap`]
+pub struct DmaBufReadGuard<'m> {
+    map: &'m DmaBufMmap,
+}
+
+impl<'m> DmaBufReadGuard<'m> {
+    /// End the access, reporting any error from the kernel
+    pub fn finish(self) -> io::Result<()> {
+        let map = self.map;
+        std::mem::forget(self);
+        map.sync(DMA_BUF_SYNC_END | DMA_BUF_SYNC_READ)
+    }
+}
+
+impl<'m> Deref for DmaBufReadGuard<'m> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.map.ptr, self.map.len) }
+    }
+}
+
+impl<'m> Drop for DmaBufReadGuard<'m> {
+    fn drop(&mut self) {
+        let _ = self.map.sync(DMA_BUF_SYNC_END | DMA_BUF_SYNC_READ);
+    }
+}
+
+/// Read-write access to a [`DmaBufMmap`]
+pub struct DmaBufWriteGuard<'m> {
+    map: &'m mut DmaBufMmap,
+}
+
+impl<'m> DmaBufWriteGuard<'m> {
+    /// End the access, reporting any error from the kernel
+    pub fn finish(self) -> io::Result<()> {
+        let res = self.map.sync(DMA_BUF_SYNC_END | DMA_BUF_SYNC_RW);
+        std::mem::forget(self);
+        res
+    }
+}
+
+impl<'m> Deref for DmaBufWriteGuard<'m> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.map.ptr, self.map.len) }
+    }
+}
+
+impl<'m> DerefMut for DmaBufWriteGuard<'m> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.map.ptr, self.map.len) }
+    }
+}
+
+impl<'m> Drop for DmaBufWriteGuard<'m> {
+    fn drop(&mut self) {
+        let _ = self.map.sync(DMA_BUF_SYNC_END | DMA_BUF_SYNC_RW);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, os::unix::io::FromRawFd};
+
+    use crate::DmaBufMmap;
+
+    #[test]
+    fn sync_fails_on_non_dmabuf() {
+        let fd = unsafe { libc::memfd_create(c"dmabuf-test".as_ptr(), 0) };
+        assert!(fd >= 0);
+
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(4096).unwrap();
+
+        let map = DmaBufMmap::new(file).unwrap();
+
+        assert_eq!(map.len(), 4096);
+        assert!(map.begin_read().is_err());
+    }
+}
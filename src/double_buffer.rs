@@ -0,0 +1,192 @@
+use std::{
+    cell::UnsafeCell,
+    io, mem, ptr,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+use crate::{msync_raw, spin::SpinLock, MmapCell, MmapMut, Pod};
+
+#[repr(C)]
+struct Slot<T> {
+    /// The generation whose value this slot holds
+    seq: AtomicU64,
+    data: UnsafeCell<T>,
+}
+
+/// Two copies of a value in a shared or file-backed mapping, of which exactly
+/// one is current at any time
+///
+/// Writers fill in the inactive copy and then publish it by atomically
+/// bumping the generation counter, so the current copy is never modified in
+/// place. A reader therefore always sees a complete value, and a process
+/// crashing part way through a write leaves the previous value intact.
+///
+/// Writers are serialized by a lock recording the holder's pid; a writer
+/// finding the lock held by a process which no longer exists takes it over.
+#[repr(C)]
+pub struct DoubleBuffered<T: Pod> {
+    generation: AtomicU64,
+    writer: SpinLock,
+    slots: [Slot<T>; 2],
+}
+
+unsafe impl<T: Pod> Sync for DoubleBuffered<T> {}
+
+impl<T: Pod> DoubleBuffered<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+            writer: SpinLock::new(),
+            slots: [
+                Slot {
+                    seq: AtomicU64::new(0),
+                    data: UnsafeCell::new(value),
+                },
+                Slot {
+                    seq: AtomicU64::new(u64::MAX),
+                    data: UnsafeCell::new(value),
+                },
+            ],
+        }
+    }
+
+    /// Write a freshly initialized buffer holding `value` at `offset` in the
+    /// mapping and return a reference to it
+    pub fn init_in<'m>(map: &'m mut MmapMut, offset: usize, value: T) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        unsafe {
+            ptr::write(ptr, Self::new(value));
+            Ok(&*ptr)
+        }
+    }
+
+    /// Attach to a buffer previously initialized at `offset`, possibly by
+    /// another process or before a crash
+    ///
+    /// Takes a [view](MmapMut::as_cell) of the mapping, as writes through the
+    /// buffer would race with any slice of the bytes.
+    pub fn attach<'m>(map: &'m MmapCell, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Number of values published so far
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Read a copy of the current value
+    pub fn read(&self) -> T {
+        loop {
+            let generation = self.generation.load(Ordering::Acquire);
+            let slot = &self.slots[(generation % 2) as usize];
+
+            let value = unsafe { ptr::read_volatile(slot.data.get()) };
+
+            fence(Ordering::Acquire);
+
+            // a slot is only rewritten once the generation has moved past it,
+            // so the copy is intact if the generation did not change
+            if slot.seq.load(Ordering::Relaxed) == generation
+                && self.generation.load(Ordering::Relaxed) == generation
+            {
+                return value;
+            }
+
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Publish `value` as the new current value
+    pub fn write(&self, value: T) {
+        self.write_inner(value, |_| Ok(())).unwrap()
+    }
+
+    /// Publish `value`, using `msync(2)` to persist the new copy before the
+    /// generation counter which points at it, and the counter before
+    /// returning
+    ///
+    /// Required for the crash guarantees to hold across power loss when the
+    /// buffer lives in a file mapping.
+    pub fn write_durable(&self, value: T) -> io::Result<()> {
+        self.write_inner(value, |ptr| unsafe {
+            msync_raw(ptr, mem::size_of::<Slot<T>>(), libc::MS_SYNC)
+        })?;
+
+        unsafe {
+            msync_raw(
+                (&self.generation as *const AtomicU64).cast(),
+                mem::size_of::<AtomicU64>(),
+                libc::MS_SYNC,
+            )
+        }
+    }
+
+    fn write_inner(
+        &self,
+        value: T,
+        persist: impl FnOnce(*const u8) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let _lock = self.writer.lock_or_recover();
+
+        let generation = self.generation.load(Ordering::Relaxed).wrapping_add(1);
+        let slot = &self.slots[(generation % 2) as usize];
+
+        slot.seq.store(u64::MAX, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        unsafe { ptr::write_volatile(slot.data.get(), value) };
+
+        slot.seq.store(generation, Ordering::Release);
+
+        persist((slot as *const Slot<T>).cast())?;
+
+        self.generation.store(generation, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{test_util::temp_file, DoubleBuffered, MmapMut};
+
+    #[test]
+    fn write_then_read() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let buffer = DoubleBuffered::init_in(&mut map, 0, [0_u64; 4]).unwrap();
+
+        assert_eq!(buffer.read(), [0; 4]);
+
+        buffer.write([1, 2, 3, 4]);
+        buffer.write([5, 6, 7, 8]);
+
+        assert_eq!(buffer.read(), [5, 6, 7, 8]);
+        assert_eq!(buffer.generation(), 2);
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn durable_write_survives_remap() {
+        let file = temp_file("double-buffer");
+        file.set_len(4096).unwrap();
+
+        {
+            let mut map = MmapMut::new_file(&file).unwrap();
+            let buffer = DoubleBuffered::init_in(&mut map, 0, 0_u32).unwrap();
+
+            buffer.write_durable(7).unwrap();
+        }
+
+        let mut map = MmapMut::new_file(&file).unwrap();
+        let cell = map.as_cell();
+        let buffer = DoubleBuffered::<u32>::attach(&cell, 0).unwrap();
+
+        assert_eq!(buffer.read(), 7);
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    fs::File,
+    io,
+    num::NonZeroUsize,
+    ops::{Range, RangeBounds},
+    os::unix::fs::FileExt,
+};
+
+use crate::{check_range, madvise_raw, MmapMut};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"MMAPENC\0");
+
+/// Size of the file header preceding the page records
+const HEADER_LEN: u64 = 32;
+
+/// Plaintext bytes encrypted together, independent of the system page size
+/// so files can move between machines
+const BLOCK_LEN: usize = 4096;
+
+/// An authenticated cipher sealing the blocks of an [`EncryptedMmap`]
+///
+/// The crate does not ship any cryptography; implement this over the AEAD of
+/// your choice, such as AES-GCM or ChaCha20-Poly1305. Each call gets the
+/// block number and a write counter which is never reused for the same
+/// block, and the implementation must derive a nonce unique to the pair and
+/// authenticate the block number, so that blocks cannot be swapped or rolled
+/// back to a different position undetected.
+pub trait PageCipher {
+    /// Length of the authentication tag stored with each block
+    fn tag_len(&self) -> usize;
+
+    /// Encrypt `block` in place, writing its tag to `tag`
+    fn seal(&self, block: u64, counter: u64, data: &mut [u8], tag: &mut [u8]) -> io::Result<()>;
+
+    /// Verify `tag` and decrypt `block` in place, failing if either has been
+    /// tampered with
+    fn open(&self, block: u64, counter: u64, data: &mut [u8], tag: &[u8]) -> io::Result<()>;
+}
+
+/// A file whose contents stay encrypted on disk, decrypted block by block
+/// into anonymous memory as they are loaded
+///
+/// The file holds a record for every 4KiB block of plaintext: the block's
+/// write counter, its authentication tag and its ciphertext. Blocks are only
+/// decrypted when a [`read`](Self::read) or [`write`](Self::write) first
+/// touches them, and [`flush`](Self::flush) re-encrypts the modified ones
+/// under a fresh counter. Blocks never written read as zeros.
+///
+/// The plaintext is excluded from core dumps. Modified blocks which have not
+/// been flushed when the mapping is dropped are flushed then, ignoring
+/// errors; call [`flush`](Self::flush) to see them.
+pub struct EncryptedMmap<C: PageCipher> {
+    file: File,
+    cipher: C,
+    plain: MmapMut<'static>,
+    len: usize,
+    blocks: Vec<Block>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Block {
+    loaded: bool,
+    dirty: bool,
+    counter: u64,
+}
+
+impl<C: PageCipher> EncryptedMmap<C> {
+    /// Format `file` to hold `len` bytes of zeros, discarding its contents
+    pub fn create(file: File, len: NonZeroUsize, cipher: C) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN as usize];
+
+        header[..8].copy_from_slice(&MAGIC.to_le_bytes());
+        header[8..16].copy_from_slice(&(BLOCK_LEN as u64).to_le_bytes());
+        header[16..24].copy_from_slice(&(cipher.tag_len() as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&(len.get() as u64).to_le_bytes());
+
+        let Some(file_len) = Self::file_len(&cipher, len.get()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "encrypted mapping is too large",
+            ));
+        };
+
+        // zeroed records have a counter of zero, marking them never written
+        file.set_len(0)?;
+        file.set_len(file_len)?;
+        file.write_all_at(&header, 0)?;
+
+        Self::new(file, len.get(), cipher)
+    }
+
+    /// Open a file formatted by [`create`](Self::create), which must be
+    /// readable and writable
+    pub fn open(file: File, cipher: C) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN as usize];
+
+        file.read_exact_at(&mut header, 0)?;
+
+        let field = |idx: usize| u64::from_le_bytes(header[idx * 8..][..8].try_into().unwrap());
+
+        if field(0) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file is not an encrypted mapping",
+            ));
+        }
+
+        if field(1) != BLOCK_LEN as u64 || field(2) != cipher.tag_len() as u64 || field(3) == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "encrypted mapping format does not match",
+            ));
+        }
+
+        // the length is checked against the file before anything is sized by
+        // it, so a corrupt header cannot ask for an impossible mapping
+        let file_len = file.metadata()?.len();
+        let len = usize::try_from(field(3))
+            .ok()
+            .filter(|&len| Self::file_len(&cipher, len).is_some_and(|need| need <= file_len));
+
+        let Some(len) = len else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "encrypted mapping is longer than its file",
+            ));
+        };
+
+        Self::new(file, len, cipher)
+    }
+
+    fn new(file: File, len: usize, cipher: C) -> io::Result<Self> {
+        let count = block_count(len);
+        let plain = MmapMut::new_anon(NonZeroUsize::new(count * BLOCK_LEN).unwrap())?;
+
+        unsafe { madvise_raw(plain.ptr, plain.len, libc::MADV_DONTDUMP)? };
+
+        Ok(Self {
+            file,
+            cipher,
+            plain,
+            len,
+            blocks: vec![Block::default(); count],
+        })
+    }
+
+    fn record_len(cipher: &C) -> u64 {
+        (8 + cipher.tag_len() + BLOCK_LEN) as u64
+    }
+
+    /// Length of the file holding `len` bytes of plaintext, or `None` if it or
+    /// the plaintext mapping would overflow
+    fn file_len(cipher: &C, len: usize) -> Option<u64> {
+        let count = block_count(len);
+
+        count.checked_mul(BLOCK_LEN)?;
+
+        Self::record_len(cipher)
+            .checked_mul(count as u64)?
+            .checked_add(HEADER_LEN)
+    }
+
+    fn record_offset(&self, block: usize) -> u64 {
+        HEADER_LEN + Self::record_len(&self.cipher) * block as u64
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decrypt every block overlapping `range` which is not loaded yet
+    pub fn load(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let range = check_range(self.len, range)?;
+
+        for block in blocks_of(&range) {
+            if !self.blocks[block].loaded {
+                self.load_block(block)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_block(&mut self, block: usize) -> io::Result<()> {
+        let tag_len = self.cipher.tag_len();
+        let mut record = vec![0; Self::record_len(&self.cipher) as usize];
+
+        self.file
+            .read_exact_at(&mut record, self.record_offset(block))?;
+
+        let (counter, rest) = record.split_at_mut(8);
+        let (tag, data) = rest.split_at_mut(tag_len);
+        let counter = u64::from_le_bytes(counter.try_into().unwrap());
+
+        if counter != 0 {
+            self.cipher.open(block as u64, counter, data, tag)?;
+        } else if tag.iter().chain(data.iter()).any(|&b| b != 0) {
+            // only a record which was never written may skip authentication,
+            // or zeroing the counter would smuggle in unauthenticated data
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unwritten block record is not zeroed",
+            ));
+        }
+
+        self.plain[block * BLOCK_LEN..][..BLOCK_LEN].copy_from_slice(data);
+        self.blocks[block] = Block {
+            loaded: true,
+            dirty: false,
+            counter,
+        };
+
+        Ok(())
+    }
+
+    /// Load and return the plaintext of `range`
+    pub fn read(&mut self, range: impl RangeBounds<usize>) -> io::Result<&[u8]> {
+        let range = check_range(self.len, range)?;
+
+        self.load(range.clone())?;
+
+        Ok(&self.plain[range])
+    }
+
+    /// Load and return the plaintext of `range` for modification, marking it
+    /// to be re-encrypted by the next [`flush`](Self::flush)
+    pub fn write(&mut self, range: impl RangeBounds<usize>) -> io::Result<&mut [u8]> {
+        let range = check_range(self.len, range)?;
+
+        self.load(range.clone())?;
+
+        for block in blocks_of(&range) {
+            self.blocks[block].dirty = true;
+        }
+
+        Ok(&mut self.plain[range])
+    }
+
+    /// Re-encrypt every modified block and write it back, then sync the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        let tag_len = self.cipher.tag_len();
+        let mut record = vec![0; Self::record_len(&self.cipher) as usize];
+        let mut wrote = false;
+
+        for block in 0..self.blocks.len() {
+            if !self.blocks[block].dirty {
+                continue;
+            }
+
+            let counter = self.blocks[block]
+                .counter
+                .checked_add(1)
+                .ok_or_else(|| io::Error::other("block write counter exhausted"))?;
+
+            let (head, rest) = record.split_at_mut(8);
+            let (tag, data) = rest.split_at_mut(tag_len);
+
+            head.copy_from_slice(&counter.to_le_bytes());
+            data.copy_from_slice(&self.plain[block * BLOCK_LEN..][..BLOCK_LEN]);
+
+            self.cipher.seal(block as u64, counter, data, tag)?;
+            self.file.write_all_at(&record, self.record_offset(block))?;
+
+            self.blocks[block].counter = counter;
+            self.blocks[block].dirty = false;
+            wrote = true;
+        }
+
+        if wrote {
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: PageCipher> Drop for EncryptedMmap<C> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn block_count(len: usize) -> usize {
+    len.div_ceil(BLOCK_LEN)
+}
+
+/// Indices of the blocks overlapping `range`
+fn blocks_of(range: &Range<usize>) -> Range<usize> {
+    if range.is_empty() {
+        return 0..0;
+    }
+
+    range.start / BLOCK_LEN..block_count(range.end)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{
+        test_util::{open_file, temp_path},
+        EncryptedMmap, PageCipher,
+    };
+
+    /// Not remotely secure, but exercises the same paths as a real AEAD
+    struct XorCipher(u8);
+
+    impl XorCipher {
+        fn apply(&self, block: u64, counter: u64, data: &mut [u8]) -> u8 {
+            let key = self.0 ^ block as u8 ^ counter as u8;
+            let mut sum = 0_u8;
+
+            for byte in data {
+                sum = sum.wrapping_add(*byte);
+                *byte ^= key;
+            }
+
+            sum
+        }
+    }
+
+    impl PageCipher for XorCipher {
+        fn tag_len(&self) -> usize {
+            1
+        }
+
+        fn seal(
+            &self,
+            block: u64,
+            counter: u64,
+            data: &mut [u8],
+            tag: &mut [u8],
+        ) -> io::Result<()> {
+            tag[0] = self.apply(block, counter, data);
+            Ok(())
+        }
+
+        fn open(&self, block: u64, counter: u64, data: &mut [u8], tag: &[u8]) -> io::Result<()> {
+            self.apply(block, counter, data);
+
+            if data.iter().fold(0_u8, |sum, &b| sum.wrapping_add(b)) != tag[0] {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad tag"));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn round_trip_through_disk() {
+        let path = temp_path("encrypted");
+
+        let mut map = EncryptedMmap::create(
+            open_file(&path),
+            NonZeroUsize::new(10_000).unwrap(),
+            XorCipher(0x5a),
+        )
+        .unwrap();
+
+        assert_eq!(map.read(9_000..9_010).unwrap(), [0; 10]);
+
+        map.write(4_090..4_100)
+            .unwrap()
+            .copy_from_slice(b"plaintext!");
+        map.flush().unwrap();
+        drop(map);
+
+        let raw = std::fs::read(&path).unwrap();
+
+        assert!(!raw.windows(10).any(|w| w == b"plaintext!"));
+
+        let mut map = EncryptedMmap::open(open_file(&path), XorCipher(0x5a)).unwrap();
+
+        assert_eq!(map.len(), 10_000);
+        assert_eq!(map.read(4_090..4_100).unwrap(), b"plaintext!");
+        drop(map);
+
+        // flip a ciphertext byte in the second block's record
+        let file = open_file(&path);
+        let offset = 32 + (8 + 1 + 4096) + 8 + 1;
+        let mut byte = [0];
+
+        file.read_exact_at(&mut byte, offset).unwrap();
+        file.write_all_at(&[byte[0] ^ 1], offset).unwrap();
+
+        let mut map = EncryptedMmap::open(file.try_clone().unwrap(), XorCipher(0x5a)).unwrap();
+
+        assert_eq!(map.read(4_090..4_096).unwrap(), b"plaint");
+        assert!(map.read(4_096..4_100).is_err());
+        drop(map);
+
+        // zero the second block's counter, claiming it was never written
+        file.write_all_at(&[0; 8], 32 + 8 + 1 + 4096).unwrap();
+
+        let mut map = EncryptedMmap::open(file.try_clone().unwrap(), XorCipher(0x5a)).unwrap();
+
+        assert_eq!(
+            map.read(4_096..4_100).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        drop(map);
+
+        // claim more blocks than the file holds, then more than fit in memory
+        for len in [20_000, u64::MAX] {
+            file.write_all_at(&len.to_le_bytes(), 24).unwrap();
+
+            let err = EncryptedMmap::open(file.try_clone().unwrap(), XorCipher(0x5a))
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
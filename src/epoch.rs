@@ -0,0 +1,328 @@
+use std::{
+    cell::Cell,
+    io,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::{pidfd::process_alive, MmapCell, MmapMut};
+
+/// Most processes or threads which may participate at once
+const PARTICIPANTS: usize = 64;
+
+/// Set in a participant's epoch while it is pinned
+const PINNED: u64 = 1 << 63;
+
+/// Pid of a slot whose dead owner is being evicted, which keeps it from being
+/// claimed until its epoch is cleared
+const EVICTING: u32 = u32::MAX;
+
+#[repr(C)]
+struct Participant {
+    /// Process which owns the slot, or zero if it is free
+    pid: AtomicU32,
+    _pad: u32,
+    epoch: AtomicU64,
+}
+
+/// Epoch-based reclamation for objects in a shared mapping
+///
+/// Shared structures which unlink nodes without locks cannot free them at
+/// once, since another process may still be reading them. Instead, readers
+/// [`pin`](EpochHandle::pin) the current epoch while they traverse the
+/// structure, and writers [`retire`](EpochHandle::retire) unlinked nodes.
+/// The global epoch only advances once every pinned participant has observed
+/// it, so a node retired in epoch `e` can no longer be reached by anyone once
+/// the global epoch reaches `e + 2`, at which point
+/// [`collect`](EpochHandle::collect) hands it back for freeing.
+///
+/// Each participant occupies one of 64 slots, which record its pid. A
+/// participant which dies while pinned would stall reclamation forever, so
+/// slots of processes which no longer exist are cleared when they are found
+/// holding the epoch back.
+#[repr(C)]
+pub struct ShmEpoch {
+    global: AtomicU64,
+    participants: [Participant; PARTICIPANTS],
+}
+
+impl ShmEpoch {
+    /// Initialize the epoch state at `offset` in the mapping and return a
+    /// reference to it
+    pub fn init_in<'m>(map: &'m mut MmapMut, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        unsafe {
+            ptr.write(Self {
+                global: AtomicU64::new(0),
+                participants: std::array::from_fn(|_| Participant {
+                    pid: AtomicU32::new(0),
+                    _pad: 0,
+                    epoch: AtomicU64::new(0),
+                }),
+            });
+
+            Ok(&*ptr)
+        }
+    }
+
+    /// Attach to epoch state previously initialized at `offset` in a
+    /// [view](MmapMut::as_cell) of the mapping, possibly by another process
+    pub fn attach<'m>(map: &'m MmapCell, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        Ok(unsafe { &*ptr })
+    }
+
+    /// The current global epoch
+    pub fn epoch(&self) -> u64 {
+        self.global.load(Ordering::SeqCst)
+    }
+
+    /// Claim a participant slot for the calling thread
+    pub fn register(&self) -> io::Result<EpochHandle<'_>> {
+        let pid = std::process::id();
+
+        for _ in 0..2 {
+            for (slot, participant) in self.participants.iter().enumerate() {
+                if participant
+                    .pid
+                    .compare_exchange(0, pid, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    participant.epoch.store(0, Ordering::SeqCst);
+
+                    return Ok(EpochHandle {
+                        shared: self,
+                        slot,
+                        pins: Cell::new(0),
+                        retired: Vec::new(),
+                    });
+                }
+            }
+
+            // make room by evicting dead processes, then try once more
+            for participant in &self.participants {
+                self.evict_if_dead(participant);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            "all epoch participant slots are in use",
+        ))
+    }
+
+    /// Free the slot of `participant` if the process which owned it is gone
+    fn evict_if_dead(&self, participant: &Participant) -> bool {
+        let pid = participant.pid.load(Ordering::Acquire);
+
+        if pid == 0 || pid == EVICTING || process_alive(pid) {
+            return false;
+        }
+
+        // claim the eviction first, so that nobody registers in the slot
+        // before the dead owner's epoch is cleared
+        if participant
+            .pid
+            .compare_exchange(pid, EVICTING, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        participant.epoch.store(0, Ordering::SeqCst);
+        participant.pid.store(0, Ordering::Release);
+
+        true
+    }
+
+    /// Advance the global epoch if every pinned participant has observed it,
+    /// returning the global epoch afterwards
+    fn try_advance(&self) -> u64 {
+        let global = self.global.load(Ordering::SeqCst);
+
+        for participant in &self.participants {
+            let epoch = participant.epoch.load(Ordering::SeqCst);
+
+            if epoch & PINNED != 0 && epoch & !PINNED != global && !self.evict_if_dead(participant)
+            {
+                return global;
+            }
+        }
+
+        match self
+            .global
+            .compare_exchange(global, global + 1, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => global + 1,
+            Err(current) => current,
+        }
+    }
+}
+
+/// One participant's registration with a [`ShmEpoch`], which frees its slot on
+/// drop
+///
+/// Nodes retired through the handle but not yet collected when it is dropped
+/// are leaked.
+pub struct EpochHandle<'e> {
+    shared: &'e ShmEpoch,
+    slot: usize,
+    pins: Cell<u32>,
+    /// Offsets of retired nodes along with the epoch they were retired in
+    retired: Vec<(u64, u64)>,
+}
+
+impl<'e> EpochHandle<'e> {
+    fn participant(&self) -> &Participant {
+        &self.shared.participants[self.slot]
+    }
+
+    /// Pin the current epoch, so nodes reachable now are not freed until the
+    /// guard is dropped
+    ///
+    /// Pins nest: only dropping the outermost guard unpins the participant.
+    pub fn pin(&self) -> EpochGuard<'_, 'e> {
+        let pins = self.pins.get();
+
+        if pins == 0 {
+            let participant = self.participant();
+
+            loop {
+                let global = self.shared.global.load(Ordering::SeqCst);
+
+                participant.epoch.store(global | PINNED, Ordering::SeqCst);
+
+                // the epoch may have advanced before the pin was visible, in
+                // which case the pin must not be trusted to hold it back
+                if self.shared.global.load(Ordering::SeqCst) == global {
+                    break;
+                }
+            }
+        }
+
+        self.pins.set(pins + 1);
+
+        EpochGuard { handle: self }
+    }
+
+    /// Whether the participant is currently pinned
+    pub fn is_pinned(&self) -> bool {
+        self.pins.get() != 0
+    }
+
+    /// Retire the node at `offset`, which must already be unreachable for
+    /// anyone who pins from now on
+    pub fn retire(&mut self, offset: u64) {
+        self.retired.push((self.shared.epoch(), offset));
+    }
+
+    /// Number of retired nodes awaiting reclamation
+    pub fn pending(&self) -> usize {
+        self.retired.len()
+    }
+
+    /// Try to advance the global epoch, then pass every retired node which no
+    /// participant can still be reading to `free`, returning how many there
+    /// were
+    pub fn collect(&mut self, mut free: impl FnMut(u64)) -> usize {
+        let global = self.shared.try_advance();
+        let before = self.retired.len();
+
+        self.retired.retain(|&(epoch, offset)| {
+            if epoch + 2 <= global {
+                free(offset);
+                false
+            } else {
+                true
+            }
+        });
+
+        before - self.retired.len()
+    }
+}
+
+impl<'e> Drop for EpochHandle<'e> {
+    fn drop(&mut self) {
+        let participant = self.participant();
+
+        participant.epoch.store(0, Ordering::SeqCst);
+        participant.pid.store(0, Ordering::Release);
+    }
+}
+
+/// Keeps an [`EpochHandle`] pinned while alive
+pub struct EpochGuard<'h, 'e> {
+    handle: &'h EpochHandle<'e>,
+}
+
+impl<'h, 'e> Drop for EpochGuard<'h, 'e> {
+    fn drop(&mut self) {
+        let pins = self.handle.pins.get() - 1;
+
+        self.handle.pins.set(pins);
+
+        if pins == 0 {
+            self.handle.participant().epoch.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{MmapMut, ShmEpoch};
+
+    #[test]
+    fn retire_after_grace_period() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        ShmEpoch::init_in(&mut map, 0).unwrap();
+
+        let cell = map.as_cell();
+        let epoch = ShmEpoch::attach(&cell, 0).unwrap();
+
+        let reader = epoch.register().unwrap();
+        let mut writer = epoch.register().unwrap();
+
+        let guard = reader.pin();
+        let nested = reader.pin();
+
+        writer.retire(0x100);
+
+        let mut freed = Vec::new();
+
+        // the reader pinned the epoch the node was retired in, so it may
+        // still hold a reference
+        for _ in 0..4 {
+            writer.collect(|offset| freed.push(offset));
+        }
+
+        assert!(freed.is_empty());
+        assert_eq!(epoch.epoch(), 1);
+
+        drop(nested);
+        assert!(reader.is_pinned());
+        drop(guard);
+
+        assert_eq!(writer.collect(|offset| freed.push(offset)), 1);
+        assert_eq!(freed, [0x100]);
+        assert_eq!(writer.pending(), 0);
+    }
+
+    #[test]
+    fn slots_are_reused() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let epoch = ShmEpoch::init_in(&mut map, 0).unwrap();
+
+        let handles: Vec<_> = (0..64).map(|_| epoch.register().unwrap()).collect();
+
+        assert!(epoch.register().is_err());
+
+        drop(handles);
+
+        assert!(epoch.register().is_ok());
+    }
+}
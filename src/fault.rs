@@ -0,0 +1,308 @@
+use std::{
+    io, mem, ptr,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use crate::{heap_backed_error, is_heap_copy};
+
+/// Maximum number of regions which may be registered at once. The registry is
+/// a fixed-size table so that the signal handler never has to allocate or take
+/// a lock.
+const MAX_REGIONS: usize = 128;
+
+const FREE: u8 = 0;
+const RESERVED: u8 = 1;
+const ACTIVE: u8 = 2;
+
+/// Information about a fault inside a registered region, passed to its
+/// [`GuardCallback`]
+#[derive(Debug)]
+pub struct GuardFault {
+    /// The faulting address
+    pub addr: *mut u8,
+    /// Start of the registered region containing `addr`
+    pub region_start: *mut u8,
+    /// Length of the registered region containing `addr`
+    pub region_len: usize,
+    /// The value passed at registration
+    pub data: usize,
+    /// Address of the faulting instruction, or zero if it is not known on
+    /// this architecture
+    pub pc: usize,
+    /// Frame pointer of the faulting function, or zero if it is not known on
+    /// this architecture
+    pub fp: usize,
+}
+
+/// What to do after a [`GuardCallback`] returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardAction {
+    /// The callback resolved the fault, for example by making the page
+    /// accessible, so the faulting instruction should be retried
+    Retry,
+    /// The fault is fatal and should be handled as if no callback were
+    /// registered
+    Abort,
+}
+
+/// A callback invoked from the `SIGSEGV` handler
+///
+/// Callbacks run in signal context and so must be async-signal-safe: they may
+/// not allocate, take locks, or perform buffered I/O. Calling `mprotect`,
+/// `mmap` or `write(2)` is fine.
+pub type GuardCallback = fn(&GuardFault) -> GuardAction;
+
+struct Slot {
+    state: AtomicU8,
+    start: AtomicUsize,
+    len: AtomicUsize,
+    callback: AtomicUsize,
+    data: AtomicUsize,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(FREE),
+            start: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            callback: AtomicUsize::new(0),
+            data: AtomicUsize::new(0),
+        }
+    }
+}
+
+static SLOTS: [Slot; MAX_REGIONS] = [const { Slot::new() }; MAX_REGIONS];
+
+static PREVIOUS_ACTION: OnceLock<io::Result<libc::sigaction>> = OnceLock::new();
+
+/// Handle to a region registered with [`register_guard`], which is
+/// unregistered on drop
+#[must_use = "the region is unregistered when this is dropped"]
+pub struct GuardRegistration {
+    slot: usize,
+}
+
+impl Drop for GuardRegistration {
+    fn drop(&mut self) {
+        SLOTS[self.slot].state.store(FREE, Ordering::Release);
+    }
+}
+
+/// Invoke `callback` whenever a `SIGSEGV` is raised by an access to the `len`
+/// bytes at `start`
+///
+/// The first registration installs a process-wide `SIGSEGV` handler with
+/// `SA_ONSTACK`. Faults outside of all registered regions, and faults whose
+/// callback returns [`GuardAction::Abort`], are forwarded to whichever handler
+/// was installed before, or the default action if there was none.
+///
+/// Faults caused by overflowing a stack can only be handled on threads which
+/// have an alternate signal stack configured with `sigaltstack(2)`. The Rust
+/// standard library sets one up for threads it spawns.
+///
+/// Heap-backed copies never fault, so registering one fails with
+/// [`Unsupported`](io::ErrorKind::Unsupported).
+pub fn register_guard(
+    start: *mut u8,
+    len: usize,
+    callback: GuardCallback,
+    data: usize,
+) -> io::Result<GuardRegistration> {
+    if is_heap_copy(start) {
+        return Err(heap_backed_error());
+    }
+
+    install_handler()?;
+
+    for (idx, slot) in SLOTS.iter().enumerate() {
+        if slot
+            .state
+            .compare_exchange(FREE, RESERVED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            continue;
+        }
+
+        slot.start.store(start as usize, Ordering::Relaxed);
+        slot.len.store(len, Ordering::Relaxed);
+        slot.callback.store(callback as usize, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+        slot.state.store(ACTIVE, Ordering::Release);
+
+        return Ok(GuardRegistration { slot: idx });
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::OutOfMemory,
+        "too many guard regions registered",
+    ))
+}
+
+fn install_handler() -> io::Result<()> {
+    let previous = PREVIOUS_ACTION.get_or_init(|| unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_sigsegv as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous: libc::sigaction = mem::zeroed();
+
+        if libc::sigaction(libc::SIGSEGV, &action, &mut previous) == 0 {
+            Ok(previous)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    });
+
+    match previous {
+        Ok(..) => Ok(()),
+        Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+    }
+}
+
+extern "C" fn handle_sigsegv(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    let addr = unsafe { (*info).si_addr() } as usize;
+
+    for slot in SLOTS.iter() {
+        if slot.state.load(Ordering::Acquire) != ACTIVE {
+            continue;
+        }
+
+        let start = slot.start.load(Ordering::Relaxed);
+        let len = slot.len.load(Ordering::Relaxed);
+
+        if addr < start || addr - start >= len {
+            continue;
+        }
+
+        let callback: GuardCallback =
+            unsafe { mem::transmute(slot.callback.load(Ordering::Relaxed)) };
+
+        let fault = GuardFault {
+            addr: addr as *mut u8,
+            region_start: start as *mut u8,
+            region_len: len,
+            data: slot.data.load(Ordering::Relaxed),
+            pc: unsafe { fault_pc(ctx) },
+            fp: unsafe { fault_fp(ctx) },
+        };
+
+        match callback(&fault) {
+            GuardAction::Retry => return,
+            GuardAction::Abort => break,
+        }
+    }
+
+    unsafe { forward_signal(sig, info, ctx) };
+}
+
+#[cfg(all(target_arch = "x86_64", target_env = "gnu"))]
+unsafe fn fault_pc(ctx: *mut libc::c_void) -> usize {
+    (*ctx.cast::<libc::ucontext_t>()).uc_mcontext.gregs[libc::REG_RIP as usize] as usize
+}
+
+#[cfg(all(target_arch = "aarch64", target_env = "gnu"))]
+unsafe fn fault_pc(ctx: *mut libc::c_void) -> usize {
+    (*ctx.cast::<libc::ucontext_t>()).uc_mcontext.pc as usize
+}
+
+#[cfg(not(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+)))]
+unsafe fn fault_pc(_ctx: *mut libc::c_void) -> usize {
+    0
+}
+
+#[cfg(all(target_arch = "x86_64", target_env = "gnu"))]
+unsafe fn fault_fp(ctx: *mut libc::c_void) -> usize {
+    (*ctx.cast::<libc::ucontext_t>()).uc_mcontext.gregs[libc::REG_RBP as usize] as usize
+}
+
+#[cfg(all(target_arch = "aarch64", target_env = "gnu"))]
+unsafe fn fault_fp(ctx: *mut libc::c_void) -> usize {
+    (*ctx.cast::<libc::ucontext_t>()).uc_mcontext.regs[29] as usize
+}
+
+#[cfg(not(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+)))]
+unsafe fn fault_fp(_ctx: *mut libc::c_void) -> usize {
+    0
+}
+
+unsafe fn forward_signal(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    if let Some(Ok(previous)) = PREVIOUS_ACTION.get() {
+        let handler = previous.sa_sigaction;
+
+        if handler != libc::SIG_DFL && handler != libc::SIG_IGN {
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                    mem::transmute(handler);
+                handler(sig, info, ctx);
+            } else {
+                let handler: extern "C" fn(libc::c_int) = mem::transmute(handler);
+                handler(sig);
+            }
+
+            return;
+        }
+    }
+
+    // restore the default action; returning re-executes the faulting
+    // instruction, which then terminates the process as usual
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = libc::SIG_DFL;
+    libc::sigaction(sig, &action, ptr::null_mut());
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::{
+        mprotect_raw, page_size, register_guard, GuardAction, GuardFault, MmapOptions, Protection,
+    };
+
+    static FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+    fn unprotect(fault: &GuardFault) -> GuardAction {
+        FAULTS.fetch_add(fault.data, Ordering::SeqCst);
+
+        match unsafe {
+            mprotect_raw(
+                fault.region_start,
+                fault.region_len,
+                Protection::READ | Protection::WRITE,
+            )
+        } {
+            Ok(()) => GuardAction::Retry,
+            Err(..) => GuardAction::Abort,
+        }
+    }
+
+    #[test]
+    fn callback_resolves_fault() {
+        let map = MmapOptions::new(NonZeroUsize::new(page_size()).unwrap())
+            .guard_pages(1)
+            .map_anon_mut()
+            .unwrap();
+
+        let guard = unsafe { map.ptr.sub(page_size()) };
+
+        let _registration = register_guard(guard, page_size(), unprotect, 1).unwrap();
+
+        unsafe { guard.add(10).write_volatile(5) };
+
+        assert_eq!(FAULTS.load(Ordering::SeqCst), 1);
+        assert_eq!(unsafe { guard.add(10).read_volatile() }, 5);
+    }
+}
@@ -0,0 +1,204 @@
+#[cfg(feature = "registry")]
+use std::{collections::BTreeMap, ops::Deref, sync::Mutex};
+use std::{
+    io, mem,
+    ops::{Add, Sub},
+};
+
+use crate::libc_compat;
+
+/// Page faults taken by the current thread
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FaultCounts {
+    /// Faults served without I/O, such as first touches of anonymous memory
+    /// or pages already in the page cache
+    pub minor: u64,
+    /// Faults which had to read the page from disk
+    pub major: u64,
+}
+
+impl FaultCounts {
+    /// Faults taken by the calling thread since it started
+    pub fn current() -> io::Result<Self> {
+        let mut usage = unsafe { mem::zeroed::<libc::rusage>() };
+
+        if unsafe { libc::getrusage(libc_compat::RUSAGE_THREAD, &mut usage) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            minor: usage.ru_minflt as u64,
+            major: usage.ru_majflt as u64,
+        })
+    }
+}
+
+impl Add for FaultCounts {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            minor: self.minor + rhs.minor,
+            major: self.major + rhs.major,
+        }
+    }
+}
+
+impl Sub for FaultCounts {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            minor: self.minor.saturating_sub(rhs.minor),
+            major: self.major.saturating_sub(rhs.major),
+        }
+    }
+}
+
+/// Cumulative page fault counts over a series of measured accesses, useful to
+/// check whether prefaulting a mapping actually avoided faults on the hot
+/// path
+///
+/// Counts come from `getrusage(RUSAGE_THREAD)`, so faults taken by other
+/// threads during a measurement are not included, but any faults of the
+/// measured closure unrelated to the mapping are.
+///
+/// With the `registry` feature, `measure_mapping` also adds the faults to
+/// cumulative counts kept for the mapping itself, which any counter in the
+/// process contributes to and `mapping_faults` reads back.
+#[derive(Debug, Default)]
+pub struct FaultCounter {
+    total: FaultCounts,
+}
+
+impl FaultCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f`, returning its result along with the faults it took, which are
+    /// also added to the running total
+    pub fn measure<R>(&mut self, f: impl FnOnce() -> R) -> io::Result<(R, FaultCounts)> {
+        let before = FaultCounts::current()?;
+        let result = f();
+        let faults = FaultCounts::current()? - before;
+
+        self.total = self.total + faults;
+
+        Ok((result, faults))
+    }
+
+    /// Faults taken across all measurements since creation or the last
+    /// [`reset`](Self::reset)
+    pub fn total(&self) -> FaultCounts {
+        self.total
+    }
+
+    pub fn reset(&mut self) {
+        self.total = FaultCounts::default();
+    }
+
+    /// [`measure`](Self::measure) `f`, which is given `map`, also adding the
+    /// faults to the cumulative counts of `map`
+    #[cfg(feature = "registry")]
+    pub fn measure_mapping<M: Deref<Target = [u8]>, R>(
+        &mut self,
+        map: &mut M,
+        f: impl FnOnce(&mut M) -> R,
+    ) -> io::Result<(R, FaultCounts)> {
+        let addr = map.as_ptr() as usize;
+        let (result, faults) = self.measure(|| f(map))?;
+
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        let total = registry.entry(addr).or_default();
+
+        *total = *total + faults;
+
+        Ok((result, faults))
+    }
+}
+
+/// Cumulative faults of each mapping measured with
+/// [`FaultCounter::measure_mapping`], by the address it starts at
+#[cfg(feature = "registry")]
+static REGISTRY: Mutex<BTreeMap<usize, FaultCounts>> = Mutex::new(BTreeMap::new());
+
+/// Faults taken in every [`FaultCounter::measure_mapping`] of `map` since it
+/// was mapped
+#[cfg(feature = "registry")]
+pub fn mapping_faults(map: &[u8]) -> FaultCounts {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&(map.as_ptr() as usize))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Forget the counts of the mapping at `ptr` as it is unmapped, so that a
+/// later mapping at the same address starts from zero
+#[cfg(feature = "registry")]
+pub(crate) fn unregister(ptr: *const u8) {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&(ptr as usize));
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, FaultCounter, MmapMut};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn counts_first_touch() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(page_size() * 16).unwrap()).unwrap();
+        let mut counter = FaultCounter::new();
+
+        let touch = |map: &mut MmapMut| {
+            for page in 0..16 {
+                map[page * page_size()] = 1;
+            }
+        };
+
+        let ((), first) = counter.measure(|| touch(&mut map)).unwrap();
+        let ((), second) = counter.measure(|| touch(&mut map)).unwrap();
+
+        assert!(first.minor >= 16);
+        assert!(second.minor < first.minor);
+        assert_eq!(counter.total().minor, first.minor + second.minor);
+    }
+
+    #[test]
+    #[cfg(feature = "registry")]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn accumulates_per_mapping() {
+        use crate::mapping_faults;
+
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(page_size() * 16).unwrap()).unwrap();
+        let other = MmapMut::new_anon(NonZeroUsize::new(page_size()).unwrap()).unwrap();
+
+        let ((), first) = FaultCounter::new()
+            .measure_mapping(&mut map, |map| map.fill(1))
+            .unwrap();
+        let ((), second) = FaultCounter::new()
+            .measure_mapping(&mut map, |map| map[0] = 2)
+            .unwrap();
+
+        assert!(first.minor >= 16);
+        assert_eq!(mapping_faults(&map), first + second);
+        assert_eq!(mapping_faults(&other), Default::default());
+
+        let addr = map.as_ptr();
+        drop(map);
+
+        // a new mapping at the same address starts from zero
+        let map = MmapMut::new_anon(NonZeroUsize::new(page_size() * 16).unwrap()).unwrap();
+
+        if map.as_ptr() == addr {
+            assert_eq!(mapping_faults(&map), Default::default());
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use std::ops::{BitOr, Deref};
 
+use crate::libc_compat;
+
 /// Only one of these flags may be present
+#[derive(Clone, Copy)]
 pub(crate) struct UniqueFlag(pub(crate) i32);
 
 #[allow(dead_code)]
@@ -19,7 +22,7 @@ impl UniqueFlag {
     /// flags (e.g., MAP_SYNC).
     ///
     /// (since Linux 4.15)
-    pub(crate) const MAP_SHARED_VALIDATE: Self = Self(libc::MAP_SHARED_VALIDATE);
+    pub(crate) const MAP_SHARED_VALIDATE: Self = Self(libc_compat::MAP_SHARED_VALIDATE);
 
     /// Create a private copy-on-write mapping. Updates to the mapping are not
     /// visible to other processes mapping the same file, and are not carried
@@ -28,7 +31,8 @@ impl UniqueFlag {
     pub(crate) const MAP_PRIVATE: Self = Self(libc::MAP_PRIVATE);
 }
 
-pub(crate) struct Flag(i32);
+#[derive(Clone, Copy)]
+pub(crate) struct Flag(pub(crate) i32);
 
 #[allow(dead_code)]
 impl Flag {
@@ -41,6 +45,7 @@ impl Flag {
     /// is ignored when MAP_FIXED is set.
     ///
     /// (since Linux 2.4.20, 2.6)
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     pub(crate) const MAP_32BIT: Self = Self(libc::MAP_32BIT);
 
     /// The mapping is not backed by any file; its contents are initialized to
@@ -76,7 +81,7 @@ impl Flag {
     /// the requested address.
     ///
     /// (since Linux 4.17)
-    pub(crate) const MAP_FIXED_NOREPLACE: Self = Self(libc::MAP_FIXED_NOREPLACE);
+    pub(crate) const MAP_FIXED_NOREPLACE: Self = Self(libc_compat::MAP_FIXED_NOREPLACE);
 
     /// This flag is used for stacks. It indicates to the kernel virtual memory
     /// system that the mapping should extend downward in memory. The return
@@ -113,8 +118,8 @@ impl Flag {
     /// discovered by listing the subdirectories in /sys/kernel/mm/hugepages.
     ///
     /// (since Linux 3.8)
-    pub(crate) const MAP_HUGE_2MB: Self = Self(libc::MAP_HUGE_2MB);
-    pub(crate) const MAP_HUGE_1GB: Self = Self(libc::MAP_HUGE_1GB);
+    pub(crate) const MAP_HUGE_2MB: Self = Self(libc_compat::MAP_HUGE_2MB);
+    pub(crate) const MAP_HUGE_1GB: Self = Self(libc_compat::MAP_HUGE_1GB);
 
     /// Mark the mapped region to be locked in the same way as mlock(2). This
     /// implementation will try to populate (prefault) the whole range but the
@@ -154,6 +159,16 @@ impl Flag {
     /// (since Linux 2.5.46)
     pub(crate) const MAP_POPULATE: Self = Self(libc::MAP_POPULATE);
 
+    /// Don't clear anonymous pages. This flag is intended to improve performance
+    /// on embedded devices. This flag is honored only if the kernel was
+    /// configured with the CONFIG_MMAP_ALLOW_UNINITIALIZED option. Because of
+    /// the security implications, that option is normally enabled only on
+    /// embedded devices (i.e., devices where one has complete control of the
+    /// contents of user memory).
+    ///
+    /// (since Linux 2.6.33)
+    pub(crate) const MAP_UNINITIALIZED: Self = Self(libc_compat::MAP_UNINITIALIZED);
+
     /// Allocate the mapping at an address suitable for a process or thread stack.
     ///
     /// This flag is currently a no-op on Linux. However, by employing this flag,
@@ -181,7 +196,7 @@ impl Flag {
     /// way of making data modifications persistent.
     ///
     /// (since Linux 4.15)
-    pub(crate) const MAP_SYNC: Self = Self(libc::MAP_SYNC);
+    pub(crate) const MAP_SYNC: Self = Self(libc_compat::MAP_SYNC);
 }
 
 impl Deref for Flag {
@@ -0,0 +1,152 @@
+use std::io;
+
+use crate::{msync_raw, MmapMut};
+
+/// Whether an [`MmapMut`] writes its dirty pages back to the backing file
+/// when dropped, set with
+/// [`MmapOptions::flush_on_drop`](crate::MmapOptions::flush_on_drop)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushOnDrop {
+    /// Wait for the write-back to complete with `MS_SYNC`
+    Sync,
+    /// Start the write-back with `MS_ASYNC` without waiting for it
+    Async,
+    /// Leave write-back to the kernel, which may lose data if the system
+    /// crashes before it gets to it
+    #[default]
+    None,
+}
+
+impl<'a> MmapMut<'a> {
+    /// Write dirty pages back to the backing file, waiting for the writes to
+    /// complete
+    ///
+    /// Does nothing for anonymous mappings.
+    pub fn flush(&self) -> io::Result<()> {
+        self.msync(libc::MS_SYNC)
+    }
+
+    /// Start writing dirty pages back to the backing file without waiting
+    pub fn flush_async(&self) -> io::Result<()> {
+        self.msync(libc::MS_ASYNC)
+    }
+
+    /// Change what happens to dirty pages when the mapping is dropped
+    pub fn set_flush_on_drop(&mut self, policy: FlushOnDrop) {
+        self.flush_on_drop = policy;
+    }
+
+    fn msync(&self, flags: i32) -> io::Result<()> {
+        unsafe { msync_raw(self.ptr, self.len, flags) }
+    }
+
+    /// Apply the flush-on-drop policy, ignoring errors as they can't be
+    /// reported from drop; call `flush` first to see them
+    pub(crate) fn flush_for_drop(&self) {
+        let _ = match self.flush_on_drop {
+            FlushOnDrop::Sync => self.flush(),
+            FlushOnDrop::Async => self.flush_async(),
+            FlushOnDrop::None => Ok(()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        mem::MaybeUninit,
+        num::NonZeroUsize,
+        os::unix::{fs::FileExt, io::AsRawFd},
+    };
+
+    use crate::{soft_dirty::pagemap, test_util::temp_file, FlushOnDrop, MmapMut, MmapOptions};
+
+    const KPF_DIRTY: u64 = 1 << 4;
+    const KPF_WRITEBACK: u64 = 1 << 8;
+
+    /// `/proc/kpageflags` entry of the page cache page holding the start of
+    /// `file`, or `None` if the page frame number is hidden from the process
+    fn page_flags(file: &File) -> Option<u64> {
+        let map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .map_file(file)
+            .unwrap();
+
+        std::hint::black_box(map[0]);
+
+        let pfn = pagemap(map.as_ptr(), 1).unwrap()[0] & ((1 << 55) - 1);
+
+        if pfn == 0 {
+            return None;
+        }
+
+        let mut flags = [0; 8];
+
+        File::open("/proc/kpageflags")
+            .ok()?
+            .read_exact_at(&mut flags, pfn * 8)
+            .ok()?;
+
+        Some(u64::from_ne_bytes(flags))
+    }
+
+    /// Whether `file` lives on tmpfs, whose pages have no backing store to be
+    /// written back to and so stay dirty however they are flushed
+    fn on_tmpfs(file: &File) -> bool {
+        let mut stat = MaybeUninit::<libc::statfs>::uninit();
+
+        assert_eq!(
+            unsafe { libc::fstatfs(file.as_raw_fd(), stat.as_mut_ptr()) },
+            0
+        );
+
+        unsafe { stat.assume_init() }.f_type == libc::TMPFS_MAGIC as _
+    }
+
+    /// Write through a mapping of `file` with `policy`, then drop it
+    fn write_and_drop(file: &File, policy: FlushOnDrop) {
+        let mut map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .flush_on_drop(policy)
+            .map_file_mut(file)
+            .unwrap();
+
+        map[..5].copy_from_slice(b"dirty");
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn flushes_on_drop() {
+        let file = temp_file("flush");
+
+        file.set_len(4096).unwrap();
+
+        if on_tmpfs(&file) {
+            return;
+        }
+
+        write_and_drop(&file, FlushOnDrop::None);
+
+        // without privileges the page cannot be found, leaving only the
+        // contents to check
+        let Some(flags) = page_flags(&file) else {
+            write_and_drop(&file, FlushOnDrop::Sync);
+
+            let mut buf = [0; 5];
+            file.read_exact_at(&mut buf, 0).unwrap();
+
+            assert_eq!(&buf, b"dirty");
+
+            return;
+        };
+
+        assert_ne!(flags & KPF_DIRTY, 0);
+
+        write_and_drop(&file, FlushOnDrop::Sync);
+
+        assert_eq!(page_flags(&file).unwrap() & (KPF_DIRTY | KPF_WRITEBACK), 0);
+
+        let mut anon = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        anon.set_flush_on_drop(FlushOnDrop::Sync);
+        anon.fill(1);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    ops::{Deref, DerefMut},
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr, slice,
+};
+
+use crate::{flag::UniqueFlag, mmap_raw, munmap_raw, Protection};
+
+const FBIOGET_VSCREENINFO: libc::Ioctl = 0x4600;
+const FBIOGET_FSCREENINFO: libc::Ioctl = 0x4602;
+
+#[repr(C)]
+#[derive(Default)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+/// `struct fb_var_screeninfo` from `<linux/fb.h>`
+#[repr(C)]
+#[derive(Default)]
+struct FbVarScreeninfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
+/// `struct fb_fix_screeninfo` from `<linux/fb.h>`
+#[repr(C)]
+#[derive(Default)]
+struct FbFixScreeninfo {
+    id: [u8; 16],
+    smem_start: libc::c_ulong,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: libc::c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// A mapping of the pixel memory of a Linux framebuffer device such as
+/// `/dev/fb0`
+///
+/// Rows are [`stride`](Self::stride) bytes apart, which may be larger than
+/// `width * bytes_per_pixel` due to padding required by the display hardware.
+pub struct Framebuffer {
+    _file: File,
+    ptr: *mut u8,
+    len: usize,
+    width: u32,
+    height: u32,
+    stride: usize,
+    bits_per_pixel: u32,
+}
+
+impl Framebuffer {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let fd = file.as_raw_fd();
+
+        let mut var = FbVarScreeninfo::default();
+        let mut fix = FbFixScreeninfo::default();
+
+        if unsafe { libc::ioctl(fd, FBIOGET_VSCREENINFO, &mut var) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if unsafe { libc::ioctl(fd, FBIOGET_FSCREENINFO, &mut fix) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let len = fix.smem_len as usize;
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "framebuffer reports no video memory",
+            ));
+        }
+
+        let ptr = unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                len,
+                Protection::READ | Protection::WRITE,
+                UniqueFlag::MAP_SHARED.0,
+                fd,
+                0,
+            )?
+        };
+
+        Ok(Self {
+            _file: file,
+            ptr,
+            len,
+            width: var.xres,
+            height: var.yres,
+            stride: fix.line_length as usize,
+            bits_per_pixel: var.bits_per_pixel,
+        })
+    }
+
+    /// Visible width in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Visible height in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Distance in bytes between the start of consecutive rows
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn bits_per_pixel(&self) -> u32 {
+        self.bits_per_pixel
+    }
+
+    /// The bytes of visible row `y`, or `None` if it is out of bounds
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [u8]> {
+        if y >= self.height {
+            return None;
+        }
+
+        let start = y as usize * self.stride;
+        let row_len = (self.width as usize * self.bits_per_pixel as usize).div_ceil(8);
+
+        self.get_mut(start..start + row_len)
+    }
+}
+
+impl Deref for Framebuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for Framebuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FbVarScreeninfo;
+
+    #[test]
+    fn var_screeninfo_matches_kernel_layout() {
+        assert_eq!(std::mem::size_of::<FbVarScreeninfo>(), 160);
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    slice,
+    time::Duration,
+};
+
+use crate::{Pod, ShmAllocator, ShmQueue};
+
+/// The location of a message in a [`FrameChannel`]'s arena, which is what
+/// actually travels through its queue
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    offset: u64,
+    len: u64,
+}
+
+unsafe impl Pod for Frame {}
+
+/// Variable-length messages passed between processes without copying them
+/// through a ring
+///
+/// A producer allocates a buffer from a shared [`ShmAllocator`] arena, fills it
+/// in place, and sends it, which only pushes its offset and length onto a
+/// shared [`ShmQueue`]. The consumer reads the message straight out of the
+/// arena and returns the buffer to it when done.
+///
+/// Any number of producers and consumers may share a channel, each attaching
+/// to the same two segments.
+pub struct FrameChannel<'m> {
+    arena: ShmAllocator<'m>,
+    queue: ShmQueue<'m, Frame>,
+}
+
+impl<'m> FrameChannel<'m> {
+    pub fn new(arena: ShmAllocator<'m>, queue: ShmQueue<'m, Frame>) -> Self {
+        Self { arena, queue }
+    }
+
+    /// Allocate a `len` byte message buffer, or `None` if the arena has no
+    /// room for it
+    ///
+    /// Dropping the buffer without sending it returns it to the arena.
+    pub fn alloc(&self, len: usize) -> io::Result<Option<OutgoingFrame<'_, 'm>>> {
+        let Some(offset) = self.arena.alloc(len)? else {
+            return Ok(None);
+        };
+
+        let frame = Frame {
+            offset,
+            len: len as u64,
+        };
+
+        Ok(Some(OutgoingFrame {
+            channel: self,
+            frame,
+            ptr: self.arena.allocation(frame.offset, frame.len)?,
+        }))
+    }
+
+    /// Receive the oldest message if there is one
+    ///
+    /// A message whose buffer does not lie in the arena fails with
+    /// [`InvalidData`](io::ErrorKind::InvalidData), returning the buffer to the
+    /// arena if it is a valid allocation.
+    pub fn try_recv(&self) -> io::Result<Option<IncomingFrame<'_, 'm>>> {
+        self.queue
+            .try_pop()
+            .map(|frame| self.incoming(frame))
+            .transpose()
+    }
+
+    /// Receive the oldest message, sleeping while there is none for at most
+    /// `timeout`, or indefinitely if it is `None`
+    pub fn recv(&self, timeout: Option<Duration>) -> io::Result<Option<IncomingFrame<'_, 'm>>> {
+        self.queue
+            .pop(timeout)?
+            .map(|frame| self.incoming(frame))
+            .transpose()
+    }
+
+    fn incoming(&self, frame: Frame) -> io::Result<IncomingFrame<'_, 'm>> {
+        // the frame came from another process, so is checked before use
+        match self.arena.allocation(frame.offset, frame.len) {
+            Ok(ptr) => Ok(IncomingFrame {
+                channel: self,
+                frame,
+                ptr,
+            }),
+            Err(err) => {
+                // a frame whose length overruns its buffer still holds one,
+                // which `free` checks in turn
+                let _ = self.arena.free(frame.offset);
+                Err(io::Error::new(io::ErrorKind::InvalidData, err))
+            }
+        }
+    }
+}
+
+/// A message buffer being filled by a producer
+pub struct OutgoingFrame<'c, 'm> {
+    channel: &'c FrameChannel<'m>,
+    frame: Frame,
+    ptr: *mut u8,
+}
+
+impl<'c, 'm> OutgoingFrame<'c, 'm> {
+    /// Send the message if the queue has room, handing the buffer back if it
+    /// does not
+    pub fn try_send(self) -> Result<(), Self> {
+        if self.channel.queue.try_push(self.frame) {
+            std::mem::forget(self);
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Send the message, sleeping while the queue is full
+    pub fn send(self) -> io::Result<()> {
+        self.channel.queue.push(self.frame, None)?;
+        std::mem::forget(self);
+        Ok(())
+    }
+}
+
+impl<'c, 'm> Deref for OutgoingFrame<'c, 'm> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.frame.len as usize) }
+    }
+}
+
+impl<'c, 'm> DerefMut for OutgoingFrame<'c, 'm> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.frame.len as usize) }
+    }
+}
+
+impl<'c, 'm> Drop for OutgoingFrame<'c, 'm> {
+    fn drop(&mut self) {
+        let _ = self.channel.arena.free(self.frame.offset);
+    }
+}
+
+/// A received message, which is returned to the arena on drop
+pub struct IncomingFrame<'c, 'm> {
+    channel: &'c FrameChannel<'m>,
+    frame: Frame,
+    ptr: *mut u8,
+}
+
+impl<'c, 'm> Deref for IncomingFrame<'c, 'm> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.frame.len as usize) }
+    }
+}
+
+impl<'c, 'm> Drop for IncomingFrame<'c, 'm> {
+    fn drop(&mut self) {
+        let _ = self.channel.arena.free(self.frame.offset);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, mem, num::NonZeroUsize, thread};
+
+    use crate::{Frame, FrameChannel, MmapMut, ShmAllocator, ShmQueue};
+
+    #[test]
+    fn send_in_place() {
+        let mut arena = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        let mut queue = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let arena = ShmAllocator::init(&mut arena).unwrap();
+        let empty = arena.free_bytes().unwrap();
+
+        let channel = FrameChannel::new(arena, ShmQueue::init(&mut queue).unwrap());
+
+        assert!(channel.try_recv().unwrap().is_none());
+        assert!(channel.alloc(8192).unwrap().is_none());
+
+        drop(channel.alloc(100).unwrap().unwrap());
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for len in 1..50 {
+                    let mut frame = loop {
+                        if let Some(frame) = channel.alloc(len).unwrap() {
+                            break frame;
+                        }
+
+                        thread::yield_now();
+                    };
+
+                    frame.fill(len as u8);
+                    frame.send().unwrap();
+                }
+            });
+
+            for len in 1..50 {
+                let frame = channel.recv(None).unwrap().unwrap();
+
+                assert_eq!(*frame, vec![len as u8; len]);
+            }
+        });
+
+        assert_eq!(channel.arena.free_bytes().unwrap(), empty);
+    }
+
+    #[test]
+    fn rejects_hostile_frames() {
+        let mut arena = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        let mut queue = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let arena = ShmAllocator::init(&mut arena).unwrap();
+        let empty = arena.free_bytes().unwrap();
+
+        let channel = FrameChannel::new(arena, ShmQueue::init(&mut queue).unwrap());
+
+        // a real buffer, but a length reaching past it
+        let frame = channel.alloc(16).unwrap().unwrap();
+        let offset = frame.frame.offset;
+        mem::forget(frame);
+
+        assert!(channel.queue.try_push(Frame { offset, len: 4000 }));
+        assert!(channel.queue.try_push(Frame {
+            offset: 8192,
+            len: 1
+        }));
+
+        for _ in 0..2 {
+            let err = channel.try_recv().err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        // the overrunning frame's buffer went back to the arena
+        assert_eq!(channel.arena.free_bytes().unwrap(), empty);
+    }
+}
@@ -0,0 +1,112 @@
+//! Futex operations on words inside shared mappings
+//!
+//! These are the building blocks for blocking cross-process synchronization:
+//! a waiter sleeps in the kernel until the word changes, and a waker wakes
+//! sleepers after changing it. The shared (non-`FUTEX_PRIVATE_FLAG`)
+//! operations are used, so waiters and wakers may be in different processes
+//! mapping the same memory, even at different addresses.
+//!
+//! Wake-ups may be spurious, so waiters must always recheck the word in a
+//! loop.
+
+use std::{io, ptr, sync::atomic::AtomicU32, time::Duration};
+
+/// Sleep until woken by [`futex_wake`], as long as `addr` still holds
+/// `expected`, or until `timeout` elapses
+///
+/// Returns `false` if the timeout elapsed and `true` otherwise, including
+/// when `addr` no longer held `expected` and the call returned immediately,
+/// or the sleep was interrupted by a signal.
+pub fn futex_wait(addr: &AtomicU32, expected: u32, timeout: Option<Duration>) -> io::Result<bool> {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        // clamped to fit a 32-bit time_t, which still allows 68 years
+        tv_sec: timeout.as_secs().min(i32::MAX as u64) as _,
+        tv_nsec: timeout.subsec_nanos() as _,
+    });
+
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            addr.as_ptr(),
+            libc::FUTEX_WAIT,
+            expected,
+            timeout
+                .as_ref()
+                .map_or(ptr::null(), |timeout| timeout as *const libc::timespec),
+        )
+    };
+
+    if res == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+
+    match err.raw_os_error() {
+        Some(libc::ETIMEDOUT) => Ok(false),
+        Some(libc::EAGAIN | libc::EINTR) => Ok(true),
+        _ => Err(err),
+    }
+}
+
+/// Wake up to `count` waiters sleeping on `addr`, returning how many were
+/// woken
+pub fn futex_wake(addr: &AtomicU32, count: u32) -> io::Result<usize> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            addr.as_ptr(),
+            libc::FUTEX_WAKE,
+            count.min(i32::MAX as u32),
+        )
+    };
+
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+/// Wake every waiter sleeping on `addr`, returning how many were woken
+pub fn futex_wake_all(addr: &AtomicU32) -> io::Result<usize> {
+    futex_wake(addr, u32::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicU32, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        futex::{futex_wait, futex_wake, futex_wake_all},
+        MmapMut,
+    };
+
+    #[test]
+    fn wait_and_wake() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        let word = &map.as_atomics::<AtomicU32>(0..4).unwrap()[0];
+
+        assert!(!futex_wait(word, 0, Some(Duration::from_millis(1))).unwrap());
+        assert!(futex_wait(word, 1, None).unwrap());
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                while word.load(Ordering::Acquire) == 0 {
+                    futex_wait(word, 0, None).unwrap();
+                }
+            });
+
+            word.store(1, Ordering::Release);
+
+            futex_wake_all(word).unwrap();
+        });
+
+        assert_eq!(futex_wake(word, 1).unwrap(), 0);
+    }
+}
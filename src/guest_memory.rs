@@ -0,0 +1,262 @@
+use std::{
+    fs::File,
+    io,
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut, RangeBounds},
+    os::unix::io::AsRawFd,
+};
+
+use crate::{madvise_raw, page_aligned_span, page_size, remove, MmapMut, MmapOptions};
+
+/// Memory laid out to be registered with KVM as the memory of a guest
+///
+/// [`new`](Self::new) maps it with the [`MmapOptions::guest_memory`]
+/// preset: page-sized, huge page aligned when large enough, and excluded
+/// from forks. Start from the preset and add
+/// [`huge_pages`](MmapOptions::huge_pages) or
+/// [`populate`](MmapOptions::populate) to back the guest with huge pages or
+/// fault it in up front, then pass the options to
+/// [`with_options`](Self::with_options).
+///
+/// Memory shared with another process, such as a vhost-user backend, is
+/// instead mapped from a memfd or hugetlbfs file with
+/// [`from_file`](Self::from_file).
+///
+/// [`host_region`](Self::host_region) gives the `userspace_addr` and
+/// `memory_size` fields of `struct kvm_userspace_memory_region`.
+pub struct GuestMemory {
+    map: MmapMut<'static>,
+    backing: GuestBacking,
+}
+
+/// What the guest memory is mapped from, which decides how pages are
+/// discarded
+enum GuestBacking {
+    PrivateAnon,
+    /// Shared anonymous memory lives in an internal tmpfs file
+    SharedAnon,
+    File(File),
+}
+
+impl GuestMemory {
+    /// Map zeroed memory for a guest of at least `size` bytes
+    pub fn new(size: NonZeroUsize) -> io::Result<Self> {
+        Self::with_options(&MmapOptions::guest_memory(size.get())?)
+    }
+
+    /// Map guest memory with `options`, usually the
+    /// [`MmapOptions::guest_memory`] preset with additions
+    ///
+    /// Fails if the length is not a multiple of the page size, which KVM
+    /// requires.
+    pub fn with_options(options: &MmapOptions) -> io::Result<Self> {
+        let map = options.map_anon_mut()?;
+
+        if !map.len().is_multiple_of(page_size()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "guest memory size is not a multiple of the page size",
+            ));
+        }
+
+        let backing = if options.is_private() {
+            GuestBacking::PrivateAnon
+        } else {
+            GuestBacking::SharedAnon
+        };
+
+        Ok(Self { map, backing })
+    }
+
+    /// Map the whole of `file`, such as a memfd or a file on hugetlbfs, as
+    /// shared guest memory
+    ///
+    /// The file's length must be a non-zero multiple of the page size.
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+
+        let len = usize::try_from(len)
+            .ok()
+            .and_then(NonZeroUsize::new)
+            .filter(|len| len.get().is_multiple_of(page_size()))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "guest memory file is empty or not a multiple of the page size",
+                )
+            })?;
+
+        let map = MmapOptions::new(len).map_file_mut(&file)?;
+
+        unsafe { madvise_raw(map.ptr, map.len, libc::MADV_DONTFORK)? };
+
+        Ok(Self {
+            map,
+            backing: GuestBacking::File(file),
+        })
+    }
+
+    /// Address of the memory in this process, as `userspace_addr`
+    pub fn host_addr(&self) -> u64 {
+        self.map.as_ptr() as u64
+    }
+
+    /// Size of the memory in bytes, as `memory_size`
+    pub fn size(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    /// The host address and size of the memory, in the order
+    /// `struct kvm_userspace_memory_region` has them
+    pub fn host_region(&self) -> (u64, u64) {
+        (self.host_addr(), self.size())
+    }
+
+    /// Return the memory backing `range`, an offset range into the guest
+    /// memory such as the pages a balloon device reports, to the host
+    ///
+    /// The range reads as zero afterwards. Private anonymous memory is
+    /// discarded with `MADV_DONTNEED`, shared anonymous memory with
+    /// `MADV_REMOVE`, and file-backed memory by punching a hole in the file,
+    /// which also frees memfd, tmpfs and hugetlbfs blocks. The range must be
+    /// page aligned, or huge page aligned for hugetlbfs.
+    pub fn discard_range(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let (ptr, len) = page_aligned_span(self.map.ptr, self.map.len, range)?;
+
+        if len == 0 {
+            return Ok(());
+        }
+
+        match &self.backing {
+            GuestBacking::PrivateAnon => unsafe { madvise_raw(ptr, len, libc::MADV_DONTNEED) },
+            GuestBacking::SharedAnon => remove::remove_range(ptr, len, ..),
+            GuestBacking::File(file) => {
+                let offset = (ptr as usize - self.map.ptr as usize) as libc::off64_t;
+
+                let ret = unsafe {
+                    libc::fallocate64(
+                        file.as_raw_fd(),
+                        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                        offset,
+                        len as libc::off64_t,
+                    )
+                };
+
+                if ret == 0 {
+                    Ok(())
+                } else {
+                    let e = io::Error::last_os_error();
+
+                    Err(match e.raw_os_error() {
+                        Some(libc::EOPNOTSUPP) => io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            "the guest memory file does not support punching holes",
+                        ),
+                        _ => e,
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl Deref for GuestMemory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl DerefMut for GuestMemory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io, num::NonZeroUsize, os::unix::io::FromRawFd};
+
+    use crate::{page_size, GuestMemory, MmapOptions};
+
+    /// The `VmFlags` of the mapping starting at `addr`
+    fn vm_flags(addr: u64) -> String {
+        let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let header = format!("{:x}-", addr);
+
+        smaps
+            .lines()
+            .skip_while(|line| !line.starts_with(&header))
+            .find_map(|line| line.strip_prefix("VmFlags:"))
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn region_for_kvm() {
+        assert_eq!(
+            GuestMemory::new(NonZeroUsize::MAX).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let mut memory = GuestMemory::new(NonZeroUsize::new(3 * page_size() + 1).unwrap()).unwrap();
+
+        let (addr, size) = memory.host_region();
+
+        assert_eq!(addr, memory.as_ptr() as u64);
+        assert_eq!(size, 4 * page_size() as u64);
+        assert!(addr.is_multiple_of(page_size() as u64));
+        assert!(vm_flags(addr).split_whitespace().any(|flag| flag == "dc"));
+
+        memory[size as usize - 1] = 1;
+        memory.discard_range(page_size()..).unwrap();
+
+        assert_eq!(memory[size as usize - 1], 0);
+        assert!(memory.discard_range(1..page_size()).is_err());
+
+        let memory =
+            GuestMemory::with_options(MmapOptions::guest_memory(4 << 20).unwrap().populate())
+                .unwrap();
+
+        assert_eq!(memory.size(), 4 << 20);
+
+        assert!(
+            GuestMemory::with_options(&MmapOptions::new(NonZeroUsize::new(100).unwrap())).is_err()
+        );
+    }
+
+    #[test]
+    fn discards_shared_memory() {
+        let page = page_size();
+
+        let mut shared = GuestMemory::with_options(
+            MmapOptions::new(NonZeroUsize::new(2 * page).unwrap()).dont_fork(),
+        )
+        .unwrap();
+
+        shared.fill(1);
+        shared.discard_range(..page).unwrap();
+
+        assert_eq!(shared[page - 1], 0);
+        assert_eq!(shared[page], 1);
+
+        let fd = unsafe { libc::memfd_create(c"mmap-guest".as_ptr(), libc::MFD_CLOEXEC) };
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        file.set_len(2 * page as u64).unwrap();
+
+        let mut memfd = GuestMemory::from_file(file.try_clone().unwrap()).unwrap();
+
+        memfd.fill(1);
+
+        memfd.discard_range(page..).unwrap();
+
+        assert_eq!(memfd[page], 0);
+        assert_eq!(memfd[page - 1], 1);
+
+        file.set_len(page as u64 + 1).unwrap();
+
+        assert!(GuestMemory::from_file(file).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+/// Evaluate `$call`, a system call wrapper returning an `io::Result`, inside a
+/// `tracing` span named `$name` with the address and length it operates on,
+/// then emit an event with how long it took and either the error or, for
+/// calls which return the address of a mapping, where it was placed
+///
+/// Expands to just `$call` without the `tracing` feature.
+macro_rules! traced {
+    ($name:literal, $addr:expr, $len:expr, $call:expr) => {
+        $crate::instrument::traced!(@inner $name, $addr, $len, $call, |_| ())
+    };
+    ($name:literal, $addr:expr, $len:expr, $call:expr => mapped) => {
+        $crate::instrument::traced!(@inner $name, $addr, $len, $call, |ptr: &*mut u8| {
+            ::tracing::Span::current().record("mapped", *ptr as usize);
+        })
+    };
+    (@inner $name:literal, $addr:expr, $len:expr, $call:expr, $on_ok:expr) => {{
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!(
+            $name,
+            addr = $addr as usize,
+            len = $len,
+            mapped = ::tracing::field::Empty,
+        )
+        .entered();
+        #[cfg(feature = "tracing")]
+        let start = ::std::time::Instant::now();
+
+        let result = $call;
+
+        #[cfg(feature = "tracing")]
+        {
+            let duration_us = start.elapsed().as_micros() as u64;
+
+            match &result {
+                Ok(value) => {
+                    ($on_ok)(value);
+                    ::tracing::debug!(duration_us, "completed");
+                }
+                Err(error) => ::tracing::debug!(duration_us, %error, "failed"),
+            }
+        }
+
+        result
+    }};
+}
+
+pub(crate) use traced;
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::{num::NonZeroUsize, sync::Mutex};
+
+    use tracing::{
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use crate::MmapMut;
+
+    /// Names of the spans opened on the test thread
+    static SPANS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+    struct Recorder;
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = SPANS.lock().unwrap();
+            spans.push(span.metadata().name());
+
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn traces_mapping_lifecycle() {
+        tracing::subscriber::with_default(Recorder, || {
+            let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+            map[0] = 1;
+            map.flush().unwrap();
+        });
+
+        assert_eq!(*SPANS.lock().unwrap(), ["mmap", "msync", "munmap"]);
+    }
+}
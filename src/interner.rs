@@ -0,0 +1,252 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fs::File,
+    hash::BuildHasher,
+    io,
+    os::unix::{fs::FileExt, prelude::MetadataExt},
+};
+
+use crate::Mmap;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"MMAPSTR\0");
+
+/// Magic number and the length of the arena in use
+const HEADER_LEN: u64 = 16;
+
+/// Length prefix of each blob
+const LEN_LEN: usize = 4;
+
+/// Smallest amount the file grows by at once
+const MIN_GROWTH: u64 = 64 * 1024;
+
+/// An append-only arena of byte strings in a file, each stored once and
+/// identified by a stable handle
+///
+/// [`intern`](Self::intern) returns the handle of an equal blob if there is
+/// one, and otherwise appends the blob; handles are offsets into the file, so
+/// they remain valid when the file is reopened and can be stored in other
+/// files. Resolving a handle with [`get`](Self::get) or
+/// [`get_str`](Self::get_str) borrows the blob straight from a mapping of the
+/// file.
+///
+/// Blobs are written with `pwrite(2)` and the file is preallocated in growing
+/// chunks, so the mapping only has to be extended occasionally. The table
+/// used to find existing blobs lives on the heap and is rebuilt by
+/// [`open`](Self::open), which reads every blob once.
+pub struct MmapInterner {
+    file: File,
+    map: Mmap<'static>,
+    /// Bytes of the file in use, including the header
+    len: u64,
+    count: usize,
+    /// Handles of the blobs with each hash
+    table: HashMap<u64, Vec<u64>>,
+    hasher: RandomState,
+}
+
+impl MmapInterner {
+    /// Format `file`, which must be readable and writable, as an empty
+    /// arena, discarding its contents
+    pub fn create(file: File) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN as usize];
+
+        header[..8].copy_from_slice(&MAGIC.to_le_bytes());
+        header[8..].copy_from_slice(&HEADER_LEN.to_le_bytes());
+
+        file.set_len(0)?;
+        file.set_len(MIN_GROWTH)?;
+        file.write_all_at(&header, 0)?;
+
+        Self::open(file)
+    }
+
+    /// Open an arena previously created with [`create`](Self::create), which
+    /// must be readable, and writable to intern new blobs
+    pub fn open(file: File) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut header = [0; HEADER_LEN as usize];
+        file.read_exact_at(&mut header, 0)?;
+
+        if u64::from_le_bytes(header[..8].try_into().unwrap()) != MAGIC {
+            return Err(invalid("file is not an interner arena"));
+        }
+
+        let len = u64::from_le_bytes(header[8..].try_into().unwrap());
+
+        if len < HEADER_LEN || len > file.metadata()?.size() {
+            return Err(invalid("interner arena is truncated"));
+        }
+
+        let mut interner = Self {
+            map: Mmap::new_file(&file)?,
+            file,
+            len: HEADER_LEN,
+            count: 0,
+            table: HashMap::new(),
+            hasher: RandomState::new(),
+        };
+
+        while interner.len < len {
+            let handle = interner.len;
+            let (hash, blob_len) = interner
+                .blob_at(handle as usize)
+                .filter(|blob| handle + (LEN_LEN + blob.len()) as u64 <= len)
+                .map(|blob| (interner.hasher.hash_one(blob), blob.len()))
+                .ok_or_else(|| invalid("interner arena is corrupt"))?;
+
+            interner.table.entry(hash).or_default().push(handle);
+            interner.len += (LEN_LEN + blob_len) as u64;
+            interner.count += 1;
+        }
+
+        Ok(interner)
+    }
+
+    /// Number of distinct blobs in the arena
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The handle of `blob`, appending it to the arena if it is not there yet
+    pub fn intern(&mut self, blob: &[u8]) -> io::Result<u64> {
+        let hash = self.hasher.hash_one(blob);
+
+        if let Some(handles) = self.table.get(&hash) {
+            if let Some(&handle) = handles
+                .iter()
+                .find(|&&handle| self.get(handle) == Some(blob))
+            {
+                return Ok(handle);
+            }
+        }
+
+        let blob_len = u32::try_from(blob.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "blob is too long"))?;
+
+        let handle = self.len;
+        let end = handle + (LEN_LEN + blob.len()) as u64;
+
+        self.reserve(end)?;
+
+        let mut record = Vec::with_capacity(LEN_LEN + blob.len());
+
+        record.extend_from_slice(&blob_len.to_le_bytes());
+        record.extend_from_slice(blob);
+
+        self.file.write_all_at(&record, handle)?;
+        // publish the blob only once it is complete
+        self.file.write_all_at(&end.to_le_bytes(), 8)?;
+
+        // heap-backed mappings are copies, which refreshing retakes to see
+        // the writes
+        self.map.refresh(&self.file)?;
+
+        self.len = end;
+        self.count += 1;
+        self.table.entry(hash).or_default().push(handle);
+
+        Ok(handle)
+    }
+
+    /// Intern `string`, which can then be resolved with
+    /// [`get_str`](Self::get_str)
+    pub fn intern_str(&mut self, string: &str) -> io::Result<u64> {
+        self.intern(string.as_bytes())
+    }
+
+    /// Grow the file and the mapping to hold at least `end` bytes
+    fn reserve(&mut self, end: u64) -> io::Result<()> {
+        if end <= self.map.len() as u64 {
+            return Ok(());
+        }
+
+        let size = end.max(self.map.len() as u64 * 2).max(MIN_GROWTH);
+
+        self.file.set_len(size)?;
+
+        self.map.refresh(&self.file)?;
+
+        Ok(())
+    }
+
+    /// The blob with `handle`, or `None` if the handle is out of bounds
+    ///
+    /// A handle which this arena did not return resolves to arbitrary bytes
+    /// of the arena.
+    pub fn get(&self, handle: u64) -> Option<&[u8]> {
+        if handle < HEADER_LEN || handle >= self.len {
+            return None;
+        }
+
+        self.blob_at(handle as usize)
+    }
+
+    /// The blob starting at `offset`, if it lies within the mapping
+    fn blob_at(&self, offset: usize) -> Option<&[u8]> {
+        let len = self.map.get(offset..offset.checked_add(LEN_LEN)?)?;
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let start = offset + LEN_LEN;
+
+        self.map.get(start..start.checked_add(len)?)
+    }
+
+    /// The string with `handle`, or `None` if the handle is out of bounds or
+    /// the blob is not UTF-8
+    pub fn get_str(&self, handle: u64) -> Option<&str> {
+        std::str::from_utf8(self.get(handle)?).ok()
+    }
+
+    /// Wait for every interned blob to reach the disk
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        test_util::{open_file, temp_path},
+        MmapInterner,
+    };
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn handles_survive_reopening() {
+        let path = temp_path("interner");
+
+        let mut interner = MmapInterner::create(open_file(&path)).unwrap();
+
+        let hello = interner.intern_str("hello").unwrap();
+        let world = interner.intern_str("world").unwrap();
+
+        assert_eq!(interner.intern_str("hello").unwrap(), hello);
+        assert_ne!(hello, world);
+
+        // enough to grow the file a few times
+        let big: Vec<u64> = (0..20_000)
+            .map(|i| interner.intern_str(&format!("symbol-{i}")).unwrap())
+            .collect();
+
+        assert_eq!(interner.len(), 20_002);
+        assert_eq!(interner.get_str(hello), Some("hello"));
+        assert_eq!(interner.get(0), None);
+
+        interner.sync().unwrap();
+        drop(interner);
+
+        let mut interner = MmapInterner::open(open_file(&path)).unwrap();
+
+        assert_eq!(interner.len(), 20_002);
+        assert_eq!(interner.get_str(world), Some("world"));
+        assert_eq!(interner.get_str(big[12_345]), Some("symbol-12345"));
+        assert_eq!(interner.intern_str("symbol-7").unwrap(), big[7]);
+        assert_eq!(interner.len(), 20_002);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,189 +1,825 @@
-#![cfg(target_os = "linux")]
-
-use std::{
-    fs::File,
-    io,
-    marker::PhantomData,
-    num::NonZeroUsize,
-    ops::{BitOr, Deref, DerefMut},
-    os::unix::{io::AsRawFd, prelude::MetadataExt},
-};
-
-use flag::{Flag, UniqueFlag};
-
-mod flag;
-
-fn mmap_anon(size: NonZeroUsize, prot: Protection) -> io::Result<*mut u8> {
-    let ptr = unsafe {
-        libc::mmap(
-            std::ptr::null_mut(),
-            size.get(),
-            prot.0,
-            (UniqueFlag::MAP_SHARED | Flag::MAP_ANONYMOUS).0,
-            -1,
-            0,
-        )
+#![cfg(any(target_os = "linux", target_os = "android"))]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// most of these are only used by the std-gated modules
+#[cfg_attr(not(feature = "std"), allow(dead_code, unused_imports))]
+mod libc_compat;
+pub mod sys;
+
+/// Everything which needs the standard library, which is all but [`sys`],
+/// gated once here and re-exported from the crate root
+#[cfg(feature = "std")]
+#[path = "."]
+mod std_api {
+    use std::{
+        fs::File,
+        io,
+        marker::PhantomData,
+        mem::ManuallyDrop,
+        num::NonZeroUsize,
+        ops::{BitOr, Bound, Deref, DerefMut, Range, RangeBounds},
+        os::unix::{io::AsRawFd, prelude::MetadataExt},
+    };
+
+    use flag::{Flag, UniqueFlag};
+    use instrument::traced;
+    use lock::FileLock;
+
+    pub use address_plan::AddressPlan;
+    pub use address_space::AddressSpace;
+    pub use arena_set::{Arena, ArenaSet};
+    #[cfg(feature = "arrow")]
+    pub use arrow::{ArrowFile, ArrowMessage};
+    pub use atomic::Atomic;
+    pub use bitset::MmapBitSet;
+    pub use capabilities::{capabilities, Capabilities};
+    pub use cell::MmapCell;
+    pub use checksummed::ChecksummedMmap;
+    pub use composite::CompositeMmap;
+    pub use compressed::{CompressedMmap, FrameDecoder};
+    #[cfg(target_os = "linux")]
+    pub use condvar::ShmCondvar;
+    pub use dax::DaxMmap;
+    pub use dedupe::{dedupe_report, DedupeReport};
+    pub use device::DeviceMmap;
+    pub use diff::{diff_pages, diff_resident_pages, PageRange};
+    pub use dmabuf::{DmaBufMmap, DmaBufReadGuard, DmaBufWriteGuard};
+    pub use double_buffer::DoubleBuffered;
+    pub use encrypted::{EncryptedMmap, PageCipher};
+    pub use epoch::{EpochGuard, EpochHandle, ShmEpoch};
+    pub use fault::{register_guard, GuardAction, GuardCallback, GuardFault, GuardRegistration};
+    #[cfg(feature = "registry")]
+    pub use fault_counter::mapping_faults;
+    pub use fault_counter::{FaultCounter, FaultCounts};
+    pub use flush::FlushOnDrop;
+    pub use framebuffer::Framebuffer;
+    pub use frames::{Frame, FrameChannel, IncomingFrame, OutgoingFrame};
+    pub use guest_memory::GuestMemory;
+    pub use interner::MmapInterner;
+    pub use linear_memory::{LinearMemory, WASM_PAGE_SIZE};
+    pub use lock::{LockKind, RangeLockGuard};
+    pub use lock_all::{lock_all_memory, LockAllFlags, LockAllGuard};
+    pub use log::{LogReader, LogWriter};
+    pub use matrix::MatrixView;
+    pub use memfd::{MemfdAlias, MemfdMmap, SealedMmap};
+    pub use notified::NotifiedMmap;
+    pub use options::MmapOptions;
+    pub use pidfd::pidfd_open;
+    pub use pod::Pod;
+    pub use prefault::PrefaultStats;
+    pub use pressure::{
+        MemoryPressure, PressureAverages, PressureCallback, PressureMonitor, Reclaim, Stall,
     };
+    pub use publish::Durability;
+    pub use queue::ShmQueue;
+    pub use raw::MmapRaw;
+    pub use register::{Access, Register, RegisterBlock};
+    pub use rel_ptr::RelPtr;
+    #[cfg(target_os = "linux")]
+    pub use robust_mutex::{RobustShmMutex, RobustShmMutexGuard};
+    pub use rt_buffer::RtBuffer;
+    pub use sealed_buffer::SealedBuffer;
+    pub use segment::{IncompatibleLayout, Segment, SegmentLayout};
+    pub use semaphore::ShmSemaphore;
+    pub use seqlock::SeqLock;
+    pub use shm_alloc::ShmAllocator;
+    #[cfg(target_os = "linux")]
+    pub use shm_segment::ShmSegment;
+    pub use shm_vec::ShmVec;
+    pub use slab::{ShmSlab, SlabCache};
+    pub use snapshot::SnapshotChild;
+    pub use soft_dirty::DirtyToken;
+    pub use sort::{external_sort, SortedRecords};
+    pub use sorted_index::{SortedIndex, SortedIndexBuilder};
+    pub use stack::ThreadStack;
+    pub use stack_pool::{PooledStack, StackPool};
+    pub use thunk::{Thunk, ThunkAllocator};
+    pub use topic::ShmTopic;
+    #[cfg(debug_assertions)]
+    pub use traced::{TracedAccess, TracedMmap};
+    pub use truncation::{Truncated, TruncationSafeMmap};
+    pub use uio::UioMmap;
+    pub use verity::{VerityAlgorithm, VerityDigest};
+    pub use watch::FileWatcher;
+    pub use watchdog::{DeadPeer, PeerTable, Watchdog};
+    pub use windowed::WindowedMmap;
+    pub use write_tracker::WriteTracker;
+
+    mod address_plan;
+    mod address_space;
+    mod arena_set;
+    #[cfg(feature = "arrow")]
+    mod arrow;
+    mod atomic;
+    mod bitset;
+    mod capabilities;
+    mod cell;
+    pub(crate) mod checksummed;
+    mod collapse;
+    mod commit;
+    pub mod compat;
+    mod composite;
+    mod compressed;
+    #[cfg(target_os = "linux")]
+    mod condvar;
+    mod dax;
+    mod dedupe;
+    mod device;
+    mod diff;
+    mod dmabuf;
+    mod double_buffer;
+    mod encrypted;
+    mod epoch;
+    mod fault;
+    mod fault_counter;
+    pub(crate) mod flag;
+    mod flush;
+    mod framebuffer;
+    mod frames;
+    pub mod futex;
+    mod guest_memory;
+    pub(crate) mod instrument;
+    mod interner;
+    mod linear_memory;
+    pub(crate) mod lock;
+    mod lock_all;
+    mod log;
+    pub mod maps;
+    mod matrix;
+    mod memfd;
+    mod notified;
+    pub(crate) mod options;
+    pub(crate) mod pidfd;
+    pub mod pmem;
+    mod pod;
+    mod populate;
+    pub(crate) mod prefault;
+    mod pressure;
+    mod protect;
+    mod publish;
+    mod queue;
+    mod raw;
+    mod refresh;
+    mod register;
+    mod rel_ptr;
+    mod remap;
+    pub mod remote;
+    pub(crate) mod remove;
+    #[cfg(target_os = "linux")]
+    mod robust_mutex;
+    mod rt_buffer;
+    pub(crate) mod scm;
+    mod sealed_buffer;
+    mod segment;
+    mod semaphore;
+    mod seqlock;
+    pub(crate) mod shim;
+    mod shm_alloc;
+    #[cfg(target_os = "linux")]
+    mod shm_segment;
+    mod shm_vec;
+    mod slab;
+    mod snapshot;
+    pub(crate) mod soft_dirty;
+    mod sort;
+    mod sorted_index;
+    pub(crate) mod spin;
+    mod stack;
+    mod stack_pool;
+    #[cfg(test)]
+    pub(crate) mod test_util;
+    mod thunk;
+    mod topic;
+    #[cfg(debug_assertions)]
+    mod traced;
+    mod truncation;
+    mod uio;
+    pub(crate) mod verity;
+    mod watch;
+    mod watchdog;
+    mod windowed;
+    mod write_tracker;
+    mod zero;
+
+    /// Thin wrapper around `mmap(2)` which converts failures into `io::Error`
+    pub(crate) unsafe fn mmap_raw(
+        addr: *mut u8,
+        len: usize,
+        prot: Protection,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> io::Result<*mut u8> {
+        if flags & libc::MAP_FIXED != 0 && is_heap_copy(addr) {
+            return Err(heap_backed_error());
+        }
 
-    if ptr == libc::MAP_FAILED {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(ptr as *mut _)
+        traced!("mmap", addr, len, {
+            let ptr = libc::mmap64(addr.cast(), len, prot.0, flags, fd, offset);
+
+            if ptr == libc::MAP_FAILED {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(ptr as *mut _)
+            }
+        } => mapped)
     }
-}
 
-fn mmap_file(file: &File, prot: Protection) -> io::Result<(*mut u8, usize)> {
-    let fd = file.as_raw_fd();
+    /// Thin wrapper around `mprotect(2)` which converts failures into `io::Error`
+    ///
+    /// Does nothing for heap-backed copies, whose pages belong to the allocator
+    /// and so must stay accessible.
+    pub(crate) unsafe fn mprotect_raw(
+        ptr: *mut u8,
+        len: usize,
+        prot: Protection,
+    ) -> io::Result<()> {
+        if is_heap_copy(ptr) {
+            return Ok(());
+        }
 
-    let size = file.metadata()?.size() as usize;
+        traced!("mprotect", ptr, len, {
+            if libc::mprotect(ptr.cast(), len, prot.0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        })
+    }
 
-    let ptr = unsafe {
-        libc::mmap(
-            std::ptr::null_mut(),
-            size,
-            prot.0,
-            UniqueFlag::MAP_SHARED.0,
-            fd,
-            0,
-        )
-    };
+    /// Thin wrapper around `mremap(2)` which converts failures into `io::Error`
+    pub(crate) unsafe fn mremap_raw(
+        old: *mut u8,
+        old_len: usize,
+        new_len: usize,
+        flags: i32,
+        new_addr: *mut u8,
+    ) -> io::Result<*mut u8> {
+        if is_heap_copy(old) {
+            return Err(heap_backed_error());
+        }
 
-    if ptr == libc::MAP_FAILED {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok((ptr as *mut _, size))
+        traced!("mremap", old, new_len, {
+            let ptr = crate::libc_compat::mremap(
+                old.cast(),
+                old_len,
+                new_len,
+                flags,
+                new_addr.cast::<libc::c_void>(),
+            );
+
+            if ptr == libc::MAP_FAILED {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(ptr as *mut _)
+            }
+        } => mapped)
     }
-}
 
-pub struct Mmap<'a> {
-    ptr: *const u8,
-    len: usize,
-    _lifetime: PhantomData<&'a ()>,
-}
+    /// Thin wrapper around `madvise(2)` which converts failures into `io::Error`
+    ///
+    /// Heap-backed copies ignore advice, except for advice which discards pages,
+    /// which fails as their contents cannot be given up.
+    pub(crate) unsafe fn madvise_raw(ptr: *mut u8, len: usize, advice: i32) -> io::Result<()> {
+        if is_heap_copy(ptr) {
+            return match advice {
+                libc::MADV_DONTNEED | libc::MADV_FREE | libc::MADV_REMOVE => {
+                    Err(heap_backed_error())
+                }
+                _ => Ok(()),
+            };
+        }
 
-pub struct MmapMut<'a> {
-    ptr: *mut u8,
-    len: usize,
-    _lifetime: PhantomData<&'a ()>,
-}
+        traced!("madvise", ptr, len, {
+            if libc::madvise(ptr.cast(), len, advice) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        })
+    }
+
+    /// `msync(2)` the pages containing `len` bytes at `ptr`, which need not be
+    /// page aligned
+    ///
+    /// Does nothing for heap-backed copies, which have no file to write back to.
+    pub(crate) unsafe fn msync_raw(ptr: *const u8, len: usize, flags: i32) -> io::Result<()> {
+        if is_heap_copy(ptr) {
+            return Ok(());
+        }
+
+        let start = ptr as usize - ptr as usize % page_size();
+        let end = ptr as usize + len;
 
-macro_rules! mmap_impl {
-    ($name:ident, $prot:ident, $target:ty) => {
-        impl<'a> $name<'a> {
-            pub fn new_anon(size: NonZeroUsize) -> io::Result<Self> {
-                let ptr = mmap_anon(size, Protection::$prot)?;
-
-                Ok(Self {
-                    ptr,
-                    len: size.get(),
-                    _lifetime: PhantomData,
-                })
+        traced!("msync", start, end - start, {
+            if libc::msync(start as *mut libc::c_void, end - start, flags) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
             }
+        })
+    }
 
-            pub fn new_anon_exec(size: NonZeroUsize) -> io::Result<Self> {
-                let ptr = mmap_anon(size, Protection::$prot | Protection::EXEC)?;
+    /// `mincore(2)` the `len` bytes at `ptr` into `vec`, one byte per page
+    ///
+    /// Every page of a heap-backed copy is reported resident.
+    pub(crate) unsafe fn mincore_raw(ptr: *const u8, len: usize, vec: &mut [u8]) -> io::Result<()> {
+        if is_heap_copy(ptr) {
+            vec.fill(1);
+            return Ok(());
+        }
 
-                Ok(Self {
-                    ptr,
-                    len: size.get(),
-                    _lifetime: PhantomData,
-                })
+        if libc::mincore(ptr as *mut libc::c_void, len, vec.as_mut_ptr()) == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// `munmap(2)` during cleanup, where failure can only be ignored
+    pub(crate) unsafe fn munmap_raw(ptr: *const u8, len: usize) {
+        let _ = traced!("munmap", ptr, len, {
+            if libc::munmap(ptr as *mut libc::c_void, len) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
             }
+        });
+    }
+
+    /// Whether [`Mmap`] and [`MmapMut`] are backed by heap allocations rather
+    /// than real mappings; see the `shim` module
+    pub(crate) const HEAP_BACKED: bool = cfg!(any(miri, feature = "asan-friendly"));
 
-            pub fn new_file(file: &File) -> io::Result<Self> {
-                let (ptr, len) = mmap_file(file, Protection::$prot)?;
+    /// Whether `ptr` points into a heap-backed stand-in for a mapping, which the
+    /// system call wrappers above treat as the copy it is rather than passing to
+    /// the kernel
+    pub(crate) fn is_heap_copy(ptr: *const u8) -> bool {
+        HEAP_BACKED && shim::owns(ptr)
+    }
+
+    pub(crate) fn heap_backed_error() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "operation requires a real mapping, but mappings are heap backed",
+        )
+    }
+
+    /// Fail with [`Unsupported`](io::ErrorKind::Unsupported) when [`HEAP_BACKED`],
+    /// for operations which must be refused before they make any system call
+    pub(crate) fn require_real_mapping() -> io::Result<()> {
+        if HEAP_BACKED {
+            Err(heap_backed_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn page_size() -> usize {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+
+    pub(crate) fn round_up_to_page(len: usize) -> usize {
+        len.next_multiple_of(page_size())
+    }
+
+    /// Length of `file` past `offset`, which is what a mapping of the rest of
+    /// the file from there covers
+    pub(crate) fn file_len_from(file: &File, offset: u64) -> io::Result<usize> {
+        usize::try_from(file.metadata()?.size().saturating_sub(offset))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file is too large to map"))
+    }
+
+    /// Heap-backed stand-ins for mappings cannot be made executable, so fail
+    /// rather than hand out memory which faults when jumped to
+    fn require_not_exec(prot: Protection) -> io::Result<()> {
+        if prot.contains(Protection::EXEC) {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "heap-backed mappings cannot be executable",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn mmap_anon(size: NonZeroUsize, prot: Protection) -> io::Result<*mut u8> {
+        if HEAP_BACKED {
+            require_not_exec(prot)?;
+            return shim::map_anon(size);
+        }
+
+        unsafe {
+            mmap_raw(
+                std::ptr::null_mut(),
+                size.get(),
+                prot,
+                (UniqueFlag::MAP_SHARED | Flag::MAP_ANONYMOUS).0,
+                -1,
+                0,
+            )
+        }
+    }
+
+    fn mmap_file(file: &File, prot: Protection) -> io::Result<(*mut u8, usize)> {
+        if HEAP_BACKED {
+            require_not_exec(prot)?;
+            return shim::map_file(file);
+        }
 
-                Ok(Self {
-                    ptr,
-                    len,
-                    _lifetime: PhantomData,
-                })
+        let fd = file.as_raw_fd();
+
+        let size = file_len_from(file, 0)?;
+
+        let ptr = unsafe {
+            mmap_raw(
+                std::ptr::null_mut(),
+                size,
+                prot,
+                UniqueFlag::MAP_SHARED.0,
+                fd,
+                0,
+            )?
+        };
+
+        Ok((ptr, size))
+    }
+
+    /// Resolve `range` against a mapping of `len` bytes, failing if it is out of
+    /// bounds
+    pub(crate) fn check_range(
+        len: usize,
+        range: impl RangeBounds<usize>,
+    ) -> io::Result<Range<usize>> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => Some(start),
+            Bound::Excluded(&start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1),
+            Bound::Excluded(&end) => Some(end),
+            Bound::Unbounded => Some(len),
+        };
+
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end && end <= len => Ok(start..end),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range out of bounds of mapping",
+            )),
+        }
+    }
+
+    /// Resolve `range` against the mapping at `ptr` and widen it outwards to page
+    /// boundaries, as required by `madvise(2)` and friends
+    ///
+    /// Only suitable for operations which do not modify the contents of the pages.
+    pub(crate) fn page_span(
+        ptr: *const u8,
+        len: usize,
+        range: impl RangeBounds<usize>,
+    ) -> io::Result<(*mut u8, usize)> {
+        let range = check_range(len, range)?;
+
+        let start = range.start - range.start % page_size();
+        let end = round_up_to_page(range.end);
+
+        Ok((unsafe { ptr.add(start) } as *mut u8, end - start))
+    }
+
+    /// Resolve `range` against the mapping at `ptr`, requiring it to start on a
+    /// page boundary and end on one or at the end of the mapping
+    ///
+    /// Used by operations which discard or otherwise modify whole pages, where
+    /// silently widening the range would affect bytes outside of it.
+    pub(crate) fn page_aligned_span(
+        ptr: *const u8,
+        len: usize,
+        range: impl RangeBounds<usize>,
+    ) -> io::Result<(*mut u8, usize)> {
+        let range = check_range(len, range)?;
+
+        if !range.start.is_multiple_of(page_size())
+            || (range.end != len && !range.end.is_multiple_of(page_size()))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range is not page aligned",
+            ));
+        }
+
+        Ok((unsafe { ptr.add(range.start) } as *mut u8, range.len()))
+    }
+
+    /// How the memory of an [`Mmap`] or [`MmapMut`] was obtained, and so how it is
+    /// released on drop
+    #[derive(Clone, Copy)]
+    pub(crate) enum Backing {
+        /// A real mapping, with `guard_len` bytes of guard pages directly below it
+        /// which are unmapped along with it
+        Mapped { guard_len: usize },
+        /// A heap allocation from the `shim` module
+        Heap,
+    }
+
+    impl Backing {
+        /// Backing of the mappings created by [`mmap_anon`] and [`mmap_file`]
+        const DEFAULT: Self = if HEAP_BACKED {
+            Backing::Heap
+        } else {
+            Backing::Mapped { guard_len: 0 }
+        };
+
+        /// Unmap the guard pages below `ptr`, for when the mapping is moved away
+        /// from them
+        pub(crate) unsafe fn unmap_guard(&mut self, ptr: *const u8) {
+            if let Backing::Mapped { guard_len } = self {
+                if *guard_len != 0 {
+                    munmap_raw(ptr.sub(*guard_len), *guard_len);
+                    *guard_len = 0;
+                }
             }
+        }
 
-            pub fn new_file_exec(file: &File) -> io::Result<Self> {
-                let (ptr, len) = mmap_file(file, Protection::$prot | Protection::EXEC)?;
+        unsafe fn release(self, ptr: *const u8, len: usize) {
+            #[cfg(feature = "registry")]
+            fault_counter::unregister(ptr);
 
-                Ok(Self {
-                    ptr,
-                    len,
-                    _lifetime: PhantomData,
-                })
+            match self {
+                Backing::Mapped { guard_len } => {
+                    munmap_raw(ptr.sub(guard_len), guard_len + len);
+                }
+                Backing::Heap => shim::unmap(ptr as *mut u8, len),
             }
         }
-    };
-}
+    }
+
+    pub struct Mmap<'a> {
+        ptr: *const u8,
+        len: usize,
+        /// Offset into the backing file the mapping starts at, or zero for
+        /// anonymous mappings
+        offset: u64,
+        /// How the memory is released on drop
+        backing: Backing,
+        /// Advisory lock on the backing file, held for as long as the mapping
+        _lock: Option<FileLock>,
+        _lifetime: PhantomData<&'a ()>,
+    }
 
-mmap_impl!(Mmap, READ, &'a [u8]);
-mmap_impl!(MmapMut, WRITE, &'a mut [u8]);
+    pub struct MmapMut<'a> {
+        ptr: *mut u8,
+        len: usize,
+        /// Offset into the backing file the mapping starts at, or zero for
+        /// anonymous mappings
+        offset: u64,
+        /// How the memory is released on drop
+        backing: Backing,
+        /// Advisory lock on the backing file, held for as long as the mapping
+        _lock: Option<FileLock>,
+        /// Whether dirty pages are written back when the mapping is dropped
+        flush_on_drop: FlushOnDrop,
+        _lifetime: PhantomData<&'a ()>,
+    }
 
-impl<'a> Deref for Mmap<'a> {
-    type Target = [u8];
+    macro_rules! mmap_impl {
+        ($name:ident, $prot:ident, $target:ty $(, $field:ident: $value:expr)*) => {
+            impl<'a> $name<'a> {
+                pub fn new_anon(size: NonZeroUsize) -> io::Result<Self> {
+                    let ptr = mmap_anon(size, Protection::$prot)?;
+
+                    Ok(Self {
+                        ptr,
+                        len: size.get(),
+                        offset: 0,
+                        backing: Backing::DEFAULT,
+                        _lock: None,
+                        $($field: $value,)*
+                        _lifetime: PhantomData,
+                    })
+                }
+
+                pub fn new_anon_exec(size: NonZeroUsize) -> io::Result<Self> {
+                    let ptr = mmap_anon(size, Protection::$prot | Protection::EXEC)?;
+
+                    Ok(Self {
+                        ptr,
+                        len: size.get(),
+                        offset: 0,
+                        backing: Backing::DEFAULT,
+                        _lock: None,
+                        $($field: $value,)*
+                        _lifetime: PhantomData,
+                    })
+                }
+
+                pub fn new_file(file: &File) -> io::Result<Self> {
+                    let (ptr, len) = mmap_file(file, Protection::$prot)?;
+
+                    Ok(Self {
+                        ptr,
+                        len,
+                        offset: 0,
+                        backing: Backing::DEFAULT,
+                        _lock: None,
+                        $($field: $value,)*
+                        _lifetime: PhantomData,
+                    })
+                }
+
+                pub fn new_file_exec(file: &File) -> io::Result<Self> {
+                    let (ptr, len) = mmap_file(file, Protection::$prot | Protection::EXEC)?;
+
+                    Ok(Self {
+                        ptr,
+                        len,
+                        offset: 0,
+                        backing: Backing::DEFAULT,
+                        _lock: None,
+                        $($field: $value,)*
+                        _lifetime: PhantomData,
+                    })
+                }
+
+                /// Map a memfd or shared memory object which is open as
+                /// `remote_fd` in the process referred to by `pidfd`
+                ///
+                /// See [`pidfd_open`] for obtaining a pidfd.
+                pub fn from_remote_fd(pidfd: &impl AsRawFd, remote_fd: i32) -> io::Result<Self> {
+                    Self::new_file(&pidfd::pidfd_getfd(pidfd, remote_fd)?)
+                }
+            }
+        };
+    }
 
-    fn deref(&self) -> &'a Self::Target {
-        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    mmap_impl!(Mmap, READ, &'a [u8]);
+    mmap_impl!(MmapMut, WRITE, &'a mut [u8], flush_on_drop: FlushOnDrop::None);
+
+    impl<'a> Mmap<'a> {
+        /// Keep the mapping for the rest of the life of the process, for handing
+        /// to APIs which need `&'static` data
+        ///
+        /// Any lock on the backing file taken with
+        /// [`MmapOptions::shared_lock`] is held forever too, so that the file
+        /// cannot be locked and modified underneath the returned slice by
+        /// cooperating processes.
+        pub fn into_leaked(self) -> &'static [u8] {
+            let this = ManuallyDrop::new(self);
+
+            unsafe { std::slice::from_raw_parts(this.ptr, this.len) }
+        }
     }
-}
 
-impl<'a> Deref for MmapMut<'a> {
-    type Target = [u8];
+    impl<'a> MmapMut<'a> {
+        /// Keep the mapping for the rest of the life of the process, for handing
+        /// to APIs which need `&'static mut` data
+        ///
+        /// Any lock on the backing file is held forever too. The
+        /// [flush-on-drop](MmapOptions::flush_on_drop) policy is never applied,
+        /// so call [`flush`](Self::flush) explicitly before exiting if needed.
+        pub fn into_leaked(self) -> &'static mut [u8] {
+            let this = ManuallyDrop::new(self);
+
+            unsafe { std::slice::from_raw_parts_mut(this.ptr, this.len) }
+        }
+
+        /// Pointer to `count` contiguous values of `T` starting `offset` bytes into
+        /// the mapping, checking bounds and alignment
+        pub(crate) fn typed_ptr<T>(&self, offset: usize, count: usize) -> io::Result<*mut T> {
+            let size = std::mem::size_of::<T>()
+                .checked_mul(count)
+                .and_then(|size| size.checked_add(offset));
+
+            if !matches!(size, Some(end) if end <= self.len) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "range out of bounds of mapping",
+                ));
+            }
 
-    fn deref(&self) -> &'a Self::Target {
-        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+            let ptr = unsafe { self.ptr.add(offset) };
+
+            if !(ptr as usize).is_multiple_of(std::mem::align_of::<T>()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "offset is not suitably aligned",
+                ));
+            }
+
+            Ok(ptr.cast())
+        }
     }
-}
 
-impl<'a> DerefMut for MmapMut<'a> {
-    fn deref_mut(&mut self) -> &'a mut Self::Target {
-        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    impl<'a> Deref for Mmap<'a> {
+        type Target = [u8];
+
+        fn deref(&self) -> &Self::Target {
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
     }
-}
 
-#[repr(transparent)]
-pub(crate) struct Protection(i32);
+    impl<'a> Deref for MmapMut<'a> {
+        type Target = [u8];
 
-impl Protection {
-    /// Pages may be read
-    const READ: Self = Protection(libc::PROT_READ);
+        fn deref(&self) -> &Self::Target {
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
 
-    /// Pages may be executed
-    const EXEC: Self = Protection(libc::PROT_EXEC);
+    impl<'a> DerefMut for MmapMut<'a> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+        }
+    }
 
-    /// Pages may be written
-    const WRITE: Self = Protection(libc::PROT_WRITE);
+    impl<'a> Drop for Mmap<'a> {
+        fn drop(&mut self) {
+            unsafe { self.backing.release(self.ptr, self.len) };
+        }
+    }
 
-    /// Pages may not be accessed
-    #[allow(dead_code)]
-    const NONE: Self = Protection(libc::PROT_NONE);
-}
+    impl<'a> Drop for MmapMut<'a> {
+        fn drop(&mut self) {
+            self.flush_for_drop();
 
-impl BitOr<Self> for Protection {
-    type Output = Self;
-    fn bitor(self, rhs: Self) -> Self::Output {
-        Self(self.0 | rhs.0)
+            unsafe { self.backing.release(self.ptr, self.len) };
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::num::NonZeroUsize;
+    /// Memory protection of a mapping, combined with `|`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct Protection(pub(crate) i32);
+
+    impl Protection {
+        /// Pages may be read
+        pub const READ: Self = Protection(libc::PROT_READ);
+
+        /// Pages may be executed
+        pub const EXEC: Self = Protection(libc::PROT_EXEC);
 
-    use crate::{Mmap, MmapMut};
+        /// Pages may be written
+        pub const WRITE: Self = Protection(libc::PROT_WRITE);
 
-    #[test]
-    fn anon_readonly() {
-        let map = Mmap::new_anon(NonZeroUsize::new(20).unwrap()).unwrap();
+        /// Pages may not be accessed
+        pub const NONE: Self = Protection(libc::PROT_NONE);
 
-        assert_eq!(&*map, &[0; 20]);
+        /// Whether every protection in `other` is also in `self`
+        pub fn contains(self, other: Self) -> bool {
+            self.0 & other.0 == other.0
+        }
     }
 
-    #[test]
-    fn anon_mut() {
-        let mut map = MmapMut::new_anon(NonZeroUsize::new(20).unwrap()).unwrap();
+    impl BitOr<Self> for Protection {
+        type Output = Self;
+        fn bitor(self, rhs: Self) -> Self::Output {
+            Self(self.0 | rhs.0)
+        }
+    }
 
-        assert_eq!(&*map, &[0; 20]);
+    #[cfg(test)]
+    mod test {
+        use std::num::NonZeroUsize;
 
-        (&mut *map)[..].copy_from_slice(&[1; 20]);
+        use crate::{Mmap, MmapMut};
 
-        assert_eq!(&*map, &[1; 20]);
+        #[test]
+        fn leaked_mapping_outlives_value() {
+            let mut map = MmapMut::new_anon(NonZeroUsize::new(20).unwrap()).unwrap();
+            map[0] = 9;
+
+            let leaked: &'static mut [u8] = map.into_leaked();
+
+            assert_eq!(leaked[0], 9);
+            assert_eq!(leaked.len(), 20);
+
+            let map = Mmap::new_anon(NonZeroUsize::new(20).unwrap()).unwrap();
+            let leaked: &'static [u8] = map.into_leaked();
+
+            assert_eq!(leaked[19], 0);
+        }
+
+        #[test]
+        fn anon_readonly() {
+            let map = Mmap::new_anon(NonZeroUsize::new(20).unwrap()).unwrap();
+
+            assert_eq!(&*map, &[0; 20]);
+        }
+
+        #[test]
+        fn anon_mut() {
+            let mut map = MmapMut::new_anon(NonZeroUsize::new(20).unwrap()).unwrap();
+
+            assert_eq!(&*map, &[0; 20]);
+
+            (&mut *map)[..].copy_from_slice(&[1; 20]);
+
+            assert_eq!(&*map, &[1; 20]);
+        }
     }
 }
+
+#[cfg(feature = "std")]
+pub use std_api::*;
@@ -0,0 +1,88 @@
+//! Constants and functions which are missing from, or not exposed by the
+//! `libc` crate for, some C libraries
+//!
+//! Where `libc` provides a definition it is used as is; otherwise the value is
+//! taken from the kernel's UAPI headers, which are the same for every C
+//! library since these are all kernel interfaces.
+
+#[cfg(target_env = "gnu")]
+pub(crate) const MADV_COLLAPSE: i32 = libc::MADV_COLLAPSE;
+#[cfg(not(target_env = "gnu"))]
+pub(crate) const MADV_COLLAPSE: i32 = 25;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) const MAP_FIXED_NOREPLACE: i32 = libc::MAP_FIXED_NOREPLACE;
+#[cfg(target_os = "android")]
+pub(crate) const MAP_FIXED_NOREPLACE: i32 = 0x100000;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) const MAP_SHARED_VALIDATE: i32 = libc::MAP_SHARED_VALIDATE;
+#[cfg(target_os = "android")]
+pub(crate) const MAP_SHARED_VALIDATE: i32 = 0x03;
+
+#[cfg(any(target_env = "gnu", target_env = "musl"))]
+pub(crate) const MAP_SYNC: i32 = libc::MAP_SYNC;
+#[cfg(not(any(target_env = "gnu", target_env = "musl")))]
+pub(crate) const MAP_SYNC: i32 = 0x80000;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) const MAP_HUGE_2MB: i32 = libc::MAP_HUGE_2MB;
+#[cfg(target_os = "android")]
+pub(crate) const MAP_HUGE_2MB: i32 = 21 << 26;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) const MAP_HUGE_1GB: i32 = libc::MAP_HUGE_1GB;
+#[cfg(target_os = "android")]
+pub(crate) const MAP_HUGE_1GB: i32 = 30 << 26;
+
+/// Only defined by `libc` for the few targets whose C library headers have it
+pub(crate) const MAP_UNINITIALIZED: i32 = 0x4000000;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) use libc::{MREMAP_DONTUNMAP, MREMAP_FIXED, MREMAP_MAYMOVE};
+#[cfg(target_os = "android")]
+pub(crate) const MREMAP_MAYMOVE: i32 = 1;
+#[cfg(target_os = "android")]
+pub(crate) const MREMAP_FIXED: i32 = 2;
+#[cfg(target_os = "android")]
+pub(crate) const MREMAP_DONTUNMAP: i32 = 4;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) use libc::RUSAGE_THREAD;
+#[cfg(target_os = "android")]
+pub(crate) const RUSAGE_THREAD: i32 = 1;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) use libc::{PTRACE_INTERRUPT, PTRACE_SEIZE};
+#[cfg(target_os = "android")]
+pub(crate) const PTRACE_SEIZE: i32 = 0x4206;
+#[cfg(target_os = "android")]
+pub(crate) const PTRACE_INTERRUPT: i32 = 0x4207;
+
+/// `mremap(2)`, which bionic implements but `libc` does not bind
+#[cfg(not(target_os = "android"))]
+pub(crate) use libc::mremap;
+
+#[cfg(target_os = "android")]
+pub(crate) unsafe fn mremap(
+    old: *mut libc::c_void,
+    old_len: usize,
+    new_len: usize,
+    flags: i32,
+    new_addr: *mut libc::c_void,
+) -> *mut libc::c_void {
+    libc::syscall(libc::SYS_mremap, old, old_len, new_len, flags, new_addr) as *mut libc::c_void
+}
+
+/// Pointer to the calling thread's `errno`
+pub(crate) unsafe fn errno_location() -> *mut i32 {
+    #[cfg(not(target_os = "android"))]
+    return libc::__errno_location();
+
+    #[cfg(target_os = "android")]
+    return libc::__errno();
+}
+
+/// `mseal(2)` was added after syscall numbers were unified, so it has the same
+/// number on every architecture but is only in recent `libc` releases
+pub(crate) const SYS_MSEAL: libc::c_long = 462;
@@ -0,0 +1,179 @@
+use std::{
+    io,
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+use crate::{register_guard, AddressSpace, GuardCallback, GuardRegistration, Protection};
+
+/// Size of a WebAssembly page
+pub const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// Most pages a 32-bit WebAssembly memory can have, making up 4GiB
+const MAX_WASM_PAGES: u32 = 65536;
+
+/// Inaccessible space kept after the maximum size, so that accesses at an
+/// offset from an in-bounds address still fault
+#[cfg(target_pointer_width = "64")]
+const GUARD_LEN: usize = 2 << 30;
+#[cfg(not(target_pointer_width = "64"))]
+const GUARD_LEN: usize = WASM_PAGE_SIZE;
+
+/// The linear memory of a WebAssembly instance, which grows in place up to a
+/// fixed maximum
+///
+/// The maximum size and a 2GiB guard region after it are reserved up front,
+/// and only the current size is accessible, so the memory never moves when
+/// it [grows](Self::grow), and every out-of-bounds access lands in
+/// inaccessible pages and faults instead of reaching other memory. With a
+/// maximum of 65536 pages, every address a 32-bit index plus a 31-bit offset
+/// can form lies within the reservation, so generated code can skip bounds
+/// checks entirely; turn the faults into traps with
+/// [`register_guard`](Self::register_guard).
+///
+/// On 32-bit targets the guard region is a single WebAssembly page.
+pub struct LinearMemory {
+    space: AddressSpace,
+    pages: u32,
+    max_pages: u32,
+}
+
+impl LinearMemory {
+    /// Reserve a memory of up to `max_pages` WebAssembly pages, of which the
+    /// first `initial_pages` are zeroed and accessible
+    pub fn new(initial_pages: u32, max_pages: u32) -> io::Result<Self> {
+        if initial_pages > max_pages || max_pages > MAX_WASM_PAGES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "initial size exceeds the maximum, or the maximum exceeds 4GiB",
+            ));
+        }
+
+        let reserved = (max_pages as usize)
+            .checked_mul(WASM_PAGE_SIZE)
+            .and_then(|len| len.checked_add(GUARD_LEN))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    "memory does not fit in the address space",
+                )
+            })?;
+
+        let mut memory = Self {
+            space: AddressSpace::reserve(NonZeroUsize::new(reserved).unwrap())?,
+            pages: 0,
+            max_pages,
+        };
+
+        memory.grow(initial_pages)?;
+
+        Ok(memory)
+    }
+
+    /// Current size, in WebAssembly pages
+    pub fn pages(&self) -> u32 {
+        self.pages
+    }
+
+    pub fn max_pages(&self) -> u32 {
+        self.max_pages
+    }
+
+    /// Size of the whole reservation, including the guard region
+    pub fn reserved_size(&self) -> usize {
+        self.space.reserved_size()
+    }
+
+    /// Make `delta` more zeroed pages accessible at the end of the memory,
+    /// returning the previous size in pages, as `memory.grow` does
+    ///
+    /// The memory stays where it is, so pointers into it remain valid. Fails
+    /// without changing the size if it would exceed the maximum.
+    pub fn grow(&mut self, delta: u32) -> io::Result<u32> {
+        let old = self.pages;
+
+        let new = old
+            .checked_add(delta)
+            .filter(|&new| new <= self.max_pages)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "memory cannot grow past its maximum",
+                )
+            })?;
+
+        if delta != 0 {
+            self.space.place_anon(
+                old as usize * WASM_PAGE_SIZE..new as usize * WASM_PAGE_SIZE,
+                Protection::READ | Protection::WRITE,
+            )?;
+        }
+
+        self.pages = new;
+
+        Ok(old)
+    }
+
+    /// Invoke `callback` when an access faults anywhere in the reservation
+    /// beyond the current size
+    ///
+    /// See [`register_guard`] for the restrictions on what the callback may do.
+    pub fn register_guard(
+        &self,
+        callback: GuardCallback,
+        data: usize,
+    ) -> io::Result<GuardRegistration> {
+        register_guard(
+            self.space.base(),
+            self.space.reserved_size(),
+            callback,
+            data,
+        )
+    }
+}
+
+impl Deref for LinearMemory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.space.base(), self.pages as usize * WASM_PAGE_SIZE) }
+    }
+}
+
+impl DerefMut for LinearMemory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.space.base(), self.pages as usize * WASM_PAGE_SIZE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{LinearMemory, WASM_PAGE_SIZE};
+
+    #[test]
+    fn grows_in_place() {
+        let mut memory = LinearMemory::new(1, 4).unwrap();
+
+        assert_eq!(memory.len(), WASM_PAGE_SIZE);
+
+        memory[WASM_PAGE_SIZE - 1] = 1;
+
+        let base = memory.as_ptr();
+
+        assert_eq!(memory.grow(2).unwrap(), 1);
+        assert_eq!(memory.pages(), 3);
+        assert_eq!(memory.as_ptr(), base);
+        assert_eq!(memory[WASM_PAGE_SIZE - 1], 1);
+        assert_eq!(memory[3 * WASM_PAGE_SIZE - 1], 0);
+
+        assert!(memory.grow(2).is_err());
+        assert_eq!(memory.grow(1).unwrap(), 3);
+        assert!(memory.grow(1).is_err());
+
+        assert!(LinearMemory::new(2, 1).is_err());
+        assert!(LinearMemory::new(0, 65537).is_err());
+    }
+}
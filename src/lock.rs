@@ -0,0 +1,182 @@
+use std::{
+    fs::{File, OpenOptions},
+    io, mem,
+    ops::RangeBounds,
+    os::unix::io::AsRawFd,
+};
+
+use crate::{check_range, Mmap, MmapMut};
+
+/// Kind of advisory lock to take on a backing file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+/// An advisory lock on a file, released when dropped
+pub(crate) struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Block until the lock can be taken
+    ///
+    /// `flock(2)` locks belong to the open file description, so the lock is
+    /// also held through the caller's handle to `file`, and is released for
+    /// both when this is dropped.
+    pub(crate) fn acquire(file: &File, kind: LockKind) -> io::Result<Self> {
+        let file = file.try_clone()?;
+
+        let op = match kind {
+            LockKind::Shared => libc::LOCK_SH,
+            LockKind::Exclusive => libc::LOCK_EX,
+        };
+
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+                return Ok(Self { file });
+            }
+
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+}
+
+/// An open file description (OFD) record lock on a byte range of a mapped file,
+/// created by `lock_range` and released when dropped
+///
+/// Each guard holds the lock through its own open file description, so guards
+/// conflict with each other even within one process, exactly as they do with
+/// guards held by other processes.
+#[must_use = "the range is unlocked when this is dropped"]
+pub struct RangeLockGuard {
+    _file: File,
+}
+
+fn lock_range(file: &File, start: u64, len: u64, kind: LockKind) -> io::Result<RangeLockGuard> {
+    // reopen the file to get a fresh open file description
+    let path = format!("/proc/self/fd/{}", file.as_raw_fd());
+
+    let file = match kind {
+        LockKind::Exclusive => OpenOptions::new().read(true).write(true).open(path)?,
+        LockKind::Shared => OpenOptions::new().read(true).open(path)?,
+    };
+
+    // the OFD commands always take the 64-bit structure, even on 32-bit targets
+    let mut lock: libc::flock64 = unsafe { mem::zeroed() };
+    lock.l_type = match kind {
+        LockKind::Shared => libc::F_RDLCK,
+        LockKind::Exclusive => libc::F_WRLCK,
+    } as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    lock.l_start = start as _;
+    lock.l_len = len as _;
+
+    loop {
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLKW, &lock) } == 0 {
+            return Ok(RangeLockGuard { _file: file });
+        }
+
+        let err = io::Error::last_os_error();
+
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+macro_rules! lock_range_impl {
+    ($name:ident) => {
+        impl<'a> $name<'a> {
+            /// Block until an OFD record lock of the given kind can be taken on
+            /// the bytes of `file` backing `range` of the mapping
+            ///
+            /// `file` must be the file the mapping was created from. Locks are
+            /// advisory, so this only coordinates with processes which also
+            /// lock the ranges they access.
+            pub fn lock_range(
+                &self,
+                file: &File,
+                range: impl RangeBounds<usize>,
+                kind: LockKind,
+            ) -> io::Result<RangeLockGuard> {
+                let range = check_range(self.len, range)?;
+
+                if range.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "cannot lock an empty range",
+                    ));
+                }
+
+                lock_range(
+                    file,
+                    self.offset + range.start as u64,
+                    range.len() as u64,
+                    kind,
+                )
+            }
+        }
+    };
+}
+
+lock_range_impl!(Mmap);
+lock_range_impl!(MmapMut);
+
+#[cfg(test)]
+mod test {
+    use std::{mem, os::unix::io::AsRawFd};
+
+    use crate::{test_util::temp_file, LockKind, MmapMut};
+
+    /// Whether a lock of `kind` on `start..start + len` would conflict with an
+    /// existing one
+    fn conflicts(file: &std::fs::File, start: i64, len: i64, kind: i32) -> bool {
+        let mut lock: libc::flock64 = unsafe { mem::zeroed() };
+        lock.l_type = kind as _;
+        lock.l_whence = libc::SEEK_SET as _;
+        lock.l_start = start;
+        lock.l_len = len;
+
+        assert_eq!(
+            unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_GETLK, &mut lock) },
+            0
+        );
+
+        lock.l_type != libc::F_UNLCK as _
+    }
+
+    #[test]
+    fn locks_ranges() {
+        let file = temp_file("range-lock");
+        file.set_len(8192).unwrap();
+
+        let map = MmapMut::new_file(&file).unwrap();
+
+        let exclusive = map.lock_range(&file, 0..100, LockKind::Exclusive).unwrap();
+        let _shared = map.lock_range(&file, 4096.., LockKind::Shared).unwrap();
+
+        assert!(conflicts(&file, 50, 10, libc::F_RDLCK));
+        assert!(!conflicts(&file, 100, 10, libc::F_WRLCK));
+        assert!(conflicts(&file, 5000, 10, libc::F_WRLCK));
+        assert!(!conflicts(&file, 5000, 10, libc::F_RDLCK));
+
+        drop(exclusive);
+
+        assert!(!conflicts(&file, 50, 10, libc::F_WRLCK));
+        assert!(map.lock_range(&file, 10..10, LockKind::Shared).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{io, mem, ops::BitOr, sync::Mutex};
+
+/// Which mappings [`lock_all_memory`] applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockAllFlags(i32);
+
+impl LockAllFlags {
+    /// Lock all pages which are currently mapped into the address space of the
+    /// process.
+    pub const CURRENT: Self = Self(libc::MCL_CURRENT);
+
+    /// Lock all pages which will become mapped into the address space of the
+    /// process in the future. These could be, for instance, new pages required
+    /// by a growing heap and stack as well as new memory-mapped files or
+    /// shared memory regions.
+    pub const FUTURE: Self = Self(libc::MCL_FUTURE);
+
+    /// Used together with CURRENT, FUTURE, or both. Mark all current (with
+    /// CURRENT) or future (with FUTURE) mappings to lock pages when they are
+    /// faulted in, rather than populating them up front.
+    ///
+    /// (since Linux 4.4)
+    pub const ONFAULT: Self = Self(libc::MCL_ONFAULT);
+}
+
+impl BitOr<Self> for LockAllFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Number of [`LockAllGuard`]s alive in the process, locked across calls to
+/// `mlockall` and `munlockall` so that a guard being dropped cannot unlock
+/// memory another is being created to lock
+static GUARDS: Mutex<usize> = Mutex::new(0);
+
+/// Keeps the address space of the process locked in RAM with `mlockall(2)`
+/// until dropped
+///
+/// Locks are per-process and do not nest, so guards are counted instead:
+/// everything is unlocked with `munlockall(2)` once the last guard alive in
+/// the process is dropped.
+#[must_use = "memory is unlocked when this is dropped"]
+pub struct LockAllGuard {
+    _private: (),
+}
+
+impl Drop for LockAllGuard {
+    fn drop(&mut self) {
+        let mut guards = GUARDS.lock().unwrap_or_else(|e| e.into_inner());
+
+        *guards -= 1;
+
+        if *guards == 0 {
+            unsafe {
+                libc::munlockall();
+            }
+        }
+    }
+}
+
+/// Lock the address space of the process into RAM so that it is never paged
+/// out, as required by real-time audio and control loops
+///
+/// Memory stays locked until every guard returned is dropped. The flags of
+/// each call replace those of earlier ones, as with `mlockall(2)` itself.
+///
+/// Failures caused by `RLIMIT_MEMLOCK` or a missing `CAP_IPC_LOCK` are
+/// reported with the current limit, rather than as a bare `ENOMEM` or `EPERM`.
+pub fn lock_all_memory(flags: LockAllFlags) -> io::Result<LockAllGuard> {
+    let mut guards = GUARDS.lock().unwrap_or_else(|e| e.into_inner());
+
+    if unsafe { libc::mlockall(flags.0) } == 0 {
+        *guards += 1;
+
+        return Ok(LockAllGuard { _private: () });
+    }
+
+    let err = io::Error::last_os_error();
+
+    match err.raw_os_error() {
+        Some(libc::ENOMEM) => Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!(
+                "mlockall exceeded RLIMIT_MEMLOCK ({}); raise the limit or grant CAP_IPC_LOCK",
+                describe_memlock_limit()
+            ),
+        )),
+        Some(libc::EPERM) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "mlockall is not permitted with RLIMIT_MEMLOCK {} and without CAP_IPC_LOCK",
+                describe_memlock_limit()
+            ),
+        )),
+        _ => Err(err),
+    }
+}
+
+fn describe_memlock_limit() -> String {
+    let mut limit: libc::rlimit = unsafe { mem::zeroed() };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return "unknown".to_owned();
+    }
+
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        "unlimited".to_owned()
+    } else {
+        format!("{} bytes", limit.rlim_cur)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{lock_all_memory, LockAllFlags};
+
+    /// `VmLck` of `/proc/self/status`, in kB
+    fn locked_kb() -> u64 {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmLck:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn unlocks_after_last_guard() {
+        let flags = LockAllFlags::CURRENT | LockAllFlags::ONFAULT;
+
+        let Ok(first) = lock_all_memory(flags) else {
+            // RLIMIT_MEMLOCK too low to lock the test process
+            return;
+        };
+
+        let second = lock_all_memory(flags).unwrap();
+
+        drop(first);
+
+        assert!(locked_kb() > 0);
+
+        drop(second);
+
+        assert_eq!(locked_kb(), 0);
+    }
+}
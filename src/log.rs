@@ -0,0 +1,347 @@
+use std::{
+    fs::File,
+    io,
+    ops::Range,
+    os::unix::{fs::FileExt, io::AsRawFd},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    checksummed::{crc32, crc32_extend},
+    file_len_from, FileWatcher, Mmap,
+};
+
+/// Length of the payload length and checksum preceding each record
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Granularity at which [`LogWriter::open`] zeroes a torn tail
+const ZERO_CHUNK: usize = 4096;
+
+/// Checksum of a record, covering its length so that zeroed space at the end
+/// of a preallocated file is not mistaken for empty records
+fn record_crc(payload: &[u8]) -> u32 {
+    crc32_extend(crc32(&(payload.len() as u32).to_le_bytes()), payload)
+}
+
+/// Appends checksummed records to a log file which [`LogReader`] reads
+///
+/// Each record is its length as a little-endian `u32`, the CRC-32 of the
+/// length and payload, then the payload. A crash part way through an append
+/// leaves a torn record at the end of the file, which readers stop at and
+/// [`open`](Self::open) overwrites with zeroes.
+pub struct LogWriter {
+    file: File,
+    len: u64,
+}
+
+impl LogWriter {
+    /// Open the log in `file` for appending, which must be readable and
+    /// writable, zeroing everything after the last valid record
+    ///
+    /// The file keeps its length, as readers following the log may have the
+    /// torn tail mapped, and truncating it would make their accesses fault.
+    pub fn open(file: File) -> io::Result<Self> {
+        let mut reader = LogReader::open(file)?;
+
+        while reader.next_record().is_some() {}
+
+        let len = reader.position();
+
+        // leave preallocated space which is already zeroed alone
+        for (idx, chunk) in reader.data()[len as usize..].chunks(ZERO_CHUNK).enumerate() {
+            if chunk.iter().any(|&b| b != 0) {
+                reader.file.write_all_at(
+                    &[0; ZERO_CHUNK][..chunk.len()],
+                    len + (idx * ZERO_CHUNK) as u64,
+                )?;
+            }
+        }
+
+        Ok(Self {
+            file: reader.file,
+            len,
+        })
+    }
+
+    /// Append `record`, returning its offset in the file
+    ///
+    /// The record is only durable once [`sync`](Self::sync) returns.
+    pub fn append(&mut self, record: &[u8]) -> io::Result<u64> {
+        let len = u32::try_from(record.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record is too long"))?;
+
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + record.len());
+
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&record_crc(record).to_le_bytes());
+        buf.extend_from_slice(record);
+
+        let offset = self.len;
+
+        self.file.write_all_at(&buf, offset)?;
+        self.len += buf.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Wait for every appended record to reach the disk
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Length of the log in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Reads the records of a log written by [`LogWriter`] through a mapping of
+/// the file
+///
+/// Records are returned in order, each checked against its checksum.
+/// Reading stops at the first record which is incomplete or fails its
+/// checksum, which is where a crash tore the end of the log, or where a
+/// concurrent writer has not finished appending.
+///
+/// [`follow`](Self::follow) tails a log which another thread or process is
+/// still appending to, remapping the file as it grows.
+pub struct LogReader {
+    file: File,
+    /// `None` until the file is non-empty, as empty files cannot be mapped
+    map: Option<Mmap<'static>>,
+    pos: usize,
+    watcher: Option<FileWatcher>,
+}
+
+impl LogReader {
+    /// Map the log in `file`, starting at the first record
+    pub fn open(file: File) -> io::Result<Self> {
+        let mut reader = Self {
+            file,
+            map: None,
+            pos: 0,
+            watcher: None,
+        };
+
+        reader.refresh()?;
+
+        Ok(reader)
+    }
+
+    /// Offset of the next record, which once
+    /// [`next_record`](Self::next_record) returns `None` is the length of the
+    /// valid part of the log
+    pub fn position(&self) -> u64 {
+        self.pos as u64
+    }
+
+    /// Whether there are bytes after [`position`](Self::position) in the
+    /// mapped part of the file, as there are after a torn record
+    pub fn has_torn_tail(&self) -> bool {
+        self.peek().is_none() && self.pos < self.data().len()
+    }
+
+    fn data(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+
+    /// The payload of the record at the current position, if it is valid
+    fn peek(&self) -> Option<Range<usize>> {
+        let rest = self.data().get(self.pos..)?;
+        let header = rest.get(..RECORD_HEADER_LEN)?;
+
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        let payload = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN.checked_add(len)?)?;
+
+        if record_crc(payload) != crc {
+            return None;
+        }
+
+        let start = self.pos + RECORD_HEADER_LEN;
+
+        Some(start..start + len)
+    }
+
+    /// The next record, or `None` at the end of the valid records in the
+    /// mapped part of the file
+    pub fn next_record(&mut self) -> Option<&[u8]> {
+        let range = self.peek()?;
+
+        self.pos = range.end;
+
+        Some(&self.data()[range])
+    }
+
+    /// Extend the mapping if the file has grown, returning whether it did
+    pub fn refresh(&mut self) -> io::Result<bool> {
+        let size = file_len_from(&self.file, 0)?;
+
+        if size <= self.data().len() {
+            return Ok(false);
+        }
+
+        match &mut self.map {
+            Some(map) => map.refresh(&self.file),
+            None => {
+                self.map = Some(Mmap::new_file(&self.file)?);
+                Ok(true)
+            }
+        }
+    }
+
+    /// The next record, waiting up to `timeout`, or indefinitely if it is
+    /// `None`, for one to be appended if there are none left
+    ///
+    /// Returns `None` if the timeout expires first.
+    pub fn follow(&mut self, timeout: Option<Duration>) -> io::Result<Option<&[u8]>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // watch before checking, so an append in between still wakes us
+        if self.watcher.is_none() {
+            self.watcher = Some(FileWatcher::new(&self.file)?);
+        }
+
+        loop {
+            if self.peek().is_none() && !self.refresh()? {
+                let remaining = match deadline {
+                    Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                        Some(remaining) => Some(remaining),
+                        None => return Ok(None),
+                    },
+                    None => None,
+                };
+
+                self.wait(remaining)?;
+                continue;
+            }
+
+            if let Some(range) = self.peek() {
+                self.pos = range.end;
+
+                return Ok(Some(&self.data()[range]));
+            }
+        }
+    }
+
+    /// Wait until the file is modified or `timeout` expires
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let watcher = self.watcher.as_ref().unwrap();
+
+        let mut pfd = libc::pollfd {
+            fd: watcher.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let timeout = match timeout {
+            // round up, so a short timeout doesn't turn into a busy loop
+            Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        if unsafe { libc::poll(&mut pfd, 1, timeout) } < 0 {
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        watcher.poll()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{os::unix::fs::FileExt, thread, time::Duration};
+
+    use crate::{
+        test_util::{open_file, temp_path},
+        LogReader, LogWriter,
+    };
+
+    #[test]
+    fn stops_at_torn_tail() {
+        let path = temp_path("log");
+
+        let mut writer = LogWriter::open(open_file(&path)).unwrap();
+
+        assert_eq!(writer.append(b"first").unwrap(), 0);
+        assert_eq!(writer.append(b"").unwrap(), 13);
+        assert_eq!(writer.append(b"third").unwrap(), 21);
+
+        // a crash part way through appending, then preallocated zeros
+        let file = open_file(&path);
+
+        file.write_all_at(&[7, 0, 0, 0, 1, 2], writer.len())
+            .unwrap();
+        file.set_len(4096).unwrap();
+        drop(writer);
+
+        let mut reader = LogReader::open(open_file(&path)).unwrap();
+
+        assert_eq!(reader.next_record(), Some(&b"first"[..]));
+        assert_eq!(reader.next_record(), Some(&b""[..]));
+        assert_eq!(reader.next_record(), Some(&b"third"[..]));
+        assert_eq!(reader.next_record(), None);
+        assert_eq!(reader.position(), 34);
+        assert!(reader.has_torn_tail());
+
+        let mut writer = LogWriter::open(open_file(&path)).unwrap();
+
+        assert_eq!(writer.len(), 34);
+        assert_eq!(writer.append(b"fourth").unwrap(), 34);
+
+        let mut reader = LogReader::open(open_file(&path)).unwrap();
+
+        assert_eq!(
+            std::iter::from_fn(|| reader.next_record().map(<[u8]>::to_vec)).count(),
+            4
+        );
+
+        // the torn record was zeroed rather than cut off
+        let file = open_file(&path);
+        let mut tail = vec![1; 4096 - 48];
+
+        assert_eq!(file.metadata().unwrap().len(), 4096);
+        file.read_exact_at(&mut tail, 48).unwrap();
+        assert!(tail.iter().all(|&b| b == 0));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn follows_appends() {
+        let path = temp_path("log-follow");
+
+        let mut writer = LogWriter::open(open_file(&path)).unwrap();
+        let mut reader = LogReader::open(open_file(&path)).unwrap();
+
+        assert_eq!(reader.follow(Some(Duration::from_millis(1))).unwrap(), None);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for record in [&b"one"[..], b"two", b"three"] {
+                    thread::sleep(Duration::from_millis(10));
+                    writer.append(record).unwrap();
+                }
+            });
+
+            for expected in [&b"one"[..], b"two", b"three"] {
+                let record = reader.follow(Some(Duration::from_secs(5))).unwrap();
+
+                assert_eq!(record, Some(expected));
+            }
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+//! Parser for `/proc/<pid>/maps`, describing every mapping in a process
+
+use std::{fs, io};
+
+/// Access permissions of a mapped region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+    /// `MAP_SHARED` if true, otherwise private copy-on-write
+    pub shared: bool,
+}
+
+/// A single line of `/proc/<pid>/maps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapRegion {
+    pub start: usize,
+    pub end: usize,
+    pub perms: Permissions,
+    /// Offset into the mapped file, or zero for anonymous mappings
+    pub offset: u64,
+    /// Major and minor number of the device holding the mapped file
+    pub dev: (u32, u32),
+    pub inode: u64,
+    /// The mapped file, or a pseudo-path such as `[heap]` or `[stack]`. Empty
+    /// for anonymous mappings.
+    pub pathname: String,
+}
+
+impl MapRegion {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+/// Parse the mappings of process `pid`
+pub fn parse(pid: libc::pid_t) -> io::Result<Vec<MapRegion>> {
+    parse_str(&fs::read_to_string(format!("/proc/{}/maps", pid))?)
+}
+
+/// Parse the mappings of the calling process
+pub fn parse_self() -> io::Result<Vec<MapRegion>> {
+    parse_str(&fs::read_to_string("/proc/self/maps")?)
+}
+
+/// Find the region containing `addr` in the output of [`parse`], which is
+/// sorted by address
+pub fn find_region(regions: &[MapRegion], addr: usize) -> Option<&MapRegion> {
+    let idx = regions.partition_point(|region| region.end <= addr);
+
+    regions.get(idx).filter(|region| region.contains(addr))
+}
+
+/// Whether every page of `[ptr, ptr + len)` is private, according to
+/// `/proc/self/maps`
+pub(crate) fn is_private(ptr: *const u8, len: usize) -> io::Result<bool> {
+    let regions = parse_self()?;
+    let mut addr = ptr as usize;
+
+    while addr < ptr as usize + len {
+        match find_region(&regions, addr) {
+            Some(region) if !region.perms.shared => addr = region.end,
+            _ => return Ok(false),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Bytes backed by transparent huge pages, from the `AnonHugePages` field of
+/// `/proc/self/smaps`, in the mappings overlapping `[start, end)`
+///
+/// Counts the whole of each overlapping mapping, which may include memory
+/// beyond the range if the kernel merged adjacent mappings.
+pub fn anon_huge_pages_self(start: usize, end: usize) -> io::Result<usize> {
+    anon_huge_pages_str(&fs::read_to_string("/proc/self/smaps")?, start, end)
+}
+
+pub(crate) fn anon_huge_pages_str(smaps: &str, start: usize, end: usize) -> io::Result<usize> {
+    let mut overlaps = false;
+    let mut total = 0;
+
+    for line in smaps.lines() {
+        if let Some(region) = parse_line(line) {
+            overlaps = region.start < end && start < region.end;
+        } else if let Some(value) = line.strip_prefix("AnonHugePages:") {
+            let kib: usize = value
+                .trim()
+                .strip_suffix(" kB")
+                .and_then(|kib| kib.parse().ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed smaps line: {:?}", line),
+                    )
+                })?;
+
+            if overlaps {
+                total += kib * 1024;
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+pub(crate) fn parse_str(maps: &str) -> io::Result<Vec<MapRegion>> {
+    maps.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            parse_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed maps line: {:?}", line),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<MapRegion> {
+    let (range, rest) = split_field(line)?;
+    let (perms, rest) = split_field(rest)?;
+    let (offset, rest) = split_field(rest)?;
+    let (dev, rest) = split_field(rest)?;
+    let (inode, rest) = split_field(rest).unwrap_or((rest, ""));
+
+    let (start, end) = range.split_once('-')?;
+    let (major, minor) = dev.split_once(':')?;
+
+    let perms = perms.as_bytes();
+
+    if perms.len() != 4 {
+        return None;
+    }
+
+    Some(MapRegion {
+        start: usize::from_str_radix(start, 16).ok()?,
+        end: usize::from_str_radix(end, 16).ok()?,
+        perms: Permissions {
+            read: perms[0] == b'r',
+            write: perms[1] == b'w',
+            exec: perms[2] == b'x',
+            shared: perms[3] == b's',
+        },
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        dev: (
+            u32::from_str_radix(major, 16).ok()?,
+            u32::from_str_radix(minor, 16).ok()?,
+        ),
+        inode: inode.parse().ok()?,
+        pathname: rest.trim_start().to_owned(),
+    })
+}
+
+/// Split off the first space-separated field, leaving the remainder untrimmed
+/// since pathnames may contain spaces
+fn split_field(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start_matches(' ');
+
+    match s.find(' ') {
+        Some(idx) => Some((&s[..idx], &s[idx + 1..])),
+        None if !s.is_empty() => Some((s, "")),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{maps, MmapMut};
+
+    #[test]
+    fn parses_lines() {
+        let regions = maps::parse_str(
+            "00400000-00452000 r-xp 00000000 08:02 173521      /usr/bin/my program\n\
+             7ffd1e000000-7ffd1e021000 rw-p 00000000 00:00 0                          [stack]\n\
+             7f0000000000-7f0000001000 rw-s 00001000 00:05 42\n",
+        )
+        .unwrap();
+
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[0].start, 0x400000);
+        assert_eq!(regions[0].len(), 0x52000);
+        assert!(regions[0].perms.exec && !regions[0].perms.write);
+        assert_eq!(regions[0].dev, (8, 2));
+        assert_eq!(regions[0].inode, 173521);
+        assert_eq!(regions[0].pathname, "/usr/bin/my program");
+        assert_eq!(regions[1].pathname, "[stack]");
+        assert!(regions[2].perms.shared);
+        assert_eq!(regions[2].offset, 0x1000);
+        assert_eq!(regions[2].pathname, "");
+
+        assert_eq!(maps::find_region(&regions, 0x400010), Some(&regions[0]));
+        assert_eq!(maps::find_region(&regions, 0x452000), None);
+
+        assert!(maps::parse_str("garbage").is_err());
+    }
+
+    #[test]
+    fn sums_anon_huge_pages() {
+        let smaps = "00400000-00600000 rw-p 00000000 00:00 0\n\
+                     Size:               2048 kB\n\
+                     AnonHugePages:      2048 kB\n\
+                     VmFlags: rd wr mr mw me ac hg\n\
+                     00600000-00a00000 rw-p 00000000 00:00 0\n\
+                     AnonHugePages:      4096 kB\n";
+
+        assert_eq!(
+            maps::anon_huge_pages_str(smaps, 0x400000, 0x401000).unwrap(),
+            2 << 20
+        );
+        assert_eq!(
+            maps::anon_huge_pages_str(smaps, 0x500000, 0x700000).unwrap(),
+            6 << 20
+        );
+        assert_eq!(maps::anon_huge_pages_str(smaps, 0, 0x1000).unwrap(), 0);
+        assert!(maps::anon_huge_pages_str("AnonHugePages: lots", 0, 1).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn finds_own_mapping() {
+        let map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let regions = maps::parse_self().unwrap();
+        let region = maps::find_region(&regions, map.ptr as usize).unwrap();
+
+        assert!(region.perms.shared);
+        assert!(region.perms.write);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{io, marker::PhantomData, mem, slice};
+
+use crate::{Mmap, MmapMut, Pod};
+
+/// A read-only 2-D view of values of `T` laid out row by row in a mapping,
+/// created with [`Mmap::as_matrix`]
+///
+/// Consecutive rows start `stride` elements apart, which may be more than the
+/// number of columns when the rows of the dump are padded.
+#[derive(Clone, Copy)]
+pub struct MatrixView<'a, T: Pod> {
+    ptr: *const T,
+    rows: usize,
+    cols: usize,
+    stride: usize,
+    _map: PhantomData<&'a [T]>,
+}
+
+unsafe impl<'a, T: Pod + Sync> Send for MatrixView<'a, T> {}
+unsafe impl<'a, T: Pod + Sync> Sync for MatrixView<'a, T> {}
+
+impl<'a, T: Pod> MatrixView<'a, T> {
+    fn new(
+        ptr: *const u8,
+        len: usize,
+        rows: usize,
+        cols: usize,
+        stride: usize,
+    ) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+        if mem::size_of::<T>() == 0 {
+            return Err(invalid("cannot view zero-sized elements"));
+        }
+
+        if stride < cols {
+            return Err(invalid("row stride is shorter than a row"));
+        }
+
+        let size = match rows.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|start| start.checked_add(cols))
+                .and_then(|count| count.checked_mul(mem::size_of::<T>())),
+            None => Some(0),
+        };
+
+        if !matches!(size, Some(size) if size <= len) {
+            return Err(invalid("matrix is larger than the mapping"));
+        }
+
+        if !(ptr as usize).is_multiple_of(mem::align_of::<T>()) {
+            return Err(invalid("mapping is not aligned for the element type"));
+        }
+
+        Ok(Self {
+            ptr: ptr as *const T,
+            rows,
+            cols,
+            stride,
+            _map: PhantomData,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Distance between the starts of consecutive rows, in elements
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Row `row`, or `None` if it is out of bounds
+    pub fn row(&self, row: usize) -> Option<&'a [T]> {
+        if row >= self.rows {
+            return None;
+        }
+
+        Some(unsafe { slice::from_raw_parts(self.ptr.add(row * self.stride), self.cols) })
+    }
+
+    /// The element at `row` and `col`, or `None` if either is out of bounds
+    pub fn get(&self, row: usize, col: usize) -> Option<&'a T> {
+        self.row(row)?.get(col)
+    }
+
+    /// Every row, in order
+    pub fn iter_rows(&self) -> impl ExactSizeIterator<Item = &'a [T]> + '_ {
+        (0..self.rows).map(|row| self.row(row).unwrap())
+    }
+
+    /// The same view as an [`ndarray::ArrayView2`]
+    #[cfg(feature = "ndarray")]
+    pub fn to_ndarray(&self) -> ndarray::ArrayView2<'a, T> {
+        use ndarray::ShapeBuilder;
+
+        let len = match self.rows {
+            0 => 0,
+            rows => (rows - 1) * self.stride + self.cols,
+        };
+
+        let elems = unsafe { slice::from_raw_parts(self.ptr, len) };
+
+        // the bounds were checked when the view was created
+        ndarray::ArrayView2::from_shape((self.rows, self.cols).strides((self.stride, 1)), elems)
+            .unwrap()
+    }
+}
+
+macro_rules! matrix_impl {
+    ($name:ident) => {
+        impl<'a> $name<'a> {
+            /// View the start of the mapping as a matrix of `rows` rows of
+            /// `cols` values of `T`, with each row starting `stride` elements
+            /// after the previous one
+            ///
+            /// Fails if the matrix does not fit in the mapping, `stride` is
+            /// less than `cols`, or the mapping is not aligned for `T`.
+            pub fn as_matrix<T: Pod>(
+                &self,
+                rows: usize,
+                cols: usize,
+                stride: usize,
+            ) -> io::Result<MatrixView<'_, T>> {
+                MatrixView::new(self.ptr, self.len, rows, cols, stride)
+            }
+        }
+    };
+}
+
+matrix_impl!(Mmap);
+matrix_impl!(MmapMut);
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::MmapMut;
+
+    #[test]
+    fn strided_rows() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        // 3 rows of 4 floats, padded to 5
+        for (idx, chunk) in map[..60].chunks_mut(4).enumerate() {
+            chunk.copy_from_slice(&(idx as f32).to_ne_bytes());
+        }
+
+        let matrix = map.as_matrix::<f32>(3, 4, 5).unwrap();
+
+        assert_eq!(matrix.row(1).unwrap(), [5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(matrix.get(2, 3), Some(&13.0));
+        assert_eq!(matrix.get(2, 4), None);
+        assert_eq!(matrix.get(3, 0), None);
+        assert_eq!(matrix.iter_rows().len(), 3);
+
+        assert!(map.as_matrix::<f32>(3, 6, 5).is_err());
+        assert!(map.as_matrix::<u64>(512, 1, 1).is_ok());
+        assert!(map.as_matrix::<u64>(513, 1, 1).is_err());
+        assert!(map.as_matrix::<u64>(usize::MAX, 2, 2).is_err());
+
+        #[cfg(feature = "ndarray")]
+        {
+            let array = matrix.to_ndarray();
+
+            assert_eq!(array.dim(), (3, 4));
+            assert_eq!(array[[2, 1]], 11.0);
+            assert_eq!(array.column(0).to_vec(), [0.0, 5.0, 10.0]);
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    ffi::CString,
+    fs::File,
+    io,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut, RangeBounds},
+    os::unix::{io::AsRawFd, io::FromRawFd},
+    ptr, slice,
+};
+
+use crate::{file_len_from, flag::UniqueFlag, mmap_raw, munmap_raw, remove, Protection};
+
+/// Seals which make the contents and size of a memfd immutable
+const READ_ONLY_SEALS: i32 =
+    libc::F_SEAL_WRITE | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL;
+
+/// A writable shared mapping of a sealable memfd
+///
+/// The memfd can be handed to other processes through [`file`](Self::file),
+/// for example over a Unix socket, and mapped by them.
+pub struct MemfdMmap {
+    file: File,
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for MemfdMmap {}
+unsafe impl Sync for MemfdMmap {}
+
+impl MemfdMmap {
+    /// Create a memfd of `len` bytes which allows sealing, and map it
+    ///
+    /// `name` is only used for debugging, and shows up in `/proc/<pid>/maps`
+    /// and `/proc/<pid>/fd` as `memfd:<name>`.
+    pub fn new(name: &str, len: NonZeroUsize) -> io::Result<Self> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(len.get() as u64)?;
+
+        let ptr = map_memfd(&file, len.get(), Protection::READ | Protection::WRITE)?;
+
+        Ok(Self {
+            file,
+            ptr,
+            len: len.get(),
+        })
+    }
+
+    /// Map the whole of a memfd received from another process for writing
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let len = file_len_from(&file, 0)?;
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty memfd",
+            ));
+        }
+
+        let ptr = map_memfd(&file, len, Protection::READ | Protection::WRITE)?;
+
+        Ok(Self { file, ptr, len })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Map the same memory a second time with protection `prot`, for example
+    /// to hand a read-only view to less trusted code while keeping this one
+    /// writable
+    ///
+    /// The alias borrows `self`, so the memfd outlives it.
+    pub fn alias(&self, prot: Protection) -> io::Result<MemfdAlias<'_>> {
+        MemfdAlias::new(&self.file, self.len, prot)
+    }
+
+    /// Free the memory backing the page aligned `range`, which reads as zeros
+    /// afterwards in every mapping of the memfd
+    ///
+    /// See [`MmapMut::remove_range`](crate::MmapMut::remove_range).
+    pub fn remove_range(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        remove::remove_range(self.ptr, self.len, range)
+    }
+
+    /// Seal the memfd so that no new writable mappings or `write(2)`s of it
+    /// can be made, while this existing writable mapping keeps working
+    ///
+    /// This is the ashmem-style sharing pattern: the producer keeps updating
+    /// the contents, and consumers receiving the file descriptor can only ever
+    /// map it read-only. Also applies `F_SEAL_SHRINK` and `F_SEAL_GROW`, so
+    /// consumers can rely on the size never changing.
+    ///
+    /// (since Linux 5.1)
+    pub fn seal_future_write(&self) -> io::Result<()> {
+        let seals = libc::F_SEAL_FUTURE_WRITE | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_ADD_SEALS, seals) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Unmap the writable view and seal the memfd against any further writes
+    /// or resizing, returning a read-only view of the now immutable contents
+    ///
+    /// Applies `F_SEAL_WRITE`, `F_SEAL_SHRINK`, `F_SEAL_GROW` and
+    /// `F_SEAL_SEAL`. Sealing fails with `EBUSY` if any other process still
+    /// holds a writable shared mapping of the memfd, in which case the
+    /// writable mapping is handed back along with the error. Should mapping
+    /// the memfd for writing again fail as well, the mapping handed back is
+    /// read-only, and writing to it raises `SIGSEGV`.
+    pub fn seal_read_only(mut self) -> Result<SealedMmap, (Self, io::Error)> {
+        // private mappings do not count as writable when sealing, and see
+        // the same pages as long as nothing writes through them
+        let read_only = match unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                self.len,
+                Protection::READ,
+                UniqueFlag::MAP_PRIVATE.0,
+                self.file.as_raw_fd(),
+                0,
+            )
+        } {
+            Ok(ptr) => ptr,
+            Err(err) => return Err((self, err)),
+        };
+
+        // F_SEAL_WRITE is refused while writable shared mappings exist
+        unsafe { munmap_raw(self.ptr, self.len) };
+
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_ADD_SEALS, READ_ONLY_SEALS) } != 0 {
+            let err = io::Error::last_os_error();
+
+            // nothing was sealed, so the memfd can be mapped for writing again,
+            // and until that succeeds the read-only view keeps `self` valid
+            self.ptr = read_only;
+
+            if let Ok(ptr) = map_memfd(&self.file, self.len, Protection::READ | Protection::WRITE) {
+                unsafe { munmap_raw(read_only, self.len) };
+
+                self.ptr = ptr;
+            }
+
+            return Err((self, err));
+        }
+
+        let this = ManuallyDrop::new(self);
+
+        Ok(SealedMmap {
+            file: unsafe { ptr::read(&this.file) },
+            ptr: read_only,
+            len: this.len,
+        })
+    }
+}
+
+impl Deref for MemfdMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for MemfdMmap {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MemfdMmap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+/// A read-only mapping of a memfd which is sealed against writes and resizing
+///
+/// Because the kernel guarantees that nobody can modify the contents, the
+/// mapping can soundly be shared as a `&[u8]` even with untrusted producers.
+pub struct SealedMmap {
+    file: File,
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for SealedMmap {}
+unsafe impl Sync for SealedMmap {}
+
+impl SealedMmap {
+    /// Map a memfd received from another process, verifying that it carries
+    /// the seals applied by [`MemfdMmap::seal_read_only`]
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let seals = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GET_SEALS) };
+
+        if seals < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if seals & READ_ONLY_SEALS != READ_ONLY_SEALS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "memfd is not sealed read-only",
+            ));
+        }
+
+        let len = file_len_from(&file, 0)?;
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty memfd",
+            ));
+        }
+
+        let ptr = map_memfd(&file, len, Protection::READ)?;
+
+        Ok(Self { file, ptr, len })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Map the same memory a second time with protection `prot`, which must
+    /// not include [`Protection::WRITE`] as the memfd is sealed against writes
+    pub fn alias(&self, prot: Protection) -> io::Result<MemfdAlias<'_>> {
+        MemfdAlias::new(&self.file, self.len, prot)
+    }
+}
+
+impl Deref for SealedMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for SealedMmap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+/// A second mapping of the memory of a [`MemfdMmap`] or [`SealedMmap`],
+/// possibly with a different protection
+///
+/// Writes through any view are immediately visible through all others, so the
+/// contents are only exposed as a raw pointer; see
+/// [`as_slice`](Self::as_slice) for the conditions under which they may be
+/// borrowed.
+pub struct MemfdAlias<'a> {
+    ptr: *mut u8,
+    len: usize,
+    prot: Protection,
+    _backing: PhantomData<&'a File>,
+}
+
+unsafe impl<'a> Send for MemfdAlias<'a> {}
+unsafe impl<'a> Sync for MemfdAlias<'a> {}
+
+impl<'a> MemfdAlias<'a> {
+    fn new(file: &'a File, len: usize, prot: Protection) -> io::Result<Self> {
+        Ok(Self {
+            ptr: map_memfd(file, len, prot)?,
+            len,
+            prot,
+            _backing: PhantomData,
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn protection(&self) -> Protection {
+        self.prot
+    }
+
+    /// Borrow the contents of a readable alias
+    ///
+    /// # Safety
+    ///
+    /// Nothing may write to the memory through any view, in this or another
+    /// process, while the slice is alive.
+    pub unsafe fn as_slice(&self) -> io::Result<&[u8]> {
+        if !self.prot.contains(Protection::READ) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "alias is not readable",
+            ));
+        }
+
+        Ok(slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+impl<'a> Drop for MemfdAlias<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+fn map_memfd(file: &File, len: usize, prot: Protection) -> io::Result<*mut u8> {
+    unsafe {
+        mmap_raw(
+            ptr::null_mut(),
+            len,
+            prot,
+            UniqueFlag::MAP_SHARED.0,
+            file.as_raw_fd(),
+            0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, num::NonZeroUsize};
+
+    use crate::{MemfdMmap, Mmap, MmapMut, Protection, SealedMmap};
+
+    #[test]
+    fn aliases_share_memory() {
+        let mut memfd = MemfdMmap::new("alias-test", NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        memfd[0] = 1;
+
+        {
+            let read_only = memfd.alias(Protection::READ).unwrap();
+            let writable = memfd.alias(Protection::READ | Protection::WRITE).unwrap();
+
+            assert_ne!(read_only.as_ptr(), writable.as_ptr());
+
+            unsafe { writable.as_ptr().write_volatile(2) };
+
+            assert_eq!(unsafe { read_only.as_ptr().read_volatile() }, 2);
+            assert!(unsafe { memfd.alias(Protection::NONE).unwrap().as_slice() }.is_err());
+        }
+
+        memfd[1] = 3;
+
+        let sealed = memfd.seal_read_only().map_err(|(_, err)| err).unwrap();
+
+        assert_eq!(
+            unsafe { sealed.alias(Protection::READ).unwrap().as_slice().unwrap()[..2].to_vec() },
+            [2, 3]
+        );
+        assert!(sealed.alias(Protection::READ | Protection::WRITE).is_err());
+    }
+
+    #[test]
+    fn seal_read_only() {
+        let mut memfd = MemfdMmap::new("seal-test", NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        memfd[..5].copy_from_slice(b"hello");
+
+        let sealed = memfd.seal_read_only().map_err(|(_, err)| err).unwrap();
+
+        assert_eq!(&sealed[..5], b"hello");
+        assert!(sealed.file().set_len(8192).is_err());
+        assert!(sealed.file().write(b"x").is_err());
+
+        let received = SealedMmap::from_file(sealed.file().try_clone().unwrap()).unwrap();
+
+        assert_eq!(&received[..5], b"hello");
+
+        let unsealed = MemfdMmap::new("unsealed", NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        assert!(SealedMmap::from_file(unsealed.file().try_clone().unwrap()).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn failed_seal_returns_memfd() {
+        let memfd = MemfdMmap::new("seal-busy", NonZeroUsize::new(4096).unwrap()).unwrap();
+        let other = MmapMut::new_file(memfd.file()).unwrap();
+
+        let (mut memfd, err) = memfd.seal_read_only().err().unwrap();
+
+        assert_eq!(err.raw_os_error(), Some(libc::EBUSY));
+
+        memfd[0] = 1;
+        assert_eq!(other[0], 1);
+
+        drop(other);
+
+        assert_eq!(memfd.seal_read_only().ok().unwrap()[0], 1);
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn seal_future_write() {
+        let mut memfd = MemfdMmap::new("future-write", NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        memfd.seal_future_write().unwrap();
+        memfd[0] = 1;
+
+        let consumer = Mmap::new_file(memfd.file()).unwrap();
+
+        assert_eq!(consumer[0], 1);
+        assert!(MmapMut::new_file(memfd.file()).is_err());
+        assert!(memfd.file().write(b"x").is_err());
+
+        memfd[0] = 2;
+
+        assert_eq!(consumer[0], 2);
+    }
+}
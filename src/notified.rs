@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::UnixStream,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    scm::{recv_fd, send_fd},
+    MemfdMmap,
+};
+
+/// A shared memfd mapping bundled with an eventfd for wake-ups
+///
+/// Writers update the memory and then call [`notify`](Self::notify); readers
+/// block in [`wait`](Self::wait), or register the eventfd exposed through
+/// [`AsRawFd`] with an epoll-based event loop and call
+/// [`try_wait`](Self::try_wait) when it becomes readable.
+///
+/// The eventfd counts notifications, so several `notify` calls made before a
+/// reader gets to run are coalesced into a single wake-up and none are lost.
+/// Reading the count resets it, so each eventfd should have a single reader.
+/// The system calls involved act as full memory barriers, so a reader woken
+/// by a notification sees every write made before it.
+pub struct NotifiedMmap {
+    map: MemfdMmap,
+    eventfd: File,
+}
+
+impl NotifiedMmap {
+    /// Create a memfd of `len` bytes and an eventfd, and map the memfd
+    pub fn new(name: &str, len: NonZeroUsize) -> io::Result<Self> {
+        let map = MemfdMmap::new(name, len)?;
+
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            map,
+            eventfd: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Send the memfd and eventfd to the process at the other end of `stream`
+    pub fn send_over(&self, stream: &UnixStream) -> io::Result<()> {
+        send_fd(stream, self.map.file())?;
+        send_fd(stream, &self.eventfd)
+    }
+
+    /// Receive and map a pair sent with [`send_over`](Self::send_over)
+    pub fn recv_from(stream: &UnixStream) -> io::Result<Self> {
+        let map = MemfdMmap::from_file(recv_fd(stream)?)?;
+        let eventfd = recv_fd(stream)?;
+
+        Ok(Self { map, eventfd })
+    }
+
+    /// Wake the reader after updating the memory
+    pub fn notify(&self) -> io::Result<()> {
+        (&self.eventfd).write_all(&1_u64.to_ne_bytes())
+    }
+
+    /// Consume pending notifications without blocking, returning how many
+    /// there were
+    pub fn try_wait(&self) -> io::Result<u64> {
+        let mut count = [0; 8];
+
+        match (&self.eventfd).read(&mut count) {
+            Ok(_) => Ok(u64::from_ne_bytes(count)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Block until notified or until `timeout` elapses, returning the number
+    /// of notifications consumed, which is zero only on timeout
+    ///
+    /// Spurious wake-ups, such as from signals, are retried internally.
+    pub fn wait(&self, timeout: Option<Duration>) -> io::Result<u64> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let count = self.try_wait()?;
+
+            if count != 0 {
+                return Ok(count);
+            }
+
+            let timeout_ms = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+
+                    if left.is_zero() {
+                        return Ok(0);
+                    }
+
+                    // round up so a sub-millisecond remainder still sleeps
+                    left.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+                }
+                None => -1,
+            };
+
+            let mut pfd = libc::pollfd {
+                fd: self.eventfd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            if unsafe { libc::poll(&mut pfd, 1, timeout_ms) } < 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for NotifiedMmap {
+    /// The eventfd, which becomes readable when notified
+    fn as_raw_fd(&self) -> RawFd {
+        self.eventfd.as_raw_fd()
+    }
+}
+
+impl Deref for NotifiedMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl DerefMut for NotifiedMmap {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, os::unix::net::UnixStream, time::Duration};
+
+    use crate::NotifiedMmap;
+
+    #[test]
+    fn notify_across_fd_passing() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let mut writer = NotifiedMmap::new("notified", NonZeroUsize::new(4096).unwrap()).unwrap();
+        writer.send_over(&a).unwrap();
+
+        let reader = NotifiedMmap::recv_from(&b).unwrap();
+
+        assert_eq!(reader.try_wait().unwrap(), 0);
+        assert_eq!(reader.wait(Some(Duration::from_millis(1))).unwrap(), 0);
+
+        writer[0] = 5;
+        writer.notify().unwrap();
+        writer.notify().unwrap();
+
+        assert_eq!(reader.wait(None).unwrap(), 2);
+        assert_eq!(reader[0], 5);
+        assert_eq!(reader.try_wait().unwrap(), 0);
+    }
+}
@@ -0,0 +1,689 @@
+use std::{fs::File, io, marker::PhantomData, num::NonZeroUsize, os::unix::io::AsRawFd};
+
+use crate::{
+    flag::{Flag, UniqueFlag},
+    lock::{FileLock, LockKind},
+    madvise_raw, mmap_raw, munmap_raw, page_size, round_up_to_page, Backing, FlushOnDrop, Mmap,
+    MmapMut, Protection, ThreadStack,
+};
+
+/// Builder for mappings which need more control than [`Mmap::new_anon`] and
+/// friends provide
+#[derive(Clone)]
+pub struct MmapOptions {
+    len: usize,
+    exec: bool,
+    sharing: UniqueFlag,
+    flags: Flag,
+    guard_pages: usize,
+    lock: Option<LockKind>,
+    offset: u64,
+    align: usize,
+    huge_pages: bool,
+    dont_fork: bool,
+    addr_hint: usize,
+    flush_on_drop: FlushOnDrop,
+}
+
+impl MmapOptions {
+    pub fn new(len: NonZeroUsize) -> Self {
+        Self {
+            len: len.get(),
+            exec: false,
+            sharing: UniqueFlag::MAP_SHARED,
+            flags: Flag(0),
+            guard_pages: 0,
+            lock: None,
+            offset: 0,
+            align: 0,
+            huge_pages: false,
+            dont_fork: false,
+            addr_hint: 0,
+            flush_on_drop: FlushOnDrop::None,
+        }
+    }
+
+    /// Preset for a thread or fiber stack of at least `size` usable bytes
+    ///
+    /// The size is rounded up to a whole number of pages and to at least
+    /// `PTHREAD_STACK_MIN`. The mapping is private, marked `MAP_STACK`, and has
+    /// a single guard page below the lowest usable address so that overflowing
+    /// the stack faults rather than silently corrupting adjacent memory.
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if rounding
+    /// `size` up overflows.
+    pub fn thread_stack(size: usize) -> io::Result<Self> {
+        let size = size
+            .max(libc::PTHREAD_STACK_MIN)
+            .checked_next_multiple_of(page_size())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "stack size overflows when rounded up to pages",
+                )
+            })?;
+
+        let mut options = Self::new(NonZeroUsize::new(size).unwrap());
+
+        options.private().stack().guard_pages(1);
+
+        Ok(options)
+    }
+
+    /// Preset for an anonymous mapping of at least `len` bytes laid out to be
+    /// backed by transparent huge pages
+    ///
+    /// The length is rounded up to, and the start aligned to, the huge page
+    /// size, so no part of the mapping straddles a huge page boundary and has
+    /// to fall back to small pages, and the mapping is private and advised
+    /// with `MADV_HUGEPAGE`. Whether huge pages are actually used is still up
+    /// to the kernel; check with
+    /// [`MmapMut::anon_huge_pages`](crate::MmapMut::anon_huge_pages).
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if rounding
+    /// `len` up overflows.
+    pub fn thp_friendly(len: usize) -> io::Result<Self> {
+        let huge_page = huge_page_size();
+        let len = len
+            .max(1)
+            .checked_next_multiple_of(huge_page)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "length overflows when rounded up to huge pages",
+                )
+            })?;
+
+        let mut options = Self::new(NonZeroUsize::new(len).unwrap());
+
+        options.private().align(huge_page).huge_pages();
+
+        Ok(options)
+    }
+
+    /// Preset for the memory of a KVM guest of at least `size` bytes, as used
+    /// by [`GuestMemory`](crate::GuestMemory)
+    ///
+    /// The size is rounded up to whole pages, as `KVM_SET_USER_MEMORY_REGION`
+    /// requires, and guests of at least a huge page are aligned to the huge
+    /// page size so that huge pages in the guest can be backed by huge pages
+    /// on the host. The mapping is private and excluded from forks with
+    /// [`dont_fork`](Self::dont_fork). Add [`huge_pages`](Self::huge_pages)
+    /// or [`populate`](Self::populate) as needed.
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if rounding
+    /// `size` up overflows.
+    pub fn guest_memory(size: usize) -> io::Result<Self> {
+        let size = size
+            .max(1)
+            .checked_next_multiple_of(page_size())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "guest memory size overflows when rounded up to pages",
+                )
+            })?;
+
+        let mut options = Self::new(NonZeroUsize::new(size).unwrap());
+
+        options.private().dont_fork();
+
+        if size >= huge_page_size() {
+            options.align(huge_page_size());
+        }
+
+        Ok(options)
+    }
+
+    /// Length of the usable part of the mapping, excluding guard pages
+    pub fn len(&mut self, len: NonZeroUsize) -> &mut Self {
+        self.len = len.get();
+        self
+    }
+
+    /// Allow pages to be executed
+    pub fn exec(&mut self) -> &mut Self {
+        self.exec = true;
+        self
+    }
+
+    /// Create a private copy-on-write mapping rather than one shared with
+    /// child processes
+    pub fn private(&mut self) -> &mut Self {
+        self.sharing = UniqueFlag::MAP_PRIVATE;
+        self
+    }
+
+    pub(crate) fn is_private(&self) -> bool {
+        self.sharing.0 == libc::MAP_PRIVATE
+    }
+
+    /// Prefault page tables for the mapping
+    pub fn populate(&mut self) -> &mut Self {
+        self.flags = self.flags | Flag::MAP_POPULATE;
+        self
+    }
+
+    /// Mark the mapping as suitable for a process or thread stack
+    pub fn stack(&mut self) -> &mut Self {
+        self.flags = self.flags | Flag::MAP_STACK;
+        self
+    }
+
+    /// Skip zeroing the pages of anonymous mappings with `MAP_UNINITIALIZED`
+    ///
+    /// Only kernels built with `CONFIG_MMAP_ALLOW_UNINITIALIZED`, which is
+    /// meant for embedded systems without untrusted processes, honour the
+    /// flag; elsewhere it is ignored and pages are zeroed as usual.
+    ///
+    /// # Safety
+    ///
+    /// The pages may hold data left behind by any process, including other
+    /// users' secrets, and must be treated as uninitialized: they must be
+    /// written before they are read.
+    pub unsafe fn uninitialized(&mut self) -> &mut Self {
+        self.flags = self.flags | Flag::MAP_UNINITIALIZED;
+        self
+    }
+
+    /// Advise anonymous mappings with `MADV_HUGEPAGE`, so the kernel backs
+    /// them with transparent huge pages even when those are only enabled on
+    /// request
+    ///
+    /// Mapping fails with [`Unsupported`](io::ErrorKind::Unsupported) if the
+    /// kernel lacks transparent huge page support.
+    pub fn huge_pages(&mut self) -> &mut Self {
+        self.huge_pages = true;
+        self
+    }
+
+    /// Keep anonymous mappings out of child processes with `MADV_DONTFORK`
+    ///
+    /// Memory shared with a device or the kernel, such as guest memory
+    /// registered with KVM or buffers pinned for DMA, must not become
+    /// copy-on-write in the parent when it forks, and children rarely need
+    /// large buffers.
+    pub fn dont_fork(&mut self) -> &mut Self {
+        self.dont_fork = true;
+        self
+    }
+
+    /// Offset into the file at which file mappings start, which must be a
+    /// multiple of the page size
+    ///
+    /// Offsets are 64-bit on every target, so windows beyond 4GiB into large
+    /// files can be mapped on 32-bit targets too.
+    pub fn offset(&mut self, offset: u64) -> &mut Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Start the usable part of the mapping at a multiple of `alignment`,
+    /// which must be a power of two
+    ///
+    /// Mappings are always page aligned; for larger alignments, such as 2MiB
+    /// to line up with huge pages, a larger range is reserved and trimmed to
+    /// the aligned part, whatever address the kernel picks. Moving the
+    /// mapping, such as by growing it, may lose the alignment.
+    pub fn align(&mut self, alignment: usize) -> &mut Self {
+        self.align = alignment;
+        self
+    }
+
+    /// Ask for the usable part of the mapping to start at `addr`, without
+    /// `MAP_FIXED`
+    ///
+    /// The kernel places the mapping there if the range is free, and anywhere
+    /// else otherwise, so existing mappings are never replaced. Use
+    /// [`hint_honored`](Self::hint_honored) to find out which happened.
+    pub fn addr_hint(&mut self, addr: *const u8) -> &mut Self {
+        self.addr_hint = addr as usize;
+        self
+    }
+
+    /// Whether `map`, created from these options, was placed at the address
+    /// passed to [`addr_hint`](Self::addr_hint)
+    pub fn hint_honored(&self, map: &[u8]) -> bool {
+        self.addr_hint != 0 && map.as_ptr() as usize == self.addr_hint
+    }
+
+    /// Reserve `pages` inaccessible pages directly below the mapping
+    pub fn guard_pages(&mut self, pages: usize) -> &mut Self {
+        self.guard_pages = pages;
+        self
+    }
+
+    /// Take a shared `flock(2)` lock on the backing file before mapping it,
+    /// blocking until any exclusive lock is released, and hold it for the
+    /// lifetime of the mapping
+    ///
+    /// Only applies to file mappings.
+    pub fn shared_lock(&mut self) -> &mut Self {
+        self.lock = Some(LockKind::Shared);
+        self
+    }
+
+    /// Take an exclusive `flock(2)` lock on the backing file before mapping
+    /// it, blocking until all other locks are released, and hold it for the
+    /// lifetime of the mapping
+    ///
+    /// Only applies to file mappings. Locks are advisory, so this only
+    /// excludes other processes which also lock the file.
+    pub fn exclusive_lock(&mut self) -> &mut Self {
+        self.lock = Some(LockKind::Exclusive);
+        self
+    }
+
+    /// Write dirty pages of writable mappings back to the file when they are
+    /// dropped, either waiting for the write-back or only starting it
+    pub fn flush_on_drop(&mut self, policy: FlushOnDrop) -> &mut Self {
+        self.flush_on_drop = policy;
+        self
+    }
+
+    /// Map the guard pages and usable region, returning the start of the whole
+    /// reservation
+    fn map(&self, prot: Protection) -> io::Result<(*mut u8, usize)> {
+        let (ptr, guard_len) = self.map_fd(prot, -1)?;
+
+        let advise = |advice| unsafe { madvise_raw(ptr.add(guard_len), self.len, advice) };
+
+        let mut advised = Ok(());
+
+        if self.huge_pages {
+            advised = advise(libc::MADV_HUGEPAGE).map_err(|e| match e.raw_os_error() {
+                Some(libc::EINVAL) => io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "transparent huge pages are not supported by this kernel",
+                ),
+                _ => e,
+            });
+        }
+
+        if self.dont_fork {
+            advised = advised.and_then(|()| advise(libc::MADV_DONTFORK));
+        }
+
+        if let Err(e) = advised {
+            unsafe { munmap_raw(ptr, guard_len + self.len) };
+            return Err(e);
+        }
+
+        Ok((ptr, guard_len))
+    }
+
+    fn map_fd(&self, prot: Protection, fd: i32) -> io::Result<(*mut u8, usize)> {
+        let prot = if self.exec {
+            prot | Protection::EXEC
+        } else {
+            prot
+        };
+
+        let guard_len = self.guard_len()?;
+        let align = self.alignment()?;
+
+        let total = guard_len.checked_add(self.len).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "mapping length overflows")
+        })?;
+
+        let (flags, offset) = if fd == -1 {
+            (self.flags | Flag::MAP_ANONYMOUS, 0)
+        } else {
+            (self.flags, self.file_offset()?)
+        };
+
+        if guard_len == 0 && align == page_size() {
+            let ptr = unsafe {
+                mmap_raw(
+                    self.addr_hint as *mut u8,
+                    total,
+                    prot,
+                    (self.sharing | flags).0,
+                    fd,
+                    offset,
+                )?
+            };
+
+            return Ok((ptr, 0));
+        }
+
+        // reserve the whole range inaccessible, then map the usable part over
+        // the top so that a file mapping starts at the requested offset
+        let hint = self.addr_hint.saturating_sub(guard_len);
+        let ptr = reserve_aligned(hint as *mut u8, total, guard_len, align)?;
+
+        if let Err(e) = unsafe {
+            mmap_raw(
+                ptr.add(guard_len),
+                self.len,
+                prot,
+                (self.sharing | flags | Flag::MAP_FIXED).0,
+                fd,
+                offset,
+            )
+        } {
+            unsafe { munmap_raw(ptr, total) };
+            return Err(e);
+        }
+
+        Ok((ptr, guard_len))
+    }
+
+    fn file_offset(&self) -> io::Result<i64> {
+        if !self.offset.is_multiple_of(page_size() as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is not a multiple of the page size",
+            ));
+        }
+
+        i64::try_from(self.offset)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "offset is too large"))
+    }
+
+    fn alignment(&self) -> io::Result<usize> {
+        if self.align != 0 && !self.align.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "alignment is not a power of two",
+            ));
+        }
+
+        Ok(self.align.max(page_size()))
+    }
+
+    fn guard_len(&self) -> io::Result<usize> {
+        self.guard_pages
+            .checked_mul(page_size())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "guard region overflows"))
+    }
+
+    pub fn map_anon<'a>(&self) -> io::Result<Mmap<'a>> {
+        let (ptr, guard_len) = self.map(Protection::READ)?;
+
+        Ok(Mmap {
+            ptr: unsafe { ptr.add(guard_len) },
+            len: self.len,
+            offset: 0,
+            backing: Backing::Mapped { guard_len },
+            _lock: None,
+            _lifetime: PhantomData,
+        })
+    }
+
+    pub fn map_anon_mut<'a>(&self) -> io::Result<MmapMut<'a>> {
+        let (ptr, guard_len) = self.map(Protection::READ | Protection::WRITE)?;
+
+        Ok(MmapMut {
+            ptr: unsafe { ptr.add(guard_len) },
+            len: self.len,
+            offset: 0,
+            backing: Backing::Mapped { guard_len },
+            _lock: None,
+            flush_on_drop: self.flush_on_drop,
+            _lifetime: PhantomData,
+        })
+    }
+
+    /// Map `len` bytes of `file` starting at the configured offset, taking any
+    /// requested lock first
+    pub fn map_file<'a>(&self, file: &File) -> io::Result<Mmap<'a>> {
+        let lock = self.lock(file)?;
+        let (ptr, guard_len) = self.map_fd(Protection::READ, file.as_raw_fd())?;
+
+        Ok(Mmap {
+            ptr: unsafe { ptr.add(guard_len) },
+            len: self.len,
+            offset: self.offset,
+            backing: Backing::Mapped { guard_len },
+            _lock: lock,
+            _lifetime: PhantomData,
+        })
+    }
+
+    /// Map `len` bytes of `file` for writing starting at the configured
+    /// offset, taking any requested lock first
+    pub fn map_file_mut<'a>(&self, file: &File) -> io::Result<MmapMut<'a>> {
+        let lock = self.lock(file)?;
+        let (ptr, guard_len) =
+            self.map_fd(Protection::READ | Protection::WRITE, file.as_raw_fd())?;
+
+        Ok(MmapMut {
+            ptr: unsafe { ptr.add(guard_len) },
+            len: self.len,
+            offset: self.offset,
+            backing: Backing::Mapped { guard_len },
+            _lock: lock,
+            flush_on_drop: self.flush_on_drop,
+            _lifetime: PhantomData,
+        })
+    }
+
+    fn lock(&self, file: &File) -> io::Result<Option<FileLock>> {
+        self.lock
+            .map(|kind| FileLock::acquire(file, kind))
+            .transpose()
+    }
+
+    /// Map a stack, usually configured through [`MmapOptions::thread_stack`]
+    pub fn map_stack(&self) -> io::Result<ThreadStack> {
+        let (ptr, guard_len) = self.map(Protection::READ | Protection::WRITE)?;
+
+        Ok(ThreadStack {
+            base: ptr,
+            guard_len,
+            len: self.len,
+        })
+    }
+}
+
+/// Size of a transparent huge page, which is 2MiB unless the kernel reports
+/// otherwise
+pub(crate) fn huge_page_size() -> usize {
+    std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/hpage_pmd_size")
+        .ok()
+        .and_then(|size| size.trim().parse().ok())
+        .unwrap_or(2 << 20)
+}
+
+/// Reserve `total` inaccessible bytes placed so that the part after the first
+/// `guard_len` bytes starts at a multiple of `align`, by over-reserving and
+/// unmapping the excess on either side
+///
+/// The reservation starts at `hint` if it is free and suitably aligned.
+fn reserve_aligned(
+    hint: *mut u8,
+    total: usize,
+    guard_len: usize,
+    align: usize,
+) -> io::Result<*mut u8> {
+    let len = round_up_to_page(total);
+    let slack = align - page_size();
+
+    let reserved = len
+        .checked_add(slack)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "mapping length overflows"))?;
+
+    let ptr = unsafe {
+        mmap_raw(
+            hint,
+            reserved,
+            Protection::NONE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_NORESERVE).0,
+            -1,
+            0,
+        )?
+    };
+
+    let head = (ptr as usize + guard_len).next_multiple_of(align) - guard_len - ptr as usize;
+
+    unsafe {
+        if head != 0 {
+            munmap_raw(ptr, head);
+        }
+
+        if slack != head {
+            munmap_raw(ptr.add(head + len), slack - head);
+        }
+
+        Ok(ptr.add(head))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io,
+        num::NonZeroUsize,
+        os::unix::{fs::FileExt, io::AsRawFd},
+    };
+
+    use crate::{
+        test_util::{open_file, temp_file, temp_path},
+        LockKind, MmapOptions,
+    };
+
+    fn try_lock(file: &File, op: i32) -> bool {
+        unsafe { libc::flock(file.as_raw_fd(), op | libc::LOCK_NB) == 0 }
+    }
+
+    #[test]
+    fn lock_held_for_mapping_lifetime() {
+        let path = temp_path("lock");
+        let file = open_file(&path);
+
+        file.set_len(4096).unwrap();
+
+        let mut map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .exclusive_lock()
+            .map_file_mut(&file)
+            .unwrap();
+
+        map[0] = 1;
+
+        // a separate open file description contends for the lock
+        let other = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!try_lock(&other, libc::LOCK_SH));
+
+        drop(map);
+
+        assert!(try_lock(&other, libc::LOCK_SH));
+
+        let shared = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .shared_lock()
+            .map_file(&file)
+            .unwrap();
+
+        assert_eq!(shared[0], 1);
+        assert!(!try_lock(&other, libc::LOCK_EX));
+    }
+
+    #[test]
+    fn aligned_mappings() {
+        const HUGE: usize = 2 << 20;
+
+        for guard_pages in [0, 3] {
+            let mut map = MmapOptions::new(NonZeroUsize::new(HUGE + 1).unwrap())
+                .align(HUGE)
+                .guard_pages(guard_pages)
+                .map_anon_mut()
+                .unwrap();
+
+            assert!((map.as_ptr() as usize).is_multiple_of(HUGE));
+
+            map[HUGE] = 1;
+        }
+
+        let file = temp_file("align");
+
+        file.set_len(4096).unwrap();
+        file.write_all_at(b"aligned", 0).unwrap();
+
+        let map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .align(64 << 10)
+            .map_file(&file)
+            .unwrap();
+
+        assert!((map.as_ptr() as usize).is_multiple_of(64 << 10));
+        assert_eq!(&map[..7], b"aligned");
+
+        assert!(MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .align(3 << 10)
+            .map_anon()
+            .is_err());
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn thp_friendly_layout() {
+        assert_eq!(
+            MmapOptions::thp_friendly(usize::MAX).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let mut map = match MmapOptions::thp_friendly(3 << 20).unwrap().map_anon_mut() {
+            Ok(map) => map,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => return,
+            Err(e) => panic!("{e}"),
+        };
+
+        assert!(map.len().is_multiple_of(2 << 20));
+        assert!((map.as_ptr() as usize).is_multiple_of(2 << 20));
+
+        map.fill(1);
+
+        // whether huge pages were used depends on the machine
+        assert!(map.anon_huge_pages().unwrap() <= map.len());
+    }
+
+    #[test]
+    fn address_hints() {
+        let len = NonZeroUsize::new(4096).unwrap();
+
+        // find a free page to hint at, then keep it in use
+        let free = MmapOptions::new(len).map_anon().unwrap().as_ptr();
+
+        let mut options = MmapOptions::new(len);
+        options.addr_hint(free);
+
+        let map = options.map_anon().unwrap();
+
+        assert!(options.hint_honored(&map));
+
+        let other = options.guard_pages(1).map_anon().unwrap();
+
+        assert!(!options.hint_honored(&other));
+        assert!(!MmapOptions::new(len).hint_honored(&map));
+    }
+
+    #[test]
+    fn maps_beyond_4gib() {
+        const OFFSET: u64 = 5 << 30;
+
+        let file = temp_file("large-offset");
+
+        // skip on filesystems without large file support
+        if file.set_len(OFFSET + 4096).is_err() {
+            return;
+        }
+
+        file.write_all_at(b"far", OFFSET + 1).unwrap();
+
+        let map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .offset(OFFSET)
+            .map_file(&file)
+            .unwrap();
+
+        assert_eq!(&map[..4], b"\0far");
+
+        let _lock = map.lock_range(&file, 0..1, LockKind::Exclusive).unwrap();
+
+        assert!(MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .offset(OFFSET + 1)
+            .map_file(&file)
+            .is_err());
+    }
+}
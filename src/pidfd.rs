@@ -0,0 +1,84 @@
+use std::{
+    fs::File,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+
+/// Obtain a pidfd referring to the process `pid` with `pidfd_open(2)`
+///
+/// (since Linux 5.3)
+pub fn pidfd_open(pid: libc::pid_t) -> io::Result<File> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd as i32) })
+    }
+}
+
+/// Duplicate the file descriptor `remote_fd` of the process referred to by
+/// `pidfd` into this process with `pidfd_getfd(2)`
+///
+/// The caller needs `PTRACE_MODE_ATTACH_REALCREDS` permission over the target,
+/// as it would to attach to it with `ptrace(2)`.
+///
+/// (since Linux 5.6)
+pub(crate) fn pidfd_getfd(pidfd: &impl AsRawFd, remote_fd: i32) -> io::Result<File> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_getfd, pidfd.as_raw_fd(), remote_fd, 0) };
+
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd as i32) })
+    }
+}
+
+/// Whether `pid` still exists, judged by whether it can be sent a null signal
+///
+/// A pid too large for `pid_t` cannot name a process, and would otherwise
+/// wrap round to a negative number naming a process group.
+pub(crate) fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+
+    // EPERM means it exists but belongs to someone else
+    io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, os::unix::io::FromRawFd};
+
+    use crate::{pidfd::process_alive, pidfd_open, Mmap, MmapMut};
+
+    #[test]
+    #[cfg_attr(miri, ignore = "sends signals")]
+    fn process_liveness() {
+        assert!(process_alive(std::process::id()));
+        assert!(!process_alive(u32::MAX));
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn maps_fd_from_pidfd() {
+        let fd = unsafe { libc::memfd_create(c"remote-test".as_ptr(), 0) };
+        assert!(fd >= 0);
+
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(4096).unwrap();
+
+        let mut writer = MmapMut::new_file(&file).unwrap();
+        writer[..4].copy_from_slice(b"ping");
+
+        let pidfd = pidfd_open(std::process::id() as i32).unwrap();
+        let map = Mmap::from_remote_fd(&pidfd, fd).unwrap();
+
+        assert_eq!(&map[..4], b"ping");
+    }
+}
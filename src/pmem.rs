@@ -0,0 +1,195 @@
+//! Cache flushing primitives for mappings of persistent memory
+//!
+//! With a `MAP_SYNC` mapping of a DAX file or device, stores become durable
+//! once the cache lines holding them have been written back to the memory
+//! controller. That only requires flushing those lines and a store fence,
+//! which is far cheaper than `msync(2)`.
+
+/// Size of the cache lines written back by [`flush_cache_range`]
+pub const CACHE_LINE: usize = 64;
+
+/// Write back every cache line overlapping `data` without waiting for the
+/// write-back to complete
+///
+/// Uses `clwb` where available, which leaves the lines in the cache, falling
+/// back to `clflushopt` and then `clflush`. Call [`drain`] afterwards to wait
+/// for the stores to become durable.
+pub fn flush_cache_range(data: &[u8]) {
+    // the pointer of an empty slice may dangle
+    if data.is_empty() {
+        return;
+    }
+
+    let start = data.as_ptr() as usize & !(CACHE_LINE - 1);
+    let end = data.as_ptr() as usize + data.len();
+
+    let flush = flush_instruction();
+
+    for line in (start..end).step_by(CACHE_LINE) {
+        unsafe { flush(line as *const u8) };
+    }
+}
+
+/// Wait for all previously issued cache line flushes to complete
+pub fn drain() {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        std::arch::x86_64::_mm_sfence()
+    };
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        std::arch::asm!("dsb sy", options(nostack, preserves_flags))
+    };
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// [`flush_cache_range`] followed by [`drain`]
+pub fn persist(data: &[u8]) {
+    flush_cache_range(data);
+    drain();
+}
+
+/// Copy `src` into `dst` and make the copy durable, like libpmem's
+/// `pmem_memcpy_persist`
+///
+/// The bulk of the copy uses non-temporal stores, which bypass the cache and
+/// so need no flushing, while the unaligned head and tail are copied normally
+/// and flushed. Panics if the slices differ in length.
+pub fn copy_persist(dst: &mut [u8], src: &[u8]) {
+    assert_eq!(
+        dst.len(),
+        src.len(),
+        "source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_stream_si128};
+
+        const CHUNK: usize = std::mem::size_of::<__m128i>();
+
+        let head = dst.as_ptr().align_offset(CHUNK).min(dst.len());
+        let body = (dst.len() - head) / CHUNK * CHUNK;
+
+        let (dst_head, rest) = dst.split_at_mut(head);
+        let (dst_body, dst_tail) = rest.split_at_mut(body);
+
+        dst_head.copy_from_slice(&src[..head]);
+        flush_cache_range(dst_head);
+
+        for (offset, chunk) in dst_body.chunks_exact_mut(CHUNK).enumerate() {
+            unsafe {
+                let value = _mm_loadu_si128(src[head + offset * CHUNK..].as_ptr().cast());
+                _mm_stream_si128(chunk.as_mut_ptr().cast(), value);
+            }
+        }
+
+        dst_tail.copy_from_slice(&src[head + body..]);
+        flush_cache_range(dst_tail);
+
+        drain();
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        dst.copy_from_slice(src);
+        persist(dst);
+    }
+}
+
+type FlushFn = unsafe fn(*const u8);
+
+#[cfg(target_arch = "x86_64")]
+fn flush_instruction() -> FlushFn {
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    const UNKNOWN: u8 = 0;
+    const CLFLUSH: u8 = 1;
+    const CLFLUSHOPT: u8 = 2;
+    const CLWB: u8 = 3;
+
+    static DETECTED: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+    let mut detected = DETECTED.load(Ordering::Relaxed);
+
+    if detected == UNKNOWN {
+        let features = std::arch::x86_64::__cpuid_count(7, 0).ebx;
+
+        detected = if features & (1 << 24) != 0 {
+            CLWB
+        } else if features & (1 << 23) != 0 {
+            CLFLUSHOPT
+        } else {
+            CLFLUSH
+        };
+
+        DETECTED.store(detected, Ordering::Relaxed);
+    }
+
+    match detected {
+        CLWB => clwb,
+        CLFLUSHOPT => clflushopt,
+        _ => clflush,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn clwb(line: *const u8) {
+    std::arch::asm!("clwb [{}]", in(reg) line, options(nostack, preserves_flags));
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn clflushopt(line: *const u8) {
+    std::arch::asm!("clflushopt [{}]", in(reg) line, options(nostack, preserves_flags));
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn clflush(line: *const u8) {
+    std::arch::x86_64::_mm_clflush(line);
+}
+
+#[cfg(target_arch = "aarch64")]
+fn flush_instruction() -> FlushFn {
+    unsafe fn dc_cvac(line: *const u8) {
+        std::arch::asm!("dc cvac, {}", in(reg) line, options(nostack, preserves_flags));
+    }
+
+    dc_cvac
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn flush_instruction() -> FlushFn {
+    unsafe fn unsupported(_: *const u8) {}
+
+    unsupported
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pmem;
+
+    #[test]
+    fn flush_ordinary_memory() {
+        let data = vec![7_u8; 1000];
+
+        pmem::persist(&data[3..]);
+        pmem::persist(&[]);
+
+        assert!(data.iter().all(|&byte| byte == 7));
+    }
+
+    #[test]
+    fn copy_persist_unaligned() {
+        let src = (0..=255).collect::<Vec<u8>>();
+        let mut dst = vec![0_u8; 300];
+
+        for (start, len) in [(0, 256), (3, 200), (5, 7), (17, 0)] {
+            pmem::copy_persist(&mut dst[start..start + len], &src[..len]);
+
+            assert_eq!(&dst[start..start + len], &src[..len]);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+/// Types which may be safely read from and written to raw mapped memory
+///
+/// # Safety
+///
+/// Implementors must be inhabited for every bit pattern, contain no padding,
+/// and hold no pointers or references, since their bytes may be written by
+/// another process at any time.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! pod_impl {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+pod_impl!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
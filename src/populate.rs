@@ -0,0 +1,87 @@
+use std::{io, num::NonZeroUsize, ops::RangeBounds};
+
+use crate::{madvise_raw, page_size, page_span, prefault::touch, Mmap, MmapMut};
+
+/// Populate `[ptr, ptr + len)` with `MADV_POPULATE_READ` or
+/// `MADV_POPULATE_WRITE`, touching each page by hand on kernels which predate
+/// them
+fn populate(ptr: *mut u8, len: usize, write: bool) -> io::Result<()> {
+    let advice = if write {
+        libc::MADV_POPULATE_WRITE
+    } else {
+        libc::MADV_POPULATE_READ
+    };
+
+    match unsafe { madvise_raw(ptr, len, advice) } {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            touch(
+                ptr,
+                len,
+                NonZeroUsize::new(page_size()).unwrap(),
+                NonZeroUsize::new(1).unwrap(),
+                write,
+            );
+
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+macro_rules! populate_read_impl {
+    ($name:ident) => {
+        impl<'a> $name<'a> {
+            /// Prefault the page tables of `range` for reading with
+            /// `MADV_POPULATE_READ`
+            ///
+            /// Unlike `MAP_POPULATE`, this may be applied to any part of the
+            /// mapping after it was created, and failures such as running out
+            /// of memory are reported instead of being silently ignored.
+            ///
+            /// On kernels older than 5.14 this falls back to reading a byte
+            /// from each page, in which case failures raise a signal instead.
+            pub fn populate_read(&self, range: impl RangeBounds<usize>) -> io::Result<()> {
+                let (ptr, len) = page_span(self.ptr, self.len, range)?;
+
+                populate(ptr, len, false)
+            }
+        }
+    };
+}
+
+populate_read_impl!(Mmap);
+populate_read_impl!(MmapMut);
+
+impl<'a> MmapMut<'a> {
+    /// Prefault the page tables of `range` for writing with
+    /// `MADV_POPULATE_WRITE`, allocating private pages and breaking
+    /// copy-on-write without modifying the contents
+    ///
+    /// On kernels older than 5.14 this falls back to an atomic no-op write to
+    /// each page, in which case failures raise a signal instead.
+    pub fn populate_write(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let (ptr, len) = page_span(self.ptr, self.len, range)?;
+
+        populate(ptr, len, true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, MmapMut};
+
+    #[test]
+    fn populate_ranges() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(8 * page_size()).unwrap()).unwrap();
+
+        map[10] = 1;
+
+        map.populate_read(..page_size() * 2).unwrap();
+        map.populate_write(page_size() + 1..).unwrap();
+
+        assert!(map.populate_read(..=page_size() * 8).is_err());
+        assert_eq!(map[10], 1);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicU8, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{page_size, Mmap, MmapMut};
+
+/// Result of prefaulting a mapping
+#[derive(Debug, Clone, Copy)]
+pub struct PrefaultStats {
+    /// Number of locations touched
+    pub pages: usize,
+    pub elapsed: Duration,
+}
+
+impl PrefaultStats {
+    pub fn pages_per_second(&self) -> f64 {
+        self.pages as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Touch one byte every `stride` bytes of `[ptr, ptr + len)`, splitting the
+/// work between `parallelism` threads
+pub(crate) fn touch(
+    ptr: *mut u8,
+    len: usize,
+    stride: NonZeroUsize,
+    parallelism: NonZeroUsize,
+    write: bool,
+) -> PrefaultStats {
+    let stride = stride.get();
+    let pages = len.div_ceil(stride);
+
+    // pointers are not `Send`, so pass the address to the workers
+    let base = ptr as usize;
+
+    let touch_range = move |first: usize, last: usize| {
+        for page in first..last {
+            let ptr = (base + page * stride) as *mut u8;
+
+            if write {
+                // an atomic no-op read-modify-write forces a write fault without
+                // clobbering bytes written concurrently by another process
+                unsafe { AtomicU8::from_ptr(ptr).fetch_or(0, Ordering::Relaxed) };
+            } else {
+                unsafe { ptr.read_volatile() };
+            }
+        }
+    };
+
+    let start = Instant::now();
+
+    let threads = parallelism.get().min(pages.max(1));
+
+    if threads == 1 {
+        touch_range(0, pages);
+    } else {
+        let per_thread = pages.div_ceil(threads);
+
+        thread::scope(|scope| {
+            for idx in 0..threads {
+                let first = idx * per_thread;
+                let last = ((idx + 1) * per_thread).min(pages);
+
+                scope.spawn(move || touch_range(first, last));
+            }
+        });
+    }
+
+    PrefaultStats {
+        pages,
+        elapsed: start.elapsed(),
+    }
+}
+
+impl<'a> Mmap<'a> {
+    /// Fault in every page of the mapping by reading one byte from each,
+    /// using up to `parallelism` threads
+    ///
+    /// Unlike `MAP_POPULATE`, this can be done after construction, in parallel,
+    /// and reports its throughput.
+    pub fn prefault(&self, parallelism: NonZeroUsize) -> PrefaultStats {
+        self.prefault_stride(NonZeroUsize::new(page_size()).unwrap(), parallelism)
+    }
+
+    /// Like [`prefault`](Self::prefault), touching one byte every `stride`
+    /// bytes, for example the huge page size of a hugetlb mapping
+    pub fn prefault_stride(
+        &self,
+        stride: NonZeroUsize,
+        parallelism: NonZeroUsize,
+    ) -> PrefaultStats {
+        touch(self.ptr as *mut u8, self.len, stride, parallelism, false)
+    }
+}
+
+impl<'a> MmapMut<'a> {
+    /// Fault in every page of the mapping for writing, using up to
+    /// `parallelism` threads
+    ///
+    /// The contents of the mapping are left unchanged. Unlike `MAP_POPULATE`,
+    /// this can be done after construction, in parallel, and reports its
+    /// throughput.
+    pub fn prefault(&mut self, parallelism: NonZeroUsize) -> PrefaultStats {
+        self.prefault_stride(NonZeroUsize::new(page_size()).unwrap(), parallelism)
+    }
+
+    /// Like [`prefault`](Self::prefault), touching one byte every `stride`
+    /// bytes, for example the huge page size of a hugetlb mapping
+    pub fn prefault_stride(
+        &mut self,
+        stride: NonZeroUsize,
+        parallelism: NonZeroUsize,
+    ) -> PrefaultStats {
+        touch(self.ptr, self.len, stride, parallelism, true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, Mmap, MmapMut};
+
+    #[test]
+    fn prefault_preserves_contents() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(64 * page_size() + 1).unwrap()).unwrap();
+
+        map[page_size() * 3] = 9;
+
+        let stats = map.prefault(NonZeroUsize::new(4).unwrap());
+
+        assert_eq!(stats.pages, 65);
+        assert_eq!(map[page_size() * 3], 9);
+
+        let map = Mmap::new_anon(NonZeroUsize::new(8 * page_size()).unwrap()).unwrap();
+
+        let stats = map.prefault_stride(
+            NonZeroUsize::new(2 * page_size()).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+        );
+
+        assert_eq!(stats.pages, 4);
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        fs::{FileExt, OpenOptionsExt},
+        io::{AsRawFd, RawFd},
+    },
+    path::Path,
+    time::Duration,
+};
+
+use crate::{madvise_raw, maps, Mmap};
+
+/// Which stalls a [`PressureMonitor`] triggers on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stall {
+    /// Time in which at least one task was stalled waiting for memory
+    Some,
+    /// Time in which every non-idle task was stalled at once
+    Full,
+}
+
+/// Percentage of time tasks were stalled, as reported in one line of a
+/// pressure file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PressureAverages {
+    /// Averaged over the last 10 seconds
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    /// Total stall time since boot, or since the cgroup was created
+    pub total: Duration,
+}
+
+/// Memory pressure at the time a [`PressureMonitor`] triggered
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryPressure {
+    pub some: PressureAverages,
+    pub full: PressureAverages,
+}
+
+/// What a [`PressureMonitor`] does to a low-priority mapping under pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reclaim {
+    /// Deactivate the pages with `MADV_COLD`, so they are reclaimed before
+    /// anything else but stay mapped until then
+    ///
+    /// (since Linux 5.4; ignored before)
+    Cold,
+    /// Reclaim the pages at once with `MADV_PAGEOUT`, so they are read back
+    /// from the file, or from swap for shared anonymous memory, when next
+    /// touched
+    ///
+    /// The kernel still skips pages it cannot reclaim straight away, such as
+    /// those mapped by other processes too, or dirty file pages awaiting
+    /// writeback. Before Linux 5.4 this falls back to `MADV_DONTNEED`, which
+    /// frees nothing by itself: the pages only leave this process's page
+    /// tables and stay in the page cache until the kernel reclaims them.
+    ///
+    /// Only for shared mappings, whose pages stay in the page cache, which
+    /// includes shared anonymous memory as it lives in tmpfs; with the
+    /// fallback, the private copies of a private mapping would be lost.
+    Discard,
+}
+
+/// Callback invoked by [`PressureMonitor::poll`] when the trigger fires
+pub type PressureCallback<'m> = Box<dyn FnMut(&MemoryPressure) + Send + 'm>;
+
+/// A pressure stall information trigger on memory, which fires when tasks
+/// spend too long waiting for memory, and the caches to give up when it does
+///
+/// Caches of mapped data can react to memory pressure before the kernel
+/// starts reclaiming more important memory or the OOM killer steps in:
+/// callbacks registered with [`on_pressure`](Self::on_pressure) run each time
+/// the trigger fires, after the pages of every mapping registered with
+/// [`register_low_priority`](Self::register_low_priority) are given up.
+/// Monitors are driven by calling [`poll`](Self::poll), and the descriptor is
+/// exposed through [`AsRawFd`] for event loops, where it becomes ready with
+/// `POLLPRI`.
+///
+/// Creating triggers on the system-wide file needs `CAP_SYS_RESOURCE` before
+/// Linux 6.4, and after that a window which is a multiple of two seconds.
+///
+/// (since Linux 5.2)
+pub struct PressureMonitor<'m> {
+    file: File,
+    low_priority: Vec<(&'m Mmap<'m>, Reclaim)>,
+    callbacks: Vec<PressureCallback<'m>>,
+}
+
+impl<'m> PressureMonitor<'m> {
+    /// Trigger when tasks of the whole system are stalled on memory for more
+    /// than `stall` within any `window`, which must be between half a second
+    /// and ten seconds
+    pub fn new(kind: Stall, stall: Duration, window: Duration) -> io::Result<Self> {
+        Self::open(Path::new("/proc/pressure/memory"), kind, stall, window)
+    }
+
+    /// Trigger on the stalls of the tasks in the cgroup v2 directory `cgroup`,
+    /// through its `memory.pressure` file
+    pub fn for_cgroup(
+        cgroup: &Path,
+        kind: Stall,
+        stall: Duration,
+        window: Duration,
+    ) -> io::Result<Self> {
+        Self::open(&cgroup.join("memory.pressure"), kind, stall, window)
+    }
+
+    fn open(path: &Path, kind: Stall, stall: Duration, window: Duration) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(path)?;
+
+        let kind = match kind {
+            Stall::Some => "some",
+            Stall::Full => "full",
+        };
+
+        // the trigger must arrive in a single write, including the nul
+        let trigger = format!("{kind} {} {}\0", stall.as_micros(), window.as_micros());
+
+        file.write_all(trigger.as_bytes())?;
+
+        Ok(Self {
+            file,
+            low_priority: Vec::new(),
+            callbacks: Vec::new(),
+        })
+    }
+
+    /// Invoke `callback` with the current pressure each time the trigger
+    /// fires
+    pub fn on_pressure(&mut self, callback: impl FnMut(&MemoryPressure) + Send + 'm) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Give up the pages of `map` as `reclaim` says each time the trigger
+    /// fires
+    ///
+    /// [`Reclaim::Discard`] is refused with
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput) unless every page of
+    /// `map` is shared, as found in `/proc/self/maps`.
+    pub fn register_low_priority(&mut self, map: &'m Mmap<'m>, reclaim: Reclaim) -> io::Result<()> {
+        if reclaim == Reclaim::Discard {
+            let regions = maps::parse_self()?;
+            let mut addr = map.ptr as usize;
+
+            while addr < map.ptr as usize + map.len {
+                match maps::find_region(&regions, addr) {
+                    Some(region) if region.perms.shared => addr = region.end,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "only shared mappings can be discarded without losing data",
+                        ))
+                    }
+                }
+            }
+        }
+
+        self.low_priority.push((map, reclaim));
+
+        Ok(())
+    }
+
+    /// Wait up to `timeout`, or indefinitely if it is `None`, for the trigger
+    /// to fire, returning whether it did
+    ///
+    /// When it fires, the low-priority mappings are given up and the
+    /// callbacks run before this returns.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut pfd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLPRI,
+            revents: 0,
+        };
+
+        let timeout = match timeout {
+            Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        match unsafe { libc::poll(&mut pfd, 1, timeout) } {
+            0 => return Ok(false),
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+
+                return match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(err),
+                };
+            }
+            _ => {}
+        }
+
+        if pfd.revents & libc::POLLERR != 0 {
+            return Err(io::Error::other(
+                "pressure file is gone, as its cgroup was removed",
+            ));
+        }
+
+        self.relieve();
+
+        let pressure = self.pressure()?;
+
+        for callback in &mut self.callbacks {
+            callback(&pressure);
+        }
+
+        Ok(true)
+    }
+
+    /// The current memory pressure, read from the monitored file
+    pub fn pressure(&self) -> io::Result<MemoryPressure> {
+        let mut buf = [0; 256];
+        let len = self.file.read_at(&mut buf, 0)?;
+
+        std::str::from_utf8(&buf[..len])
+            .ok()
+            .and_then(parse)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed pressure file"))
+    }
+
+    /// Give up the pages of every low-priority mapping now, as when the
+    /// trigger fires
+    ///
+    /// Failures are ignored, as the advice only helps the kernel reclaim
+    /// sooner.
+    pub fn relieve(&self) {
+        for &(map, reclaim) in &self.low_priority {
+            let ptr = map.ptr as *mut u8;
+
+            let _ = match reclaim {
+                Reclaim::Cold => unsafe { madvise_raw(ptr, map.len, libc::MADV_COLD) },
+                // MADV_PAGEOUT is newer than pressure stall information
+                Reclaim::Discard => unsafe { madvise_raw(ptr, map.len, libc::MADV_PAGEOUT) }
+                    .or_else(|err| match err.raw_os_error() {
+                        Some(libc::EINVAL) => unsafe {
+                            madvise_raw(ptr, map.len, libc::MADV_DONTNEED)
+                        },
+                        _ => Err(err),
+                    }),
+            };
+        }
+    }
+}
+
+impl AsRawFd for PressureMonitor<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Parse the `some` and `full` lines of a pressure file, the latter of which
+/// is missing before Linux 5.13 for the system-wide file
+fn parse(contents: &str) -> Option<MemoryPressure> {
+    let mut pressure = MemoryPressure::default();
+
+    for line in contents.lines() {
+        let (kind, fields) = line.split_once(' ')?;
+
+        let mut averages = PressureAverages::default();
+
+        for field in fields.split_whitespace() {
+            match field.split_once('=')? {
+                ("avg10", value) => averages.avg10 = value.parse().ok()?,
+                ("avg60", value) => averages.avg60 = value.parse().ok()?,
+                ("avg300", value) => averages.avg300 = value.parse().ok()?,
+                ("total", value) => averages.total = Duration::from_micros(value.parse().ok()?),
+                _ => {}
+            }
+        }
+
+        match kind {
+            "some" => pressure.some = averages,
+            "full" => pressure.full = averages,
+            _ => {}
+        }
+    }
+
+    Some(pressure)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize, os::unix::fs::FileExt, time::Duration};
+
+    use crate::{page_size, test_util::temp_file, MmapOptions, PressureMonitor, Reclaim, Stall};
+
+    use super::parse;
+
+    #[test]
+    fn parses_pressure_file() {
+        let pressure = parse(
+            "some avg10=1.50 avg60=0.25 avg300=0.00 total=123456\n\
+             full avg10=0.10 avg60=0.00 avg300=0.00 total=42\n",
+        )
+        .unwrap();
+
+        assert_eq!(pressure.some.avg10, 1.5);
+        assert_eq!(pressure.some.avg60, 0.25);
+        assert_eq!(pressure.some.total, Duration::from_micros(123456));
+        assert_eq!(pressure.full.total, Duration::from_micros(42));
+
+        assert!(parse("some avg10=oops").is_none());
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn monitors_memory_pressure() {
+        let len = NonZeroUsize::new(4 * page_size()).unwrap();
+
+        let file = temp_file("pressure");
+
+        file.write_all_at(&[7; 64], 0).unwrap();
+        file.set_len(len.get() as u64).unwrap();
+
+        let cache = MmapOptions::new(len).map_file(&file).unwrap();
+        let anon = MmapOptions::new(len).private().map_anon().unwrap();
+        let private = MmapOptions::new(len).private().map_file(&file).unwrap();
+
+        let mut monitor = match PressureMonitor::new(
+            Stall::Some,
+            Duration::from_millis(500),
+            Duration::from_secs(2),
+        ) {
+            Ok(monitor) => monitor,
+            // no pressure stall information, or not allowed to create
+            // triggers
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound
+                        | io::ErrorKind::PermissionDenied
+                        | io::ErrorKind::Unsupported
+                ) =>
+            {
+                return
+            }
+            Err(e) => panic!("{e}"),
+        };
+
+        monitor
+            .register_low_priority(&cache, Reclaim::Discard)
+            .unwrap();
+        monitor.register_low_priority(&anon, Reclaim::Cold).unwrap();
+
+        assert!(monitor
+            .register_low_priority(&anon, Reclaim::Discard)
+            .is_err());
+        assert!(monitor
+            .register_low_priority(&private, Reclaim::Discard)
+            .is_err());
+
+        monitor.on_pressure(|pressure| assert!(pressure.some.total > Duration::ZERO));
+
+        // fires only if the machine happens to be under pressure
+        monitor.poll(Some(Duration::ZERO)).unwrap();
+
+        assert!(monitor.pressure().unwrap().some.avg10 >= 0.0);
+        monitor.relieve();
+
+        // discarded pages are read back from the file
+        assert_eq!(cache[..64], [7; 64]);
+
+        assert!(PressureMonitor::new(Stall::Full, Duration::from_secs(1), Duration::ZERO).is_err());
+    }
+}
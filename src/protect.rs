@@ -0,0 +1,171 @@
+use std::{
+    io,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    ptr, slice,
+};
+
+use crate::{mprotect_raw, page_span, Mmap, MmapMut, Protection};
+
+impl<'a> Mmap<'a> {
+    /// Make the mapping writable for the duration of `f`, then read-only again
+    ///
+    /// This keeps data which is only occasionally updated, such as
+    /// configuration shared with other processes, protected from stray writes
+    /// the rest of the time. File mappings require the file to be open for
+    /// writing. Any execute permission is dropped afterwards.
+    ///
+    /// If `f` panics the mapping is made inaccessible with `PROT_NONE` before
+    /// the panic continues, so that a half-written update can neither be
+    /// read nor written through this mapping by mistake; reading it afterwards
+    /// raises `SIGSEGV`. A later successful `with_write` makes it readable
+    /// again.
+    ///
+    /// Takes `&mut self` so that no slice of the mapping can be alive while
+    /// `f` is writing to it.
+    pub fn with_write<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> io::Result<R> {
+        let (ptr, len) = page_span(self.ptr, self.len, ..)?;
+
+        unsafe { mprotect_raw(ptr, len, Protection::READ | Protection::WRITE)? };
+
+        let buf = unsafe { slice::from_raw_parts_mut(self.ptr as *mut u8, self.len) };
+
+        let poison = Poison { ptr, len };
+        let res = f(buf);
+
+        // `f` returned, so there is nothing to poison
+        mem::forget(poison);
+
+        unsafe { mprotect_raw(ptr, len, Protection::READ)? };
+
+        Ok(res)
+    }
+}
+
+/// Makes the pages inaccessible when dropped, which only happens while
+/// unwinding out of the closure passed to [`Mmap::with_write`]
+struct Poison {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Drop for Poison {
+    fn drop(&mut self) {
+        // nothing more can be done about a failure while unwinding
+        let _ = unsafe { mprotect_raw(self.ptr, self.len, Protection::NONE) };
+    }
+}
+
+impl<'a> MmapMut<'a> {
+    /// Make the mapping read-only without moving it, so that pointers into it
+    /// which have already been handed to readers stay valid while any further
+    /// writes are refused by the hardware
+    ///
+    /// The [flush-on-drop](crate::MmapOptions::flush_on_drop) policy is
+    /// applied now, as nothing can dirty the pages afterwards.
+    pub fn freeze_in_place(self) -> io::Result<Mmap<'a>> {
+        let (ptr, len) = page_span(self.ptr, self.len, ..)?;
+
+        unsafe { mprotect_raw(ptr, len, Protection::READ)? };
+
+        self.flush_for_drop();
+
+        let this = ManuallyDrop::new(self);
+
+        Ok(Mmap {
+            ptr: this.ptr,
+            len: this.len,
+            offset: this.offset,
+            backing: this.backing,
+            _lock: unsafe { ptr::read(&this._lock) },
+            _lifetime: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io::Read,
+        num::NonZeroUsize,
+        panic::{self, AssertUnwindSafe},
+    };
+
+    use crate::{Mmap, MmapMut, HEAP_BACKED};
+
+    /// Protection of the mapping containing `addr`, as in `/proc/self/maps`
+    fn protection(addr: *const u8) -> String {
+        let mut maps = String::new();
+        File::open("/proc/self/maps")
+            .unwrap()
+            .read_to_string(&mut maps)
+            .unwrap();
+
+        maps.lines()
+            .find_map(|line| {
+                let (range, rest) = line.split_once(' ')?;
+                let (start, end) = range.split_once('-')?;
+                let start = usize::from_str_radix(start, 16).ok()?;
+                let end = usize::from_str_radix(end, 16).ok()?;
+
+                (start..end)
+                    .contains(&(addr as usize))
+                    .then(|| rest[..3].to_owned())
+            })
+            .unwrap()
+    }
+
+    /// Check the protection of the mapping containing `addr`, which stays
+    /// `rw-` for heap-backed copies as their pages belong to the allocator
+    fn assert_protection(addr: *const u8, expected: &str) {
+        let expected = if HEAP_BACKED { "rw-" } else { expected };
+
+        assert_eq!(protection(addr), expected);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "reads /proc/self/maps")]
+    fn write_then_poison() {
+        let mut map = Mmap::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let len = map.with_write(|buf| {
+            buf[..3].copy_from_slice(b"cfg");
+            buf.len()
+        });
+
+        assert_eq!(len.unwrap(), 4096);
+        assert_eq!(&map[..3], b"cfg");
+        assert_protection(map.as_ptr(), "r--");
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            map.with_write(|buf| {
+                buf[0] = b'x';
+                panic!("interrupted update");
+            })
+        }));
+
+        assert!(res.is_err());
+        assert_protection(map.as_ptr(), "---");
+
+        map.with_write(|buf| buf[0] = b'c').unwrap();
+
+        assert_eq!(&map[..3], b"cfg");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "reads /proc/self/maps")]
+    fn freeze_keeps_address() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(100).unwrap()).unwrap();
+        map[..6].copy_from_slice(b"frozen");
+
+        let reader = map.as_ptr();
+
+        let map = map.freeze_in_place().unwrap();
+
+        assert_eq!(map.as_ptr(), reader);
+        assert_eq!(&map[..6], b"frozen");
+        assert_protection(reader, "r--");
+        assert_eq!(unsafe { std::slice::from_raw_parts(reader, 6) }, b"frozen");
+    }
+}
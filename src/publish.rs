@@ -0,0 +1,142 @@
+use std::{
+    io,
+    ops::RangeBounds,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{check_range, msync_raw, Mmap, MmapMut};
+
+/// How far [`MmapMut::publish`] makes data reach before publishing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Visible to every process sharing the mapping, which is all that
+    /// anonymous and `memfd` mappings can offer
+    Visible,
+    /// Also written back to the backing file with `msync(2)`, so a reader
+    /// which acquires the new sequence number can rely on the data surviving
+    /// a crash of the whole system
+    Durable,
+}
+
+/// The sequence counter at `offset`, which must be 8-byte aligned
+fn counter<'m>(ptr: *const u8, len: usize, offset: usize) -> io::Result<&'m AtomicU64> {
+    let range = check_range(len, offset..offset.saturating_add(8))?;
+    let ptr = unsafe { ptr.add(range.start) };
+
+    if !(ptr as usize).is_multiple_of(std::mem::align_of::<AtomicU64>()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "sequence counter is not 8-byte aligned",
+        ));
+    }
+
+    Ok(unsafe { &*ptr.cast::<AtomicU64>() })
+}
+
+impl<'a> MmapMut<'a> {
+    /// Publish the writes made to `range` by incrementing the sequence counter
+    /// at `seq_offset`, returning the new sequence number
+    ///
+    /// The counter is a `u64` which must be 8-byte aligned and must not
+    /// overlap `range`. The protocol gives the following guarantees, across
+    /// processes just as across threads:
+    ///
+    /// - a reader which observes sequence number `n` with
+    ///   [`acquire`](Self::acquire) sees every write this process made to the
+    ///   mapping before the publish which returned `n`
+    /// - with [`Durability::Durable`], those writes to `range` have also
+    ///   reached the backing file by the time `n` can be observed
+    ///
+    /// It does not stop readers from seeing writes made after the publish, so
+    /// data must not be modified once published; write new data elsewhere and
+    /// publish again, or use [`SeqLock`](crate::SeqLock) for data updated in
+    /// place.
+    ///
+    /// Takes `&mut self` as the counter is written through the mapping, so no
+    /// slice of it can be alive meanwhile.
+    pub fn publish(
+        &mut self,
+        seq_offset: usize,
+        range: impl RangeBounds<usize>,
+        durability: Durability,
+    ) -> io::Result<u64> {
+        let range = check_range(self.len, range)?;
+        let seq = counter(self.ptr, self.len, seq_offset)?;
+
+        if seq_offset < range.end && seq_offset + 8 > range.start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sequence counter overlaps the published range",
+            ));
+        }
+
+        if durability == Durability::Durable && !range.is_empty() {
+            unsafe { msync_raw(self.ptr.add(range.start), range.len(), libc::MS_SYNC)? };
+        }
+
+        // the release ordering carries the preceding writes along with the
+        // new sequence number
+        Ok(seq.fetch_add(1, Ordering::Release).wrapping_add(1))
+    }
+}
+
+macro_rules! acquire_impl {
+    ($name:ident) => {
+        impl<'a> $name<'a> {
+            /// Load the sequence counter at `seq_offset`, making visible every
+            /// write published up to the returned sequence number
+            ///
+            /// See [`MmapMut::publish`] for the guarantees this provides. A
+            /// reader typically polls until the number changes from the last
+            /// one it processed.
+            pub fn acquire(&self, seq_offset: usize) -> io::Result<u64> {
+                Ok(counter(self.ptr, self.len, seq_offset)?.load(Ordering::Acquire))
+            }
+        }
+    };
+}
+
+acquire_impl!(Mmap);
+acquire_impl!(MmapMut);
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, thread};
+
+    use crate::{Durability, MemfdMmap, MmapOptions};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn publish_and_acquire() {
+        let len = NonZeroUsize::new(4096).unwrap();
+
+        // two mappings of one file, as a reader in another process would have
+        let memfd = MemfdMmap::new("publish", len).unwrap();
+        let file = memfd.file().try_clone().unwrap();
+
+        let mut map = MmapOptions::new(len).map_file_mut(&file).unwrap();
+
+        assert_eq!(map.acquire(0).unwrap(), 0);
+
+        thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let map = MmapOptions::new(len).map_file(&file).unwrap();
+
+                while map.acquire(0).unwrap() == 0 {
+                    std::hint::spin_loop();
+                }
+
+                u64::from_ne_bytes(map[8..16].try_into().unwrap())
+            });
+
+            map[8..16].copy_from_slice(&42_u64.to_ne_bytes());
+
+            assert_eq!(map.publish(0, 8..16, Durability::Durable).unwrap(), 1);
+            assert_eq!(reader.join().unwrap(), 42);
+        });
+
+        assert!(map.publish(0, 4..16, Durability::Visible).is_err());
+        assert!(map.publish(4, 16..24, Durability::Visible).is_err());
+        assert!(map.acquire(4092).is_err());
+    }
+}
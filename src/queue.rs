@@ -0,0 +1,387 @@
+use std::{
+    cell::UnsafeCell,
+    io,
+    marker::PhantomData,
+    mem, ptr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    futex::{futex_wait, futex_wake},
+    MmapCell, MmapMut, Pod,
+};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMQUEUE");
+
+/// Slots start this far into the mapping
+const HEADER_LEN: usize = 256;
+
+/// Each field is on its own cache line, so producers and consumers do not
+/// contend on the same one
+#[repr(C, align(64))]
+struct Padded<T>(T);
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    capacity: u64,
+    elem_size: u64,
+    enqueue: Padded<AtomicU64>,
+    dequeue: Padded<AtomicU64>,
+    wake: Padded<Wake>,
+}
+
+const _: () = assert!(mem::size_of::<Header>() <= HEADER_LEN);
+
+/// Futex words bumped after every push and pop respectively, with counts of
+/// the processes sleeping on them so wakes can be skipped when nobody is
+/// waiting
+#[repr(C)]
+struct Wake {
+    pushed: AtomicU32,
+    pop_waiters: AtomicU32,
+    popped: AtomicU32,
+    push_waiters: AtomicU32,
+}
+
+#[repr(C)]
+struct Slot<T> {
+    seq: AtomicU64,
+    value: UnsafeCell<T>,
+}
+
+/// A bounded multi-producer, multi-consumer queue of `T` living in a shared
+/// mapping
+///
+/// This is Dmitry Vyukov's ring of slots, each carrying a sequence number
+/// which tells producers and consumers whether it is theirs to fill or empty,
+/// so neither side takes a lock. The non-blocking [`try_push`](Self::try_push)
+/// and [`try_pop`](Self::try_pop) never make a system call; the blocking
+/// [`push`](Self::push) and [`pop`](Self::pop) sleep on a futex while the
+/// queue is full or empty.
+///
+/// A process which dies between claiming a slot and filling or emptying it
+/// stalls the queue at that slot.
+pub struct ShmQueue<'m, T: Pod> {
+    header: *const Header,
+    slots: *const Slot<T>,
+    _map: PhantomData<&'m MmapMut<'m>>,
+}
+
+unsafe impl<'m, T: Pod> Send for ShmQueue<'m, T> {}
+unsafe impl<'m, T: Pod> Sync for ShmQueue<'m, T> {}
+
+impl<'m, T: Pod> ShmQueue<'m, T> {
+    /// Format the mapping as an empty queue, with a capacity of the largest
+    /// power of two that fits
+    pub fn init(map: &'m mut MmapMut) -> io::Result<Self> {
+        let (header, slots, capacity) = Self::layout(&map.as_cell())?;
+
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mapping too small for a queue",
+            ));
+        }
+
+        unsafe {
+            for idx in 0..capacity {
+                ptr::addr_of_mut!((*slots.add(idx as usize)).seq).write(AtomicU64::new(idx));
+            }
+
+            header.write(Header {
+                magic: AtomicU64::new(0),
+                capacity,
+                elem_size: mem::size_of::<T>() as u64,
+                enqueue: Padded(AtomicU64::new(0)),
+                dequeue: Padded(AtomicU64::new(0)),
+                wake: Padded(Wake {
+                    pushed: AtomicU32::new(0),
+                    pop_waiters: AtomicU32::new(0),
+                    popped: AtomicU32::new(0),
+                    push_waiters: AtomicU32::new(0),
+                }),
+            });
+
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+
+        Ok(Self {
+            header,
+            slots,
+            _map: PhantomData,
+        })
+    }
+
+    /// Attach to a queue previously created with [`init`](Self::init),
+    /// possibly by another process, checking it holds elements of the same
+    /// size
+    ///
+    /// Takes a [view](MmapMut::as_cell) of the mapping, so that no slices of
+    /// the slots can be alive while elements are pushed.
+    pub fn attach(map: &'m MmapCell) -> io::Result<Self> {
+        let (header, slots, capacity) = Self::layout(map)?;
+
+        let hdr = unsafe { &*header };
+
+        if hdr.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mapping does not contain a queue",
+            ));
+        }
+
+        if hdr.elem_size != mem::size_of::<T>() as u64 || hdr.capacity != capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "queue layout does not match",
+            ));
+        }
+
+        Ok(Self {
+            header,
+            slots,
+            _map: PhantomData,
+        })
+    }
+
+    fn layout(map: &MmapCell) -> io::Result<(*mut Header, *mut Slot<T>, u64)> {
+        if mem::align_of::<T>() > HEADER_LEN || mem::size_of::<T>() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported element type",
+            ));
+        }
+
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let fits = map.len.saturating_sub(HEADER_LEN) / mem::size_of::<Slot<T>>();
+        let capacity = match fits {
+            0 => 0,
+            fits => 1 << fits.ilog2(),
+        };
+        let slots = map.typed_ptr::<Slot<T>>(HEADER_LEN, capacity)?;
+
+        Ok((header, slots, capacity as u64))
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    fn slot(&self, pos: u64) -> &Slot<T> {
+        let idx = pos & (self.header().capacity - 1);
+
+        unsafe { &*self.slots.add(idx as usize) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.header().capacity as usize
+    }
+
+    /// Append `value` if there is room, returning whether it was pushed
+    pub fn try_push(&self, value: T) -> bool {
+        let header = self.header();
+        let mut pos = header.enqueue.0.load(Ordering::Relaxed);
+
+        let slot = loop {
+            let slot = self.slot(pos);
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            match (seq as i64).wrapping_sub(pos as i64) {
+                // the slot is free for this position: claim it
+                0 => match header.enqueue.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break slot,
+                    Err(current) => pos = current,
+                },
+                // the slot still holds the value from a lap ago: full
+                diff if diff < 0 => return false,
+                // another producer claimed it first
+                _ => pos = header.enqueue.0.load(Ordering::Relaxed),
+            }
+        };
+
+        unsafe { ptr::write_volatile(slot.value.get(), value) };
+
+        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+
+        Self::signal(&header.wake.0.pushed, &header.wake.0.pop_waiters);
+
+        true
+    }
+
+    /// Remove the oldest value, if there is one
+    pub fn try_pop(&self) -> Option<T> {
+        let header = self.header();
+        let mut pos = header.dequeue.0.load(Ordering::Relaxed);
+
+        let slot = loop {
+            let slot = self.slot(pos);
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            match (seq as i64).wrapping_sub(pos.wrapping_add(1) as i64) {
+                // the slot has been filled for this position: claim it
+                0 => match header.dequeue.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break slot,
+                    Err(current) => pos = current,
+                },
+                // not filled yet: empty
+                diff if diff < 0 => return None,
+                // another consumer claimed it first
+                _ => pos = header.dequeue.0.load(Ordering::Relaxed),
+            }
+        };
+
+        let value = unsafe { ptr::read_volatile(slot.value.get()) };
+
+        // hand the slot to the producer one lap ahead
+        slot.seq
+            .store(pos.wrapping_add(header.capacity), Ordering::Release);
+
+        Self::signal(&header.wake.0.popped, &header.wake.0.push_waiters);
+
+        Some(value)
+    }
+
+    /// Append `value`, sleeping while the queue is full for at most `timeout`,
+    /// or indefinitely if it is `None`, and returning whether it was pushed
+    pub fn push(&self, value: T, timeout: Option<Duration>) -> io::Result<bool> {
+        let header = self.header();
+
+        Self::block(
+            &header.wake.0.popped,
+            &header.wake.0.push_waiters,
+            timeout,
+            || self.try_push(value).then_some(()),
+        )
+        .map(|pushed| pushed.is_some())
+    }
+
+    /// Remove the oldest value, sleeping while the queue is empty for at most
+    /// `timeout`, or indefinitely if it is `None`
+    pub fn pop(&self, timeout: Option<Duration>) -> io::Result<Option<T>> {
+        let header = self.header();
+
+        Self::block(
+            &header.wake.0.pushed,
+            &header.wake.0.pop_waiters,
+            timeout,
+            || self.try_pop(),
+        )
+    }
+
+    /// Bump `word` and wake one sleeper if there are any
+    fn signal(word: &AtomicU32, waiters: &AtomicU32) {
+        // sequentially consistent so that either this sees the waiter, or the
+        // waiter sees the bumped word and does not sleep
+        word.fetch_add(1, Ordering::SeqCst);
+
+        if waiters.load(Ordering::SeqCst) != 0 {
+            let _ = futex_wake(word, 1);
+        }
+    }
+
+    /// Retry `attempt` until it succeeds or `timeout` elapses, sleeping on
+    /// `word` in between
+    fn block<R>(
+        word: &AtomicU32,
+        waiters: &AtomicU32,
+        timeout: Option<Duration>,
+        mut attempt: impl FnMut() -> Option<R>,
+    ) -> io::Result<Option<R>> {
+        if let Some(res) = attempt() {
+            return Ok(Some(res));
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        waiters.fetch_add(1, Ordering::SeqCst);
+
+        let res = loop {
+            let seen = word.load(Ordering::SeqCst);
+
+            if let Some(res) = attempt() {
+                break Ok(Some(res));
+            }
+
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => break Ok(None),
+                },
+                None => None,
+            };
+
+            if let Err(err) = futex_wait(word, seen, remaining) {
+                break Err(err);
+            }
+        };
+
+        waiters.fetch_sub(1, Ordering::SeqCst);
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, thread, time::Duration};
+
+    use crate::{MmapMut, ShmQueue};
+
+    #[test]
+    fn many_producers_and_consumers() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let queue = ShmQueue::<u64>::init(&mut map).unwrap();
+
+        assert_eq!(queue.capacity(), 128);
+        assert_eq!(queue.try_pop(), None);
+        assert_eq!(queue.pop(Some(Duration::from_millis(1))).unwrap(), None);
+
+        for i in 0..128 {
+            assert!(queue.try_push(i));
+        }
+
+        assert!(!queue.try_push(128));
+        assert!(!queue.push(128, Some(Duration::from_millis(1))).unwrap());
+
+        while queue.try_pop().is_some() {}
+
+        let sum: u64 = thread::scope(|s| {
+            for producer in 0..4 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        assert!(queue.push(producer * 1000 + i, None).unwrap());
+                    }
+                });
+            }
+
+            let consumers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..1000)
+                            .map(|_| queue.pop(None).unwrap().unwrap())
+                            .sum::<u64>()
+                    })
+                })
+                .collect();
+
+            consumers.into_iter().map(|c| c.join().unwrap()).sum()
+        });
+
+        assert_eq!(sum, (0..4000).sum());
+        assert!(ShmQueue::<u32>::attach(&map.as_cell()).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{io, num::NonZeroUsize, slice};
+
+use crate::{mmap_raw, munmap_raw, Protection};
+
+/// A mapping created with arguments passed straight through to `mmap(2)`,
+/// for flag combinations which [`MmapOptions`](crate::MmapOptions) does not
+/// model
+///
+/// The mapping is unmapped on drop, like every other mapping in this crate.
+pub struct MmapRaw {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for MmapRaw {}
+unsafe impl Sync for MmapRaw {}
+
+impl MmapRaw {
+    /// Call `mmap(addr_hint, len, prot, flags, fd, offset)` and take ownership
+    /// of the resulting mapping
+    ///
+    /// `prot` and `flags` are the raw `PROT_*` and `MAP_*` values and are not
+    /// checked in any way.
+    ///
+    /// # Safety
+    ///
+    /// With `MAP_FIXED`, any existing mappings in the range are replaced, so
+    /// nothing may still be referring to memory in it. The flags must produce
+    /// a mapping of exactly `len` bytes at the returned address, which rules
+    /// out flags such as `MAP_GROWSDOWN` that let the kernel extend it.
+    pub unsafe fn map(
+        addr_hint: *mut u8,
+        len: NonZeroUsize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> io::Result<Self> {
+        let ptr = mmap_raw(addr_hint, len.get(), Protection(prot), flags, fd, offset)?;
+
+        Ok(Self {
+            ptr,
+            len: len.get(),
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The contents of the mapping
+    ///
+    /// # Safety
+    ///
+    /// The mapping must be readable, and nothing may write to it, through
+    /// this or any other mapping of the same memory, while the slice is alive.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        slice::from_raw_parts(self.ptr, self.len)
+    }
+
+    /// The contents of the mapping, for writing
+    ///
+    /// # Safety
+    ///
+    /// The mapping must be readable and writable, and nothing may access it
+    /// through any other mapping of the same memory while the slice is alive.
+    pub unsafe fn as_mut_slice(&mut self) -> &mut [u8] {
+        slice::from_raw_parts_mut(self.ptr, self.len)
+    }
+}
+
+impl Drop for MmapRaw {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, ptr};
+
+    use crate::{libc_compat, MmapRaw};
+
+    #[test]
+    fn passes_arguments_through() {
+        let len = NonZeroUsize::new(8192).unwrap();
+
+        let mut map = unsafe {
+            MmapRaw::map(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+            .unwrap()
+        };
+
+        unsafe { map.as_mut_slice()[8191] = 1 };
+
+        assert_eq!(unsafe { map.as_slice() }[8191], 1);
+
+        let addr = map.as_ptr();
+
+        // the same address can't be claimed without replacing the mapping
+        let taken = unsafe {
+            MmapRaw::map(
+                addr,
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc_compat::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+        };
+
+        assert!(taken.is_err());
+
+        drop(map);
+
+        let reused = unsafe {
+            MmapRaw::map(
+                addr,
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc_compat::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(reused.as_ptr(), addr);
+        assert_eq!(unsafe { reused.as_slice() }[8191], 0);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fs::File, io};
+
+use crate::{file_len_from, libc_compat, mremap_raw, shim, Backing, FileWatcher, Mmap, MmapMut};
+
+macro_rules! refresh_impl {
+    ($name:ident) => {
+        impl<'a> $name<'a> {
+            /// Extend the mapping to cover `file` if it has grown since the
+            /// mapping was created, returning whether the length changed
+            ///
+            /// The mapping is grown with `mremap(2)`, which may move it to a
+            /// new address. The file must be the one the mapping was created
+            /// from. Shrinking files are not handled, and the mapping is never
+            /// made smaller. Mappings starting at an offset into the file grow
+            /// to cover the rest of the file from that offset.
+            ///
+            /// A heap-backed copy of the file is instead retaken whole, which
+            /// also picks up changes within its old length.
+            pub fn refresh(&mut self, file: &File) -> io::Result<bool> {
+                if let Backing::Heap = self.backing {
+                    let (ptr, len) = shim::map_file(file)?;
+                    let changed = len != self.len;
+
+                    unsafe { self.backing.release(self.ptr, self.len) };
+
+                    self.ptr = ptr;
+                    self.len = len;
+
+                    return Ok(changed);
+                }
+
+                let size = file_len_from(file, self.offset)?;
+
+                if size <= self.len {
+                    return Ok(false);
+                }
+
+                let old = self.ptr;
+
+                self.ptr = unsafe {
+                    mremap_raw(
+                        self.ptr as *mut u8,
+                        self.len,
+                        size,
+                        libc_compat::MREMAP_MAYMOVE,
+                        std::ptr::null_mut(),
+                    )?
+                };
+                self.len = size;
+
+                if self.ptr != old {
+                    unsafe { self.backing.unmap_guard(old) };
+                }
+
+                Ok(true)
+            }
+
+            /// Block until `watcher` reports a modification of `file`, then
+            /// [`refresh`](Self::refresh) the mapping
+            pub fn refresh_on_change(
+                &mut self,
+                file: &File,
+                watcher: &FileWatcher,
+            ) -> io::Result<bool> {
+                watcher.wait()?;
+
+                self.refresh(file)
+            }
+        }
+    };
+}
+
+refresh_impl!(Mmap);
+refresh_impl!(MmapMut);
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{page_size, test_util::temp_file, FileWatcher, Mmap, MmapOptions};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn follows_growing_file() {
+        let mut file = temp_file("refresh");
+
+        file.write_all(b"head").unwrap();
+
+        let mut map = Mmap::new_file(&file).unwrap();
+        assert!(!map.refresh(&file).unwrap());
+
+        let watcher = FileWatcher::new(&file).unwrap();
+        file.write_all(b"tail").unwrap();
+
+        assert!(map.refresh_on_change(&file, &watcher).unwrap());
+        assert_eq!(&*map, b"headtail");
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn grows_from_offset() {
+        let page = page_size();
+        let file = temp_file("refresh-offset");
+
+        file.write_all_at(&vec![1; 2 * page], 0).unwrap();
+
+        let mut map = MmapOptions::new(NonZeroUsize::new(page).unwrap())
+            .offset(page as u64)
+            .map_file(&file)
+            .unwrap();
+
+        assert!(!map.refresh(&file).unwrap());
+
+        file.write_all_at(b"tail", 2 * page as u64).unwrap();
+
+        assert!(map.refresh(&file).unwrap());
+        assert_eq!(map.len(), page + 4);
+        assert_eq!(&map[page..], b"tail");
+    }
+}
@@ -0,0 +1,153 @@
+use std::{io, marker::PhantomData};
+
+use crate::{DeviceMmap, Pod};
+
+/// Which operations a register permits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+/// A named hardware register of width `T` at a fixed offset into a
+/// [`RegisterBlock`]
+///
+/// Registers are intended to be declared as constants describing a device's
+/// register map:
+///
+/// ```no_run
+/// use mmap::{DeviceMmap, Register, RegisterBlock};
+///
+/// const CTRL: Register<u32> = Register::read_write(0x00);
+/// const STATUS: Register<u32> = Register::read_only(0x04);
+///
+/// let map = DeviceMmap::open("/dev/uio0", 0, 4096)?;
+/// let regs = RegisterBlock::new(&map, 0);
+///
+/// regs.modify(CTRL, |ctrl| ctrl | 1)?;
+/// let _ready = regs.read(STATUS)? & 1 != 0;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Register<T: Pod> {
+    offset: usize,
+    access: Access,
+    _width: PhantomData<T>,
+}
+
+impl<T: Pod> Clone for Register<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Pod> Copy for Register<T> {}
+
+impl<T: Pod> Register<T> {
+    pub const fn new(offset: usize, access: Access) -> Self {
+        Self {
+            offset,
+            access,
+            _width: PhantomData,
+        }
+    }
+
+    pub const fn read_only(offset: usize) -> Self {
+        Self::new(offset, Access::ReadOnly)
+    }
+
+    pub const fn write_only(offset: usize) -> Self {
+        Self::new(offset, Access::WriteOnly)
+    }
+
+    pub const fn read_write(offset: usize) -> Self {
+        Self::new(offset, Access::ReadWrite)
+    }
+
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub const fn access(&self) -> Access {
+        self.access
+    }
+}
+
+/// A bank of registers starting at `base` bytes into a device mapping
+///
+/// All accesses are volatile and check the register's declared [`Access`]
+/// before touching the device.
+pub struct RegisterBlock<'m> {
+    map: &'m DeviceMmap,
+    base: usize,
+}
+
+impl<'m> RegisterBlock<'m> {
+    pub fn new(map: &'m DeviceMmap, base: usize) -> Self {
+        Self { map, base }
+    }
+
+    fn offset_of<T: Pod>(&self, reg: Register<T>) -> io::Result<usize> {
+        self.base.checked_add(reg.offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "register out of bounds of mapping",
+            )
+        })
+    }
+
+    pub fn read<T: Pod>(&self, reg: Register<T>) -> io::Result<T> {
+        if reg.access == Access::WriteOnly {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "register is write-only",
+            ));
+        }
+
+        self.map.read_volatile_at(self.offset_of(reg)?)
+    }
+
+    pub fn write<T: Pod>(&self, reg: Register<T>, val: T) -> io::Result<()> {
+        if reg.access == Access::ReadOnly {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "register is read-only",
+            ));
+        }
+
+        self.map.write_volatile_at(self.offset_of(reg)?, val)
+    }
+
+    /// Read-modify-write a read-write register
+    pub fn modify<T: Pod>(&self, reg: Register<T>, f: impl FnOnce(T) -> T) -> io::Result<()> {
+        let val = self.read(reg)?;
+
+        self.write(reg, f(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{DeviceMmap, Register, RegisterBlock};
+
+    const CTRL: Register<u32> = Register::read_write(0x00);
+    const STATUS: Register<u16> = Register::read_only(0x04);
+    const DOORBELL: Register<u8> = Register::write_only(0x06);
+
+    #[test]
+    fn access_is_enforced() {
+        let map = DeviceMmap::open("/dev/zero", 0, 4096).unwrap();
+        let regs = RegisterBlock::new(&map, 0x100);
+
+        regs.write(CTRL, 2).unwrap();
+        regs.modify(CTRL, |ctrl| ctrl | 1).unwrap();
+
+        assert_eq!(regs.read(CTRL).unwrap(), 3);
+        assert_eq!(map.read_volatile_at::<u32>(0x100).unwrap(), 3);
+
+        assert!(regs.write(STATUS, 1).is_err());
+        assert!(regs.read(DOORBELL).is_err());
+        regs.write(DOORBELL, 1).unwrap();
+    }
+}
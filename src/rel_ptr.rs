@@ -0,0 +1,155 @@
+use std::{fmt, io, marker::PhantomData};
+
+use crate::{MmapMut, Pod, ShmAllocator};
+
+/// A pointer to a `T` stored as an offset from the start of a shared segment
+///
+/// Processes generally map a shared segment at different addresses, so raw
+/// pointers stored inside it are meaningless to every process but the one
+/// which wrote them. A `RelPtr` stays valid everywhere, and is resolved
+/// against the local mapping with bounds and alignment checks.
+///
+/// An offset of zero is reserved as the null pointer, since the start of a
+/// segment is normally occupied by its header.
+#[repr(transparent)]
+pub struct RelPtr<T> {
+    offset: u64,
+    _target: PhantomData<*const T>,
+}
+
+// manual impls so that `T` is not required to implement these traits
+impl<T> Clone for RelPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for RelPtr<T> {}
+
+impl<T> PartialEq for RelPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for RelPtr<T> {}
+
+impl<T> fmt::Debug for RelPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RelPtr({:#x})", self.offset)
+    }
+}
+
+impl<T> Default for RelPtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+unsafe impl<T> Send for RelPtr<T> {}
+unsafe impl<T> Sync for RelPtr<T> {}
+
+/// A `RelPtr` is an integer, so it may itself be placed in shared memory
+unsafe impl<T: 'static> Pod for RelPtr<T> {}
+
+impl<T> RelPtr<T> {
+    pub const fn null() -> Self {
+        Self::from_offset(0)
+    }
+
+    pub const fn from_offset(offset: u64) -> Self {
+        Self {
+            offset,
+            _target: PhantomData,
+        }
+    }
+
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub const fn is_null(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Resolve the pointer against this process's mapping of the segment,
+    /// failing if it is null, out of bounds, or misaligned
+    pub fn resolve(&self, map: &MmapMut) -> io::Result<*mut T> {
+        if self.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot resolve a null relative pointer",
+            ));
+        }
+
+        let offset = usize::try_from(self.offset).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range out of bounds of mapping",
+            )
+        })?;
+
+        map.typed_ptr(offset, 1)
+    }
+
+    /// Resolve the pointer and borrow its target
+    ///
+    /// # Safety
+    ///
+    /// The target must be initialized, and must not be modified by this or
+    /// any other process while the reference is alive, except through
+    /// interior mutability such as atomics.
+    pub unsafe fn get<'m>(&self, map: &'m MmapMut) -> io::Result<&'m T> {
+        Ok(&*self.resolve(map)?)
+    }
+}
+
+impl<'m> ShmAllocator<'m> {
+    /// Allocate space for a `T`, returning a pointer which is valid in every
+    /// process attached to the heap
+    ///
+    /// Types aligned to more than 16 bytes are not supported, and fail with
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput).
+    pub fn alloc_typed<T>(&self) -> io::Result<Option<RelPtr<T>>> {
+        if std::mem::align_of::<T>() > 16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "type is aligned to more than 16 bytes",
+            ));
+        }
+
+        Ok(self
+            .alloc(std::mem::size_of::<T>())?
+            .map(RelPtr::from_offset))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{MmapMut, RelPtr, ShmAllocator};
+
+    #[test]
+    fn resolves_against_mapping() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let node = ShmAllocator::init(&mut map)
+            .unwrap()
+            .alloc_typed::<[u64; 2]>()
+            .unwrap()
+            .unwrap();
+
+        unsafe { node.resolve(&map).unwrap().write([1, 2]) };
+
+        // a second mapping of the same memory at a different address
+        let alias = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        unsafe { std::ptr::copy_nonoverlapping(map.ptr, alias.ptr, 4096) };
+
+        assert_eq!(unsafe { *node.get(&alias).unwrap() }, [1, 2]);
+
+        assert!(RelPtr::<u64>::null().resolve(&map).is_err());
+        assert!(RelPtr::<u64>::from_offset(4).resolve(&map).is_err());
+        assert!(RelPtr::<u64>::from_offset(4096).resolve(&map).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    io,
+    marker::PhantomData,
+    ops::{Range, RangeBounds},
+    ptr,
+};
+
+use crate::{
+    flag::{Flag, UniqueFlag},
+    libc_compat, maps, mmap_raw, mremap_raw, munmap_raw, page_aligned_span, page_size,
+    require_real_mapping, round_up_to_page, Backing, FlushOnDrop, MmapMut, Protection,
+};
+
+impl<'a> MmapMut<'a> {
+    /// Move the pages of the mapping to a new address with `MREMAP_DONTUNMAP`,
+    /// leaving the original range mapped but empty
+    ///
+    /// Afterwards `self` refers to the new location, and the returned mapping
+    /// covers the original range. Accesses to the original range fault as if
+    /// it were freshly mapped, which lets userfaultfd-based live migration and
+    /// garbage collection schemes intercept them.
+    ///
+    /// Only private anonymous mappings are supported before Linux 5.13.
+    ///
+    /// (since Linux 5.7)
+    pub fn relocate_keep_source(&mut self) -> io::Result<MmapMut<'a>> {
+        let new = unsafe {
+            mremap_raw(
+                self.ptr,
+                self.len,
+                self.len,
+                libc_compat::MREMAP_MAYMOVE | libc_compat::MREMAP_DONTUNMAP,
+                ptr::null_mut(),
+            )?
+        };
+
+        let source = MmapMut {
+            ptr: self.ptr,
+            len: self.len,
+            offset: self.offset,
+            backing: self.backing,
+            _lock: None,
+            flush_on_drop: FlushOnDrop::None,
+            _lifetime: PhantomData,
+        };
+
+        // the guard pages stay below the source
+        self.ptr = new;
+        self.backing = Backing::Mapped { guard_len: 0 };
+
+        Ok(source)
+    }
+
+    /// Move the mapping to exactly `addr` with `MREMAP_FIXED`, keeping its
+    /// contents
+    ///
+    /// `addr` must be page aligned, and the source and destination ranges must
+    /// not overlap.
+    ///
+    /// # Safety
+    ///
+    /// Any existing mappings in `[addr, addr + len)` are unmapped, so nothing
+    /// may still be referring to memory in that range.
+    pub unsafe fn move_to(&mut self, addr: *mut u8) -> io::Result<()> {
+        if !(addr as usize).is_multiple_of(page_size()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "destination address is not page aligned",
+            ));
+        }
+
+        let old = self.ptr;
+
+        self.ptr = mremap_raw(
+            self.ptr,
+            self.len,
+            self.len,
+            libc_compat::MREMAP_MAYMOVE | libc_compat::MREMAP_FIXED,
+            addr,
+        )?;
+
+        self.backing.unmap_guard(old);
+
+        Ok(())
+    }
+
+    /// Move the pages in `range` to start `dst` bytes into the mapping by
+    /// remapping them with `mremap(2)`, leaving the vacated pages zeroed
+    ///
+    /// Only page table entries change hands, so compacting a large arena costs
+    /// time proportional to the number of pages moved rather than copying
+    /// their contents. Whatever was in the destination is discarded, and the
+    /// two spans may overlap.
+    ///
+    /// `range` and `dst` must start on page boundaries, and `range` must end
+    /// on one or at the end of the mapping. Only private anonymous mappings
+    /// are supported, as file-backed pages cannot be moved to other offsets of
+    /// the file. If moving fails part way, both spans are left mapped but
+    /// with their contents replaced by zeroes.
+    pub fn move_pages_within(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        dst: usize,
+    ) -> io::Result<()> {
+        let (src, len) = page_aligned_span(self.ptr, self.len, range)?;
+        let dst = destination(self.ptr, self.len, dst, len)?;
+
+        unsafe { move_pages(src, dst, len) }
+    }
+
+    /// Move the pages in `range` to start `dst` bytes into `target`, as
+    /// [`move_pages_within`](Self::move_pages_within) does within one mapping
+    ///
+    /// Both mappings must be private and anonymous.
+    pub fn move_pages_into(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        target: &mut MmapMut<'_>,
+        dst: usize,
+    ) -> io::Result<()> {
+        let (src, len) = page_aligned_span(self.ptr, self.len, range)?;
+        let dst = destination(target.ptr, target.len, dst, len)?;
+
+        unsafe { move_pages(src, dst, len) }
+    }
+}
+
+/// Resolve the span of `len` bytes `offset` bytes into the mapping at `ptr`
+/// which pages are moved into
+///
+/// A span whose last page is partial must end at the end of the mapping, so
+/// that moving the whole page does not overwrite bytes past the span.
+fn destination(ptr: *mut u8, map_len: usize, offset: usize, len: usize) -> io::Result<*mut u8> {
+    let fits = offset.is_multiple_of(page_size())
+        && offset.checked_add(len).is_some_and(|end| {
+            end == map_len || (end < map_len && len.is_multiple_of(page_size()))
+        });
+
+    if !fits {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "destination is not a page aligned span within the mapping",
+        ));
+    }
+
+    Ok(unsafe { ptr.add(offset) })
+}
+
+/// Move the private anonymous pages covering `len` bytes at `src` to `dst`,
+/// mapping fresh zeroed pages over whatever part of `src` is vacated
+unsafe fn move_pages(src: *mut u8, dst: *mut u8, len: usize) -> io::Result<()> {
+    require_real_mapping()?;
+
+    let len = round_up_to_page(len);
+    let (src_start, dst_start) = (src as usize, dst as usize);
+
+    if len == 0 || src_start == dst_start {
+        return Ok(());
+    }
+
+    let regions = maps::parse_self()?;
+
+    for span in [src_start..src_start + len, dst_start..dst_start + len] {
+        require_private_anon(&regions, span)?;
+    }
+
+    let moved = if src_start < dst_start + len && dst_start < src_start + len {
+        // mremap refuses overlapping spans, so park the pages elsewhere first
+        let staging = mmap_raw(
+            ptr::null_mut(),
+            len,
+            Protection::NONE,
+            (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_NORESERVE).0,
+            -1,
+            0,
+        )?;
+
+        let mut unstaged = 0;
+        let moved = splice(src, staging, len, &mut 0)
+            .and_then(|()| splice(staging, dst, len, &mut unstaged));
+
+        if moved.is_err() {
+            // pages already moved on to `dst` left holes in the staging area
+            // which may have been mapped since, so only unmap what remains
+            munmap_raw(staging.add(unstaged), len - unstaged);
+        }
+
+        moved
+    } else {
+        splice(src, dst, len, &mut 0)
+    };
+
+    if let Err(err) = moved {
+        // neither mapping may be left with holes, whose pages would fault
+        for start in [src, dst] {
+            let _ = fresh_pages(start, len);
+        }
+
+        return Err(err);
+    }
+
+    let vacated = if src_start < dst_start {
+        src_start..(src_start + len).min(dst_start)
+    } else {
+        (dst_start + len).max(src_start)..src_start + len
+    };
+
+    fresh_pages(vacated.start as *mut u8, vacated.len())
+}
+
+/// Map zeroed private anonymous pages over `len` bytes at `ptr`
+unsafe fn fresh_pages(ptr: *mut u8, len: usize) -> io::Result<()> {
+    mmap_raw(
+        ptr,
+        len,
+        Protection::READ | Protection::WRITE,
+        (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_FIXED).0,
+        -1,
+        0,
+    )?;
+
+    Ok(())
+}
+
+/// Fail unless every page in `span` belongs to a private anonymous mapping
+fn require_private_anon(regions: &[maps::MapRegion], span: Range<usize>) -> io::Result<()> {
+    let mut addr = span.start;
+
+    while addr < span.end {
+        match maps::find_region(regions, addr) {
+            Some(region) if !region.perms.shared && region.inode == 0 => addr = region.end,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "pages can only be moved between private anonymous mappings",
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `mremap(2)` the pages at `src` to `dst` one mapping at a time, as a single
+/// call cannot span several
+///
+/// `moved` is set to how many bytes were moved, so that callers can tell how
+/// far a failed move got.
+unsafe fn splice(src: *mut u8, dst: *mut u8, len: usize, moved: &mut usize) -> io::Result<()> {
+    let regions = maps::parse_self()?;
+
+    while *moved < len {
+        let offset = *moved;
+        let addr = src as usize + offset;
+        let chunk = maps::find_region(&regions, addr)
+            .map(|region| (region.end - addr).min(len - offset))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "pages are not mapped"))?;
+
+        mremap_raw(
+            addr as *mut u8,
+            chunk,
+            chunk,
+            libc_compat::MREMAP_MAYMOVE | libc_compat::MREMAP_FIXED,
+            dst.add(offset),
+        )?;
+
+        *moved += chunk;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize};
+
+    use crate::{page_size, MmapMut, MmapOptions};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn relocate_keeps_source_mapped() {
+        let mut map = MmapOptions::new(NonZeroUsize::new(8192).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        map.fill(4);
+
+        let old = map.ptr;
+        let source = map.relocate_keep_source().unwrap();
+
+        assert_ne!(map.ptr, old);
+        assert_eq!(source.ptr, old);
+        assert!(map.iter().all(|&b| b == 4));
+        assert!(source.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn move_to_fixed_address() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        map[..4].copy_from_slice(b"move");
+
+        // leaked, as the move replaces its pages
+        let target = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+        let addr = target.into_leaked().as_mut_ptr();
+
+        unsafe { map.move_to(addr).unwrap() };
+
+        assert_eq!(map.ptr, addr);
+        assert_eq!(&map[..4], b"move");
+        assert!(unsafe { map.move_to(addr.add(1)) }.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn moves_pages_by_remapping() {
+        let page = page_size();
+        let mut map = MmapOptions::new(NonZeroUsize::new(8 * page).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        for (idx, chunk) in map.chunks_mut(page).enumerate() {
+            chunk.fill(idx as u8 + 1);
+        }
+
+        // overlapping, towards the start
+        map.move_pages_within(2 * page..5 * page, page).unwrap();
+
+        let firsts: Vec<u8> = map.chunks(page).map(|chunk| chunk[0]).collect();
+        assert_eq!(firsts, [1, 3, 4, 5, 0, 6, 7, 8]);
+
+        // disjoint, ending at the end of the mapping
+        map.move_pages_within(6 * page.., 0).unwrap();
+
+        let firsts: Vec<u8> = map.chunks(page).map(|chunk| chunk[0]).collect();
+        assert_eq!(firsts, [7, 8, 4, 5, 0, 6, 0, 0]);
+        assert!(map[..page].iter().all(|&b| b == 7));
+
+        let mut other = MmapOptions::new(NonZeroUsize::new(2 * page).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        map.move_pages_into(2 * page..4 * page, &mut other, 0)
+            .unwrap();
+
+        assert_eq!(other[0], 4);
+        assert_eq!(other[page], 5);
+        assert_eq!(map[2 * page], 0);
+
+        assert!(map.move_pages_within(1..page, 0).is_err());
+        assert!(map.move_pages_within(..page, 7 * page + 1).is_err());
+        assert!(map.move_pages_into(..2 * page, &mut other, page).is_err());
+
+        let mut shared = MmapMut::new_anon(NonZeroUsize::new(2 * page).unwrap()).unwrap();
+
+        assert_eq!(
+            shared.move_pages_within(..page, page).err().unwrap().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
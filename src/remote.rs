@@ -0,0 +1,282 @@
+//! Bulk copies between local mappings and the address space of another process
+//! with `process_vm_readv(2)` and `process_vm_writev(2)`
+//!
+//! The caller needs `PTRACE_MODE_ATTACH_REALCREDS` permission over the target
+//! process. Copies do not go through the page cache of either process and the
+//! target is not stopped, so the data may be inconsistent if it is concurrently
+//! modified.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    marker::PhantomData,
+    num::NonZeroUsize,
+    os::unix::fs::FileExt,
+    ptr,
+};
+
+use crate::{libc_compat, MmapMut};
+
+/// Copy `buf.len()` bytes starting at `remote_addr` in process `pid` into
+/// `buf`, which is typically a [`MmapMut`]
+///
+/// Returns the number of bytes copied, which is less than `buf.len()` if the
+/// end of the readable remote region was reached.
+pub fn read_into(pid: libc::pid_t, remote_addr: usize, buf: &mut [u8]) -> io::Result<usize> {
+    transfer(buf.len(), |done| {
+        let local = libc::iovec {
+            iov_base: buf[done..].as_mut_ptr().cast(),
+            iov_len: buf.len() - done,
+        };
+
+        let remote = libc::iovec {
+            iov_base: (remote_addr + done) as *mut _,
+            iov_len: buf.len() - done,
+        };
+
+        unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) }
+    })
+}
+
+/// Copy all of `buf`, which is typically a [`Mmap`](crate::Mmap), to
+/// `remote_addr` in process `pid`
+///
+/// Returns the number of bytes copied, which is less than `buf.len()` if the
+/// end of the writable remote region was reached.
+pub fn write_from(pid: libc::pid_t, remote_addr: usize, buf: &[u8]) -> io::Result<usize> {
+    transfer(buf.len(), |done| {
+        let local = libc::iovec {
+            iov_base: buf[done..].as_ptr() as *mut _,
+            iov_len: buf.len() - done,
+        };
+
+        let remote = libc::iovec {
+            iov_base: (remote_addr + done) as *mut _,
+            iov_len: buf.len() - done,
+        };
+
+        unsafe { libc::process_vm_writev(pid, &local, 1, &remote, 1, 0) }
+    })
+}
+
+/// Repeat a partial transfer until `len` bytes were copied or the remote region
+/// ends
+fn transfer(len: usize, mut step: impl FnMut(usize) -> isize) -> io::Result<usize> {
+    let mut done = 0;
+
+    while done < len {
+        match step(done) {
+            0 => break,
+            n if n > 0 => done += n as usize,
+            _ => {
+                let err = io::Error::last_os_error();
+
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // a fault after some progress means the remote region
+                    // ended part way through
+                    Some(libc::EFAULT) if done > 0 => break,
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+
+    Ok(done)
+}
+
+/// Random access to the memory of another process through `/proc/<pid>/mem`
+///
+/// If the process cannot be opened directly, for example because Yama
+/// restricts access to non-descendants, [`attach`](Self::attach) falls back to
+/// attaching with `ptrace(2)`. In that case the target thread stays stopped
+/// until this is dropped, which must happen on the thread that attached, as
+/// only the tracer may detach; so `RemoteMemory` is not `Send`.
+pub struct RemoteMemory {
+    pid: libc::pid_t,
+    mem: File,
+    traced: bool,
+    _tracer: PhantomData<*const ()>,
+}
+
+impl RemoteMemory {
+    pub fn attach(pid: libc::pid_t) -> io::Result<Self> {
+        match open_mem(pid) {
+            Ok(mem) => Ok(Self {
+                pid,
+                mem,
+                traced: false,
+                _tracer: PhantomData,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Self::attach_stopped(pid),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attach with `ptrace(2)` and stop the thread `pid` for as long as this
+    /// is alive
+    ///
+    /// Only that thread is stopped, which for a process is its main thread;
+    /// its other threads keep running, so reads of memory they modify may
+    /// still observe an inconsistent state.
+    pub fn attach_stopped(pid: libc::pid_t) -> io::Result<Self> {
+        unsafe {
+            if libc::ptrace(
+                libc_compat::PTRACE_SEIZE,
+                pid,
+                ptr::null_mut::<libc::c_void>(),
+                0,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::ptrace(
+                libc_compat::PTRACE_INTERRUPT,
+                pid,
+                ptr::null_mut::<libc::c_void>(),
+                0,
+            ) < 0
+            {
+                let err = io::Error::last_os_error();
+                detach(pid);
+                return Err(err);
+            }
+
+            let mut status = 0;
+
+            while libc::waitpid(pid, &mut status, libc::__WALL) < 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() != io::ErrorKind::Interrupted {
+                    detach(pid);
+                    return Err(err);
+                }
+            }
+        }
+
+        match open_mem(pid) {
+            Ok(mem) => Ok(Self {
+                pid,
+                mem,
+                traced: true,
+                _tracer: PhantomData,
+            }),
+            Err(e) => {
+                detach(pid);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Whether the thread is attached with `ptrace(2)` and stopped
+    pub fn is_stopped(&self) -> bool {
+        self.traced
+    }
+
+    /// Read into `buf` from `remote_addr`, returning the number of bytes read
+    pub fn read_at(&self, remote_addr: usize, buf: &mut [u8]) -> io::Result<usize> {
+        self.mem.read_at(buf, remote_addr as u64)
+    }
+
+    /// Write `buf` to `remote_addr`, returning the number of bytes written
+    pub fn write_at(&self, remote_addr: usize, buf: &[u8]) -> io::Result<usize> {
+        self.mem.write_at(buf, remote_addr as u64)
+    }
+
+    /// Copy `len` bytes starting at `remote_addr` into a new anonymous mapping
+    pub fn snapshot<'a>(&self, remote_addr: usize, len: NonZeroUsize) -> io::Result<MmapMut<'a>> {
+        let mut map = MmapMut::new_anon(len)?;
+
+        self.mem.read_exact_at(&mut map, remote_addr as u64)?;
+
+        Ok(map)
+    }
+}
+
+impl Drop for RemoteMemory {
+    fn drop(&mut self) {
+        if self.traced {
+            detach(self.pid);
+        }
+    }
+}
+
+fn open_mem(pid: libc::pid_t) -> io::Result<File> {
+    let path = format!("/proc/{}/mem", pid);
+
+    match OpenOptions::new().read(true).write(true).open(&path) {
+        Ok(file) => Ok(file),
+        // fall back to read-only access, in which case writes fail with EBADF
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            OpenOptions::new().read(true).open(&path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn detach(pid: libc::pid_t) {
+    unsafe {
+        libc::ptrace(libc::PTRACE_DETACH, pid, ptr::null_mut::<libc::c_void>(), 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{
+        remote::{self, RemoteMemory},
+        MmapMut,
+    };
+
+    #[test]
+    fn copies_with_own_process() {
+        let pid = std::process::id() as i32;
+        let source = vec![7_u8; 8192];
+
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(8192).unwrap()).unwrap();
+
+        assert_eq!(
+            remote::read_into(pid, source.as_ptr() as usize, &mut map).unwrap(),
+            8192
+        );
+        assert!(map.iter().all(|&b| b == 7));
+
+        let mut dest = vec![0_u8; 100];
+        map[..100].fill(9);
+
+        assert_eq!(
+            remote::write_from(pid, dest.as_mut_ptr() as usize, &map[..100]).unwrap(),
+            100
+        );
+        assert_eq!(dest, vec![9; 100]);
+    }
+
+    #[test]
+    fn proc_mem_access() {
+        let memory = RemoteMemory::attach(std::process::id() as i32).unwrap();
+
+        let mut local = vec![3_u8; 64];
+        let addr = local.as_mut_ptr() as usize;
+
+        assert!(!memory.is_stopped());
+        assert_eq!(memory.write_at(addr + 8, &[4; 8]).unwrap(), 8);
+
+        let mut buf = [0; 16];
+        assert_eq!(memory.read_at(addr, &mut buf).unwrap(), 16);
+        assert_eq!(&buf[..], &local[..16]);
+
+        let snapshot = memory
+            .snapshot(addr, NonZeroUsize::new(64).unwrap())
+            .unwrap();
+        assert_eq!(
+            &snapshot[..16],
+            &[3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4]
+        );
+    }
+}
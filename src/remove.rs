@@ -0,0 +1,95 @@
+use std::{io, ops::RangeBounds};
+
+use crate::{madvise_raw, page_aligned_span, MmapMut};
+
+/// `MADV_REMOVE` the page aligned `range` of the mapping at `ptr`
+pub(crate) fn remove_range(
+    ptr: *mut u8,
+    len: usize,
+    range: impl RangeBounds<usize>,
+) -> io::Result<()> {
+    let (ptr, len) = page_aligned_span(ptr, len, range)?;
+
+    if len == 0 {
+        return Ok(());
+    }
+
+    unsafe { madvise_raw(ptr, len, libc::MADV_REMOVE) }.map_err(|e| match e.raw_os_error() {
+        Some(libc::EINVAL) => io::Error::new(
+            io::ErrorKind::Unsupported,
+            "MADV_REMOVE is only supported on shared mappings of hole-punching file systems",
+        ),
+        Some(libc::EOPNOTSUPP) => io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the mapped file system does not support punching holes",
+        ),
+        _ => e,
+    })
+}
+
+impl<'a> MmapMut<'a> {
+    /// Free the pages backing `range` and the blocks of the shared memory or
+    /// file behind them, with `MADV_REMOVE`
+    ///
+    /// Unlike `MADV_DONTNEED`, which only drops a shared mapping's pages from
+    /// this process, this punches a hole in the shared memory object or file,
+    /// so the memory is returned to the system and every mapping of it reads
+    /// zeros afterwards. That makes it the way to shrink a memfd or tmpfs
+    /// backed cache in place.
+    ///
+    /// Only shared mappings of files which support punching holes can be
+    /// removed from, which includes shared anonymous mappings, memfd and
+    /// tmpfs, and most disk file systems; hugetlbfs is supported since Linux
+    /// 4.3. Other mappings fail with
+    /// [`Unsupported`](io::ErrorKind::Unsupported). The range must be page
+    /// aligned.
+    pub fn remove_range(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        remove_range(self.ptr, self.len, range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize};
+
+    use crate::{page_size, MemfdMmap, MmapMut, MmapOptions};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn frees_shared_pages() {
+        let page = page_size();
+
+        let mut memfd =
+            MemfdMmap::new("remove-test", NonZeroUsize::new(3 * page).unwrap()).unwrap();
+        let mut map = MmapMut::new_file(memfd.file()).unwrap();
+
+        map.fill(1);
+        map.remove_range(page..2 * page).unwrap();
+
+        assert_eq!(memfd[page], 0);
+        assert_eq!(memfd[2 * page], 1);
+        assert!(map.remove_range(1..page).is_err());
+
+        memfd.remove_range(2 * page..).unwrap();
+
+        assert_eq!(map[2 * page], 0);
+        assert_eq!(map[0], 1);
+
+        let mut anon = MmapMut::new_anon(NonZeroUsize::new(page).unwrap()).unwrap();
+
+        anon[0] = 1;
+        anon.remove_range(..).unwrap();
+
+        assert_eq!(anon[0], 0);
+
+        let mut private = MmapOptions::new(NonZeroUsize::new(page).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        assert_eq!(
+            private.remove_range(..).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
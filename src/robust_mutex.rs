@@ -0,0 +1,206 @@
+use std::{
+    cell::UnsafeCell,
+    io,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{MmapCell, MmapMut, Pod};
+
+/// A mutex placed inside a shared mapping which survives its owner dying
+///
+/// This is a `pthread_mutex_t` initialized with `PTHREAD_PROCESS_SHARED` and
+/// `PTHREAD_MUTEX_ROBUST`. When a process or thread dies while holding the
+/// lock, the next one to lock it is told so, and gets the chance to repair the
+/// protected value, which may have been left half-updated, before anyone
+/// else sees it.
+///
+/// Only available on Linux, as Android's libc has no robust mutexes.
+#[repr(C)]
+pub struct RobustShmMutex<T: Pod> {
+    mutex: UnsafeCell<libc::pthread_mutex_t>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Pod> Sync for RobustShmMutex<T> {}
+
+impl<T: Pod> RobustShmMutex<T> {
+    /// Initialize a mutex protecting `value` at `offset` in the mapping and
+    /// return a reference to it
+    pub fn init_in<'m>(map: &'m mut MmapMut, offset: usize, value: T) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        unsafe {
+            ptr::write(ptr::addr_of_mut!((*ptr).data), UnsafeCell::new(value));
+
+            let mut attr = mem::MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
+
+            check(libc::pthread_mutexattr_init(attr.as_mut_ptr()))?;
+
+            let res = check(libc::pthread_mutexattr_setpshared(
+                attr.as_mut_ptr(),
+                libc::PTHREAD_PROCESS_SHARED,
+            ))
+            .and_then(|()| {
+                check(libc::pthread_mutexattr_setrobust(
+                    attr.as_mut_ptr(),
+                    libc::PTHREAD_MUTEX_ROBUST,
+                ))
+            })
+            .and_then(|()| {
+                // the mutex must be initialized where it will live, as it may
+                // not be moved afterwards
+                check(libc::pthread_mutex_init(
+                    UnsafeCell::raw_get(ptr::addr_of!((*ptr).mutex)),
+                    attr.as_ptr(),
+                ))
+            });
+
+            libc::pthread_mutexattr_destroy(attr.as_mut_ptr());
+
+            res.map(|()| &*ptr)
+        }
+    }
+
+    /// Attach to a mutex previously initialized at `offset` in a
+    /// [view](MmapMut::as_cell) of the mapping, possibly by another process
+    pub fn attach<'m>(map: &'m MmapCell, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Lock the mutex, blocking until it is available
+    ///
+    /// If the previous owner died while holding the lock, `recover` is called
+    /// with the protected value to make it consistent again. Returning `true`
+    /// marks the mutex usable again; returning `false` gives up, making every
+    /// later attempt to lock it fail with `ENOTRECOVERABLE`, which is also
+    /// the error returned here.
+    pub fn lock(
+        &self,
+        recover: impl FnOnce(&mut T) -> bool,
+    ) -> io::Result<RobustShmMutexGuard<'_, T>> {
+        let guard = match unsafe { libc::pthread_mutex_lock(self.mutex.get()) } {
+            0 => RobustShmMutexGuard {
+                mutex: self,
+                _not_send: PhantomData,
+            },
+            libc::EOWNERDEAD => {
+                // unlocked on every path by the guard's drop
+                let mut guard = RobustShmMutexGuard {
+                    mutex: self,
+                    _not_send: PhantomData,
+                };
+
+                if !recover(&mut guard) {
+                    return Err(io::Error::from_raw_os_error(libc::ENOTRECOVERABLE));
+                }
+
+                check(unsafe { libc::pthread_mutex_consistent(self.mutex.get()) })?;
+
+                guard
+            }
+            err => return Err(io::Error::from_raw_os_error(err)),
+        };
+
+        Ok(guard)
+    }
+}
+
+/// Exclusive access to the value protected by a [`RobustShmMutex`], which is
+/// unlocked on drop
+///
+/// The guard cannot be sent to another thread, as only the thread which
+/// locked a pthread mutex may unlock it.
+pub struct RobustShmMutexGuard<'a, T: Pod> {
+    pub(crate) mutex: &'a RobustShmMutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: Pod + Sync> Sync for RobustShmMutexGuard<'a, T> {}
+
+impl<'a, T: Pod> Deref for RobustShmMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: Pod> DerefMut for RobustShmMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: Pod> Drop for RobustShmMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::pthread_mutex_unlock(self.mutex.mutex.get());
+        }
+    }
+}
+
+/// Convert the error number returned by a pthread function
+fn check(res: i32) -> io::Result<()> {
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(res))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{mem, num::NonZeroUsize, thread};
+
+    use crate::{MmapMut, RobustShmMutex};
+
+    #[test]
+    fn recovers_from_dead_owner() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        RobustShmMutex::init_in(&mut map, 0, [0_u32; 2]).unwrap();
+
+        let cell = map.as_cell();
+        let mutex = RobustShmMutex::<[u32; 2]>::attach(&cell, 0).unwrap();
+
+        *mutex.lock(|_| unreachable!()).unwrap() = [1, 1];
+
+        // a thread exiting with the lock held is treated like a dead process
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut guard = mutex.lock(|_| unreachable!()).unwrap();
+                guard[0] = 2;
+                mem::forget(guard);
+            });
+        });
+
+        let mut recovered = false;
+
+        let guard = mutex
+            .lock(|value| {
+                recovered = true;
+                value[1] = value[0];
+                true
+            })
+            .unwrap();
+
+        assert!(recovered);
+        assert_eq!(*guard, [2, 2]);
+        drop(guard);
+
+        thread::scope(|s| {
+            s.spawn(|| mem::forget(mutex.lock(|_| unreachable!()).unwrap()));
+        });
+
+        assert!(mutex.lock(|_| false).is_err());
+        assert_eq!(
+            mutex.lock(|_| unreachable!()).err().unwrap().raw_os_error(),
+            Some(libc::ENOTRECOVERABLE)
+        );
+    }
+}
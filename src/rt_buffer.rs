@@ -0,0 +1,118 @@
+use std::{
+    io,
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+use crate::{madvise_raw, mincore_raw, munmap_raw, page_size, round_up_to_page, MmapOptions};
+
+/// An anonymous buffer prepared for use from real-time threads
+///
+/// Construction performs the usual checklist so that accessing the buffer can
+/// never page-fault or block:
+///
+///  - pages are populated and written to up front
+///  - pages are locked into RAM with `mlock(2)`
+///  - `MADV_DONTFORK` keeps child processes from sharing the pages, which
+///    would otherwise reintroduce copy-on-write faults in the parent
+///  - `MADV_NOHUGEPAGE` prevents khugepaged from collapsing the region, which
+///    stalls accesses while pages are migrated
+///  - `mincore(2)` confirms every page is resident before returning
+///
+/// The buffer is unlocked and unmapped on drop.
+pub struct RtBuffer {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for RtBuffer {}
+unsafe impl Sync for RtBuffer {}
+
+impl RtBuffer {
+    pub fn new(len: NonZeroUsize) -> io::Result<Self> {
+        // ownership passes to the buffer, which unmaps it itself
+        let map = MmapOptions::new(len)
+            .private()
+            .populate()
+            .map_anon_mut()?
+            .into_leaked();
+
+        let buffer = Self {
+            ptr: map.as_mut_ptr(),
+            len: map.len(),
+        };
+
+        unsafe {
+            madvise_raw(buffer.ptr, buffer.len, libc::MADV_DONTFORK)?;
+            madvise_raw(buffer.ptr, buffer.len, libc::MADV_NOHUGEPAGE)?;
+        }
+
+        // MAP_POPULATE only maps the shared zero page for reads, so touch
+        // every page with a write to allocate it
+        for page in (0..buffer.len).step_by(page_size()) {
+            unsafe { buffer.ptr.add(page).write_volatile(0) };
+        }
+
+        if unsafe { libc::mlock(buffer.ptr.cast(), buffer.len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if buffer.resident_pages()? != buffer.len.div_ceil(page_size()) {
+            return Err(io::Error::other(
+                "buffer is not fully resident after locking",
+            ));
+        }
+
+        Ok(buffer)
+    }
+
+    /// Number of pages of the buffer currently resident in RAM
+    pub fn resident_pages(&self) -> io::Result<usize> {
+        let mut vec = vec![0_u8; round_up_to_page(self.len) / page_size()];
+
+        unsafe { mincore_raw(self.ptr, self.len, &mut vec)? };
+
+        Ok(vec.iter().filter(|&&page| page & 1 != 0).count())
+    }
+}
+
+impl Deref for RtBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for RtBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for RtBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munlock(self.ptr.cast(), self.len);
+            munmap_raw(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, RtBuffer};
+
+    #[test]
+    fn fully_resident() {
+        let mut buffer = RtBuffer::new(NonZeroUsize::new(16 * page_size()).unwrap()).unwrap();
+
+        assert_eq!(buffer.resident_pages().unwrap(), 16);
+
+        buffer.fill(3);
+        assert!(buffer.iter().all(|&b| b == 3));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    fs::File,
+    io, mem,
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixStream,
+    },
+    ptr,
+};
+
+/// Send `file` over `stream` as `SCM_RIGHTS` ancillary data, along with a
+/// single byte of regular data
+pub(crate) fn send_fd(stream: &UnixStream, file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let mut byte = [0_u8];
+
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: 1,
+    };
+
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<i32>() as u32) } as usize;
+    let mut control = vec![0_u8; space];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<i32>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), fd);
+    }
+
+    loop {
+        if unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) } >= 0 {
+            return Ok(());
+        }
+
+        let err = io::Error::last_os_error();
+
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Receive a file descriptor sent with [`send_fd`]
+pub(crate) fn recv_fd(stream: &UnixStream) -> io::Result<File> {
+    let mut byte = [0_u8];
+
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: 1,
+    };
+
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<i32>() as u32) } as usize;
+    let mut control = vec![0_u8; space];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+
+    loop {
+        let received =
+            unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+
+        if received > 0 {
+            break;
+        }
+
+        if received == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream closed before a file descriptor was received",
+            ));
+        }
+
+        let err = io::Error::last_os_error();
+
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    // take ownership of every descriptor received before checking anything,
+    // so that unexpected ones are closed rather than leaked
+    let mut fds = Vec::new();
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg);
+                let len = ((*cmsg).cmsg_len as usize).saturating_sub(data as usize - cmsg as usize);
+
+                for idx in 0..len / mem::size_of::<i32>() {
+                    let fd: i32 = ptr::read_unaligned(data.cast::<i32>().add(idx));
+
+                    fds.push(File::from_raw_fd(fd));
+                }
+            }
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    // descriptors which did not fit were discarded by the kernel
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message carried more than one file descriptor",
+        ));
+    }
+
+    match <[File; 1]>::try_from(fds) {
+        Ok([file]) => Ok(file),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message did not carry exactly one file descriptor",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io::{self, Write},
+        mem,
+        os::unix::{io::AsRawFd, net::UnixStream},
+        ptr,
+    };
+
+    use super::{recv_fd, send_fd};
+
+    /// Send `fds` in a single `SCM_RIGHTS` message
+    fn send_fds(stream: &UnixStream, fds: &[i32]) {
+        let mut byte = [0_u8];
+
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr().cast(),
+            iov_len: 1,
+        };
+
+        let len = mem::size_of_val(fds) as u32;
+        let space = unsafe { libc::CMSG_SPACE(len) } as usize;
+        let mut control = vec![0_u8; space];
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+
+            assert_eq!(libc::sendmsg(stream.as_raw_fd(), &msg, 0), 1);
+        }
+    }
+
+    #[test]
+    fn receives_exactly_one_fd() {
+        let (mut a, b) = UnixStream::pair().unwrap();
+        let file = File::open("/dev/null").unwrap();
+
+        send_fd(&a, &file).unwrap();
+        assert!(recv_fd(&b).is_ok());
+
+        send_fds(&a, &[file.as_raw_fd(), file.as_raw_fd()]);
+        assert_eq!(recv_fd(&b).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        a.write_all(b"x").unwrap();
+        assert_eq!(recv_fd(&b).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{io, num::NonZeroUsize, ops::Deref, os::unix::net::UnixStream};
+
+use crate::{
+    scm::{recv_fd, send_fd},
+    MemfdMmap, SealedMmap,
+};
+
+/// An immutable blob in a sealed memfd, which can be passed between processes
+/// without copying
+///
+/// The sender fills a memfd, seals it read-only, and passes the file
+/// descriptor over a Unix socket with `SCM_RIGHTS`. The receiver verifies the
+/// seals before mapping it, so it can trust the contents will never change
+/// underneath it.
+pub struct SealedBuffer {
+    map: SealedMmap,
+}
+
+impl SealedBuffer {
+    /// Copy `data` into a new sealed buffer
+    pub fn new(name: &str, data: &[u8]) -> io::Result<Self> {
+        let len = NonZeroUsize::new(data.len()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "cannot seal an empty buffer")
+        })?;
+
+        Self::with_writer(name, len, |buf| buf.copy_from_slice(data))
+    }
+
+    /// Create a sealed buffer of `len` bytes, populated in place by `f`
+    pub fn with_writer(
+        name: &str,
+        len: NonZeroUsize,
+        f: impl FnOnce(&mut [u8]),
+    ) -> io::Result<Self> {
+        let mut memfd = MemfdMmap::new(name, len)?;
+
+        f(&mut memfd);
+
+        Ok(Self {
+            map: memfd.seal_read_only().map_err(|(_, err)| err)?,
+        })
+    }
+
+    /// Send the buffer to the process at the other end of `stream`
+    pub fn send_over(&self, stream: &UnixStream) -> io::Result<()> {
+        send_fd(stream, self.map.file())
+    }
+
+    /// Receive a buffer sent with [`send_over`](Self::send_over), failing if
+    /// the received memfd is not sealed read-only
+    pub fn recv_from(stream: &UnixStream) -> io::Result<Self> {
+        Ok(Self {
+            map: SealedMmap::from_file(recv_fd(stream)?)?,
+        })
+    }
+}
+
+impl Deref for SealedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io::Write,
+        os::unix::{io::FromRawFd, net::UnixStream},
+    };
+
+    use crate::{scm::send_fd, SealedBuffer};
+
+    #[test]
+    fn send_and_receive() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let buffer = SealedBuffer::new("payload", b"zero copy").unwrap();
+        buffer.send_over(&a).unwrap();
+
+        let received = SealedBuffer::recv_from(&b).unwrap();
+
+        assert_eq!(&*received, b"zero copy");
+    }
+
+    #[test]
+    fn rejects_unsealed_fd() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let fd = unsafe { libc::memfd_create(c"unsealed".as_ptr(), 0) };
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(b"data").unwrap();
+
+        send_fd(&a, &file).unwrap();
+
+        assert!(SealedBuffer::recv_from(&b).is_err());
+    }
+}
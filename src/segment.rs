@@ -0,0 +1,221 @@
+use std::{
+    any,
+    error::Error,
+    fmt, io,
+    marker::PhantomData,
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::MmapMut;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"MMAPSEG\0");
+
+/// A description of what a shared segment contains, which producers and
+/// consumers must agree on before touching the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentLayout {
+    /// Application-defined layout version, bumped on incompatible changes
+    pub version: u32,
+    /// Hash identifying the types stored in the segment
+    pub schema_hash: u64,
+}
+
+impl SegmentLayout {
+    pub const fn new(version: u32, schema_hash: u64) -> Self {
+        Self {
+            version,
+            schema_hash,
+        }
+    }
+
+    /// A layout whose schema hash is derived from the name, size and
+    /// alignment of `T`
+    ///
+    /// Type names are not guaranteed stable across compiler versions, so
+    /// processes built by different toolchains should pass an explicit hash
+    /// to [`new`](Self::new) instead.
+    pub fn of<T>(version: u32) -> Self {
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+
+        let name = any::type_name::<T>().as_bytes();
+        let size = (mem::size_of::<T>() as u64).to_le_bytes();
+        let align = (mem::align_of::<T>() as u64).to_le_bytes();
+
+        for &byte in name.iter().chain(&size).chain(&align) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+
+        Self::new(version, hash)
+    }
+}
+
+/// The error wrapped in an [`io::Error`] of kind
+/// [`InvalidData`](io::ErrorKind::InvalidData) when opening a [`Segment`]
+/// created with a different [`SegmentLayout`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncompatibleLayout {
+    pub expected: SegmentLayout,
+    pub found: SegmentLayout,
+}
+
+impl fmt::Display for IncompatibleLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "segment has layout version {} (schema {:#x}), expected version {} (schema {:#x})",
+            self.found.version,
+            self.found.schema_hash,
+            self.expected.version,
+            self.expected.schema_hash,
+        )
+    }
+}
+
+impl Error for IncompatibleLayout {}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    version: u32,
+    creator_pid: u32,
+    schema_hash: u64,
+    _reserved: [u64; 5],
+}
+
+/// A shared mapping prefixed by a standard header recording its layout and
+/// the process which created it
+///
+/// The header occupies the first [`Segment::HEADER_LEN`] bytes; the rest is
+/// available to the application through [`data`](Self::data).
+pub struct Segment<'m> {
+    header: *const Header,
+    data: *mut u8,
+    data_len: usize,
+    _map: PhantomData<&'m MmapMut<'m>>,
+}
+
+unsafe impl<'m> Send for Segment<'m> {}
+unsafe impl<'m> Sync for Segment<'m> {}
+
+impl<'m> Segment<'m> {
+    pub const HEADER_LEN: usize = mem::size_of::<Header>();
+
+    /// Write a header describing `layout` to the start of the mapping
+    ///
+    /// The magic number is published last, so a concurrent [`open`](Self::open)
+    /// never sees a partially written header.
+    pub fn create(map: &'m mut MmapMut, layout: SegmentLayout) -> io::Result<Self> {
+        let header = map.typed_ptr::<Header>(0, 1)?;
+
+        unsafe {
+            header.write(Header {
+                magic: AtomicU64::new(0),
+                version: layout.version,
+                creator_pid: std::process::id(),
+                schema_hash: layout.schema_hash,
+                _reserved: [0; 5],
+            });
+
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+
+        Ok(Self::from_header(map, header))
+    }
+
+    /// Verify the header written by [`create`](Self::create), failing with
+    /// an error wrapping [`IncompatibleLayout`] if it does not match `layout`
+    pub fn open(map: &'m MmapMut, layout: SegmentLayout) -> io::Result<Self> {
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let hdr = unsafe { &*header };
+
+        if hdr.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mapping does not contain a segment header",
+            ));
+        }
+
+        let found = SegmentLayout::new(hdr.version, hdr.schema_hash);
+
+        if found != layout {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                IncompatibleLayout {
+                    expected: layout,
+                    found,
+                },
+            ));
+        }
+
+        Ok(Self::from_header(map, header))
+    }
+
+    fn from_header(map: &MmapMut, header: *mut Header) -> Self {
+        Self {
+            header,
+            data: unsafe { header.cast::<u8>().add(Self::HEADER_LEN) },
+            data_len: map.len - Self::HEADER_LEN,
+            _map: PhantomData,
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    pub fn layout(&self) -> SegmentLayout {
+        SegmentLayout::new(self.header().version, self.header().schema_hash)
+    }
+
+    /// Pid of the process which called [`create`](Self::create)
+    pub fn creator_pid(&self) -> u32 {
+        self.header().creator_pid
+    }
+
+    /// Pointer to the bytes following the header
+    pub fn data(&self) -> *mut u8 {
+        self.data
+    }
+
+    pub fn data_len(&self) -> usize {
+        self.data_len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{IncompatibleLayout, MmapMut, Segment, SegmentLayout};
+
+    #[test]
+    fn create_then_open() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        assert!(Segment::open(&map, SegmentLayout::of::<u64>(1)).is_err());
+
+        let segment = Segment::create(&mut map, SegmentLayout::of::<u64>(1)).unwrap();
+
+        assert_eq!(segment.creator_pid(), std::process::id());
+        assert_eq!(segment.data_len(), 4096 - Segment::HEADER_LEN);
+
+        let segment = Segment::open(&map, SegmentLayout::of::<u64>(1)).unwrap();
+
+        assert_eq!(segment.layout(), SegmentLayout::of::<u64>(1));
+
+        let err = Segment::open(&map, SegmentLayout::of::<u64>(2))
+            .err()
+            .unwrap();
+        let incompatible = err
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<IncompatibleLayout>()
+            .unwrap();
+
+        assert_eq!(incompatible.found.version, 1);
+
+        assert!(Segment::open(&map, SegmentLayout::of::<u32>(1)).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use std::{ffi::CString, io, time::Duration};
+
+/// A named POSIX counting semaphore, for coordinating processes which share
+/// a mapping
+///
+/// The semaphore of a [`ShmSegment`](crate::ShmSegment), from
+/// [`ShmSegment::semaphore`](crate::ShmSegment::semaphore), is created and
+/// unlinked along with the segment.
+///
+/// Standalone semaphores are owned by the process which
+/// [`create`](Self::create)s them: dropping that handle unlinks the name as
+/// well as closing the semaphore. Processes which [`open`](Self::open) it
+/// only close their handle.
+pub struct ShmSemaphore {
+    sem: *mut libc::sem_t,
+    name: CString,
+    owner: bool,
+}
+
+unsafe impl Send for ShmSemaphore {}
+unsafe impl Sync for ShmSemaphore {}
+
+impl ShmSemaphore {
+    /// Create a semaphore called `name` with an initial count of `value`,
+    /// failing if one of that name already exists
+    ///
+    /// `name` must start with a `/` and contain no other slashes.
+    pub fn create(name: &str, value: u32) -> io::Result<Self> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let sem = unsafe {
+            libc::sem_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL,
+                0o600 as libc::c_uint,
+                value as libc::c_uint,
+            )
+        };
+
+        Self::from_raw(sem, name, true)
+    }
+
+    /// Open an existing semaphore created by another process
+    pub fn open(name: &str) -> io::Result<Self> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let sem = unsafe { libc::sem_open(name.as_ptr(), 0) };
+
+        Self::from_raw(sem, name, false)
+    }
+
+    /// Open the semaphore called `name`, creating it with an initial count of
+    /// `value` if it does not exist, without unlinking it on drop
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn open_or_create(name: CString, value: u32) -> io::Result<Self> {
+        let sem = unsafe {
+            libc::sem_open(
+                name.as_ptr(),
+                libc::O_CREAT,
+                0o600 as libc::c_uint,
+                value as libc::c_uint,
+            )
+        };
+
+        Self::from_raw(sem, name, false)
+    }
+
+    fn from_raw(sem: *mut libc::sem_t, name: CString, owner: bool) -> io::Result<Self> {
+        if sem == libc::SEM_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { sem, name, owner })
+    }
+
+    /// Increment the count, waking a waiter if there is one
+    pub fn post(&self) -> io::Result<()> {
+        if unsafe { libc::sem_post(self.sem) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Decrement the count, blocking while it is zero
+    pub fn wait(&self) -> io::Result<()> {
+        loop {
+            if unsafe { libc::sem_wait(self.sem) } == 0 {
+                return Ok(());
+            }
+
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Decrement the count if it is not zero, returning whether it was
+    pub fn try_wait(&self) -> io::Result<bool> {
+        if unsafe { libc::sem_trywait(self.sem) } == 0 {
+            return Ok(true);
+        }
+
+        let err = io::Error::last_os_error();
+
+        match err.raw_os_error() {
+            Some(libc::EAGAIN) => Ok(false),
+            _ => Err(err),
+        }
+    }
+
+    /// Decrement the count, blocking for at most `timeout` while it is zero,
+    /// and returning whether it was decremented
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<bool> {
+        // sem_timedwait takes an absolute CLOCK_REALTIME deadline
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        if unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let nanos = now.tv_nsec as u64 + timeout.subsec_nanos() as u64;
+
+        let deadline = libc::timespec {
+            tv_sec: now
+                .tv_sec
+                .saturating_add(timeout.as_secs().min(i32::MAX as u64) as _)
+                .saturating_add((nanos / 1_000_000_000) as _),
+            tv_nsec: (nanos % 1_000_000_000) as _,
+        };
+
+        loop {
+            if unsafe { libc::sem_timedwait(self.sem, &deadline) } == 0 {
+                return Ok(true);
+            }
+
+            let err = io::Error::last_os_error();
+
+            match err.raw_os_error() {
+                Some(libc::ETIMEDOUT) => return Ok(false),
+                Some(libc::EINTR) => continue,
+                _ => return Err(err),
+            }
+        }
+    }
+
+    /// The current count, which may be stale by the time it is returned
+    pub fn value(&self) -> io::Result<u32> {
+        let mut value = 0;
+
+        if unsafe { libc::sem_getvalue(self.sem, &mut value) } == 0 {
+            Ok(value.max(0) as u32)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl Drop for ShmSemaphore {
+    fn drop(&mut self) {
+        unsafe {
+            libc::sem_close(self.sem);
+
+            if self.owner {
+                libc::sem_unlink(self.name.as_ptr());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use crate::ShmSemaphore;
+
+    #[test]
+    fn count_and_unlink() {
+        let name = format!("/mmap-sem-{}", std::process::id());
+
+        let sem = ShmSemaphore::create(&name, 1).unwrap();
+
+        assert!(ShmSemaphore::create(&name, 0).is_err());
+
+        let other = ShmSemaphore::open(&name).unwrap();
+
+        assert!(other.try_wait().unwrap());
+        assert!(!other.try_wait().unwrap());
+        assert!(!other.wait_timeout(Duration::from_millis(1)).unwrap());
+
+        thread::scope(|s| {
+            s.spawn(|| sem.post().unwrap());
+
+            other.wait().unwrap();
+        });
+
+        assert_eq!(sem.value().unwrap(), 0);
+
+        drop(other);
+        drop(sem);
+
+        assert!(ShmSemaphore::open(&name).is_err());
+    }
+}
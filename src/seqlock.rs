@@ -0,0 +1,165 @@
+use std::{
+    cell::UnsafeCell,
+    io, ptr,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+use crate::{MmapCell, MmapMut, Pod};
+
+/// A sequence lock placed inside a shared mapping
+///
+/// Writers increment the sequence counter to an odd value before modifying the
+/// protected data and to the next even value afterwards. Readers never block
+/// writers; instead they retry whenever they observe an odd sequence number or
+/// the sequence number changed while they were copying the data out.
+///
+/// This makes it suitable for a high-rate producer publishing small snapshots
+/// (metrics, positions, counters) to any number of low-rate consumers.
+#[repr(C)]
+pub struct SeqLock<T: Pod> {
+    seq: AtomicU64,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Pod> Sync for SeqLock<T> {}
+
+impl<T: Pod> SeqLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Write a freshly initialized lock holding `value` at `offset` in the
+    /// mapping and return a reference to it
+    pub fn init_in<'m>(map: &'m mut MmapMut, offset: usize, value: T) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        unsafe {
+            ptr::write(ptr, Self::new(value));
+            Ok(&*ptr)
+        }
+    }
+
+    /// Attach to a lock previously initialized at `offset`, possibly by another
+    /// process
+    ///
+    /// The lock writes to the mapping through a shared reference, so it is
+    /// attached through an [`MmapCell`], which hands out no slices of the bytes
+    /// which could be alive meanwhile.
+    pub fn attach<'m>(map: &'m MmapCell, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Read a consistent copy of the protected value, spinning while a write
+    /// is in progress
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Attempt to read a consistent copy of the protected value, returning
+    /// `None` if a write was in progress or raced with the read
+    pub fn try_read(&self) -> Option<T> {
+        self.try_read_versioned().map(|(_, value)| value)
+    }
+
+    /// Like [`try_read`](Self::try_read), also returning the sequence number
+    /// the value was read at
+    pub(crate) fn try_read_versioned(&self) -> Option<(u64, T)> {
+        let before = self.seq.load(Ordering::Acquire);
+
+        if before & 1 == 1 {
+            return None;
+        }
+
+        // the value may be torn, so it is only returned once the sequence
+        // number confirms no writer touched it
+        let value = unsafe { ptr::read_volatile(self.data.get()) };
+
+        fence(Ordering::Acquire);
+
+        let after = self.seq.load(Ordering::Relaxed);
+
+        if before == after {
+            Some((before, value))
+        } else {
+            None
+        }
+    }
+
+    /// Replace the protected value
+    ///
+    /// Concurrent writers are serialized by spinning on the sequence counter.
+    pub fn write(&self, value: T) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+
+        loop {
+            if seq & 1 == 1 {
+                std::hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+
+            match self.seq.compare_exchange_weak(
+                seq,
+                seq.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+
+        fence(Ordering::Release);
+
+        unsafe { ptr::write_volatile(self.data.get(), value) };
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// The current sequence number. Odd values indicate a write in progress.
+    pub fn sequence(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{MmapMut, SeqLock};
+
+    #[test]
+    fn write_then_read() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let lock = SeqLock::init_in(&mut map, 0, [0_u64; 4]).unwrap();
+
+        lock.write([1, 2, 3, 4]);
+
+        assert_eq!(lock.read(), [1, 2, 3, 4]);
+        assert_eq!(lock.sequence(), 2);
+
+        let cell = map.as_cell();
+        let lock = SeqLock::<[u64; 4]>::attach(&cell, 0).unwrap();
+
+        assert_eq!(lock.read(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_misaligned_offset() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        assert!(SeqLock::init_in(&mut map, 3, 0_u64).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Heap-backed stand-ins for anonymous and file mappings, used under Miri and
+//! with the `asan-friendly` feature
+//!
+//! Neither Miri nor AddressSanitizer can see into memory obtained directly
+//! from `mmap(2)`, so with this backend [`Mmap`](crate::Mmap) and
+//! [`MmapMut`](crate::MmapMut) are instead backed by page-aligned heap
+//! allocations. This lets downstream crates run their tests under those tools
+//! unchanged, with the following differences:
+//!
+//! - file mappings are a private copy of the file's contents, so writes are
+//!   not carried through to the file and changes to the file are only seen
+//!   once [`refresh`](crate::Mmap::refresh) retakes the copy
+//! - executable mappings cannot be created, so the `*_exec` constructors fail
+//!   with [`Unsupported`](io::ErrorKind::Unsupported)
+//! - the allocation is not shared with child processes
+//! - operations which unmap or move a mapping, such as
+//!   [`MmapMut::move_to`](crate::MmapMut::move_to), fail with
+//!   [`Unsupported`](io::ErrorKind::Unsupported)
+//! - `msync(2)` does nothing, advice which discards pages fails with
+//!   [`Unsupported`](io::ErrorKind::Unsupported), and other advice is ignored
+//! - protection changes are ignored, so pages stay readable and writable, and
+//!   guard regions cannot be registered over them
+//!
+//! The system call wrappers in the crate root look allocations up with
+//! [`owns`] to give them these semantics, so individual operations need not.
+//!
+//! The remaining, more specialized mapping types always use real mappings.
+
+use std::{
+    alloc::{self, Layout},
+    collections::BTreeMap,
+    fs::File,
+    io,
+    num::NonZeroUsize,
+    os::unix::fs::FileExt,
+    sync::Mutex,
+};
+
+use crate::{file_len_from, page_size, round_up_to_page};
+
+/// Start and length of every live allocation
+static LIVE: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Allocate whole pages, so that page-granular calls such as `madvise(2)` on
+/// the allocation cannot affect neighbouring heap memory
+fn alloc_zeroed(len: NonZeroUsize) -> io::Result<*mut u8> {
+    let layout = Layout::from_size_align(round_up_to_page(len.get()), page_size())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let ptr = unsafe { alloc::alloc_zeroed(layout) };
+
+    if ptr.is_null() {
+        return Err(io::Error::from(io::ErrorKind::OutOfMemory));
+    }
+
+    LIVE.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(ptr as usize, layout.size());
+
+    Ok(ptr)
+}
+
+/// Whether `ptr` points into a live allocation from this module
+pub(crate) fn owns(ptr: *const u8) -> bool {
+    let live = LIVE.lock().unwrap_or_else(|e| e.into_inner());
+
+    live.range(..=ptr as usize)
+        .next_back()
+        .is_some_and(|(&start, &len)| (ptr as usize) < start + len)
+}
+
+/// Free memory returned by [`map_anon`] or [`map_file`]
+///
+/// # Safety
+///
+/// `ptr` and `len` must be those of a live allocation from this module.
+pub(crate) unsafe fn unmap(ptr: *mut u8, len: usize) {
+    let layout = Layout::from_size_align_unchecked(round_up_to_page(len), page_size());
+
+    LIVE.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&(ptr as usize));
+
+    alloc::dealloc(ptr, layout);
+}
+
+pub(crate) fn map_anon(size: NonZeroUsize) -> io::Result<*mut u8> {
+    alloc_zeroed(size)
+}
+
+pub(crate) fn map_file(file: &File) -> io::Result<(*mut u8, usize)> {
+    let size = file_len_from(file, 0)?;
+
+    let len = NonZeroUsize::new(size)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cannot map an empty file"))?;
+
+    let ptr = alloc_zeroed(len)?;
+
+    let buf = unsafe { std::slice::from_raw_parts_mut(ptr, size) };
+
+    let mut read = 0;
+
+    // positioned reads leave the caller's file offset untouched
+    while read < size {
+        match file.read_at(&mut buf[read..], read as u64)? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok((ptr, size))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        num::NonZeroUsize,
+        ptr,
+    };
+
+    use super::{map_anon, map_file, owns, unmap};
+    use crate::{madvise_raw, mremap_raw, msync_raw, test_util::temp_file, MmapMut};
+
+    #[test]
+    fn heap_backed_file_copy() {
+        let mut file = temp_file("shim");
+        file.write_all(b"shim contents").unwrap();
+
+        let (ptr, len) = map_file(&file).unwrap();
+
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(ptr, len) },
+            b"shim contents"
+        );
+        assert_eq!(ptr as usize % crate::page_size(), 0);
+
+        unsafe { unmap(ptr, len) };
+
+        let ptr = map_anon(NonZeroUsize::new(10).unwrap()).unwrap();
+
+        assert_eq!(unsafe { std::slice::from_raw_parts(ptr, 10) }, &[0; 10]);
+        assert!(owns(unsafe { ptr.add(crate::page_size() - 1) }));
+
+        unsafe { unmap(ptr, 10) };
+
+        assert!(!owns(ptr));
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(any(miri, feature = "asan-friendly")),
+        ignore = "needs heap-backed mappings"
+    )]
+    fn system_calls_on_copies() {
+        let len = NonZeroUsize::new(crate::page_size()).unwrap();
+
+        let map = MmapMut::new_anon(len).unwrap();
+        let ptr = map.as_ptr() as *mut u8;
+
+        unsafe {
+            assert!(msync_raw(ptr, len.get(), libc::MS_SYNC).is_ok());
+            assert!(madvise_raw(ptr, len.get(), libc::MADV_WILLNEED).is_ok());
+
+            for err in [
+                madvise_raw(ptr, len.get(), libc::MADV_DONTNEED),
+                mremap_raw(ptr, len.get(), 2 * len.get(), 0, ptr::null_mut()).map(drop),
+            ] {
+                assert_eq!(err.unwrap_err().kind(), io::ErrorKind::Unsupported);
+            }
+        }
+
+        assert_eq!(
+            MmapMut::new_anon_exec(len).err().unwrap().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    io,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    spin::{SpinGuard, SpinLock},
+    MmapCell, MmapMut,
+};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMALLOC");
+
+/// Size of the segment header, which also keeps the first block aligned
+const HEADER_LEN: u64 = 64;
+
+/// Every block starts with its size and, while free, the offset of the next
+/// free block
+const BLOCK_HEADER_LEN: u64 = 16;
+
+const ALIGN: u64 = 16;
+
+/// Smallest block worth splitting off, so a free block can hold its header
+const MIN_BLOCK_LEN: u64 = BLOCK_HEADER_LEN + ALIGN;
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    lock: SpinLock,
+    _pad: u32,
+    /// Offset of the first free block, or zero if there is none. The free list
+    /// is kept sorted by offset so neighbouring blocks can be coalesced.
+    free_head: AtomicU64,
+    len: AtomicU64,
+}
+
+/// A general purpose allocator managing the memory of a shared mapping
+///
+/// All bookkeeping, including the free list, lives inside the mapping itself,
+/// and allocations are identified by their offset from the start of the
+/// mapping rather than by pointer. Any process which maps the same segment,
+/// at any address, can therefore allocate, free, and resolve allocations.
+///
+/// Operations are serialized by a spin lock in the segment header. A process
+/// which dies while holding it leaves the segment locked.
+pub struct ShmAllocator<'m> {
+    base: *mut u8,
+    len: u64,
+    _map: PhantomData<&'m MmapMut<'m>>,
+}
+
+unsafe impl<'m> Send for ShmAllocator<'m> {}
+unsafe impl<'m> Sync for ShmAllocator<'m> {}
+
+impl<'m> ShmAllocator<'m> {
+    /// Format the mapping as an empty heap, discarding its contents
+    pub fn init(map: &'m mut MmapMut) -> io::Result<Self> {
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let len = map.len as u64 & !(ALIGN - 1);
+
+        if len < HEADER_LEN + MIN_BLOCK_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mapping is too small for an allocator",
+            ));
+        }
+
+        let allocator = Self {
+            base: map.ptr,
+            len,
+            _map: PhantomData,
+        };
+
+        unsafe {
+            header.write(Header {
+                magic: AtomicU64::new(0),
+                lock: SpinLock::new(),
+                _pad: 0,
+                free_head: AtomicU64::new(HEADER_LEN),
+                len: AtomicU64::new(len),
+            });
+
+            allocator.set_block_len(HEADER_LEN, len - HEADER_LEN);
+            allocator.set_next(HEADER_LEN, 0);
+
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+
+        Ok(allocator)
+    }
+
+    /// Attach to a heap previously formatted with [`init`](Self::init),
+    /// possibly by another process
+    ///
+    /// Takes a [view](MmapMut::as_cell) of the mapping, as allocations are
+    /// written through a shared allocator while slices of the bytes could
+    /// otherwise be alive.
+    pub fn attach(map: &'m MmapCell) -> io::Result<Self> {
+        let header = unsafe { &*map.typed_ptr::<Header>(0, 1)? };
+
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mapping does not contain an allocator",
+            ));
+        }
+
+        let len = header.len.load(Ordering::Relaxed);
+
+        if len > map.len as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "allocator is larger than the mapping",
+            ));
+        }
+
+        Ok(Self {
+            base: map.ptr,
+            len,
+            _map: PhantomData,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.base.cast::<Header>() }
+    }
+
+    fn lock(&self) -> SpinGuard<'_> {
+        self.header().lock.lock()
+    }
+
+    unsafe fn word(&self, offset: u64) -> *mut u64 {
+        self.base.add(offset as usize).cast()
+    }
+
+    unsafe fn block_len(&self, block: u64) -> u64 {
+        self.word(block).read()
+    }
+
+    unsafe fn set_block_len(&self, block: u64, len: u64) {
+        self.word(block).write(len)
+    }
+
+    unsafe fn next(&self, block: u64) -> u64 {
+        self.word(block + 8).read()
+    }
+
+    unsafe fn set_next(&self, block: u64, next: u64) {
+        self.word(block + 8).write(next)
+    }
+
+    unsafe fn set_prev_next(&self, prev: u64, next: u64) {
+        if prev == 0 {
+            self.header().free_head.store(next, Ordering::Relaxed);
+        } else {
+            self.set_next(prev, next);
+        }
+    }
+
+    /// The length of the free or allocated block at `block`, checking both
+    /// lie within the heap, as any process attached to it may have
+    /// overwritten them
+    unsafe fn checked_block_len(&self, block: u64) -> io::Result<u64> {
+        if block < HEADER_LEN || block >= self.len || !block.is_multiple_of(ALIGN) {
+            return Err(corrupt());
+        }
+
+        let len = self.block_len(block);
+
+        if len < MIN_BLOCK_LEN || len > self.len - block || !len.is_multiple_of(ALIGN) {
+            return Err(corrupt());
+        }
+
+        Ok(len)
+    }
+
+    /// The free block following `block`, which must come after it as the
+    /// list is sorted, so that a corrupt list cannot send a walk round in
+    /// circles
+    unsafe fn checked_next(&self, block: u64) -> io::Result<u64> {
+        match self.next(block) {
+            next if next != 0 && next <= block => Err(corrupt()),
+            next => Ok(next),
+        }
+    }
+
+    /// Allocate `size` bytes aligned to 16, returning the offset of the
+    /// allocation from the start of the mapping, or `None` if no free block is
+    /// large enough
+    ///
+    /// Fails with [`InvalidData`](io::ErrorKind::InvalidData) if the free list
+    /// is corrupt.
+    pub fn alloc(&self, size: usize) -> io::Result<Option<u64>> {
+        let Some(need) = (size as u64).checked_add(BLOCK_HEADER_LEN + ALIGN - 1) else {
+            return Ok(None);
+        };
+        let need = need.max(MIN_BLOCK_LEN) & !(ALIGN - 1);
+
+        let _lock = self.lock();
+
+        unsafe {
+            let mut prev = 0;
+            let mut block = self.header().free_head.load(Ordering::Relaxed);
+
+            while block != 0 {
+                let len = self.checked_block_len(block)?;
+                let next = self.checked_next(block)?;
+
+                if len >= need {
+                    if len - need >= MIN_BLOCK_LEN {
+                        let rest = block + need;
+
+                        self.set_block_len(rest, len - need);
+                        self.set_next(rest, next);
+                        self.set_block_len(block, need);
+                        self.set_prev_next(prev, rest);
+                    } else {
+                        self.set_prev_next(prev, next);
+                    }
+
+                    return Ok(Some(block + BLOCK_HEADER_LEN));
+                }
+
+                prev = block;
+                block = next;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Return an allocation made by [`alloc`](Self::alloc) to the heap
+    ///
+    /// Offsets outside the heap or not aligned like an allocation are
+    /// rejected, as is a block overlapping one which is already free. Other
+    /// offsets which did not come from `alloc` are not detected, and corrupt
+    /// the heap.
+    pub fn free(&self, offset: u64) -> io::Result<()> {
+        let block = self.block_of(offset)?;
+
+        let _lock = self.lock();
+
+        unsafe {
+            let len = self.checked_block_len(block)?;
+
+            let mut prev = 0;
+            let mut prev_len = 0;
+            let mut next = self.header().free_head.load(Ordering::Relaxed);
+
+            while next != 0 && next < block {
+                prev = next;
+                prev_len = self.checked_block_len(next)?;
+                next = self.checked_next(next)?;
+            }
+
+            let next_len = match next {
+                0 => 0,
+                next => self.checked_block_len(next)?,
+            };
+
+            if (prev != 0 && prev + prev_len > block) || (next != 0 && block + len > next) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "allocation overlaps a free block",
+                ));
+            }
+
+            // merge with the following free block
+            if next != 0 && block + len == next {
+                let after = self.checked_next(next)?;
+
+                self.set_block_len(block, len + next_len);
+                self.set_next(block, after);
+            } else {
+                self.set_next(block, next);
+            }
+
+            // merge into the preceding free block
+            if prev != 0 && prev + prev_len == block {
+                self.set_block_len(prev, prev_len + self.block_len(block));
+                self.set_next(prev, self.next(block));
+            } else {
+                self.set_prev_next(prev, block);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve an offset returned by [`alloc`](Self::alloc), which may have
+    /// been sent by another process, to a pointer in this process's mapping
+    pub fn ptr(&self, offset: u64) -> io::Result<*mut u8> {
+        self.span(offset, 1)
+    }
+
+    /// Resolve the first `len` bytes of the allocation at `offset`, which may
+    /// have been sent by another process, checking the allocation's block
+    /// holds that many
+    pub(crate) fn allocation(&self, offset: u64, len: u64) -> io::Result<*mut u8> {
+        let block = self.block_of(offset)?;
+
+        let _lock = self.lock();
+        let block_len = unsafe { self.checked_block_len(block)? };
+
+        if len > block_len - BLOCK_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "span overruns its allocation",
+            ));
+        }
+
+        Ok(unsafe { self.base.add(offset as usize) })
+    }
+
+    /// The block holding the allocation at `offset`
+    fn block_of(&self, offset: u64) -> io::Result<u64> {
+        if offset < HEADER_LEN + BLOCK_HEADER_LEN
+            || offset >= self.len
+            || !offset.is_multiple_of(ALIGN)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is not an allocation",
+            ));
+        }
+
+        Ok(offset - BLOCK_HEADER_LEN)
+    }
+
+    /// Resolve `len` bytes at `offset`, which may have been sent by another
+    /// process, checking they lie within the heap
+    fn span(&self, offset: u64, len: u64) -> io::Result<*mut u8> {
+        match offset.checked_add(len) {
+            Some(end) if offset >= HEADER_LEN && end <= self.len => {
+                Ok(unsafe { self.base.add(offset as usize) })
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "span out of bounds of the heap",
+            )),
+        }
+    }
+
+    /// Total bytes available in free blocks, including their headers
+    pub fn free_bytes(&self) -> io::Result<u64> {
+        let _lock = self.lock();
+
+        let mut total = 0;
+        let mut block = self.header().free_head.load(Ordering::Relaxed);
+
+        while block != 0 {
+            unsafe {
+                total += self.checked_block_len(block)?;
+                block = self.checked_next(block)?;
+            }
+        }
+
+        Ok(total)
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "allocator free list is corrupt")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize};
+
+    use crate::{MmapMut, ShmAllocator};
+
+    #[test]
+    fn alloc_free_coalesce() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let heap = ShmAllocator::init(&mut map).unwrap();
+        let empty = heap.free_bytes().unwrap();
+
+        let a = heap.alloc(100).unwrap().unwrap();
+        let b = heap.alloc(200).unwrap().unwrap();
+        let c = heap.alloc(300).unwrap().unwrap();
+
+        assert_eq!(a % 16, 0);
+        assert!(a + 100 <= b && b + 200 <= c);
+
+        unsafe { heap.ptr(b).unwrap().write_bytes(0xff, 200) };
+
+        heap.free(b).unwrap();
+        heap.free(a).unwrap();
+
+        // the two freed neighbours coalesce and satisfy a larger request
+        assert_eq!(heap.alloc(300).unwrap(), Some(a));
+
+        heap.free(a).unwrap();
+        heap.free(c).unwrap();
+
+        assert_eq!(heap.free_bytes().unwrap(), empty);
+        assert_eq!(heap.alloc(8192).unwrap(), None);
+        assert!(heap.free(3).is_err());
+        assert!(heap.ptr(4096).is_err());
+    }
+
+    #[test]
+    fn attach_shares_heap() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let first = ShmAllocator::init(&mut map)
+            .unwrap()
+            .alloc(64)
+            .unwrap()
+            .unwrap();
+
+        let cell = map.as_cell();
+        let heap = ShmAllocator::attach(&cell).unwrap();
+        let second = heap.alloc(64).unwrap().unwrap();
+
+        assert_ne!(first, second);
+
+        let mut blank = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        assert!(ShmAllocator::attach(&blank.as_cell()).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_free_list() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let heap = ShmAllocator::init(&mut map).unwrap();
+        let a = heap.alloc(100).unwrap().unwrap();
+        let b = heap.alloc(100).unwrap().unwrap();
+
+        heap.free(a).unwrap();
+
+        // a peer points the free block at one before it, then past the heap
+        let next = heap.ptr(a - 8).unwrap().cast::<u64>();
+
+        for hostile in [16, 1 << 40] {
+            unsafe { next.write(hostile) };
+
+            assert_eq!(
+                heap.alloc(1000).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+
+            assert!(heap.free_bytes().is_err());
+            assert!(heap.free(b).is_err());
+        }
+
+        // and gives the block a length reaching past the end of the heap
+        unsafe {
+            next.write(0);
+            heap.ptr(a - 16).unwrap().cast::<u64>().write(1 << 20);
+        }
+
+        assert!(heap.alloc(16).is_err());
+    }
+}
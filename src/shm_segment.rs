@@ -0,0 +1,413 @@
+use std::{
+    ffi::{CStr, CString},
+    fs::File,
+    io,
+    mem::ManuallyDrop,
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut},
+    os::unix::io::FromRawFd,
+    ptr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::{file_len_from, pidfd::process_alive, spin::SpinLock, MmapMut, ShmSemaphore};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMSEGRC");
+
+/// Data starts this far into the segment
+const HEADER_LEN: usize = 256;
+
+/// Most attachments a segment can have at once
+const MAX_ATTACHED: usize = 56;
+
+/// How long to wait for the creator of a segment to finish setting it up
+const CREATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    lock: SpinLock,
+    /// Set by the last process to detach once it has unlinked the name, so
+    /// a process which opened the name just before knows to start again
+    unlinked: AtomicU32,
+    /// Pid of the process holding each attachment, or zero for a free slot
+    attached: [AtomicU32; MAX_ATTACHED],
+}
+
+const _: () = assert!(std::mem::size_of::<Header>() <= HEADER_LEN);
+
+/// A named POSIX shared memory segment which is unlinked when the last
+/// process detaches from it
+///
+/// The header at the start of the segment records the pid of every
+/// attachment. Dropping a `ShmSegment` releases its attachment, and whoever
+/// releases the last one calls `shm_unlink(3)`, so the segment does not
+/// linger in `/dev/shm` once everyone is done with it. Attachments of
+/// processes which crashed are cleared whenever another process attaches or
+/// detaches; a segment whose users all crashed is reclaimed by the next
+/// [`open`](Self::open), or with [`unlink_if_abandoned`](Self::unlink_if_abandoned).
+/// The header's lock records the pid of its holder, so a process which
+/// crashed while holding it does not block everyone else.
+///
+/// The segment dereferences to the data following the header. Processes
+/// which need to count rather than lock can share a
+/// [`semaphore`](Self::semaphore), whose lifetime is tied to the segment's.
+pub struct ShmSegment {
+    map: MmapMut<'static>,
+    name: CString,
+    slot: usize,
+}
+
+unsafe impl Send for ShmSegment {}
+unsafe impl Sync for ShmSegment {}
+
+impl ShmSegment {
+    /// Attach to the segment called `name`, creating it with `len` bytes of
+    /// zeroed data if it does not exist
+    ///
+    /// An existing segment keeps its size, whatever `len` is. `name` must
+    /// start with a `/` and contain no other slashes.
+    pub fn open(name: &str, len: NonZeroUsize) -> io::Result<Self> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let total = len.get().checked_add(HEADER_LEN).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "segment length overflows")
+        })?;
+
+        loop {
+            let (file, created) = match shm_open(&name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL)
+            {
+                Ok(file) => (file, true),
+                Err(err) if err.raw_os_error() == Some(libc::EEXIST) => {
+                    match shm_open(&name, libc::O_RDWR) {
+                        Ok(file) => (file, false),
+                        // unlinked in between, so try creating it again
+                        Err(err) if err.raw_os_error() == Some(libc::ENOENT) => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Err(err) => return Err(err),
+            };
+
+            if created {
+                return Self::create(file, name, total);
+            }
+
+            if let Some(segment) = Self::attach(&file, &name)? {
+                return Ok(segment);
+            }
+        }
+    }
+
+    fn create(file: File, name: CString, total: usize) -> io::Result<Self> {
+        let res = file.set_len(total as u64).and_then(|()| {
+            let map = MmapMut::new_file(&file)?;
+            let header = map.typed_ptr::<Header>(0, 1)?;
+
+            unsafe {
+                header.write(Header {
+                    magic: AtomicU64::new(0),
+                    lock: SpinLock::new(),
+                    unlinked: AtomicU32::new(0),
+                    attached: std::array::from_fn(|_| AtomicU32::new(0)),
+                });
+
+                (*header).attached[0].store(std::process::id(), Ordering::Relaxed);
+                (*header).magic.store(MAGIC, Ordering::Release);
+            }
+
+            Ok(map)
+        });
+
+        match res {
+            Ok(map) => {
+                // left behind if every user of a previous segment of this
+                // name crashed
+                unsafe { libc::sem_unlink(semaphore_name(&name).as_ptr()) };
+
+                Ok(Self { map, name, slot: 0 })
+            }
+            Err(err) => {
+                unsafe { libc::shm_unlink(name.as_ptr()) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Attach to a segment another process created, or return `None` if it
+    /// was unlinked before this could attach
+    fn attach(file: &File, name: &CString) -> io::Result<Option<Self>> {
+        let mut waited = Duration::ZERO;
+
+        // the creator sizes the segment, then publishes the header
+        let map = loop {
+            if file_len_from(file, 0)? > HEADER_LEN {
+                let map = MmapMut::new_file(file)?;
+                let header = unsafe { &*map.typed_ptr::<Header>(0, 1)? };
+
+                if header.magic.load(Ordering::Acquire) == MAGIC {
+                    break map;
+                }
+            }
+
+            if waited >= CREATE_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "segment was never initialized by its creator",
+                ));
+            }
+
+            thread::sleep(Duration::from_millis(1));
+            waited += Duration::from_millis(1);
+        };
+
+        let header = unsafe { &*map.typed_ptr::<Header>(0, 1)? };
+        let _lock = header.lock.lock_or_recover();
+
+        if header.unlinked.load(Ordering::Relaxed) != 0 {
+            return Ok(None);
+        }
+
+        prune(header);
+
+        let slot = header
+            .attached
+            .iter()
+            .position(|pid| pid.load(Ordering::Relaxed) == 0)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    "segment has too many attachments",
+                )
+            })?;
+
+        header.attached[slot].store(std::process::id(), Ordering::Relaxed);
+
+        Ok(Some(Self {
+            map,
+            name: name.clone(),
+            slot,
+        }))
+    }
+
+    /// Unlink the segment called `name` if every process attached to it has
+    /// died, returning whether it was unlinked
+    pub fn unlink_if_abandoned(name: &str) -> io::Result<bool> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let file = shm_open(&name, libc::O_RDWR)?;
+
+        if file_len_from(&file, 0)? <= HEADER_LEN {
+            return Ok(false);
+        }
+
+        let map = MmapMut::new_file(&file)?;
+        let header = unsafe { &*map.typed_ptr::<Header>(0, 1)? };
+
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Ok(false);
+        }
+
+        let _lock = header.lock.lock_or_recover();
+
+        Ok(release_if_unused(header, &name))
+    }
+
+    /// Open the counting semaphore which belongs to the segment, creating it
+    /// with a count of `value` if no process has yet
+    ///
+    /// The semaphore is named after the segment with `.sem` appended, and is
+    /// unlinked along with it when the last process detaches. A new segment
+    /// of the same name starts with a new semaphore.
+    pub fn semaphore(&self, value: u32) -> io::Result<ShmSemaphore> {
+        ShmSemaphore::open_or_create(semaphore_name(&self.name), value)
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.map.typed_ptr::<Header>(0, 1).unwrap() }
+    }
+
+    /// Number of live attachments, including this one
+    pub fn attachments(&self) -> usize {
+        let header = self.header();
+        let _lock = header.lock.lock_or_recover();
+
+        prune(header);
+
+        header
+            .attached
+            .iter()
+            .filter(|pid| pid.load(Ordering::Relaxed) != 0)
+            .count()
+    }
+
+    /// Release this attachment, returning whether it was the last one and
+    /// the segment was unlinked
+    pub fn detach(self) -> bool {
+        let unlinked = self.release();
+
+        // the attachment is already released, so drop the fields without
+        // running `Drop` again
+        let this = ManuallyDrop::new(self);
+
+        unsafe {
+            drop(ptr::read(&this.map));
+            drop(ptr::read(&this.name));
+        }
+
+        unlinked
+    }
+
+    /// Release this attachment, unlinking the segment if it was the last
+    fn release(&self) -> bool {
+        let header = self.header();
+        let _lock = header.lock.lock_or_recover();
+
+        header.attached[self.slot].store(0, Ordering::Relaxed);
+
+        release_if_unused(header, &self.name)
+    }
+}
+
+/// Clear the attachments of processes which no longer exist
+fn prune(header: &Header) {
+    for pid in &header.attached {
+        let holder = pid.load(Ordering::Relaxed);
+
+        if holder != 0 && !process_alive(holder) {
+            pid.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Unlink `name` if nobody is attached, with the header locked
+fn release_if_unused(header: &Header, name: &CString) -> bool {
+    prune(header);
+
+    if header
+        .attached
+        .iter()
+        .any(|pid| pid.load(Ordering::Relaxed) != 0)
+    {
+        return false;
+    }
+
+    header.unlinked.store(1, Ordering::Relaxed);
+
+    unsafe {
+        libc::sem_unlink(semaphore_name(name).as_ptr());
+        libc::shm_unlink(name.as_ptr()) == 0
+    }
+}
+
+/// Name of the semaphore of the segment called `name`
+fn semaphore_name(name: &CStr) -> CString {
+    let mut sem = name.to_bytes().to_vec();
+    sem.extend_from_slice(b".sem");
+
+    // `name` has no nul bytes, and neither does the suffix
+    CString::new(sem).unwrap()
+}
+
+fn shm_open(name: &CString, flags: i32) -> io::Result<File> {
+    let fd = unsafe { libc::shm_open(name.as_ptr(), flags | libc::O_CLOEXEC, 0o600) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+impl Deref for ShmSegment {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[HEADER_LEN..]
+    }
+}
+
+impl DerefMut for ShmSegment {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.map[HEADER_LEN..]
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::CString, num::NonZeroUsize, process::Command, sync::atomic::Ordering};
+
+    use crate::{ShmSegment, ShmSemaphore};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn unlinks_after_last_detach() {
+        let name = format!("/mmap-segment-{}", std::process::id());
+        let len = NonZeroUsize::new(4096).unwrap();
+
+        let mut first = ShmSegment::open(&name, len).unwrap();
+        let second = ShmSegment::open(&name, len).unwrap();
+
+        first[..4].copy_from_slice(b"ping");
+
+        assert_eq!(&second[..4], b"ping");
+        assert_eq!(second.len(), 4096);
+        assert_eq!(second.attachments(), 2);
+
+        // an attachment left behind by a process which crashed
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+
+        second.header().attached[5].store(dead, Ordering::Relaxed);
+
+        assert_eq!(second.attachments(), 2);
+
+        drop(first);
+
+        assert!(!ShmSegment::unlink_if_abandoned(&name).unwrap());
+        assert!(second.detach());
+
+        let c_name = CString::new(name.clone()).unwrap();
+
+        assert!(unsafe { libc::shm_open(c_name.as_ptr(), libc::O_RDWR, 0) } < 0);
+        assert!(ShmSegment::unlink_if_abandoned(&name).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn semaphore_follows_segment() {
+        let name = format!("/mmap-segment-sem-{}", std::process::id());
+        let len = NonZeroUsize::new(4096).unwrap();
+
+        let first = ShmSegment::open(&name, len).unwrap();
+        let second = ShmSegment::open(&name, len).unwrap();
+
+        let sem = first.semaphore(0).unwrap();
+        // already created, so the count is left alone
+        let other = second.semaphore(5).unwrap();
+
+        sem.post().unwrap();
+
+        assert!(other.try_wait().unwrap());
+        assert!(!other.try_wait().unwrap());
+
+        drop(first);
+
+        assert!(ShmSemaphore::open(&format!("{name}.sem")).is_ok());
+
+        drop(second);
+
+        assert!(ShmSemaphore::open(&format!("{name}.sem")).is_err());
+    }
+}
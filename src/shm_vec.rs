@@ -0,0 +1,291 @@
+use std::{
+    io,
+    marker::PhantomData,
+    mem, ptr,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+use crate::{spin::SpinLock, MmapCell, MmapMut, Pod};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMVEC\0\0");
+
+/// Elements start this far into the mapping
+const HEADER_LEN: usize = 64;
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    /// Sequence counter, odd while a writer is modifying the vector
+    seq: AtomicU64,
+    capacity: u64,
+    elem_size: u64,
+    len: AtomicU64,
+    lock: SpinLock,
+}
+
+/// A growable array of `T` living in a shared mapping, with its length and
+/// capacity stored in a header in front of the elements
+///
+/// Writers from any process are serialized by a spin lock in the header, and
+/// publish each modification through a sequence counter as in
+/// [`SeqLock`](crate::SeqLock). Readers never take the lock: they retry any
+/// copy which raced with a writer, so the elements returned by one call were
+/// all in the vector at the same time.
+pub struct ShmVec<'m, T: Pod> {
+    header: *const Header,
+    elems: *mut T,
+    _map: PhantomData<&'m MmapMut<'m>>,
+}
+
+unsafe impl<'m, T: Pod> Send for ShmVec<'m, T> {}
+unsafe impl<'m, T: Pod> Sync for ShmVec<'m, T> {}
+
+impl<'m, T: Pod> ShmVec<'m, T> {
+    /// Format the mapping as an empty vector using all available space
+    pub fn init(map: &'m mut MmapMut) -> io::Result<Self> {
+        let (header, elems, capacity) = Self::layout(&map.as_cell())?;
+
+        unsafe {
+            header.write(Header {
+                magic: AtomicU64::new(0),
+                seq: AtomicU64::new(0),
+                capacity,
+                elem_size: mem::size_of::<T>() as u64,
+                len: AtomicU64::new(0),
+                lock: SpinLock::new(),
+            });
+
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+
+        Ok(Self {
+            header,
+            elems,
+            _map: PhantomData,
+        })
+    }
+
+    /// Attach to a vector previously created with [`init`](Self::init),
+    /// possibly by another process, checking it holds elements of the same
+    /// size
+    ///
+    /// Takes a [view](MmapMut::as_cell) of the mapping, so that no slices of
+    /// the elements can be alive while they are modified.
+    pub fn attach(map: &'m MmapCell) -> io::Result<Self> {
+        let (header, elems, capacity) = Self::layout(map)?;
+
+        let hdr = unsafe { &*header };
+
+        if hdr.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mapping does not contain a vector",
+            ));
+        }
+
+        if hdr.elem_size != mem::size_of::<T>() as u64 || hdr.capacity > capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "vector layout does not match",
+            ));
+        }
+
+        Ok(Self {
+            header,
+            elems,
+            _map: PhantomData,
+        })
+    }
+
+    fn layout(map: &MmapCell) -> io::Result<(*mut Header, *mut T, u64)> {
+        if mem::align_of::<T>() > HEADER_LEN || mem::size_of::<T>() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported element type",
+            ));
+        }
+
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let capacity = map.len.saturating_sub(HEADER_LEN) / mem::size_of::<T>();
+        let elems = map.typed_ptr::<T>(HEADER_LEN, capacity)?;
+
+        Ok((header, elems, capacity as u64))
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    pub fn len(&self) -> usize {
+        self.header().len.load(Ordering::Acquire) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.header().capacity as usize
+    }
+
+    /// Number of modifications made to the vector so far
+    pub fn version(&self) -> u64 {
+        self.header().seq.load(Ordering::Acquire) / 2
+    }
+
+    /// Run `modify` with the sequence counter odd, so readers retry around it
+    ///
+    /// The caller must hold the lock.
+    fn write(&self, modify: impl FnOnce(&Header)) {
+        let header = self.header();
+        let seq = header.seq.load(Ordering::Relaxed);
+
+        header.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        modify(header);
+
+        header.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Run `copy` on the current length until it completes without racing a
+    /// writer
+    fn read<R>(&self, copy: impl Fn(usize) -> R) -> R {
+        let header = self.header();
+
+        loop {
+            let before = header.seq.load(Ordering::Acquire);
+
+            if before & 1 == 0 {
+                // the copy may be torn, so it is only returned once the
+                // sequence number confirms no writer touched it
+                let value = copy(header.len.load(Ordering::Relaxed) as usize);
+
+                fence(Ordering::Acquire);
+
+                if header.seq.load(Ordering::Relaxed) == before {
+                    return value;
+                }
+            }
+
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Append `value`, returning its index, or an error if the vector is full
+    pub fn push(&self, value: T) -> io::Result<usize> {
+        let header = self.header();
+        let _lock = header.lock.lock();
+
+        let len = header.len.load(Ordering::Relaxed);
+
+        if len >= header.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "shared vector is full",
+            ));
+        }
+
+        self.write(|header| {
+            unsafe { ptr::write_volatile(self.elems.add(len as usize), value) };
+
+            header.len.store(len + 1, Ordering::Relaxed);
+        });
+
+        Ok(len as usize)
+    }
+
+    /// Shorten the vector to `len` elements, doing nothing if it is already
+    /// shorter
+    pub fn truncate(&self, len: usize) {
+        let header = self.header();
+        let _lock = header.lock.lock();
+
+        if (len as u64) < header.len.load(Ordering::Relaxed) {
+            self.write(|header| header.len.store(len as u64, Ordering::Relaxed));
+        }
+    }
+
+    /// Copy out the element at `idx`
+    pub fn get(&self, idx: usize) -> Option<T> {
+        self.read(|len| (idx < len).then(|| unsafe { ptr::read_volatile(self.elems.add(idx)) }))
+    }
+
+    /// Copy out all elements
+    pub fn to_vec(&self) -> Vec<T> {
+        self.read(|len| {
+            (0..len)
+                .map(|idx| unsafe { ptr::read_volatile(self.elems.add(idx)) })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use crate::{MmapMut, ShmVec};
+
+    #[test]
+    fn push_and_truncate() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(64 + 8 * 4).unwrap()).unwrap();
+
+        let vec = ShmVec::<u64>::init(&mut map).unwrap();
+
+        assert_eq!(vec.capacity(), 4);
+
+        for i in 0..4 {
+            assert_eq!(vec.push(i * 10).unwrap(), i as usize);
+        }
+
+        assert!(vec.push(40).is_err());
+
+        vec.truncate(2);
+
+        assert_eq!(vec.to_vec(), vec![0, 10]);
+        assert_eq!(vec.version(), 5);
+
+        let cell = map.as_cell();
+        let attached = ShmVec::<u64>::attach(&cell).unwrap();
+
+        assert_eq!(attached.get(1), Some(10));
+        assert_eq!(attached.get(2), None);
+        assert!(ShmVec::<u32>::attach(&cell).is_err());
+    }
+
+    #[test]
+    fn readers_see_consistent_copies() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(64 + 8 * 4).unwrap()).unwrap();
+
+        let vec = ShmVec::<u64>::init(&mut map).unwrap();
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for round in 0..10_000 {
+                    vec.truncate(0);
+
+                    for _ in 0..4 {
+                        vec.push(round).unwrap();
+                    }
+                }
+
+                done.store(true, Ordering::Release);
+            });
+
+            while !done.load(Ordering::Acquire) {
+                let elems = vec.to_vec();
+
+                // every copy holds elements pushed in the same round
+                assert!(elems.windows(2).all(|pair| pair[0] == pair[1]));
+            }
+        });
+
+        // the first truncation found the vector already empty
+        assert_eq!(vec.version(), 10_000 * 5 - 1);
+    }
+}
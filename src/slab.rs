@@ -0,0 +1,489 @@
+use std::{
+    io,
+    marker::PhantomData,
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    spin::{SpinGuard, SpinLock},
+    MmapCell, MmapMut,
+};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMSLAB\0");
+
+/// Objects start this far into the mapping
+const HEADER_LEN: u64 = 512;
+
+const MAX_CLASSES: usize = 16;
+
+const ALIGN: u64 = 16;
+
+/// Objects a [`SlabCache`] moves between itself and the shared free lists at a
+/// time
+const BATCH: usize = 16;
+
+#[repr(C)]
+struct Class {
+    size: u64,
+    lock: SpinLock,
+    _pad: u32,
+    /// Offset of the first free object, or zero if there is none. Each free
+    /// object holds the offset of the next in its first 8 bytes.
+    free_head: AtomicU64,
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    len: u64,
+    class_count: u64,
+    /// Offset of the first byte never handed out
+    bump: AtomicU64,
+    classes: [Class; MAX_CLASSES],
+}
+
+const _: () = assert!(mem::size_of::<Header>() as u64 <= HEADER_LEN);
+
+/// An allocator of fixed-size objects in a shared mapping, with a free list
+/// for each size class
+///
+/// Like [`ShmAllocator`](crate::ShmAllocator), all bookkeeping lives in the
+/// mapping and objects are identified by offset, so any attached process can
+/// allocate and free them. Objects are carved from the unused end of the
+/// segment on demand and never coalesced, so each class only ever grows to
+/// its peak usage; in exchange allocation and freeing are constant time.
+///
+/// Each class's free list is protected by its own spin lock. Processes which
+/// allocate heavily can take a [`SlabCache`] to touch the shared lists only
+/// once per batch of objects.
+pub struct ShmSlab<'m> {
+    base: *mut u8,
+    /// Length and number of classes, kept out of the header so that other
+    /// processes cannot change them once attached
+    len: u64,
+    class_count: usize,
+    _map: PhantomData<&'m MmapMut<'m>>,
+}
+
+unsafe impl<'m> Send for ShmSlab<'m> {}
+unsafe impl<'m> Sync for ShmSlab<'m> {}
+
+impl<'m> ShmSlab<'m> {
+    /// Format the mapping as an empty slab serving objects of the given sizes,
+    /// which are rounded up to a multiple of 16 and must be in increasing
+    /// order
+    pub fn init(map: &'m mut MmapMut, sizes: &[usize]) -> io::Result<Self> {
+        if sizes.is_empty() || sizes.len() > MAX_CLASSES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported number of size classes",
+            ));
+        }
+
+        if sizes.windows(2).any(|pair| pair[0] >= pair[1]) || sizes[0] == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "size classes must be increasing and non-zero",
+            ));
+        }
+
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let len = map.len as u64 & !(ALIGN - 1);
+
+        if len <= HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mapping is too small for a slab",
+            ));
+        }
+
+        if sizes[sizes.len() - 1] as u64 > len - HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "size class does not fit in the mapping",
+            ));
+        }
+
+        unsafe {
+            header.write(Header {
+                magic: AtomicU64::new(0),
+                len,
+                class_count: sizes.len() as u64,
+                bump: AtomicU64::new(HEADER_LEN),
+                classes: std::array::from_fn(|idx| Class {
+                    size: sizes
+                        .get(idx)
+                        .map_or(0, |&size| (size as u64 + ALIGN - 1) & !(ALIGN - 1)),
+                    lock: SpinLock::new(),
+                    _pad: 0,
+                    free_head: AtomicU64::new(0),
+                }),
+            });
+
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+
+        Ok(Self {
+            base: map.ptr,
+            len,
+            class_count: sizes.len(),
+            _map: PhantomData,
+        })
+    }
+
+    /// Attach to a slab previously formatted with [`init`](Self::init),
+    /// possibly by another process, through a [view](MmapMut::as_cell) of the
+    /// mapping which no slices of the objects can be taken from
+    pub fn attach(map: &'m MmapCell) -> io::Result<Self> {
+        let header = unsafe { &*map.typed_ptr::<Header>(0, 1)? };
+
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mapping does not contain a slab",
+            ));
+        }
+
+        if header.len > map.len as u64 || header.class_count as usize > MAX_CLASSES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "slab layout does not match the mapping",
+            ));
+        }
+
+        Ok(Self {
+            base: map.ptr,
+            len: header.len,
+            class_count: header.class_count as usize,
+            _map: PhantomData,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.base.cast::<Header>() }
+    }
+
+    /// The size class `class`, checking its size is one which
+    /// [`init`](Self::init) could have written, as any process attached to the
+    /// slab may have overwritten it
+    fn class(&self, class: usize) -> io::Result<&Class> {
+        if class >= self.class_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "size class out of range",
+            ));
+        }
+
+        let class = &self.header().classes[class];
+
+        if class.size == 0 || !class.size.is_multiple_of(ALIGN) || class.size > self.len {
+            return Err(corrupt());
+        }
+
+        Ok(class)
+    }
+
+    /// Check an object offset read from the free list of `class`
+    fn checked_object(&self, class: &Class, object: u64) -> io::Result<u64> {
+        match object.checked_add(class.size) {
+            Some(end)
+                if object >= HEADER_LEN && end <= self.len && object.is_multiple_of(ALIGN) =>
+            {
+                Ok(object)
+            }
+            _ => Err(corrupt()),
+        }
+    }
+
+    unsafe fn next(&self, object: u64) -> u64 {
+        self.base.add(object as usize).cast::<u64>().read()
+    }
+
+    unsafe fn set_next(&self, object: u64, next: u64) {
+        self.base.add(object as usize).cast::<u64>().write(next)
+    }
+
+    /// Number of size classes
+    pub fn classes(&self) -> usize {
+        self.class_count
+    }
+
+    /// Size of the objects in `class`
+    pub fn class_size(&self, class: usize) -> io::Result<usize> {
+        Ok(self.class(class)?.size as usize)
+    }
+
+    /// The smallest class whose objects can hold `size` bytes
+    pub fn class_for(&self, size: usize) -> io::Result<Option<usize>> {
+        for class in 0..self.classes() {
+            if self.class_size(class)? >= size {
+                return Ok(Some(class));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Allocate an object of `class`, returning its offset from the start of
+    /// the mapping, or `None` if the segment is exhausted
+    ///
+    /// Fails with [`InvalidData`](io::ErrorKind::InvalidData) if the free list
+    /// of `class` is corrupt.
+    pub fn alloc(&self, class: usize) -> io::Result<Option<u64>> {
+        let mut object = None;
+        self.alloc_batch(class, 1, |offset| object = Some(offset))?;
+        Ok(object)
+    }
+
+    /// Return an object allocated from `class` to its free list
+    ///
+    /// Offsets which cannot be objects are rejected, but freeing an object
+    /// twice or to the wrong class corrupts the slab.
+    pub fn free(&self, class: usize, offset: u64) -> io::Result<()> {
+        self.check(offset)?;
+
+        let class = self.class(class)?;
+        let lock = class.lock.lock();
+
+        unsafe { self.push(class, &lock, offset) };
+
+        Ok(())
+    }
+
+    fn check(&self, offset: u64) -> io::Result<()> {
+        if offset < HEADER_LEN
+            || offset >= self.header().bump.load(Ordering::Acquire)
+            || !offset.is_multiple_of(ALIGN)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset is not an object",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Push `offset` onto the free list, which the caller has locked
+    unsafe fn push(&self, class: &Class, _lock: &SpinGuard<'_>, offset: u64) {
+        self.set_next(offset, class.free_head.load(Ordering::Relaxed));
+        class.free_head.store(offset, Ordering::Relaxed);
+    }
+
+    /// Allocate up to `count` objects of `class` under a single acquisition
+    /// of its lock, passing each to `f`
+    fn alloc_batch(&self, class: usize, count: usize, mut f: impl FnMut(u64)) -> io::Result<()> {
+        let header = self.header();
+        let class = self.class(class)?;
+        let _lock = class.lock.lock();
+
+        let mut remaining = count;
+
+        while remaining > 0 {
+            let object = class.free_head.load(Ordering::Relaxed);
+
+            if object == 0 {
+                break;
+            }
+
+            let object = self.checked_object(class, object)?;
+
+            class
+                .free_head
+                .store(unsafe { self.next(object) }, Ordering::Relaxed);
+
+            f(object);
+            remaining -= 1;
+        }
+
+        // carve the rest from the unused end of the segment
+        while remaining > 0 {
+            let carved = header
+                .bump
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bump| {
+                    bump.checked_add(class.size).filter(|&end| end <= self.len)
+                });
+
+            match carved {
+                Ok(object) => f(self.checked_object(class, object)?),
+                Err(_) => break,
+            }
+
+            remaining -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Push `objects` onto the free list of `class` under a single
+    /// acquisition of its lock
+    fn release(&self, class: usize, objects: &[u64]) -> io::Result<()> {
+        let class = self.class(class)?;
+        let lock = class.lock.lock();
+
+        for &object in objects {
+            unsafe { self.push(class, &lock, object) };
+        }
+
+        Ok(())
+    }
+
+    /// Take a per-process cache of free objects in front of the shared lists
+    pub fn cache(&self) -> SlabCache<'_, 'm> {
+        SlabCache {
+            slab: self,
+            free: vec![Vec::new(); self.classes()],
+        }
+    }
+}
+
+/// Free objects held back by one process to reduce contention on a
+/// [`ShmSlab`]'s shared free lists
+///
+/// Objects are fetched and returned in batches. Objects held by the cache are
+/// unavailable to other processes until it is dropped, which hands them all
+/// back; if the process dies first, they are lost.
+pub struct SlabCache<'s, 'm> {
+    slab: &'s ShmSlab<'m>,
+    free: Vec<Vec<u64>>,
+}
+
+impl<'s, 'm> SlabCache<'s, 'm> {
+    /// Allocate an object of `class`, as [`ShmSlab::alloc`]
+    pub fn alloc(&mut self, class: usize) -> io::Result<Option<u64>> {
+        self.slab.class(class)?;
+
+        let free = &mut self.free[class];
+
+        if free.is_empty() {
+            self.slab
+                .alloc_batch(class, BATCH, |object| free.push(object))?;
+        }
+
+        Ok(free.pop())
+    }
+
+    /// Free an object of `class`, as [`ShmSlab::free`]
+    pub fn free(&mut self, class: usize, offset: u64) -> io::Result<()> {
+        self.slab.class(class)?;
+        self.slab.check(offset)?;
+
+        let free = &mut self.free[class];
+
+        free.push(offset);
+
+        if free.len() >= BATCH * 2 {
+            let spill = free.split_off(BATCH);
+            self.slab.release(class, &spill)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'s, 'm> Drop for SlabCache<'s, 'm> {
+    fn drop(&mut self) {
+        for (class, free) in self.free.iter().enumerate() {
+            // the objects are lost if the class has been corrupted meanwhile
+            let _ = self.slab.release(class, free);
+        }
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "slab free list is corrupt")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, io, num::NonZeroUsize, thread};
+
+    use crate::{MmapMut, ShmSlab};
+
+    #[test]
+    fn alloc_and_reuse() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let slab = ShmSlab::init(&mut map, &[24, 64]).unwrap();
+
+        assert_eq!(slab.class_size(0).unwrap(), 32);
+        assert_eq!(slab.class_for(40).unwrap(), Some(1));
+        assert_eq!(slab.class_for(65).unwrap(), None);
+        assert!(slab.class_size(2).is_err());
+
+        let a = slab.alloc(0).unwrap().unwrap();
+        let b = slab.alloc(1).unwrap().unwrap();
+
+        assert_eq!(b, a + 32);
+
+        slab.free(0, a).unwrap();
+
+        assert_eq!(slab.alloc(0).unwrap(), Some(a));
+        assert!(slab.free(0, 3).is_err());
+        assert!(slab.free(0, 4096).is_err());
+
+        let cell = map.as_cell();
+        let slab = ShmSlab::attach(&cell).unwrap();
+
+        // the caches between them take every remaining object of 64 bytes
+        let objects: HashSet<_> = thread::scope(|s| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut cache = slab.cache();
+                        let mut mine = Vec::new();
+
+                        while let Some(object) = cache.alloc(1).unwrap() {
+                            mine.push(object);
+                        }
+
+                        mine
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect()
+        });
+
+        assert_eq!(objects.len(), (4096 - 512 - 32 - 64) / 64);
+
+        let mut cache = slab.cache();
+
+        for &object in &objects {
+            cache.free(1, object).unwrap();
+        }
+
+        drop(cache);
+
+        assert!(objects.contains(&slab.alloc(1).unwrap().unwrap()));
+    }
+
+    #[test]
+    fn rejects_corrupt_free_list() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let slab = ShmSlab::init(&mut map, &[32]).unwrap();
+        let object = slab.alloc(0).unwrap().unwrap();
+        slab.free(0, object).unwrap();
+
+        // point the free list of class 0 past the end of the mapping
+        map[48..56].copy_from_slice(&8192u64.to_ne_bytes());
+
+        let cell = map.as_cell();
+        let slab = ShmSlab::attach(&cell).unwrap();
+
+        let err = slab.alloc(0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // and give the class an impossible size
+        unsafe { cell.ptr.add(32).cast::<u64>().write(0) };
+
+        let err = slab.alloc(0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(slab.cache().alloc(0).is_err());
+    }
+}
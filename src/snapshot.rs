@@ -0,0 +1,220 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    marker::PhantomData,
+    os::unix::io::FromRawFd,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{maps, Mmap, MmapMut};
+
+/// A forked child process reading a point-in-time snapshot of a mapping,
+/// created by [`MmapMut::snapshot_via_fork`]
+///
+/// The child is reaped when this is dropped, blocking until it exits.
+#[must_use = "the child is waited for when this is dropped"]
+pub struct SnapshotChild {
+    pid: libc::pid_t,
+    status: File,
+    reaped: bool,
+}
+
+impl SnapshotChild {
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Wait for the child to exit, returning the error its closure returned,
+    /// if any
+    pub fn wait(mut self) -> io::Result<()> {
+        self.reap()
+    }
+
+    fn reap(&mut self) -> io::Result<()> {
+        let mut errno = [0; 4];
+        let read = read_full(&mut self.status, &mut errno);
+
+        let mut status = 0;
+
+        loop {
+            if unsafe { libc::waitpid(self.pid, &mut status, 0) } >= 0 {
+                break;
+            }
+
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        self.reaped = true;
+
+        match read? {
+            4 => match i32::from_ne_bytes(errno) {
+                0 => Err(io::Error::other("snapshot closure failed")),
+                errno => Err(io::Error::from_raw_os_error(errno)),
+            },
+            _ if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "snapshot child terminated abnormally with status {}",
+                status
+            ))),
+        }
+    }
+}
+
+impl Drop for SnapshotChild {
+    fn drop(&mut self) {
+        if !self.reaped {
+            let _ = self.reap();
+        }
+    }
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match file.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+impl<'a> MmapMut<'a> {
+    /// Fork the process and run `f` in the child over a copy-on-write view of
+    /// the mapping as it was at the moment of the fork, while the parent is
+    /// free to keep mutating it
+    ///
+    /// The point-in-time guarantee relies on copy-on-write, so the mapping must
+    /// be private (see [`MmapOptions::private`](crate::MmapOptions::private)):
+    /// the child of a shared mapping would observe the parent's later writes,
+    /// so those are rejected with [`InvalidInput`](io::ErrorKind::InvalidInput).
+    ///
+    /// As with any `fork` of a multithreaded process, `f` runs with only the
+    /// forking thread alive and should avoid taking locks, including those
+    /// inside the allocator, which another thread may have held at the time
+    /// of the fork.
+    pub fn snapshot_via_fork(
+        &self,
+        f: impl FnOnce(&Mmap) -> io::Result<()>,
+    ) -> io::Result<SnapshotChild> {
+        if !maps::is_private(self.ptr, self.len)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "snapshot of a shared mapping would not be point-in-time",
+            ));
+        }
+
+        let mut fds = [0; 2];
+
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let (read, write) = unsafe { (File::from_raw_fd(fds[0]), fds[1]) };
+
+        let pid = unsafe { libc::fork() };
+
+        if pid < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(write) };
+            return Err(err);
+        }
+
+        if pid == 0 {
+            let snapshot = Mmap {
+                ptr: self.ptr,
+                len: self.len,
+                offset: self.offset,
+                backing: self.backing,
+                _lock: None,
+                _lifetime: PhantomData,
+            };
+
+            let code = match panic::catch_unwind(AssertUnwindSafe(|| f(&snapshot))) {
+                Ok(Ok(())) => 0,
+                Ok(Err(e)) => {
+                    let errno = e.raw_os_error().unwrap_or(0).to_ne_bytes();
+                    unsafe { libc::write(write, errno.as_ptr().cast(), errno.len()) };
+                    1
+                }
+                Err(..) => 101,
+            };
+
+            unsafe { libc::_exit(code) };
+        }
+
+        unsafe { libc::close(write) };
+
+        Ok(SnapshotChild {
+            pid,
+            status: read,
+            reaped: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize};
+
+    use crate::MmapOptions;
+
+    #[test]
+    fn child_sees_point_in_time_view() {
+        let mut map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        map.fill(1);
+
+        let child = map
+            .snapshot_via_fork(|snapshot| {
+                if snapshot.iter().all(|&b| b == 1) {
+                    Ok(())
+                } else {
+                    Err(io::Error::from_raw_os_error(libc::EINVAL))
+                }
+            })
+            .unwrap();
+
+        map.fill(2);
+
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn child_error_is_reported() {
+        let map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        let child = map
+            .snapshot_via_fork(|_| Err(io::Error::from_raw_os_error(libc::ENOSPC)))
+            .unwrap();
+
+        assert_eq!(child.wait().unwrap_err().raw_os_error(), Some(libc::ENOSPC));
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn rejects_shared_mapping() {
+        let map = MmapOptions::new(NonZeroUsize::new(4096).unwrap())
+            .map_anon_mut()
+            .unwrap();
+
+        assert!(matches!(
+            map.snapshot_via_fork(|_| Ok(())),
+            Err(err) if err.kind() == io::ErrorKind::InvalidInput
+        ));
+    }
+}
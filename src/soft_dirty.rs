@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::FileExt,
+    sync::Mutex,
+};
+
+use crate::{capabilities, maps, page_size, require_real_mapping, MmapMut};
+
+/// Soft-dirty flag of a `/proc/self/pagemap` entry
+pub(crate) const PM_SOFT_DIRTY: u64 = 1 << 55;
+
+/// Number of times this crate has reset the soft-dirty flags of the process,
+/// locked across reading the flags and resetting them so that no export
+/// misses another's reset
+static GENERATION: Mutex<u64> = Mutex::new(0);
+
+/// The point an [`export_dirty_since`](MmapMut::export_dirty_since) was taken
+/// at, from which the next export continues
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyToken {
+    generation: u64,
+}
+
+impl<'a> MmapMut<'a> {
+    /// Write every page dirtied since `since` to `writer`, returning the token
+    /// to pass to the next export
+    ///
+    /// Each page is written as its offset in the mapping, as a little-endian
+    /// `u64`, followed by its bytes: a whole page, except for the last page
+    /// of a mapping whose length is not a multiple of the page size. Backing
+    /// up a large mapped database file is then a full export followed by
+    /// incremental ones which only read what changed, found from the
+    /// soft-dirty flags in `/proc/self/pagemap` rather than by rescanning.
+    ///
+    /// Soft-dirty flags can only be reset for the whole process at once, so
+    /// an export invalidates the tokens of every other, and exporting with
+    /// no token or an invalidated one writes every page; only one mapping per
+    /// process can be backed up incrementally. Only writes made by this
+    /// process are tracked, and anything else resetting the flags through
+    /// `/proc/self/clear_refs` causes pages to be missed.
+    ///
+    /// Only private mappings are tracked reliably. The flags of shared pages,
+    /// whether of a file or of shared anonymous memory, are lost when the
+    /// kernel writes them back or reclaims them, so exports of a mapping with
+    /// any shared pages always write every page.
+    ///
+    /// Fails with [`Unsupported`](io::ErrorKind::Unsupported) if the kernel
+    /// does not track soft-dirty pages; see
+    /// [`Capabilities::soft_dirty`](crate::Capabilities::soft_dirty).
+    pub fn export_dirty_since(
+        &self,
+        since: Option<DirtyToken>,
+        mut writer: impl Write,
+    ) -> io::Result<DirtyToken> {
+        require_real_mapping()?;
+
+        if !capabilities().soft_dirty {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "kernel does not track soft-dirty pages",
+            ));
+        }
+
+        let page = page_size();
+        let pages = self.len.div_ceil(page);
+
+        let (dirty, token) = {
+            let mut generation = GENERATION.lock().unwrap_or_else(|e| e.into_inner());
+
+            let dirty: Vec<usize> = match since {
+                Some(token)
+                    if token.generation == *generation && maps::is_private(self.ptr, self.len)? =>
+                {
+                    pagemap(self.ptr, pages)?
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &entry)| entry & PM_SOFT_DIRTY != 0)
+                        .map(|(idx, _)| idx)
+                        .collect()
+                }
+                _ => (0..pages).collect(),
+            };
+
+            // reset before reading the pages, so writes racing with the
+            // export are exported again next time rather than lost
+            fs::write("/proc/self/clear_refs", "4")?;
+            *generation += 1;
+
+            (
+                dirty,
+                DirtyToken {
+                    generation: *generation,
+                },
+            )
+        };
+
+        for idx in dirty {
+            let offset = idx * page;
+
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+            writer.write_all(&self[offset..(offset + page).min(self.len)])?;
+        }
+
+        Ok(token)
+    }
+}
+
+/// Read the `/proc/self/pagemap` entries of the `pages` pages from `ptr`
+pub(crate) fn pagemap(ptr: *const u8, pages: usize) -> io::Result<Vec<u64>> {
+    let mut buf = vec![0_u8; pages * 8];
+
+    File::open("/proc/self/pagemap")?
+        .read_exact_at(&mut buf, (ptr as usize / page_size() * 8) as u64)?;
+
+    Ok(buf
+        .chunks_exact(8)
+        .map(|entry| u64::from_ne_bytes(entry.try_into().unwrap()))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize};
+
+    use crate::{capabilities, page_size, MmapMut, MmapOptions};
+
+    /// Offsets of the pages in an export, checking each page's contents
+    fn exported_offsets(map: &MmapMut, export: &[u8]) -> Vec<usize> {
+        let page = page_size();
+
+        export
+            .chunks(8 + page)
+            .map(|record| {
+                let offset = u64::from_le_bytes(record[..8].try_into().unwrap()) as usize;
+
+                assert_eq!(&record[8..], &map[offset..offset + page]);
+
+                offset
+            })
+            .collect()
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn exports_pages_dirtied_since_token() {
+        let page = page_size();
+        let mut map = MmapOptions::new(NonZeroUsize::new(4 * page).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        if !capabilities().soft_dirty {
+            assert_eq!(
+                map.export_dirty_since(None, io::sink())
+                    .err()
+                    .unwrap()
+                    .kind(),
+                io::ErrorKind::Unsupported
+            );
+
+            return;
+        }
+
+        map.fill(1);
+
+        let mut full = Vec::new();
+        let token = map.export_dirty_since(None, &mut full).unwrap();
+
+        assert_eq!(exported_offsets(&map, &full), [0, page, 2 * page, 3 * page]);
+
+        map[page + 5] = 2;
+        map[3 * page] = 3;
+
+        let mut incremental = Vec::new();
+        let next = map
+            .export_dirty_since(Some(token), &mut incremental)
+            .unwrap();
+
+        assert_eq!(exported_offsets(&map, &incremental), [page, 3 * page]);
+
+        // the old token was invalidated by the export
+        let mut stale = Vec::new();
+        map.export_dirty_since(Some(token), &mut stale).unwrap();
+
+        assert_eq!(exported_offsets(&map, &stale).len(), 4);
+        assert_ne!(next, token);
+
+        // shared pages lose their flags on writeback, so are always exported
+        let shared = MmapMut::new_anon(NonZeroUsize::new(2 * page).unwrap()).unwrap();
+        let token = shared.export_dirty_since(None, io::sink()).unwrap();
+
+        let mut export = Vec::new();
+        shared.export_dirty_since(Some(token), &mut export).unwrap();
+
+        assert_eq!(exported_offsets(&shared, &export), [0, page]);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{File, OpenOptions},
+    io, mem,
+    num::NonZeroUsize,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+    ptr, slice, vec,
+};
+
+use crate::{page_size, sys::RawMapping, Pod, WindowedMmap};
+
+/// A sorted run spilled to a temporary file, read back in order
+struct Run {
+    map: WindowedMmap,
+    /// Offset of the next record
+    pos: u64,
+    len: u64,
+}
+
+impl Run {
+    fn next<T: Pod>(&mut self) -> io::Result<Option<T>> {
+        if self.pos == self.len {
+            return Ok(None);
+        }
+
+        let bytes = self.map.read_at(self.pos, mem::size_of::<T>())?;
+
+        self.pos += mem::size_of::<T>() as u64;
+
+        Ok(Some(unsafe {
+            ptr::read_unaligned(bytes.as_ptr() as *const T)
+        }))
+    }
+}
+
+/// The records of an [`external_sort`], in ascending order
+pub struct SortedRecords<T: Pod + Ord> {
+    inner: Inner<T>,
+}
+
+enum Inner<T: Pod + Ord> {
+    /// Everything fit in memory, so nothing was spilled
+    Memory(vec::IntoIter<T>),
+    Merge {
+        runs: Vec<Run>,
+        /// The next record of each run which has any left, tagged with the
+        /// run, which also keeps equal records in input order
+        heads: BinaryHeap<Reverse<(T, usize)>>,
+    },
+}
+
+impl<T: Pod + Ord> SortedRecords<T> {
+    /// Number of runs which were spilled to disk, or zero if the input fit in
+    /// memory
+    pub fn spilled_runs(&self) -> usize {
+        match &self.inner {
+            Inner::Memory(..) => 0,
+            Inner::Merge { runs, .. } => runs.len(),
+        }
+    }
+}
+
+impl<T: Pod + Ord> Iterator for SortedRecords<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            Inner::Memory(records) => records.next().map(Ok),
+            Inner::Merge { runs, heads } => {
+                let Reverse((record, run)) = heads.pop()?;
+
+                match runs[run].next() {
+                    Ok(Some(next)) => heads.push(Reverse((next, run))),
+                    Ok(None) => {}
+                    Err(err) => {
+                        heads.clear();
+                        return Some(Err(err));
+                    }
+                }
+
+                Some(Ok(record))
+            }
+        }
+    }
+}
+
+/// Sort `input`, which may be much larger than memory, keeping at most
+/// `memory` bytes of records in memory at once
+///
+/// Records are collected until the memory budget is full, sorted and spilled
+/// as a run to an anonymous `O_TMPFILE` file in `spill_dir` through a shared
+/// mapping, which leaves writing them out to the kernel's writeback. The runs
+/// are then merged, each read through a [`WindowedMmap`] advised as
+/// sequential, so the kernel reads ahead within each run and the address
+/// space used stays within the budget. The temporary files disappear when
+/// the returned iterator is dropped, or if the process dies.
+///
+/// If the whole input fits in the budget, it is sorted in memory and never
+/// touches the disk.
+pub fn external_sort<T: Pod + Ord>(
+    input: impl IntoIterator<Item = T>,
+    memory: NonZeroUsize,
+    spill_dir: &Path,
+) -> io::Result<SortedRecords<T>> {
+    if mem::size_of::<T>() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot sort zero-sized records",
+        ));
+    }
+
+    let capacity = (memory.get() / mem::size_of::<T>()).max(1);
+
+    let mut input = input.into_iter().peekable();
+    let mut buf = Vec::with_capacity(capacity);
+    let mut files = Vec::new();
+
+    loop {
+        buf.extend(input.by_ref().take(capacity - buf.len()));
+
+        // a full buffer only spills if more records follow, so an input of
+        // exactly the budget still sorts in memory
+        if buf.len() < capacity || input.peek().is_none() {
+            break;
+        }
+
+        buf.sort();
+        files.push(spill(&buf, spill_dir)?);
+        buf.clear();
+    }
+
+    buf.sort();
+
+    if files.is_empty() {
+        return Ok(SortedRecords {
+            inner: Inner::Memory(buf.into_iter()),
+        });
+    }
+
+    if !buf.is_empty() {
+        files.push(spill(&buf, spill_dir)?);
+    }
+
+    drop(buf);
+
+    // share the budget between the runs' windows
+    let window = NonZeroUsize::new((memory.get() / files.len()).max(page_size())).unwrap();
+
+    let mut runs = Vec::with_capacity(files.len());
+    let mut heads = BinaryHeap::with_capacity(files.len());
+
+    for file in files {
+        let mut map = WindowedMmap::with_max_windows(&file, window, NonZeroUsize::MIN)?;
+
+        map.set_sequential(true)?;
+
+        let mut run = Run {
+            len: file.metadata()?.len(),
+            map,
+            pos: 0,
+        };
+
+        if let Some(head) = run.next()? {
+            heads.push(Reverse((head, runs.len())));
+        }
+
+        runs.push(run);
+    }
+
+    Ok(SortedRecords {
+        inner: Inner::Merge { runs, heads },
+    })
+}
+
+/// Write `records` to a new temporary file through a mapping of it
+fn spill<T: Pod>(records: &[T], dir: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .mode(0o600)
+        .open(dir)?;
+
+    let len = mem::size_of_val(records);
+
+    // allocate the blocks up front, so that running out of space fails here
+    // rather than raising SIGBUS when the mapping is written
+    let size = libc::off_t::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "run is too large"))?;
+
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, size) } {
+        0 => {}
+        err => return Err(io::Error::from_raw_os_error(err)),
+    }
+
+    let mut map = RawMapping::from_fd(file.as_raw_fd(), 0, NonZeroUsize::new(len).unwrap(), true)?;
+    let bytes = unsafe { slice::from_raw_parts(records.as_ptr() as *const u8, len) };
+
+    unsafe { map.as_mut_slice() }.copy_from_slice(bytes);
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::external_sort;
+
+    #[test]
+    fn merges_spilled_runs() {
+        // a permutation of 0..100_000
+        let input = (0..100_000_u64).map(|i| (i * 7_919) % 100_000);
+
+        let sorted = external_sort(
+            input,
+            NonZeroUsize::new(64 * 1024).unwrap(),
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+
+        assert_eq!(sorted.spilled_runs(), 13);
+
+        let sorted: Vec<u64> = sorted.map(Result::unwrap).collect();
+
+        assert_eq!(sorted, (0..100_000).collect::<Vec<_>>());
+
+        let sorted = external_sort(
+            [3_u32, 1, 2],
+            NonZeroUsize::new(64).unwrap(),
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+
+        assert_eq!(sorted.spilled_runs(), 0);
+        assert_eq!(sorted.map(Result::unwrap).collect::<Vec<_>>(), [1, 2, 3]);
+
+        // exactly fills the budget, then one record past it
+        for (len, runs) in [(16, 0), (17, 2)] {
+            let sorted = external_sort(
+                (0..len).rev(),
+                NonZeroUsize::new(64).unwrap(),
+                &std::env::temp_dir(),
+            )
+            .unwrap();
+
+            assert_eq!(sorted.spilled_runs(), runs);
+            assert_eq!(
+                sorted.map(Result::unwrap).collect::<Vec<u32>>(),
+                (0..len).collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs::File,
+    io,
+    marker::PhantomData,
+    mem,
+    ops::{Bound, RangeBounds},
+    os::unix::fs::FileExt,
+    slice,
+};
+
+use crate::{Mmap, Pod};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"MMAPIDX\0");
+
+/// Keys start this far into the file, and values at the next multiple of it
+/// after the keys
+const HEADER_LEN: usize = 64;
+
+/// A read-only map from `K` to `V`, stored in a file as a sorted array of keys
+/// followed by the array of their values, and searched in place through a
+/// mapping
+///
+/// Lookups binary search the mapped keys, so opening an index costs nothing
+/// but the mapping, and only the pages a search touches are read from disk.
+/// Write the file with a [`SortedIndexBuilder`].
+///
+/// The file records the sizes of `K` and `V`, but not the types themselves,
+/// nor the byte order, so it must be opened with the types it was built with
+/// on a machine of the same endianness.
+pub struct SortedIndex<K: Pod + Ord, V: Pod> {
+    map: Mmap<'static>,
+    len: usize,
+    values_offset: usize,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K: Pod + Ord, V: Pod> SortedIndex<K, V> {
+    /// Map an index previously written by [`SortedIndexBuilder::write`]
+    pub fn open(file: &File) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        check_types::<K, V>()?;
+
+        let map = Mmap::new_file(file)?;
+
+        let header = map
+            .get(..HEADER_LEN)
+            .ok_or_else(|| invalid("file is too short to be an index"))?;
+        let field = |idx: usize| u64::from_le_bytes(header[idx * 8..][..8].try_into().unwrap());
+
+        if field(0) != MAGIC {
+            return Err(invalid("file is not a sorted index"));
+        }
+
+        if field(1) != mem::size_of::<K>() as u64 || field(2) != mem::size_of::<V>() as u64 {
+            return Err(invalid("index entry sizes do not match"));
+        }
+
+        let len = usize::try_from(field(3)).map_err(|_| invalid("index is too long"))?;
+        let values_offset = values_offset::<K>(len).ok_or_else(|| invalid("index is too long"))?;
+
+        let end = len
+            .checked_mul(mem::size_of::<V>())
+            .and_then(|size| size.checked_add(values_offset));
+
+        if !matches!(end, Some(end) if end <= map.len) {
+            return Err(invalid("index is longer than the file"));
+        }
+
+        Ok(Self {
+            map,
+            len,
+            values_offset,
+            _types: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Every key, in ascending order
+    pub fn keys(&self) -> &[K] {
+        // mappings are page aligned, and `check_types` ensures the offsets
+        // are aligned for the types
+        unsafe { slice::from_raw_parts(self.map.ptr.add(HEADER_LEN) as *const K, self.len) }
+    }
+
+    /// The value of every key, in the same order as [`keys`](Self::keys)
+    pub fn values(&self) -> &[V] {
+        unsafe { slice::from_raw_parts(self.map.ptr.add(self.values_offset) as *const V, self.len) }
+    }
+
+    /// The value of `key`, if it is in the index
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.keys()
+            .binary_search(key)
+            .ok()
+            .map(|idx| &self.values()[idx])
+    }
+
+    /// The entries whose keys fall in `range`, in ascending order
+    pub fn range(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> impl ExactSizeIterator<Item = (&K, &V)> + DoubleEndedIterator {
+        let keys = self.keys();
+
+        let start = match range.start_bound() {
+            Bound::Included(start) => keys.partition_point(|key| key < start),
+            Bound::Excluded(start) => keys.partition_point(|key| key <= start),
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(end) => keys.partition_point(|key| key <= end),
+            Bound::Excluded(end) => keys.partition_point(|key| key < end),
+            Bound::Unbounded => keys.len(),
+        }
+        .max(start);
+
+        keys[start..end].iter().zip(&self.values()[start..end])
+    }
+
+    /// The entry with the greatest key less than or equal to `key`, such as
+    /// the block containing an offset in a sparse index of block start offsets
+    pub fn nearest(&self, key: &K) -> Option<(&K, &V)> {
+        let idx = self
+            .keys()
+            .partition_point(|probe| probe <= key)
+            .checked_sub(1)?;
+
+        Some((&self.keys()[idx], &self.values()[idx]))
+    }
+}
+
+/// Collects entries and writes them as a file for [`SortedIndex`]
+pub struct SortedIndexBuilder<K: Pod + Ord, V: Pod> {
+    entries: Vec<(K, V)>,
+}
+
+impl<K: Pod + Ord, V: Pod> Default for SortedIndexBuilder<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Pod + Ord, V: Pod> SortedIndexBuilder<K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Add an entry, in any order
+    pub fn insert(&mut self, key: K, value: V) {
+        self.entries.push((key, value));
+    }
+
+    /// Sort the entries and write them to `file`, replacing its contents
+    ///
+    /// Fails if any key was inserted more than once.
+    pub fn write(mut self, file: &File) -> io::Result<()> {
+        check_types::<K, V>()?;
+
+        self.entries.sort_unstable_by_key(|&(key, _)| key);
+
+        if self.entries.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index contains a duplicate key",
+            ));
+        }
+
+        let len = self.entries.len();
+        let keys: Vec<K> = self.entries.iter().map(|&(key, _)| key).collect();
+        let values: Vec<V> = self.entries.iter().map(|&(_, value)| value).collect();
+
+        let values_offset = values_offset::<K>(len).unwrap();
+
+        let mut header = [0; HEADER_LEN];
+
+        for (idx, field) in [
+            MAGIC,
+            mem::size_of::<K>() as u64,
+            mem::size_of::<V>() as u64,
+            len as u64,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            header[idx * 8..][..8].copy_from_slice(&field.to_le_bytes());
+        }
+
+        file.set_len(0)?;
+        file.write_all_at(&header, 0)?;
+        file.write_all_at(as_bytes(&keys), HEADER_LEN as u64)?;
+        file.write_all_at(as_bytes(&values), values_offset as u64)?;
+        // keep the file mappable when empty
+        file.set_len((values_offset + len * mem::size_of::<V>()) as u64)?;
+
+        file.sync_data()
+    }
+}
+
+/// Offset of the values of an index of `len` entries
+fn values_offset<K>(len: usize) -> Option<usize> {
+    let end = len
+        .checked_mul(mem::size_of::<K>())?
+        .checked_add(HEADER_LEN)?;
+
+    end.checked_next_multiple_of(HEADER_LEN)
+}
+
+fn check_types<K, V>() -> io::Result<()> {
+    if mem::align_of::<K>() > HEADER_LEN
+        || mem::align_of::<V>() > HEADER_LEN
+        || mem::size_of::<K>() == 0
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unsupported key or value type",
+        ));
+    }
+
+    Ok(())
+}
+
+fn as_bytes<T: Pod>(values: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, mem::size_of_val(values)) }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{test_util::temp_file, SortedIndex, SortedIndexBuilder};
+
+    #[test]
+    fn lookups() {
+        let file = temp_file("sorted-index");
+
+        let mut builder = SortedIndexBuilder::new();
+
+        for key in (0..1000_u64).rev() {
+            builder.insert(key * 10, [key as u32, 1]);
+        }
+
+        builder.write(&file).unwrap();
+
+        let index = SortedIndex::<u64, [u32; 2]>::open(&file).unwrap();
+
+        assert_eq!(index.len(), 1000);
+        assert_eq!(index.get(&420), Some(&[42, 1]));
+        assert_eq!(index.get(&421), None);
+
+        assert_eq!(
+            index.range(15..=40).map(|(&k, _)| k).collect::<Vec<_>>(),
+            [20, 30, 40]
+        );
+        assert_eq!(index.range(9_990..).len(), 1);
+        assert_eq!(index.range(..0).len(), 0);
+
+        assert_eq!(index.nearest(&425), Some((&420, &[42, 1])));
+        assert_eq!(index.nearest(&0), Some((&0, &[0, 1])));
+        assert_eq!(index.nearest(&u64::MAX).unwrap().0, &9_990);
+
+        assert!(SortedIndex::<u32, [u32; 2]>::open(&file).is_err());
+
+        let mut builder = SortedIndexBuilder::new();
+
+        builder.insert(1_u8, 0_u8);
+        builder.insert(1, 1);
+
+        assert!(builder.write(&file).is_err());
+    }
+}
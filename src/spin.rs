@@ -0,0 +1,98 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+
+use crate::pidfd::process_alive;
+
+/// A minimal spin lock which can live in shared memory, yielding to the
+/// scheduler while contended
+///
+/// The lock holds the pid of the process holding it, or zero when unlocked.
+/// A process which dies while holding the lock leaves it locked forever for
+/// [`lock`](Self::lock), while [`lock_or_recover`](Self::lock_or_recover)
+/// takes it over.
+#[repr(transparent)]
+pub(crate) struct SpinLock(AtomicU32);
+
+impl SpinLock {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub(crate) fn lock(&self) -> SpinGuard<'_> {
+        self.acquire(false)
+    }
+
+    /// Lock, taking the lock over if the process holding it has died
+    ///
+    /// Only for locks whose critical sections leave the data they protect
+    /// consistent after every store, as the dead holder may have stopped
+    /// anywhere.
+    pub(crate) fn lock_or_recover(&self) -> SpinGuard<'_> {
+        self.acquire(true)
+    }
+
+    fn acquire(&self, recover: bool) -> SpinGuard<'_> {
+        let pid = std::process::id();
+
+        loop {
+            let holder =
+                match self
+                    .0
+                    .compare_exchange_weak(0, pid, Ordering::Acquire, Ordering::Relaxed)
+                {
+                    Ok(_) => break,
+                    Err(holder) => holder,
+                };
+
+            // a holder with our pid is another thread of this process
+            if recover
+                && holder != 0
+                && holder != pid
+                && !process_alive(holder)
+                && self
+                    .0
+                    .compare_exchange(holder, pid, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break;
+            }
+
+            thread::yield_now();
+        }
+
+        SpinGuard { lock: self }
+    }
+}
+
+pub(crate) struct SpinGuard<'a> {
+    lock: &'a SpinLock,
+}
+
+impl<'a> Drop for SpinGuard<'a> {
+    fn drop(&mut self) {
+        self.lock.0.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{process::Command, sync::atomic::AtomicU32};
+
+    use super::SpinLock;
+
+    #[test]
+    fn recovers_from_dead_holder() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+
+        let lock = SpinLock(AtomicU32::new(dead));
+
+        drop(lock.lock_or_recover());
+
+        // released normally once recovered
+        drop(lock.lock());
+    }
+}
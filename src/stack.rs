@@ -0,0 +1,129 @@
+use std::io;
+
+use crate::{munmap_raw, register_guard, GuardCallback, GuardRegistration};
+
+/// A read-write stack region with guard pages below it, created by
+/// [`MmapOptions::map_stack`](crate::MmapOptions::map_stack)
+///
+/// The stack is unmapped on drop, so it must outlive any thread running on it.
+pub struct ThreadStack {
+    pub(crate) base: *mut u8,
+    pub(crate) guard_len: usize,
+    pub(crate) len: usize,
+}
+
+unsafe impl Send for ThreadStack {}
+
+impl ThreadStack {
+    /// Lowest usable address of the stack, as expected by
+    /// `pthread_attr_setstack`
+    pub fn stack_addr(&self) -> *mut u8 {
+        unsafe { self.base.add(self.guard_len) }
+    }
+
+    /// Number of usable bytes, as expected by `pthread_attr_setstack`
+    pub fn stack_size(&self) -> usize {
+        self.len
+    }
+
+    /// One past the highest usable address, from which the stack grows
+    /// downwards on all architectures supported by Linux except PA-RISC
+    pub fn top(&self) -> *mut u8 {
+        unsafe { self.stack_addr().add(self.len) }
+    }
+
+    /// Size of the inaccessible region below the stack
+    pub fn guard_size(&self) -> usize {
+        self.guard_len
+    }
+
+    /// Total address space consumed, including guard pages
+    pub fn reserved_size(&self) -> usize {
+        self.guard_len + self.len
+    }
+
+    /// Invoke `callback` when the stack overflows into its guard pages
+    ///
+    /// See [`register_guard`] for the restrictions on what the callback may do.
+    pub fn register_guard(
+        &self,
+        callback: GuardCallback,
+        data: usize,
+    ) -> io::Result<GuardRegistration> {
+        register_guard(self.base, self.guard_len, callback, data)
+    }
+}
+
+impl Drop for ThreadStack {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.base, self.reserved_size());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, mem::MaybeUninit, ptr};
+
+    use crate::{page_size, MmapOptions};
+
+    extern "C" fn write_local(_arg: *mut libc::c_void) -> *mut libc::c_void {
+        let local = std::hint::black_box([7_u8; 512]);
+        let sum: usize = local.iter().map(|&b| b as usize).sum();
+        sum as *mut _
+    }
+
+    #[test]
+    fn stack_layout() {
+        assert_eq!(
+            MmapOptions::thread_stack(usize::MAX).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let stack = MmapOptions::thread_stack(1).unwrap().map_stack().unwrap();
+
+        assert!(stack.stack_size() >= libc::PTHREAD_STACK_MIN);
+        assert_eq!(stack.guard_size(), page_size());
+        assert_eq!(stack.stack_addr() as usize % page_size(), 0);
+        assert_eq!(stack.top() as usize % 16, 0);
+    }
+
+    #[test]
+    fn runs_pthread() {
+        let stack = MmapOptions::thread_stack(64 * 1024)
+            .unwrap()
+            .map_stack()
+            .unwrap();
+
+        unsafe {
+            let mut attr = MaybeUninit::uninit();
+            assert_eq!(libc::pthread_attr_init(attr.as_mut_ptr()), 0);
+            assert_eq!(
+                libc::pthread_attr_setstack(
+                    attr.as_mut_ptr(),
+                    stack.stack_addr().cast(),
+                    stack.stack_size()
+                ),
+                0
+            );
+
+            let mut thread = MaybeUninit::uninit();
+            assert_eq!(
+                libc::pthread_create(
+                    thread.as_mut_ptr(),
+                    attr.as_ptr(),
+                    write_local,
+                    ptr::null_mut()
+                ),
+                0
+            );
+
+            let mut ret = ptr::null_mut();
+            assert_eq!(libc::pthread_join(thread.assume_init(), &mut ret), 0);
+            assert_eq!(ret as usize, 7 * 512);
+
+            libc::pthread_attr_destroy(attr.as_mut_ptr());
+        }
+    }
+}
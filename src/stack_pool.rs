@@ -0,0 +1,142 @@
+use std::{io, num::NonZeroUsize, sync::Mutex};
+
+use crate::{
+    madvise_raw, mprotect_raw, page_size, round_up_to_page, MmapOptions, Protection, ThreadStack,
+};
+
+/// A fixed set of guard-paged stacks carved out of a single reservation
+///
+/// Stacks are handed out with [`acquire`](Self::acquire) and returned to the
+/// pool when the [`PooledStack`] is dropped. Returned stacks are
+/// `MADV_DONTNEED`ed so their memory is given back to the kernel, while the
+/// address range stays reserved for the next user.
+pub struct StackPool {
+    region: ThreadStack,
+    slot_len: usize,
+    stack_len: usize,
+    free: Mutex<Vec<usize>>,
+}
+
+impl StackPool {
+    /// Map `count` stacks of at least `size` usable bytes each, every one with
+    /// a guard page directly below it
+    pub fn new(count: NonZeroUsize, size: usize) -> io::Result<Self> {
+        let stack_len = round_up_to_page(size.max(libc::PTHREAD_STACK_MIN));
+        let slot_len = stack_len + page_size();
+
+        let total = slot_len.checked_mul(count.get()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "stack pool size overflows")
+        })?;
+
+        // the first guard page comes from the reservation itself, the rest
+        // are carved out of the usable region below
+        let region = MmapOptions::thread_stack(total - page_size())?.map_stack()?;
+
+        for slot in 1..count.get() {
+            unsafe {
+                mprotect_raw(
+                    region.base.add(slot * slot_len),
+                    page_size(),
+                    Protection::NONE,
+                )?;
+            }
+        }
+
+        Ok(Self {
+            region,
+            slot_len,
+            stack_len,
+            free: Mutex::new((0..count.get()).rev().collect()),
+        })
+    }
+
+    /// Take a stack out of the pool, or `None` if all are in use
+    pub fn acquire(&self) -> Option<PooledStack<'_>> {
+        let slot = self.free.lock().unwrap().pop()?;
+
+        Some(PooledStack { pool: self, slot })
+    }
+
+    /// Number of stacks currently available
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    /// Usable size of each stack
+    pub fn stack_size(&self) -> usize {
+        self.stack_len
+    }
+
+    fn slot_addr(&self, slot: usize) -> *mut u8 {
+        unsafe { self.region.base.add(slot * self.slot_len + page_size()) }
+    }
+
+    fn release(&self, slot: usize) {
+        // a stack which keeps its old contents is still usable, so failure
+        // only costs memory
+        let _ = unsafe { madvise_raw(self.slot_addr(slot), self.stack_len, libc::MADV_DONTNEED) };
+
+        self.free.lock().unwrap().push(slot);
+    }
+}
+
+unsafe impl Sync for StackPool {}
+
+/// A stack borrowed from a [`StackPool`], returned to it on drop
+pub struct PooledStack<'p> {
+    pool: &'p StackPool,
+    slot: usize,
+}
+
+impl<'p> PooledStack<'p> {
+    /// Lowest usable address of the stack
+    pub fn stack_addr(&self) -> *mut u8 {
+        self.pool.slot_addr(self.slot)
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.pool.stack_len
+    }
+
+    /// One past the highest usable address
+    pub fn top(&self) -> *mut u8 {
+        unsafe { self.stack_addr().add(self.pool.stack_len) }
+    }
+}
+
+impl<'p> Drop for PooledStack<'p> {
+    fn drop(&mut self) {
+        self.pool.release(self.slot);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::StackPool;
+
+    #[test]
+    fn acquire_and_release() {
+        let pool = StackPool::new(NonZeroUsize::new(2).unwrap(), 32 * 1024).unwrap();
+
+        let a = pool.acquire().unwrap();
+        let b = pool.acquire().unwrap();
+
+        assert!(pool.acquire().is_none());
+        assert_ne!(a.stack_addr(), b.stack_addr());
+
+        unsafe { a.top().sub(1).write(42) };
+
+        let addr = a.stack_addr();
+        drop(a);
+
+        assert_eq!(pool.available(), 1);
+
+        let a = pool.acquire().unwrap();
+
+        assert_eq!(a.stack_addr(), addr);
+        // the returned stack was discarded, so it reads back as zero
+        assert_eq!(unsafe { a.top().sub(1).read() }, 0);
+    }
+}
@@ -0,0 +1,206 @@
+//! Mapping primitives which depend only on `core` and `libc`
+//!
+//! These are available without the `std` feature, for environments such as
+//! minimal init processes which cannot link the standard library. Errors are
+//! reported as a bare [`Errno`] rather than an `io::Error`.
+
+use core::{fmt, mem::MaybeUninit, num::NonZeroUsize, ptr::NonNull, slice};
+
+/// An error number returned by a failed system call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i32);
+
+impl Errno {
+    /// The calling thread's current `errno`
+    pub fn last() -> Self {
+        Self(unsafe { *crate::libc_compat::errno_location() })
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "os error {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Errno {}
+
+#[cfg(feature = "std")]
+impl From<Errno> for std::io::Error {
+    fn from(errno: Errno) -> Self {
+        std::io::Error::from_raw_os_error(errno.0)
+    }
+}
+
+/// A mapping which is unmapped on drop
+pub struct RawMapping {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl RawMapping {
+    /// Map `len` bytes of zeroed, private, read-write anonymous memory
+    pub fn anon(len: NonZeroUsize) -> Result<Self, Errno> {
+        Self::map(
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    }
+
+    /// Map `len` bytes of the file open as `fd`, starting at `offset`, which
+    /// must be a multiple of the page size
+    ///
+    /// The mapping is shared, so writes are carried through to the file, and
+    /// is only writable if `writable` is set, which requires `fd` to be open
+    /// for writing.
+    ///
+    /// Fails with `EINVAL` if `fd` is a regular file which ends before
+    /// `offset + len`, as accessing pages past its end would raise `SIGBUS`.
+    pub fn from_fd(fd: i32, offset: u64, len: NonZeroUsize, writable: bool) -> Result<Self, Errno> {
+        let prot = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+
+        let end = offset
+            .checked_add(len.get() as u64)
+            .ok_or(Errno(libc::EOVERFLOW))?;
+
+        let mut stat = MaybeUninit::<libc::stat64>::uninit();
+
+        if unsafe { libc::fstat64(fd, stat.as_mut_ptr()) } != 0 {
+            return Err(Errno::last());
+        }
+
+        let stat = unsafe { stat.assume_init() };
+
+        // devices and the like report no size, so only files are checked
+        if stat.st_mode & libc::S_IFMT == libc::S_IFREG && end > stat.st_size as u64 {
+            return Err(Errno(libc::EINVAL));
+        }
+
+        let offset = i64::try_from(offset).map_err(|_| Errno(libc::EOVERFLOW))?;
+
+        Self::map(len, prot, libc::MAP_SHARED, fd, offset)
+    }
+
+    fn map(len: NonZeroUsize, prot: i32, flags: i32, fd: i32, offset: i64) -> Result<Self, Errno> {
+        let ptr = unsafe { mmap(len.get(), prot, flags, fd, offset)? };
+
+        Ok(Self {
+            ptr: NonNull::new(ptr).ok_or(Errno(libc::EFAULT))?,
+            len: len.get(),
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// The contents of the mapping, which must be writable
+    ///
+    /// # Safety
+    ///
+    /// The mapping must have been created writable; writing to a read-only
+    /// mapping raises `SIGSEGV`.
+    pub unsafe fn as_mut_slice(&mut self) -> &mut [u8] {
+        slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len)
+    }
+}
+
+impl Drop for RawMapping {
+    fn drop(&mut self) {
+        unsafe { munmap(self.ptr.as_ptr(), self.len) };
+    }
+}
+
+/// `mmap(2)` a new mapping, through the crate's traced wrapper when the
+/// standard library is available
+unsafe fn mmap(len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> Result<*mut u8, Errno> {
+    #[cfg(feature = "std")]
+    return crate::mmap_raw(
+        core::ptr::null_mut(),
+        len,
+        crate::Protection(prot),
+        flags,
+        fd,
+        offset,
+    )
+    .map_err(|e| Errno(e.raw_os_error().unwrap_or(libc::EINVAL)));
+
+    #[cfg(not(feature = "std"))]
+    match libc::mmap64(core::ptr::null_mut(), len, prot, flags, fd, offset) {
+        libc::MAP_FAILED => Err(Errno::last()),
+        ptr => Ok(ptr.cast()),
+    }
+}
+
+/// `munmap(2)` a mapping, through the crate's traced wrapper when the
+/// standard library is available
+unsafe fn munmap(ptr: *mut u8, len: usize) {
+    #[cfg(feature = "std")]
+    crate::munmap_raw(ptr, len);
+
+    #[cfg(not(feature = "std"))]
+    libc::munmap(ptr.cast(), len);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::{num::NonZeroUsize, os::unix::io::AsRawFd};
+
+    use crate::sys::{Errno, RawMapping};
+    use crate::test_util::temp_file;
+
+    #[test]
+    fn anon_and_fd_mappings() {
+        let mut anon = RawMapping::anon(NonZeroUsize::new(100).unwrap()).unwrap();
+
+        unsafe { anon.as_mut_slice()[99] = 1 };
+
+        assert_eq!(anon.as_slice()[99], 1);
+
+        let file = temp_file("sys");
+        file.set_len(4096).unwrap();
+
+        let mut map =
+            RawMapping::from_fd(file.as_raw_fd(), 0, NonZeroUsize::new(4096).unwrap(), true)
+                .unwrap();
+
+        unsafe { map.as_mut_slice()[0] = 7 };
+
+        for (offset, len) in [(0, 8192), (4096, 4096)] {
+            assert!(matches!(
+                RawMapping::from_fd(
+                    file.as_raw_fd(),
+                    offset,
+                    NonZeroUsize::new(len).unwrap(),
+                    false
+                ),
+                Err(Errno(libc::EINVAL))
+            ));
+        }
+
+        assert!(matches!(
+            RawMapping::from_fd(-1, 0, NonZeroUsize::new(4096).unwrap(), false),
+            Err(Errno(libc::EBADF))
+        ));
+    }
+}
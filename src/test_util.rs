@@ -0,0 +1,38 @@
+//! Fixtures shared by the unit tests
+
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+/// A path in the temporary directory named after `name` and the process, with
+/// any file left there by an earlier run removed
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mmap-{}-{}", name, std::process::id()));
+
+    let _ = fs::remove_file(&path);
+
+    path
+}
+
+/// Open the file at `path` for reading and writing, creating it if needed
+pub(crate) fn open_file(path: &Path) -> File {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .unwrap()
+}
+
+/// An empty file in the temporary directory, unlinked straight away so that
+/// it is removed once closed
+pub(crate) fn temp_file(name: &str) -> File {
+    let path = temp_path(name);
+    let file = open_file(&path);
+
+    fs::remove_file(&path).unwrap();
+
+    file
+}
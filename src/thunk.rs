@@ -0,0 +1,306 @@
+use std::{
+    io,
+    num::NonZeroUsize,
+    os::unix::io::AsRawFd,
+    ptr,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{flag::UniqueFlag, mmap_raw, munmap_raw, page_size, MemfdMmap, Protection};
+
+/// Alignment of every thunk, which is enough for the instruction alignment
+/// requirements of every architecture and for branch target alignment
+const THUNK_ALIGN: usize = 16;
+
+/// Pages in each pool of thunks
+const POOL_PAGES: usize = 4;
+
+/// Sub-allocates small executable stubs, such as FFI closure trampolines, out
+/// of pooled mappings
+///
+/// Every pool is a memfd mapped twice: once writable, which code is copied
+/// through, and once executable, which is handed out. No page is ever both
+/// writable and executable, so this works under W^X policies, and writing a
+/// new thunk never makes the pages of other thunks briefly inaccessible.
+///
+/// Freed thunks are reused before a new pool is mapped.
+pub struct ThunkAllocator {
+    thunk_size: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    pools: Vec<Pool>,
+    /// `(pool, slot)` of each freed thunk
+    free: Vec<(usize, usize)>,
+    /// Slots of the last pool which have never been handed out
+    next_slot: usize,
+}
+
+struct Pool {
+    write: MemfdMmap,
+    exec: *mut u8,
+}
+
+unsafe impl Send for Pool {}
+
+impl Pool {
+    fn new() -> io::Result<Self> {
+        let len = POOL_PAGES * page_size();
+        let write = MemfdMmap::new("mmap-thunks", NonZeroUsize::new(len).unwrap())?;
+
+        let exec = unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                len,
+                Protection::READ | Protection::EXEC,
+                UniqueFlag::MAP_SHARED.0,
+                write.file().as_raw_fd(),
+                0,
+            )?
+        };
+
+        Ok(Self { write, exec })
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        unsafe {
+            munmap_raw(self.exec, self.write.len());
+        }
+    }
+}
+
+impl ThunkAllocator {
+    /// Create an allocator handing out thunks of up to `thunk_size` bytes,
+    /// which must be no larger than a page
+    pub fn new(thunk_size: NonZeroUsize) -> io::Result<Self> {
+        let thunk_size = thunk_size.get().next_multiple_of(THUNK_ALIGN);
+
+        if thunk_size > page_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "thunks cannot be larger than a page",
+            ));
+        }
+
+        Ok(Self {
+            thunk_size,
+            state: Mutex::new(State {
+                pools: Vec::new(),
+                free: Vec::new(),
+                next_slot: 0,
+            }),
+        })
+    }
+
+    /// Size of each thunk, `thunk_size` rounded up to the thunk alignment
+    pub fn thunk_size(&self) -> usize {
+        self.thunk_size
+    }
+
+    /// Copy `code` into a free thunk and make it executable
+    ///
+    /// Any remaining bytes of the thunk are zeroed.
+    pub fn alloc(&self, code: &[u8]) -> io::Result<Thunk<'_>> {
+        if code.len() > self.thunk_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "code does not fit in a thunk",
+            ));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let (pool, slot) = self.take_slot(&mut state)?;
+
+        let thunk = Thunk {
+            allocator: self,
+            pool,
+            slot,
+        };
+
+        thunk.write(&mut state, code);
+
+        Ok(thunk)
+    }
+
+    /// Number of pools mapped so far
+    pub fn pools(&self) -> usize {
+        self.state.lock().unwrap().pools.len()
+    }
+
+    fn slots_per_pool(&self) -> usize {
+        POOL_PAGES * page_size() / self.thunk_size
+    }
+
+    fn take_slot(&self, state: &mut State) -> io::Result<(usize, usize)> {
+        if let Some(free) = state.free.pop() {
+            return Ok(free);
+        }
+
+        if state.pools.is_empty() || state.next_slot == self.slots_per_pool() {
+            state.pools.push(Pool::new()?);
+            state.next_slot = 0;
+        }
+
+        state.next_slot += 1;
+
+        Ok((state.pools.len() - 1, state.next_slot - 1))
+    }
+}
+
+/// A thunk allocated from a [`ThunkAllocator`], returned to it on drop
+pub struct Thunk<'t> {
+    allocator: &'t ThunkAllocator,
+    pool: usize,
+    slot: usize,
+}
+
+impl<'t> Thunk<'t> {
+    /// Executable address of the thunk, to be cast to a function pointer
+    pub fn as_ptr(&self) -> *const u8 {
+        let state = self.allocator.state.lock().unwrap();
+
+        unsafe {
+            state.pools[self.pool]
+                .exec
+                .add(self.slot * self.allocator.thunk_size)
+        }
+    }
+
+    /// Replace the code of the thunk, for example to hot-patch the target of a
+    /// trampoline
+    ///
+    /// # Safety
+    ///
+    /// No thread may be executing the thunk, as it may observe a mix of the
+    /// old and new instructions.
+    pub unsafe fn rewrite(&mut self, code: &[u8]) -> io::Result<()> {
+        if code.len() > self.allocator.thunk_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "code does not fit in a thunk",
+            ));
+        }
+
+        self.write(&mut self.allocator.state.lock().unwrap(), code);
+
+        Ok(())
+    }
+
+    fn write(&self, state: &mut MutexGuard<'_, State>, code: &[u8]) {
+        let pool = &mut state.pools[self.pool];
+        let offset = self.slot * self.allocator.thunk_size;
+
+        let dst = &mut pool.write[offset..offset + self.allocator.thunk_size];
+        dst[..code.len()].copy_from_slice(code);
+        dst[code.len()..].fill(0);
+
+        unsafe { flush_icache(pool.exec.add(offset), self.allocator.thunk_size) };
+    }
+}
+
+impl<'t> Drop for Thunk<'t> {
+    fn drop(&mut self) {
+        self.allocator
+            .state
+            .lock()
+            .unwrap()
+            .free
+            .push((self.pool, self.slot));
+    }
+}
+
+/// Make instructions written through another mapping visible to instruction
+/// fetches from the `len` bytes at `exec`
+///
+/// x86 keeps the instruction cache coherent with stores, so this is only
+/// needed elsewhere.
+#[allow(unused_variables)]
+unsafe fn flush_icache(exec: *const u8, len: usize) {
+    #[cfg(target_arch = "aarch64")]
+    {
+        // Linux lets userspace read the cache line sizes from CTR_EL0
+        let ctr: usize;
+        std::arch::asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack, preserves_flags));
+
+        let data_line = 4 << ((ctr >> 16) & 0xf);
+        let inst_line = 4 << (ctr & 0xf);
+
+        let end = exec as usize + len;
+
+        for line in (exec as usize & !(data_line - 1)..end).step_by(data_line) {
+            std::arch::asm!("dc cvau, {}", in(reg) line, options(nostack, preserves_flags));
+        }
+
+        std::arch::asm!("dsb ish", options(nostack, preserves_flags));
+
+        for line in (exec as usize & !(inst_line - 1)..end).step_by(inst_line) {
+            std::arch::asm!("ic ivau, {}", in(reg) line, options(nostack, preserves_flags));
+        }
+
+        std::arch::asm!("dsb ish", "isb", options(nostack, preserves_flags));
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        extern "C" {
+            fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+        }
+
+        __clear_cache(exec as *mut _, exec.add(len) as *mut _);
+    }
+}
+
+// only architectures the test has machine code for
+#[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::ThunkAllocator;
+
+    /// `return 42`
+    #[cfg(target_arch = "x86_64")]
+    const RETURN_42: &[u8] = &[0xb8, 0x2a, 0x00, 0x00, 0x00, 0xc3];
+    #[cfg(target_arch = "aarch64")]
+    const RETURN_42: &[u8] = &[0x40, 0x05, 0x80, 0x52, 0xc0, 0x03, 0x5f, 0xd6];
+
+    /// `return 7`
+    #[cfg(target_arch = "x86_64")]
+    const RETURN_7: &[u8] = &[0xb8, 0x07, 0x00, 0x00, 0x00, 0xc3];
+    #[cfg(target_arch = "aarch64")]
+    const RETURN_7: &[u8] = &[0xe0, 0x00, 0x80, 0x52, 0xc0, 0x03, 0x5f, 0xd6];
+
+    #[test]
+    fn call_and_reuse_thunks() {
+        let allocator = ThunkAllocator::new(NonZeroUsize::new(40).unwrap()).unwrap();
+
+        assert_eq!(allocator.thunk_size(), 48);
+
+        let mut thunk = allocator.alloc(RETURN_42).unwrap();
+        let call = |ptr: *const u8| unsafe {
+            std::mem::transmute::<*const u8, extern "C" fn() -> u32>(ptr)()
+        };
+
+        assert_eq!(call(thunk.as_ptr()), 42);
+
+        unsafe { thunk.rewrite(RETURN_7).unwrap() };
+
+        assert_eq!(call(thunk.as_ptr()), 7);
+
+        let other = allocator.alloc(RETURN_42).unwrap();
+
+        assert_eq!(other.as_ptr() as usize - thunk.as_ptr() as usize, 48);
+
+        let addr = thunk.as_ptr();
+        drop(thunk);
+
+        let reused = allocator.alloc(RETURN_42).unwrap();
+
+        assert_eq!(reused.as_ptr(), addr);
+        assert_eq!(call(reused.as_ptr()), 42);
+        assert_eq!(allocator.pools(), 1);
+        assert!(allocator.alloc(&[0; 49]).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    io,
+    marker::PhantomData,
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{MmapCell, MmapMut, Pod, SeqLock};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMTOPIC");
+
+/// Slots start this far into the mapping
+const HEADER_LEN: usize = 64;
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    capacity: u64,
+    elem_size: u64,
+    /// Number of messages published so far
+    head: AtomicU64,
+}
+
+/// A broadcast channel in a shared mapping, which one process publishes to at
+/// a high rate and any number of processes sample
+///
+/// Messages go round a ring of [`SeqLock`]ed slots, so readers never
+/// coordinate with the writer or with each other: the writer always
+/// overwrites the oldest message, and a reader which falls more than
+/// [`capacity`](Self::capacity) messages behind simply misses some. With a
+/// capacity of one, this is a single slot holding the latest value.
+///
+/// There must only be one writer at a time, which publishing through `&mut`
+/// enforces within a process; other processes must attach read-only or not
+/// publish while this one does.
+pub struct ShmTopic<'m, T: Pod> {
+    header: *const Header,
+    slots: *const SeqLock<T>,
+    /// Kept out of the header so that other processes cannot change it once
+    /// attached
+    capacity: u64,
+    _map: PhantomData<&'m MmapMut<'m>>,
+}
+
+unsafe impl<'m, T: Pod> Send for ShmTopic<'m, T> {}
+unsafe impl<'m, T: Pod> Sync for ShmTopic<'m, T> {}
+
+impl<'m, T: Pod> ShmTopic<'m, T> {
+    /// Format the mapping as a topic with no messages, keeping the last
+    /// `capacity` published messages
+    pub fn init(map: &'m mut MmapMut, capacity: usize, initial: T) -> io::Result<Self> {
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "topic capacity must not be zero",
+            ));
+        }
+
+        let (header, slots) = Self::layout(&map.as_cell(), capacity)?;
+
+        unsafe {
+            for idx in 0..capacity {
+                slots.add(idx).write(SeqLock::new(initial));
+            }
+
+            header.write(Header {
+                magic: AtomicU64::new(0),
+                capacity: capacity as u64,
+                elem_size: mem::size_of::<T>() as u64,
+                head: AtomicU64::new(0),
+            });
+
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+
+        Ok(Self {
+            header,
+            slots,
+            capacity: capacity as u64,
+            _map: PhantomData,
+        })
+    }
+
+    /// Attach to a topic previously created with [`init`](Self::init),
+    /// possibly by another process, checking it holds messages of the same
+    /// size
+    ///
+    /// Takes a [view](MmapMut::as_cell) of the mapping, so that no slice of the
+    /// slots can be alive while they are published to.
+    pub fn attach(map: &'m MmapCell) -> io::Result<Self> {
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let hdr = unsafe { &*header };
+
+        if hdr.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "mapping does not contain a topic",
+            ));
+        }
+
+        if hdr.elem_size != mem::size_of::<T>() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "topic layout does not match",
+            ));
+        }
+
+        let capacity = match usize::try_from(hdr.capacity) {
+            Ok(capacity) if capacity != 0 => capacity,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "topic capacity is corrupt",
+                ))
+            }
+        };
+
+        let (header, slots) = Self::layout(map, capacity)?;
+
+        Ok(Self {
+            header,
+            slots,
+            capacity: capacity as u64,
+            _map: PhantomData,
+        })
+    }
+
+    fn layout(map: &MmapCell, capacity: usize) -> io::Result<(*mut Header, *mut SeqLock<T>)> {
+        if mem::align_of::<SeqLock<T>>() > HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported message type",
+            ));
+        }
+
+        let header = map.typed_ptr::<Header>(0, 1)?;
+        let slots = map.typed_ptr::<SeqLock<T>>(HEADER_LEN, capacity)?;
+
+        Ok((header, slots))
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    fn slot(&self, msg: u64) -> &SeqLock<T> {
+        unsafe { &*self.slots.add((msg % self.capacity) as usize) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Number of messages published so far, which is also the number the next
+    /// message will get
+    pub fn head(&self) -> u64 {
+        self.header().head.load(Ordering::Acquire)
+    }
+
+    /// Publish `value`, overwriting the oldest message, and return its number
+    pub fn publish(&mut self, value: T) -> u64 {
+        let header = self.header();
+        let msg = header.head.load(Ordering::Relaxed);
+
+        self.slot(msg).write(value);
+
+        header.head.store(msg + 1, Ordering::Release);
+
+        msg
+    }
+
+    /// Read message number `msg`, or `None` if it has not been published yet
+    /// or has already been overwritten
+    pub fn get(&self, msg: u64) -> Option<T> {
+        let capacity = self.capacity;
+        // each slot's sequence number advances by two for every write to it
+        let expected = (msg / capacity + 1) * 2;
+
+        loop {
+            if msg >= self.head() {
+                return None;
+            }
+
+            match self.slot(msg).try_read_versioned() {
+                Some((seq, value)) if seq == expected => return Some(value),
+                Some(_) => return None,
+                // the writer is overwriting the slot, so the message is gone
+                // unless it is the write of this very message, which has not
+                // been published yet
+                None => std::hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Read the most recently published message along with its number, or
+    /// `None` if nothing has been published
+    pub fn latest(&self) -> Option<(u64, T)> {
+        loop {
+            let msg = self.head().checked_sub(1)?;
+
+            // only fails if the writer lapped the ring while this was reading
+            if let Some(value) = self.get(msg) {
+                return Some((msg, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, num::NonZeroUsize, thread};
+
+    use crate::{MemfdMmap, MmapMut, MmapOptions, ShmTopic};
+
+    #[test]
+    fn publish_and_sample() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        let mut topic = ShmTopic::init(&mut map, 4, [0_u64; 2]).unwrap();
+
+        assert_eq!(topic.latest(), None);
+
+        for i in 0..6 {
+            assert_eq!(topic.publish([i, i]), i);
+        }
+
+        assert_eq!(topic.latest(), Some((5, [5, 5])));
+        assert_eq!(topic.get(1), None);
+        assert_eq!(topic.get(2), Some([2, 2]));
+        assert_eq!(topic.get(6), None);
+
+        assert!(ShmTopic::init(&mut map, 0, [0_u64; 2]).is_err());
+
+        // a zero capacity in the header would have every read divide by it
+        map[8..16].copy_from_slice(&0_u64.to_ne_bytes());
+
+        let cell = map.as_cell();
+        let err = ShmTopic::<[u64; 2]>::attach(&cell).err().unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn samples_while_publishing() {
+        let len = NonZeroUsize::new(4096).unwrap();
+        let memfd = MemfdMmap::new("topic", len).unwrap();
+
+        // the writer and the readers each map the topic, as separate
+        // processes would
+        let mut writer_map = MmapOptions::new(len).map_file_mut(memfd.file()).unwrap();
+        let mut reader_map = MmapOptions::new(len).map_file_mut(memfd.file()).unwrap();
+
+        let mut writer = ShmTopic::init(&mut writer_map, 4, [0_u64; 2]).unwrap();
+        let reader_cell = reader_map.as_cell();
+        let reader = ShmTopic::<[u64; 2]>::attach(&reader_cell).unwrap();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..10_000 {
+                    writer.publish([i, i]);
+                }
+            });
+
+            for _ in 0..3 {
+                s.spawn(|| {
+                    let mut last = 0;
+
+                    while last < 9_999 {
+                        let Some((msg, [a, b])) = reader.latest() else {
+                            continue;
+                        };
+
+                        assert_eq!(a, b);
+                        assert_eq!(a, msg);
+                        assert!(msg >= last);
+
+                        last = msg;
+                    }
+                });
+            }
+        });
+    }
+}
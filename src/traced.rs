@@ -0,0 +1,365 @@
+use std::{
+    io,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    mprotect_raw, page_size, register_guard, GuardAction, GuardFault, GuardRegistration, MmapMut,
+    Protection,
+};
+
+/// Number of accesses recorded before further ones are only counted
+const LOG_CAPACITY: usize = 1024;
+
+/// Most return addresses recorded for each access
+const BACKTRACE_DEPTH: usize = 16;
+
+/// The first access to a page of a [`TracedMmap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedAccess {
+    /// Offset of the accessed byte from the start of the mapping
+    pub offset: usize,
+    /// Address of the accessed byte
+    pub addr: usize,
+    /// Address of the instruction which made the access, or zero if it is not
+    /// known on this architecture
+    pub pc: usize,
+    /// Return addresses of the calls which led to the access, innermost
+    /// first
+    ///
+    /// Found by following frame pointers, so this is only complete when
+    /// everything was built with `-C force-frame-pointers=yes`, and empty on
+    /// architectures where the frame pointer is not known.
+    pub backtrace: Vec<usize>,
+}
+
+struct LogEntry {
+    offset: AtomicUsize,
+    pc: AtomicUsize,
+    /// Return addresses, followed by zeros if there are fewer than
+    /// [`BACKTRACE_DEPTH`]
+    frames: [AtomicUsize; BACKTRACE_DEPTH],
+}
+
+struct TraceState {
+    page_size: usize,
+    log_to_stderr: bool,
+    next: AtomicUsize,
+    log: Box<[LogEntry]>,
+}
+
+/// A debugging aid which reports the first access to each page of a mapping,
+/// acting as a watchpoint over the whole region
+///
+/// Attaching makes every page inaccessible. Each fault is recorded along with
+/// the accessed address, the address of the faulting instruction and a
+/// backtrace, optionally printed to stderr, and resolved by granting access to
+/// that page. Resolve the instruction addresses to source locations with
+/// `addr2line` or a debugger.
+///
+/// Every first touch of a page costs a signal and an `mprotect(2)`, so this is
+/// only intended for tracking down unexpected accesses during development, and
+/// only exists in builds with debug assertions.
+pub struct TracedMmap<'m> {
+    ptr: *mut u8,
+    len: usize,
+    // declared before `state` so the handler is unregistered before the
+    // state it points to is freed
+    _registration: GuardRegistration,
+    state: Box<TraceState>,
+    _map: PhantomData<&'m mut [u8]>,
+}
+
+impl<'m> TracedMmap<'m> {
+    /// Start tracing accesses to `map`, printing each one to stderr if
+    /// `log_to_stderr` is set
+    pub fn attach(map: &'m mut MmapMut, log_to_stderr: bool) -> io::Result<Self> {
+        let state = Box::new(TraceState {
+            page_size: page_size(),
+            log_to_stderr,
+            next: AtomicUsize::new(0),
+            log: (0..LOG_CAPACITY)
+                .map(|_| LogEntry {
+                    offset: AtomicUsize::new(0),
+                    pc: AtomicUsize::new(0),
+                    frames: [const { AtomicUsize::new(0) }; BACKTRACE_DEPTH],
+                })
+                .collect(),
+        });
+
+        let registration = register_guard(
+            map.ptr,
+            map.len,
+            record_access,
+            &*state as *const TraceState as usize,
+        )?;
+
+        unsafe { mprotect_raw(map.ptr, map.len, Protection::NONE)? };
+
+        Ok(Self {
+            ptr: map.ptr,
+            len: map.len,
+            _registration: registration,
+            state,
+            _map: PhantomData,
+        })
+    }
+
+    /// Accesses recorded since attaching or the last [`rearm`](Self::rearm),
+    /// in the order they happened
+    pub fn accesses(&self) -> Vec<TracedAccess> {
+        let recorded = self.state.next.load(Ordering::Acquire).min(LOG_CAPACITY);
+
+        self.state.log[..recorded]
+            .iter()
+            .map(|entry| {
+                let pc = entry.pc.load(Ordering::Acquire);
+                let offset = entry.offset.load(Ordering::Relaxed);
+
+                TracedAccess {
+                    offset,
+                    addr: self.ptr as usize + offset,
+                    pc,
+                    backtrace: entry
+                        .frames
+                        .iter()
+                        .map(|frame| frame.load(Ordering::Relaxed))
+                        .take_while(|&frame| frame != 0)
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Number of accesses which happened after the log filled up and so were
+    /// not recorded
+    pub fn dropped(&self) -> usize {
+        self.state
+            .next
+            .load(Ordering::Acquire)
+            .saturating_sub(LOG_CAPACITY)
+    }
+
+    /// Clear the log and make every page inaccessible again
+    pub fn rearm(&mut self) -> io::Result<()> {
+        unsafe { mprotect_raw(self.ptr, self.len, Protection::NONE)? };
+
+        self.state.next.store(0, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+fn record_access(fault: &GuardFault) -> GuardAction {
+    let state = unsafe { &*(fault.data as *const TraceState) };
+
+    let offset = fault.addr as usize - fault.region_start as usize;
+
+    let idx = state.next.fetch_add(1, Ordering::AcqRel);
+
+    let mut frames = [0; BACKTRACE_DEPTH];
+    walk_frames(fault.fp, &mut frames);
+
+    if let Some(entry) = state.log.get(idx) {
+        entry.offset.store(offset, Ordering::Relaxed);
+
+        for (slot, frame) in entry.frames.iter().zip(frames) {
+            slot.store(frame, Ordering::Relaxed);
+        }
+
+        entry.pc.store(fault.pc, Ordering::Release);
+    }
+
+    if state.log_to_stderr {
+        log_access(fault.addr as usize, fault.pc, &frames);
+    }
+
+    let page_start = unsafe { fault.region_start.add(offset - offset % state.page_size) };
+
+    match unsafe {
+        mprotect_raw(
+            page_start,
+            state.page_size,
+            Protection::READ | Protection::WRITE,
+        )
+    } {
+        Ok(()) => GuardAction::Retry,
+        Err(..) => GuardAction::Abort,
+    }
+}
+
+/// Follow the chain of frame pointers from `fp`, filling `frames` with return
+/// addresses until it ends
+///
+/// Each frame record is read with `process_vm_readv(2)` on this process,
+/// which fails rather than faulting if a frame pointer is bogus, as it is in
+/// code built without frame pointers.
+fn walk_frames(mut fp: usize, frames: &mut [usize]) {
+    for frame in frames {
+        if fp == 0 || !fp.is_multiple_of(mem::align_of::<usize>()) {
+            break;
+        }
+
+        // the caller's frame pointer, then the return address
+        let mut record = [0_usize; 2];
+
+        let local = libc::iovec {
+            iov_base: record.as_mut_ptr().cast(),
+            iov_len: mem::size_of_val(&record),
+        };
+
+        let remote = libc::iovec {
+            iov_base: fp as *mut _,
+            iov_len: mem::size_of_val(&record),
+        };
+
+        let read = unsafe { libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0) };
+
+        if read != mem::size_of_val(&record) as isize || record[1] == 0 {
+            break;
+        }
+
+        *frame = record[1];
+
+        // callers' frames are further up the stack
+        if record[0] <= fp {
+            break;
+        }
+
+        fp = record[0];
+    }
+}
+
+/// Print an access to stderr without allocating, as this runs in signal
+/// context
+fn log_access(addr: usize, pc: usize, frames: &[usize]) {
+    fn push_hex(buf: &mut [u8], len: &mut usize, value: usize) {
+        for shift in (0..usize::BITS).step_by(4).rev() {
+            buf[*len] = b"0123456789abcdef"[(value >> shift) & 0xf];
+            *len += 1;
+        }
+    }
+
+    let mut buf = [0_u8; 64 + BACKTRACE_DEPTH * 24];
+    let mut len = 0;
+
+    let labelled = [(&b"mmap: access to 0x"[..], addr), (b" from pc 0x", pc)]
+        .into_iter()
+        .chain(
+            frames
+                .iter()
+                .take_while(|&&frame| frame != 0)
+                .map(|&frame| (&b" <- 0x"[..], frame)),
+        );
+
+    for (label, value) in labelled {
+        buf[len..len + label.len()].copy_from_slice(label);
+        len += label.len();
+
+        push_hex(&mut buf, &mut len, value);
+    }
+
+    buf[len] = b'\n';
+    len += 1;
+
+    unsafe { libc::write(libc::STDERR_FILENO, buf.as_ptr().cast(), len) };
+}
+
+impl<'m> Deref for TracedMmap<'m> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<'m> DerefMut for TracedMmap<'m> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<'m> Drop for TracedMmap<'m> {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = mprotect_raw(self.ptr, self.len, Protection::READ | Protection::WRITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, MmapMut, TracedMmap};
+
+    use super::{walk_frames, BACKTRACE_DEPTH};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn records_first_access_per_page() {
+        let page = page_size();
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(page * 4).unwrap()).unwrap();
+
+        let mut traced = TracedMmap::attach(&mut map, false).unwrap();
+
+        assert!(traced.accesses().is_empty());
+
+        let byte = unsafe { traced.as_ptr().add(page * 2 + 5).read_volatile() };
+        traced[page * 2 + 6] = byte;
+        traced[3] = 1;
+
+        let accesses = traced.accesses();
+
+        assert_eq!(
+            accesses
+                .iter()
+                .map(|access| access.offset)
+                .collect::<Vec<_>>(),
+            vec![page * 2 + 5, 3]
+        );
+        assert_eq!(accesses[0].addr, traced.as_ptr() as usize + page * 2 + 5);
+        assert!(accesses[0].backtrace.len() <= BACKTRACE_DEPTH);
+        assert_eq!(traced.dropped(), 0);
+
+        traced.rearm().unwrap();
+        traced[page * 3] = 2;
+
+        assert_eq!(traced.accesses().len(), 1);
+
+        drop(traced);
+
+        assert_eq!(map[3], 1);
+    }
+
+    #[test]
+    fn walks_frame_records() {
+        // frame records as a function with frame pointers lays them out,
+        // each linking to its caller's further up the stack
+        let mut records = [[0_usize; 2]; 3];
+        let base = records.as_ptr() as usize;
+        let record = std::mem::size_of::<[usize; 2]>();
+
+        records[0] = [base + record, 0x10];
+        records[1] = [base + 2 * record, 0x20];
+        records[2] = [0, 0x30];
+
+        // the records are read behind the compiler's back
+        let base = std::hint::black_box(&records).as_ptr() as usize;
+
+        let mut frames = [0; 4];
+        walk_frames(base, &mut frames);
+
+        assert_eq!(frames, [0x10, 0x20, 0x30, 0]);
+
+        // a bogus frame pointer ends the walk instead of faulting
+        let mut frames = [0; 4];
+        walk_frames(8, &mut frames);
+
+        assert_eq!(frames, [0; 4]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io,
+    ops::{Deref, RangeBounds},
+    os::unix::prelude::MetadataExt,
+};
+
+use crate::{
+    check_range,
+    flag::{Flag, UniqueFlag},
+    mmap_raw, round_up_to_page, FileWatcher, Mmap, Protection,
+};
+
+/// The error wrapped in an [`io::Error`] of kind
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) when reading a part of a
+/// [`TruncationSafeMmap`] which was truncated away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncated {
+    /// Current length of the backing file
+    pub file_len: usize,
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "backing file was truncated to {} bytes", self.file_len)
+    }
+}
+
+impl Error for Truncated {}
+
+/// A read-only file mapping which survives the file being truncated by
+/// another process
+///
+/// Touching a page of a file mapping past the end of the file raises `SIGBUS`.
+/// This watches the file with inotify, and before each access replaces the
+/// pages lost to truncation with inaccessible anonymous memory, reporting
+/// [`Truncated`] instead of crashing.
+///
+/// A truncation racing with an access which has already been checked can
+/// still raise `SIGBUS`; this guards against the common case of readers
+/// noticing a shrunk file late, not against adversarial writers.
+///
+/// Checking replaces pages of the mapping, so both [`check`](Self::check) and
+/// [`get`](Self::get) borrow it mutably, and a slice returned by `get` must be
+/// dropped before the file is checked again.
+pub struct TruncationSafeMmap<'a> {
+    map: Mmap<'a>,
+    file: File,
+    watcher: FileWatcher,
+    valid_len: usize,
+}
+
+impl<'a> TruncationSafeMmap<'a> {
+    pub fn new(file: &File) -> io::Result<Self> {
+        let file = file.try_clone()?;
+        let watcher = FileWatcher::new(&file)?;
+        let map = Mmap::new_file(&file)?;
+
+        Ok(Self {
+            valid_len: map.len,
+            map,
+            file,
+            watcher,
+        })
+    }
+
+    /// Process pending notifications and return the number of leading bytes of
+    /// the mapping which are still backed by the file
+    pub fn check(&mut self) -> io::Result<usize> {
+        if !self.watcher.poll()? {
+            return Ok(self.valid_len);
+        }
+
+        let file_len = self.file.metadata()?.size() as usize;
+        let valid_len = self.valid_len;
+
+        if file_len < valid_len {
+            // the page containing the new end of file stays readable, the
+            // kernel zero-fills it past the end
+            let keep = round_up_to_page(file_len);
+            let end = round_up_to_page(valid_len);
+
+            if keep < end {
+                let replaced = unsafe {
+                    mmap_raw(
+                        self.map.ptr.add(keep) as *mut u8,
+                        end - keep,
+                        Protection::NONE,
+                        (UniqueFlag::MAP_PRIVATE | Flag::MAP_ANONYMOUS | Flag::MAP_FIXED).0,
+                        -1,
+                        0,
+                    )
+                };
+
+                match replaced {
+                    Ok(_) => {}
+                    // a heap-backed copy of the file cannot fault, so is left
+                    // alone
+                    Err(e) if e.kind() == io::ErrorKind::Unsupported => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            self.valid_len = file_len;
+        }
+
+        Ok(self.valid_len)
+    }
+
+    /// The bytes in `range`, or an error wrapping [`Truncated`] if the file no
+    /// longer extends that far
+    pub fn get(&mut self, range: impl RangeBounds<usize>) -> io::Result<&[u8]> {
+        let range = check_range(self.map.len, range)?;
+        let valid_len = self.check()?;
+
+        if range.end > valid_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                Truncated {
+                    file_len: valid_len,
+                },
+            ));
+        }
+
+        Ok(&self.map.deref()[range])
+    }
+
+    /// Length of the mapping when it was created
+    pub fn len(&self) -> usize {
+        self.map.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.len == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use crate::{page_size, test_util::temp_file, Truncated, TruncationSafeMmap};
+
+    #[test]
+    fn reports_truncation() {
+        let mut file = temp_file("truncate");
+
+        file.write_all(&vec![1; page_size() * 3]).unwrap();
+
+        let mut map = TruncationSafeMmap::new(&file).unwrap();
+
+        assert_eq!(map.get(..).unwrap().len(), page_size() * 3);
+
+        file.set_len(page_size() as u64 + 10).unwrap();
+
+        let err = map.get(..).unwrap_err();
+        let truncated = err.get_ref().unwrap().downcast_ref::<Truncated>().unwrap();
+
+        assert_eq!(truncated.file_len, page_size() + 10);
+        assert!(map.get(..page_size() + 10).unwrap().iter().all(|&b| b == 1));
+        assert!(map.get(page_size() * 2..).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fs, io, ops::Deref, path::Path};
+
+use crate::{page_size, DeviceMmap, RegisterBlock};
+
+/// A memory region of a Userspace I/O device, mapped from `/dev/uioN`
+///
+/// UIO selects which of a device's memory regions to map through the mmap
+/// offset: region `M` is requested at offset `M * page_size`. The kernel only
+/// maps whole pages, so the start of the region itself may lie
+/// [`region_offset`](Self::region_offset) bytes into the mapping.
+pub struct UioMmap {
+    map: DeviceMmap,
+    region_offset: usize,
+    region_len: usize,
+}
+
+impl UioMmap {
+    /// Map region `map_index` of `/dev/uio{index}`, reading its geometry from
+    /// `/sys/class/uio/uio{index}/maps/map{map_index}/`
+    pub fn open(index: u32, map_index: u32) -> io::Result<Self> {
+        let sysfs = format!("/sys/class/uio/uio{}/maps/map{}", index, map_index);
+        let sysfs = Path::new(&sysfs);
+
+        let region_len = read_sysfs_usize(&sysfs.join("size"))?;
+        let region_offset = read_sysfs_usize(&sysfs.join("offset"))?;
+
+        let map = DeviceMmap::open(
+            format!("/dev/uio{}", index),
+            u64::from(map_index) * page_size() as u64,
+            region_offset + region_len,
+        )?;
+
+        Ok(Self {
+            map,
+            region_offset,
+            region_len,
+        })
+    }
+
+    /// Offset of the start of the device region within the mapping
+    pub fn region_offset(&self) -> usize {
+        self.region_offset
+    }
+
+    /// Size of the device region as reported by the kernel
+    pub fn region_len(&self) -> usize {
+        self.region_len
+    }
+
+    /// Registers of the region, with offsets relative to the region start
+    pub fn registers(&self) -> RegisterBlock<'_> {
+        RegisterBlock::new(&self.map, self.region_offset)
+    }
+}
+
+impl Deref for UioMmap {
+    type Target = DeviceMmap;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+fn read_sysfs_usize(path: &Path) -> io::Result<usize> {
+    parse_sysfs_usize(&fs::read_to_string(path)?)
+}
+
+/// UIO reports region geometry as `0x`-prefixed hexadecimal
+fn parse_sysfs_usize(s: &str) -> io::Result<usize> {
+    let s = s.trim();
+
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    parsed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_sysfs_usize;
+
+    #[test]
+    fn parses_sysfs_values() {
+        assert_eq!(parse_sysfs_usize("0x00001000\n").unwrap(), 0x1000);
+        assert_eq!(parse_sysfs_usize("0x0\n").unwrap(), 0);
+        assert_eq!(parse_sysfs_usize("4096").unwrap(), 4096);
+        assert!(parse_sysfs_usize("0xzz").is_err());
+    }
+}
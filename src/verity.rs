@@ -0,0 +1,199 @@
+use std::{fs::File, io, os::unix::io::AsRawFd};
+
+use crate::{page_size, Mmap};
+
+/// `_IOW('f', 133, struct fsverity_enable_arg)`
+const FS_IOC_ENABLE_VERITY: libc::Ioctl = 0x4080_6685;
+
+/// `_IOWR('f', 134, struct fsverity_digest)`
+const FS_IOC_MEASURE_VERITY: libc::Ioctl = 0xc004_6686_u32 as libc::Ioctl;
+
+const FS_VERITY_HASH_ALG_SHA256: u32 = 1;
+
+/// Longest digest of any supported hash algorithm (SHA-512)
+const MAX_DIGEST_LEN: usize = 64;
+
+#[repr(C)]
+struct FsverityEnableArg {
+    version: u32,
+    hash_algorithm: u32,
+    block_size: u32,
+    salt_size: u32,
+    salt_ptr: u64,
+    sig_size: u32,
+    _reserved1: u32,
+    sig_ptr: u64,
+    _reserved2: [u64; 11],
+}
+
+#[repr(C)]
+struct FsverityDigest {
+    digest_algorithm: u16,
+    digest_size: u16,
+    digest: [u8; MAX_DIGEST_LEN],
+}
+
+/// Hash algorithm of an fs-verity file's Merkle tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerityAlgorithm {
+    Sha256,
+    Sha512,
+    /// An algorithm added to the kernel since, by its `FS_VERITY_HASH_ALG_*`
+    /// number
+    Other(u16),
+}
+
+/// The fs-verity digest of a file, which authenticates its entire contents
+///
+/// Compare it against a digest known to be good, for example one shipped
+/// with or signed alongside the application, to know that the file is the
+/// expected one; the kernel then guarantees every page read from it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerityDigest {
+    pub algorithm: VerityAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+impl VerityDigest {
+    /// The digest of `file`, or `None` if fs-verity is not enabled on it
+    ///
+    /// Fails with [`Unsupported`](io::ErrorKind::Unsupported) if the
+    /// filesystem does not support fs-verity.
+    pub fn measure(file: &File) -> io::Result<Option<Self>> {
+        let mut arg = FsverityDigest {
+            digest_algorithm: 0,
+            digest_size: MAX_DIGEST_LEN as u16,
+            digest: [0; MAX_DIGEST_LEN],
+        };
+
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_MEASURE_VERITY, &mut arg) } < 0 {
+            let err = io::Error::last_os_error();
+
+            return match err.raw_os_error() {
+                Some(libc::ENODATA) => Ok(None),
+                Some(libc::ENOTTY | libc::EOPNOTSUPP) => Err(unsupported()),
+                _ => Err(err),
+            };
+        }
+
+        let algorithm = match arg.digest_algorithm {
+            1 => VerityAlgorithm::Sha256,
+            2 => VerityAlgorithm::Sha512,
+            other => VerityAlgorithm::Other(other),
+        };
+
+        Ok(Some(Self {
+            algorithm,
+            digest: arg.digest[..arg.digest_size as usize].to_vec(),
+        }))
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "filesystem does not support fs-verity",
+    )
+}
+
+/// Enable fs-verity on `file` with SHA-256 and the page size as block size,
+/// which every kernel supporting fs-verity accepts
+fn enable_verity(file: &File) -> io::Result<()> {
+    let arg = FsverityEnableArg {
+        version: 1,
+        hash_algorithm: FS_VERITY_HASH_ALG_SHA256,
+        block_size: page_size() as u32,
+        salt_size: 0,
+        salt_ptr: 0,
+        sig_size: 0,
+        _reserved1: 0,
+        sig_ptr: 0,
+        _reserved2: [0; 11],
+    };
+
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_ENABLE_VERITY, &arg) } < 0 {
+        let err = io::Error::last_os_error();
+
+        return match err.raw_os_error() {
+            // enabled concurrently by someone else
+            Some(libc::EEXIST) => Ok(()),
+            Some(libc::ENOTTY | libc::EOPNOTSUPP) => Err(unsupported()),
+            Some(libc::ETXTBSY) => Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                "file is open for writing, so fs-verity cannot be enabled",
+            )),
+            _ => Err(err),
+        };
+    }
+
+    Ok(())
+}
+
+impl<'a> Mmap<'a> {
+    /// Map `file` read-only after making sure fs-verity is enabled on it,
+    /// returning the mapping and the file's digest
+    ///
+    /// Every page of an fs-verity file is checked against its Merkle tree as
+    /// the kernel reads it in, so a mapping of it never observes contents
+    /// other than the ones the digest describes, even if the underlying
+    /// storage is modified. Check the digest to authenticate the file itself.
+    ///
+    /// If fs-verity is not enabled yet, it is enabled first, which makes the
+    /// file permanently read-only and requires that nobody has it open for
+    /// writing, including through `file`. Fails with
+    /// [`Unsupported`](io::ErrorKind::Unsupported) if the filesystem does not
+    /// support fs-verity or it was not enabled for it with
+    /// `tune2fs -O verity` or the equivalent.
+    ///
+    /// (since Linux 5.4)
+    pub fn new_file_verified(file: &File) -> io::Result<(Self, VerityDigest)> {
+        let digest = match VerityDigest::measure(file)? {
+            Some(digest) => digest,
+            None => {
+                enable_verity(file)?;
+
+                VerityDigest::measure(file)?
+                    .ok_or_else(|| io::Error::other("fs-verity was not enabled"))?
+            }
+        };
+
+        Ok((Self::new_file(file)?, digest))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io, mem::size_of};
+
+    use crate::{
+        test_util::temp_path,
+        verity::{FsverityDigest, FsverityEnableArg},
+        Mmap, VerityAlgorithm,
+    };
+
+    #[test]
+    fn argument_layout() {
+        assert_eq!(size_of::<FsverityEnableArg>(), 128);
+        assert_eq!(size_of::<FsverityDigest>(), 4 + 64);
+    }
+
+    #[test]
+    fn verified_or_unsupported() {
+        let path = temp_path("verity");
+
+        std::fs::write(&path, b"model weights").unwrap();
+
+        let file = File::open(&path).unwrap();
+
+        match Mmap::new_file_verified(&file) {
+            Ok((map, digest)) => {
+                assert_eq!(&map[..], b"model weights");
+                assert_eq!(digest.algorithm, VerityAlgorithm::Sha256);
+                assert_eq!(digest.digest.len(), 32);
+            }
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::Unsupported),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
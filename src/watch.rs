@@ -0,0 +1,89 @@
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, Read},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+};
+
+/// An inotify watch on the file backing a mapping, used to find out when
+/// another process changes its size
+///
+/// The underlying inotify descriptor is exposed through [`AsRawFd`], so it can
+/// be registered with epoll-based event loops instead of calling
+/// [`wait`](Self::wait).
+pub struct FileWatcher {
+    inotify: File,
+}
+
+impl FileWatcher {
+    /// Watch `file` for modifications, which includes it being extended or
+    /// truncated
+    pub fn new(file: &File) -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let inotify = unsafe { File::from_raw_fd(fd) };
+
+        // the magic symlink resolves to the open file, even if it was since
+        // renamed
+        let path = CString::new(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap();
+
+        if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), libc::IN_MODIFY) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { inotify })
+    }
+
+    /// Consume any pending events without blocking, returning whether the
+    /// file was modified since the last call
+    pub fn poll(&self) -> io::Result<bool> {
+        let mut modified = false;
+
+        loop {
+            let mut pfd = libc::pollfd {
+                fd: self.inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            match unsafe { libc::poll(&mut pfd, 1, 0) } {
+                0 => return Ok(modified),
+                n if n > 0 => {
+                    self.wait()?;
+                    modified = true;
+                }
+                _ => {
+                    let err = io::Error::last_os_error();
+
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Block until the file is modified
+    pub fn wait(&self) -> io::Result<()> {
+        let mut buf = [0_u8; 4096];
+
+        loop {
+            match (&self.inotify).read(&mut buf) {
+                Ok(0) => continue,
+                Ok(..) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl AsRawFd for FileWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
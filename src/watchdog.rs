@@ -0,0 +1,322 @@
+use std::{
+    fs::File,
+    io,
+    os::unix::io::AsRawFd,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::{pidfd_open, MmapCell, MmapMut};
+
+/// Most peers a table can hold
+const MAX_PEERS: usize = 64;
+
+/// Pid of a slot whose dead peer's resources are being reclaimed, which keeps
+/// it from being joined until they are
+const RECLAIMING: u32 = u32::MAX;
+
+/// A table of the processes sharing a segment, placed inside it so that each
+/// can watch the others with a [`Watchdog`]
+///
+/// Peers [`join`](Self::join) on startup, which records their pid in a slot.
+/// The slot number is the peer's identity for as long as it lives, so
+/// structures which hand out resources, such as buffers taken from a queue
+/// but not yet released, should record which slot holds each, letting a
+/// survivor reclaim them from a [`Watchdog::on_reclaim`] callback.
+#[repr(C)]
+pub struct PeerTable {
+    peers: [AtomicU32; MAX_PEERS],
+}
+
+/// A peer which a [`Watchdog`] found dead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadPeer {
+    /// The slot the peer occupied in the [`PeerTable`], which is now free
+    pub slot: usize,
+    pub pid: u32,
+}
+
+impl PeerTable {
+    /// Initialize an empty table at `offset` in the mapping and return a
+    /// reference to it
+    pub fn init_in<'m>(map: &'m mut MmapMut, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        unsafe {
+            ptr.write(Self {
+                peers: std::array::from_fn(|_| AtomicU32::new(0)),
+            });
+
+            Ok(&*ptr)
+        }
+    }
+
+    /// Attach to a table previously initialized at `offset` in a
+    /// [view](MmapMut::as_cell) of the mapping, possibly by another process
+    pub fn attach<'m>(map: &'m MmapCell, offset: usize) -> io::Result<&'m Self> {
+        let ptr = map.typed_ptr::<Self>(offset, 1)?;
+
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Record the calling process in a free slot, returning the slot
+    pub fn join(&self) -> io::Result<usize> {
+        let pid = std::process::id();
+
+        self.peers
+            .iter()
+            .position(|peer| {
+                peer.compare_exchange(0, pid, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::OutOfMemory, "peer table is full"))
+    }
+
+    /// Free a slot claimed by [`join`](Self::join) when leaving cleanly
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) unless the
+    /// calling process holds `slot`.
+    pub fn leave(&self, slot: usize) -> io::Result<()> {
+        self.peers
+            .get(slot)
+            .and_then(|peer| {
+                peer.compare_exchange(std::process::id(), 0, Ordering::AcqRel, Ordering::Relaxed)
+                    .ok()
+            })
+            .map(drop)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "slot is not held by this process",
+                )
+            })
+    }
+
+    /// The occupied slots and the pids in them
+    pub fn peers(&self) -> Vec<(usize, u32)> {
+        self.peers
+            .iter()
+            .enumerate()
+            .map(|(slot, peer)| (slot, peer.load(Ordering::Acquire)))
+            .filter(|&(_, pid)| pid != 0 && pid != RECLAIMING)
+            .collect()
+    }
+
+    /// Start watching the other peers in the table
+    pub fn watchdog(&self) -> Watchdog<'_> {
+        Watchdog {
+            table: self,
+            watched: Vec::new(),
+            reclaimers: Vec::new(),
+        }
+    }
+}
+
+struct Watched {
+    slot: usize,
+    pid: u32,
+    pidfd: File,
+}
+
+/// Detects the death of the other processes in a [`PeerTable`] through
+/// pidfds, so that survivors can clean up after them
+///
+/// A pidfd becomes readable when its process exits, however it exits, so a
+/// single `poll(2)` covers every peer. Locks a dead peer held in a
+/// [`RobustShmMutex`](crate::RobustShmMutex) are recovered by the next
+/// process to lock them; what the watchdog adds is learning which peer died,
+/// so resources recorded against its slot can be reclaimed.
+///
+/// Peers are identified by pid, so a peer which dies and has its pid reused
+/// before the watchdog first looks at it is mistaken for the new process.
+/// Watching starts as soon as a peer is seen, which keeps that window short.
+///
+/// (since Linux 5.3)
+pub struct Watchdog<'t> {
+    table: &'t PeerTable,
+    watched: Vec<Watched>,
+    reclaimers: Vec<ReclaimCallback<'t>>,
+}
+
+/// Callback invoked by a [`Watchdog`] for each dead peer before its slot is
+/// freed
+pub type ReclaimCallback<'t> = Box<dyn FnMut(DeadPeer) + Send + 't>;
+
+impl<'t> Watchdog<'t> {
+    /// Invoke `reclaim` for each peer found dead, to release the resources
+    /// recorded against its slot, such as buffers it took from a queue
+    ///
+    /// Callbacks run before the slot is freed, so no new peer can join in it
+    /// and be confused with the dead one until they return. Only the watchdog
+    /// which frees a slot runs its callbacks, so each dead peer is reclaimed
+    /// once across all survivors.
+    pub fn on_reclaim(&mut self, reclaim: impl FnMut(DeadPeer) + Send + 't) {
+        self.reclaimers.push(Box::new(reclaim));
+    }
+
+    /// Wait up to `timeout`, or indefinitely if it is `None`, for peers to
+    /// die, returning the ones which did and freeing their slots
+    ///
+    /// Returns at once if a peer is already known to be dead. Peers which
+    /// joined since the last call are picked up first.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> io::Result<Vec<DeadPeer>> {
+        let mut dead = self.refresh()?;
+
+        let mut fds: Vec<_> = self
+            .watched
+            .iter()
+            .map(|watched| libc::pollfd {
+                fd: watched.pidfd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+
+        let timeout = match timeout {
+            _ if !dead.is_empty() => 0,
+            Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+
+        if res < 0 {
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        } else {
+            for (idx, fd) in fds.iter().enumerate().rev() {
+                if fd.revents != 0 {
+                    let watched = self.watched.swap_remove(idx);
+
+                    dead.push(self.reap(watched.slot, watched.pid));
+                }
+            }
+        }
+
+        Ok(dead.into_iter().flatten().collect())
+    }
+
+    /// Start watching peers which joined since the last call, and stop
+    /// watching those which left, returning any already found dead
+    fn refresh(&mut self) -> io::Result<Vec<Option<DeadPeer>>> {
+        let peers = self.table.peers();
+        let me = std::process::id();
+
+        self.watched
+            .retain(|watched| peers.contains(&(watched.slot, watched.pid)));
+
+        let mut dead = Vec::new();
+
+        for (slot, pid) in peers {
+            if pid == me
+                || self
+                    .watched
+                    .iter()
+                    .any(|watched| watched.slot == slot && watched.pid == pid)
+            {
+                continue;
+            }
+
+            match pidfd_open(pid as libc::pid_t) {
+                Ok(pidfd) => self.watched.push(Watched { slot, pid, pidfd }),
+                Err(err) if err.raw_os_error() == Some(libc::ESRCH) => {
+                    dead.push(self.reap(slot, pid));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(dead)
+    }
+
+    /// Reclaim the resources of a dead peer and free its slot, unless someone
+    /// else got there first
+    fn reap(&mut self, slot: usize, pid: u32) -> Option<DeadPeer> {
+        let peer = &self.table.peers[slot];
+
+        peer.compare_exchange(pid, RECLAIMING, Ordering::AcqRel, Ordering::Relaxed)
+            .ok()?;
+
+        let dead = DeadPeer { slot, pid };
+
+        for reclaim in &mut self.reclaimers {
+            reclaim(dead);
+        }
+
+        peer.store(0, Ordering::Release);
+
+        Some(dead)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        process::Command,
+        sync::{atomic::Ordering, Mutex},
+        time::Duration,
+    };
+
+    use crate::{DeadPeer, MmapMut, PeerTable};
+
+    #[test]
+    fn reports_dead_peer() {
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(4096).unwrap()).unwrap();
+
+        PeerTable::init_in(&mut map, 0).unwrap();
+
+        let cell = map.as_cell();
+        let table = PeerTable::attach(&cell, 0).unwrap();
+        let me = table.join().unwrap();
+
+        let mut child = Command::new("sleep").arg("0.05").spawn().unwrap();
+        let pid = child.id();
+
+        // the child stands in for a peer which joined the table itself
+        table.peers[1].store(pid, Ordering::Release);
+
+        let reclaimed = Mutex::new(Vec::new());
+
+        let mut watchdog = table.watchdog();
+
+        watchdog.on_reclaim(|dead| {
+            // the slot stays taken until its resources are reclaimed
+            assert!(table.peers().iter().all(|&(slot, _)| slot != dead.slot));
+            assert!(table.join().unwrap() != dead.slot);
+
+            reclaimed.lock().unwrap().push(dead);
+        });
+
+        assert!(watchdog
+            .poll(Some(Duration::from_millis(1)))
+            .unwrap()
+            .is_empty());
+
+        child.wait().unwrap();
+
+        assert_eq!(
+            watchdog.poll(Some(Duration::from_secs(5))).unwrap(),
+            [DeadPeer { slot: 1, pid }]
+        );
+        drop(watchdog);
+
+        assert_eq!(*reclaimed.lock().unwrap(), [DeadPeer { slot: 1, pid }]);
+
+        // the callback joined slot 2 while slot 1 was being reclaimed
+        assert_eq!(
+            table.peers(),
+            [(me, std::process::id()), (2, std::process::id())]
+        );
+
+        table.leave(me).unwrap();
+        table.leave(2).unwrap();
+
+        assert!(table.peers().is_empty());
+        assert!(table.leave(me).is_err());
+        assert!(table.leave(1000).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fs::File, io, num::NonZeroUsize, os::unix::io::AsRawFd, ptr, slice};
+
+use crate::{
+    flag::UniqueFlag, madvise_raw, mmap_raw, munmap_raw, page_size, round_up_to_page, Protection,
+};
+
+/// Number of windows kept mapped by [`WindowedMmap::new`]
+const DEFAULT_MAX_WINDOWS: usize = 4;
+
+struct Window {
+    offset: u64,
+    ptr: *mut u8,
+    len: usize,
+    last_used: u64,
+}
+
+/// Read-only access to a file through a bounded number of mapped windows,
+/// for files too large to map at once
+///
+/// Each [`read_at`](Self::read_at) is served from a window containing the
+/// requested bytes, mapping a new one on a miss and unmapping the least
+/// recently used window once the limit is reached. This bounds the address
+/// space used no matter the size of the file, which matters on 32-bit targets
+/// and for keeping the virtual size of a process in check.
+pub struct WindowedMmap {
+    file: File,
+    window_size: usize,
+    max_windows: usize,
+    windows: Vec<Window>,
+    clock: u64,
+    /// Length of the file when last checked, to avoid a `fstat(2)` per read
+    file_len: u64,
+    /// Advice applied to every new window
+    advice: i32,
+}
+
+impl WindowedMmap {
+    /// Access `file` through up to four windows of at least `window_size`
+    /// bytes each
+    pub fn new(file: &File, window_size: NonZeroUsize) -> io::Result<Self> {
+        Self::with_max_windows(
+            file,
+            window_size,
+            NonZeroUsize::new(DEFAULT_MAX_WINDOWS).unwrap(),
+        )
+    }
+
+    /// Access `file` through up to `max_windows` windows of at least
+    /// `window_size` bytes each
+    pub fn with_max_windows(
+        file: &File,
+        window_size: NonZeroUsize,
+        max_windows: NonZeroUsize,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            file: file.try_clone()?,
+            window_size: round_up_to_page(window_size.get()),
+            max_windows: max_windows.get(),
+            windows: Vec::with_capacity(max_windows.get()),
+            clock: 0,
+            file_len: file.metadata()?.len(),
+            advice: libc::MADV_NORMAL,
+        })
+    }
+
+    /// The `len` bytes of the file starting at `offset`, remapping a window
+    /// if none of the current ones contains them
+    ///
+    /// Requests longer than the window size are given a window of their own.
+    pub fn read_at(&mut self, offset: u64, len: usize) -> io::Result<&[u8]> {
+        let end = offset.saturating_add(len as u64);
+
+        if end > self.file_len {
+            self.file_len = self.file.metadata()?.len();
+
+            if end > self.file_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "read past the end of the file",
+                ));
+            }
+        }
+
+        if len == 0 {
+            return Ok(&[]);
+        }
+
+        self.clock += 1;
+
+        let idx = match self
+            .windows
+            .iter()
+            .position(|w| w.offset <= offset && end <= w.offset + w.len as u64)
+        {
+            Some(idx) => idx,
+            None => self.map_window(offset, end)?,
+        };
+
+        let window = &mut self.windows[idx];
+        window.last_used = self.clock;
+
+        let start = (offset - window.offset) as usize;
+
+        Ok(unsafe { slice::from_raw_parts(window.ptr.add(start), len) })
+    }
+
+    /// Map a window covering `offset..end`, evicting the least recently used
+    /// window if at the limit, and return its index
+    fn map_window(&mut self, offset: u64, end: u64) -> io::Result<usize> {
+        let window_offset = offset - offset % page_size() as u64;
+        let len = round_up_to_page((end - window_offset) as usize).max(self.window_size);
+
+        let window_offset_i64 = i64::try_from(window_offset)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "offset is too large"))?;
+
+        if self.windows.len() == self.max_windows {
+            let lru = self
+                .windows
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, w)| w.last_used)
+                .map(|(idx, _)| idx)
+                .unwrap();
+
+            let evicted = self.windows.swap_remove(lru);
+            unsafe { munmap_raw(evicted.ptr, evicted.len) };
+        }
+
+        // the window may extend past the end of the file, but only the pages
+        // backing requested bytes are ever touched
+        let ptr = unsafe {
+            mmap_raw(
+                ptr::null_mut(),
+                len,
+                Protection::READ,
+                UniqueFlag::MAP_SHARED.0,
+                self.file.as_raw_fd(),
+                window_offset_i64,
+            )?
+        };
+
+        if self.advice != libc::MADV_NORMAL {
+            if let Err(err) = unsafe { madvise_raw(ptr, len, self.advice) } {
+                unsafe { munmap_raw(ptr, len) };
+                return Err(err);
+            }
+        }
+
+        self.windows.push(Window {
+            offset: window_offset,
+            ptr,
+            len,
+            last_used: self.clock,
+        });
+
+        Ok(self.windows.len() - 1)
+    }
+
+    /// Advise the kernel that the file is read from start to end, so it
+    /// reads further ahead into each window and drops pages soon after they
+    /// are read
+    pub fn set_sequential(&mut self, sequential: bool) -> io::Result<()> {
+        self.advice = if sequential {
+            libc::MADV_SEQUENTIAL
+        } else {
+            libc::MADV_NORMAL
+        };
+
+        for window in &self.windows {
+            unsafe { madvise_raw(window.ptr, window.len, self.advice)? };
+        }
+
+        Ok(())
+    }
+
+    /// Number of windows currently mapped
+    pub fn mapped_windows(&self) -> usize {
+        self.windows.len()
+    }
+}
+
+impl Drop for WindowedMmap {
+    fn drop(&mut self) {
+        for window in &self.windows {
+            unsafe { munmap_raw(window.ptr, window.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, os::unix::fs::FileExt};
+
+    use crate::{page_size, test_util::temp_file, WindowedMmap};
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let file = temp_file("windowed");
+
+        let page = page_size() as u64;
+        file.set_len(page * 8).unwrap();
+
+        for i in 0..8 {
+            file.write_all_at(&[i as u8; 4], page * i + 10).unwrap();
+        }
+
+        let mut map = WindowedMmap::with_max_windows(
+            &file,
+            NonZeroUsize::new(page_size()).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(map.read_at(10, 4).unwrap(), &[0; 4]);
+        assert_eq!(map.read_at(page * 5 + 10, 4).unwrap(), &[5; 4]);
+        assert_eq!(map.read_at(page * 7 + 10, 4).unwrap(), &[7; 4]);
+        assert_eq!(map.mapped_windows(), 2);
+
+        // spans two pages, so needs a window larger than the window size
+        let spanning = map.read_at(page * 2 + 10, page as usize).unwrap();
+
+        assert_eq!(&spanning[..4], &[2; 4]);
+        assert_eq!(spanning.len(), page_size());
+
+        assert_eq!(
+            map.read_at(page * 3 + 6, 8).unwrap(),
+            &[0, 0, 0, 0, 3, 3, 3, 3]
+        );
+
+        assert!(map.read_at(page * 8 - 2, 4).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    io,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    mprotect_raw, page_size, register_guard, GuardAction, GuardFault, GuardRegistration, MmapMut,
+    Protection,
+};
+
+struct TrackerState {
+    page_size: usize,
+    dirty: Box<[AtomicU64]>,
+}
+
+/// Precise dirty-page tracking for a mapping using write-protection faults
+///
+/// Attaching makes every page of the mapping read-only. The first write to each
+/// page raises a `SIGSEGV`, which is caught, recorded in the dirty set, and
+/// resolved by making that page writable again, so subsequent writes to it run
+/// at full speed.
+///
+/// Only writes made by this process are observed; writes by other processes
+/// sharing the mapping do not fault here.
+pub struct WriteTracker<'m> {
+    ptr: *mut u8,
+    len: usize,
+    // declared before `state` so the handler is unregistered before the
+    // state it points to is freed
+    _registration: GuardRegistration,
+    state: Box<TrackerState>,
+    _map: PhantomData<&'m mut [u8]>,
+}
+
+impl<'m> WriteTracker<'m> {
+    pub fn attach(map: &'m mut MmapMut) -> io::Result<Self> {
+        let page_size = page_size();
+        let pages = map.len.div_ceil(page_size);
+
+        let state = Box::new(TrackerState {
+            page_size,
+            dirty: (0..pages.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+        });
+
+        let registration = register_guard(
+            map.ptr,
+            map.len,
+            mark_dirty,
+            &*state as *const TrackerState as usize,
+        )?;
+
+        unsafe { mprotect_raw(map.ptr, map.len, Protection::READ)? };
+
+        Ok(Self {
+            ptr: map.ptr,
+            len: map.len,
+            _registration: registration,
+            state,
+            _map: PhantomData,
+        })
+    }
+
+    pub fn is_dirty(&self, page: usize) -> bool {
+        self.state
+            .dirty
+            .get(page / 64)
+            .is_some_and(|word| word.load(Ordering::Acquire) & (1 << (page % 64)) != 0)
+    }
+
+    /// Indices of the pages written since attaching or the last
+    /// [`reset`](Self::reset), in ascending order
+    pub fn dirty_pages(&self) -> Vec<usize> {
+        let pages = self.len.div_ceil(self.state.page_size);
+
+        (0..pages).filter(|&page| self.is_dirty(page)).collect()
+    }
+
+    /// Clear the dirty set and write-protect every page again
+    pub fn reset(&mut self) -> io::Result<()> {
+        unsafe { mprotect_raw(self.ptr, self.len, Protection::READ)? };
+
+        for word in self.state.dirty.iter() {
+            word.store(0, Ordering::Release);
+        }
+
+        Ok(())
+    }
+}
+
+fn mark_dirty(fault: &GuardFault) -> GuardAction {
+    let state = unsafe { &*(fault.data as *const TrackerState) };
+
+    let page = (fault.addr as usize - fault.region_start as usize) / state.page_size;
+
+    state.dirty[page / 64].fetch_or(1 << (page % 64), Ordering::AcqRel);
+
+    let page_start = unsafe { fault.region_start.add(page * state.page_size) };
+
+    match unsafe {
+        mprotect_raw(
+            page_start,
+            state.page_size,
+            Protection::READ | Protection::WRITE,
+        )
+    } {
+        Ok(()) => GuardAction::Retry,
+        Err(..) => GuardAction::Abort,
+    }
+}
+
+impl<'m> Deref for WriteTracker<'m> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<'m> DerefMut for WriteTracker<'m> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<'m> Drop for WriteTracker<'m> {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = mprotect_raw(self.ptr, self.len, Protection::READ | Protection::WRITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use crate::{page_size, MmapMut, WriteTracker};
+
+    #[test]
+    #[cfg_attr(any(miri, feature = "asan-friendly"), ignore = "needs real mappings")]
+    fn records_first_write_per_page() {
+        let page = page_size();
+        let mut map = MmapMut::new_anon(NonZeroUsize::new(page * 4).unwrap()).unwrap();
+
+        let mut tracker = WriteTracker::attach(&mut map).unwrap();
+
+        assert!(tracker.dirty_pages().is_empty());
+
+        tracker[page + 1] = 1;
+        tracker[page + 2] = 2;
+        tracker[page * 3] = 3;
+
+        assert_eq!(tracker.dirty_pages(), vec![1, 3]);
+
+        tracker.reset().unwrap();
+        tracker[0] = 4;
+
+        assert_eq!(tracker.dirty_pages(), vec![0]);
+
+        drop(tracker);
+
+        assert_eq!(&map[page + 1..page + 3], &[1, 2]);
+        map[page * 2] = 5;
+    }
+}
@@ -0,0 +1,122 @@
+use std::{io, ops::RangeBounds};
+
+use crate::{check_range, madvise_raw, maps, page_size, remove, MmapMut};
+
+/// Ranges shorter than this are always zeroed with `memset`, which is cheaper
+/// than reading `/proc/self/maps` and the page faults that follow discarding
+const MEMSET_THRESHOLD: usize = 256 * 1024;
+
+impl<'a> MmapMut<'a> {
+    /// Zero `range` with the cheapest mechanism which is correct for the
+    /// mapping
+    ///
+    /// Short ranges and the partial pages at either end are written with
+    /// `memset`. The whole pages in between are discarded instead where that
+    /// reads back as zeros: with `MADV_DONTNEED` for private anonymous
+    /// memory, and with `MADV_REMOVE`, which punches a hole in the file, for
+    /// shared mappings. Private file mappings, whose discarded pages would
+    /// read back the file, and anything the kernel refuses to discard fall
+    /// back to `memset`. Discarding also returns the memory to the system.
+    pub fn zero_range(&mut self, range: impl RangeBounds<usize>) -> io::Result<()> {
+        let range = check_range(self.len, range)?;
+
+        let page = page_size();
+
+        let start = self.ptr as usize + range.start;
+        let end = self.ptr as usize + range.end;
+
+        let inner_start = start.next_multiple_of(page);
+        let inner_end = end - end % page;
+
+        if range.len() < MEMSET_THRESHOLD || inner_start >= inner_end {
+            self[range].fill(0);
+            return Ok(());
+        }
+
+        memset(start, inner_start);
+        memset(inner_end, end);
+
+        let regions = maps::parse_self()?;
+
+        let mut addr = inner_start;
+
+        while addr < inner_end {
+            let region = maps::find_region(&regions, addr)
+                .ok_or_else(|| io::Error::other("mapping is missing from /proc/self/maps"))?;
+
+            let chunk_end = region.end.min(inner_end);
+            let (ptr, len) = (addr as *mut u8, chunk_end - addr);
+
+            let discarded = if !region.perms.shared && region.inode == 0 {
+                unsafe { madvise_raw(ptr, len, libc::MADV_DONTNEED) }
+            } else if region.perms.shared {
+                remove::remove_range(ptr, len, ..)
+            } else {
+                Err(io::ErrorKind::Unsupported.into())
+            };
+
+            if discarded.is_err() {
+                memset(addr, chunk_end);
+            }
+
+            addr = chunk_end;
+        }
+
+        Ok(())
+    }
+}
+
+fn memset(start: usize, end: usize) {
+    unsafe { (start as *mut u8).write_bytes(0, end - start) };
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, num::NonZeroUsize};
+
+    use crate::{page_size, test_util::temp_file, MmapMut, MmapOptions};
+
+    #[test]
+    fn zeroes_every_kind_of_mapping() {
+        let len = 1 << 20;
+
+        let shared = MmapMut::new_anon(NonZeroUsize::new(len).unwrap()).unwrap();
+        let private = MmapOptions::new(NonZeroUsize::new(len).unwrap())
+            .private()
+            .map_anon_mut()
+            .unwrap();
+
+        let mut file = temp_file("zero");
+
+        file.write_all(&vec![7; len]).unwrap();
+
+        let copy_on_write = MmapOptions::new(NonZeroUsize::new(len).unwrap())
+            .private()
+            .map_file_mut(&file)
+            .unwrap();
+
+        for mut map in [shared, private, copy_on_write] {
+            map.fill(1);
+
+            // unaligned at both ends, and large enough to discard pages
+            map.zero_range(100..len - 100).unwrap();
+
+            assert_eq!(map[99], 1);
+            assert!(map[100..len - 100].iter().all(|&b| b == 0));
+            assert_eq!(map[len - 100], 1);
+
+            map.fill(1);
+            map.zero_range(page_size()..page_size() + 10).unwrap();
+
+            assert_eq!(
+                map[page_size() - 1..page_size() + 11],
+                [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+            );
+        }
+
+        assert!(MmapMut::new_anon(NonZeroUsize::new(len).unwrap())
+            .unwrap()
+            .zero_range(..len + 1)
+            .is_err());
+    }
+}